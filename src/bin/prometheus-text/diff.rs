@@ -0,0 +1,162 @@
+//! Compares the series of two scrapes
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use opentelemetry_prometheus_text_exporter::MetricFamily;
+
+use crate::openmetrics::{write_labels, write_value};
+
+/// How a series differs between two scrapes
+#[derive(Debug, PartialEq)]
+pub(crate) enum Change {
+    Added { series: String, value: f64 },
+    Removed { series: String, value: f64 },
+    Changed { series: String, old: f64, new: f64 },
+}
+
+impl Change {
+    fn series(&self) -> &str {
+        match self {
+            Self::Added { series, .. }
+            | Self::Removed { series, .. }
+            | Self::Changed { series, .. } => series,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Added { series, value } => write!(f, "+ {series} {}", Value(*value)),
+            Self::Removed { series, value } => write!(f, "- {series} {}", Value(*value)),
+            Self::Changed { series, old, new } => write!(
+                f,
+                "~ {series} {} -> {} ({})",
+                Value(*old),
+                Value(*new),
+                Delta(new - old)
+            ),
+        }
+    }
+}
+
+/// Formats a sample value like in an exposition
+struct Value(f64);
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buffer = Vec::new();
+        write_value(&mut buffer, self.0).map_err(|_| fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&buffer))
+    }
+}
+
+/// Formats the difference between two values, with its sign
+struct Delta(f64);
+
+impl fmt::Display for Delta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_finite() {
+            write!(f, "{:+}", self.0)
+        } else {
+            write!(f, "{}", Value(self.0))
+        }
+    }
+}
+
+/// The series of a scrape, by name and sorted labels, with their value
+fn series(families: &[MetricFamily]) -> BTreeMap<String, f64> {
+    families
+        .iter()
+        .flat_map(|family| &family.samples)
+        .map(|sample| {
+            let mut labels = sample.labels.clone();
+            labels.sort();
+
+            let mut series = sample.name.as_bytes().to_vec();
+            // Writing to a `Vec` doesn't fail
+            let _ = write_labels(&mut series, &labels);
+            (String::from_utf8_lossy(&series).into_owned(), sample.value)
+        })
+        .collect()
+}
+
+/// List the series added, removed and changed between two scrapes, ordered by
+/// series
+#[allow(
+    clippy::float_cmp,
+    reason = "unchanged series have the exact same value"
+)]
+pub(crate) fn diff(old: &[MetricFamily], new: &[MetricFamily]) -> Vec<Change> {
+    let old = series(old);
+    let mut new = series(new);
+
+    let mut changes = Vec::new();
+    for (series, old) in old {
+        match new.remove(&series) {
+            None => changes.push(Change::Removed { series, value: old }),
+            // `NaN` values are the same, like stale markers
+            Some(new) if old.to_bits() == new.to_bits() || old == new => {}
+            Some(new) => changes.push(Change::Changed { series, old, new }),
+        }
+    }
+    changes.extend(
+        new.into_iter()
+            .map(|(series, value)| Change::Added { series, value }),
+    );
+
+    changes.sort_by(|a, b| a.series().cmp(b.series()));
+    changes
+}
+
+/// A line counting the changes of each kind
+pub(crate) fn summary(changes: &[Change]) -> String {
+    let count = |kind: fn(&Change) -> bool| changes.iter().filter(|change| kind(change)).count();
+    format!(
+        "{} added, {} removed, {} changed",
+        count(|change| matches!(change, Change::Added { .. })),
+        count(|change| matches!(change, Change::Removed { .. })),
+        count(|change| matches!(change, Change::Changed { .. })),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry_prometheus_text_exporter::{ExpositionFormat, parse_exposition};
+
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let old = parse_exposition(
+            "requests_total{method=\"GET\",code=\"200\"} 10\n\
+             requests_total{method=\"POST\",code=\"200\"} 3\n\
+             up 1\n\
+             ratio NaN\n",
+            ExpositionFormat::Text,
+        )
+        .unwrap();
+        let new = parse_exposition(
+            "requests_total{code=\"200\",method=\"GET\"} 12.5\n\
+             requests_total{method=\"PUT\",code=\"200\"} 1\n\
+             up 1\n\
+             ratio NaN\n",
+            ExpositionFormat::Text,
+        )
+        .unwrap();
+
+        let changes = diff(&old, &new);
+        let lines: Vec<String> = changes.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "~ requests_total{code=\"200\",method=\"GET\"} 10 -> 12.5 (+2.5)",
+                "- requests_total{code=\"200\",method=\"POST\"} 3",
+                "+ requests_total{code=\"200\",method=\"PUT\"} 1",
+            ]
+        );
+        assert_eq!(summary(&changes), "1 added, 1 removed, 1 changed");
+        assert!(diff(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,582 @@
+//! Converts OTLP metrics to the Prometheus text formats, lints expositions,
+//! compares scrapes and watches the series of an endpoint change, with the
+//! translation rules of the exporter.
+
+#![deny(clippy::all, clippy::pedantic)]
+
+mod diff;
+mod openmetrics;
+mod top;
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use opentelemetry::KeyValue;
+use opentelemetry_prometheus_text_exporter::{
+    ExporterBuilder, ExpositionFormat, LintRule, Linter, MetadataOverride, MetricSelector,
+    OtlpMetrics, RelabelConfig, parse_exposition,
+};
+
+type Error = Box<dyn std::error::Error>;
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+#[allow(
+    clippy::large_enum_variant,
+    reason = "the command line is only parsed once"
+)]
+enum Command {
+    /// Convert OTLP JSON or protobuf metrics to an exposition
+    Convert(ConvertArgs),
+
+    /// Check the naming conventions of expositions, exiting with 1 if there
+    /// are findings
+    Lint(LintArgs),
+
+    /// Compare the series of two scrapes, exiting with 1 if they differ
+    Diff(DiffArgs),
+
+    /// Scrape an endpoint periodically, showing the series changing the most
+    /// and their rates
+    Top(TopArgs),
+}
+
+#[derive(Debug, Args)]
+struct ConvertArgs {
+    /// The OTLP file to convert, or `-` for the standard input
+    #[arg(default_value = "-")]
+    input: PathBuf,
+
+    /// The encoding of the input, guessed from the file extension by default
+    #[arg(long, value_enum)]
+    input_format: Option<InputFormat>,
+
+    /// The format of the exposition
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    output_format: Format,
+
+    /// Write the exposition to a file instead of the standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    #[command(flatten)]
+    exporter: ExporterArgs,
+}
+
+/// The options of the exporter which apply to a single rendering.
+///
+/// Of the relabeling actions, only `labeldrop` and `labelkeep` have flags: the
+/// other ones take several fields each, like source labels, a target label and
+/// a replacement, which don't fit in a single flag. Instruments are kept or
+/// left out with `--include` and `--exclude` instead.
+#[derive(Debug, Args)]
+#[allow(
+    clippy::struct_excessive_bools,
+    reason = "Each boolean is a command-line flag"
+)]
+struct ExporterArgs {
+    /// Read the `OTEL_EXPORTER_PROMETHEUS_*` environment variables first
+    #[arg(long)]
+    from_env: bool,
+
+    /// Don't add unit suffixes to the metric names
+    #[arg(long)]
+    without_units: bool,
+
+    /// Don't add the `_total` suffix to counters
+    #[arg(long)]
+    without_counter_suffixes: bool,
+
+    /// Don't render the `target_info` family
+    #[arg(long)]
+    without_target_info: bool,
+
+    /// Don't add the `otel_scope_*` labels
+    #[arg(long)]
+    without_scope_info: bool,
+
+    /// Prefix the name of every metric with a namespace
+    #[arg(long)]
+    namespace: Option<String>,
+
+    /// Add a label to every series, like `env=prod`
+    #[arg(long = "const-label", value_name = "NAME=VALUE", value_parser = parse_pair::<String>)]
+    const_labels: Vec<(String, String)>,
+
+    /// Add the resource attributes matching a glob pattern as labels
+    #[arg(long = "resource-constant-label", value_name = "PATTERN")]
+    resource_constant_labels: Vec<String>,
+
+    /// Don't add the resource attributes matching a glob pattern as labels
+    #[arg(long = "without-resource-constant-label", value_name = "PATTERN")]
+    without_resource_constant_labels: Vec<String>,
+
+    /// Generate a `HELP` text for the metrics without a description
+    #[arg(long)]
+    generated_help: bool,
+
+    /// Fold the series over this limit, per metric, in an overflow series
+    #[arg(long, value_name = "LIMIT")]
+    cardinality_limit: Option<usize>,
+
+    /// Set the cardinality limit of an instrument, like
+    /// `http.server.request.duration=100`
+    #[arg(
+        long = "metric-cardinality-limit",
+        value_name = "INSTRUMENT=LIMIT",
+        value_parser = parse_pair::<usize>
+    )]
+    metric_cardinality_limits: Vec<(String, usize)>,
+
+    /// Leave out the series with more labels than this
+    #[arg(long, value_name = "LIMIT")]
+    label_limit: Option<usize>,
+
+    /// Cut the label names longer than this
+    #[arg(long, value_name = "LIMIT")]
+    label_name_length_limit: Option<usize>,
+
+    /// Cut the label values longer than this
+    #[arg(long, value_name = "LIMIT")]
+    label_value_length_limit: Option<usize>,
+
+    /// Only render the instruments with a name matching a glob pattern
+    #[arg(long = "include", value_name = "PATTERN")]
+    include: Vec<String>,
+
+    /// Leave out the instruments with a name matching a glob pattern
+    #[arg(long = "exclude", value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Remove the labels with a name matching a regular expression
+    #[arg(long = "drop-labels", value_name = "REGEX")]
+    drop_labels: Vec<String>,
+
+    /// Only keep the labels with a name matching a regular expression
+    #[arg(long = "keep-labels", value_name = "REGEX")]
+    keep_labels: Vec<String>,
+
+    /// Set the Prometheus name of an instrument, like `db.query=db_queries`
+    #[arg(long = "rename", value_name = "INSTRUMENT=NAME", value_parser = parse_pair::<String>)]
+    renames: Vec<(String, String)>,
+
+    /// Set the description of an instrument
+    #[arg(long = "description", value_name = "INSTRUMENT=TEXT", value_parser = parse_pair::<String>)]
+    descriptions: Vec<(String, String)>,
+
+    /// Set the unit of an instrument, like `db.query.duration=ms`
+    #[arg(long = "unit", value_name = "INSTRUMENT=UNIT", value_parser = parse_pair::<String>)]
+    units: Vec<(String, String)>,
+
+    /// Leave out the families with the lowest priority to keep the
+    /// exposition under this size
+    #[arg(long, value_name = "BYTES")]
+    size_budget: Option<usize>,
+
+    /// Set the priority of the families of an instrumentation scope within
+    /// the size budget, like `my-library=10`
+    #[arg(
+        long = "scope-priority",
+        value_name = "SCOPE=PRIORITY",
+        value_parser = parse_pair::<i32>
+    )]
+    scope_priorities: Vec<(String, i32)>,
+
+    /// Set the priority of the families with a name matching a glob pattern
+    /// within the size budget, like `http_server_*=10`
+    #[arg(
+        long = "name-priority",
+        value_name = "PATTERN=PRIORITY",
+        value_parser = parse_pair::<i32>
+    )]
+    name_priorities: Vec<(String, i32)>,
+}
+
+impl ExporterArgs {
+    fn builder(&self) -> Result<ExporterBuilder, Error> {
+        let mut builder = if self.from_env {
+            ExporterBuilder::from_env()?
+        } else {
+            ExporterBuilder::default()
+        };
+
+        if self.without_units {
+            builder = builder.without_units();
+        }
+        if self.without_counter_suffixes {
+            builder = builder.without_counter_suffixes();
+        }
+        if self.without_target_info {
+            builder = builder.without_target_info();
+        }
+        if self.without_scope_info {
+            builder = builder.without_scope_info();
+        }
+        if let Some(namespace) = &self.namespace {
+            builder = builder.with_namespace(namespace);
+        }
+        builder = builder
+            .with_const_labels(
+                self.const_labels
+                    .iter()
+                    .map(|(name, value)| KeyValue::new(name.clone(), value.clone())),
+            )
+            .with_resource_constant_labels(&self.resource_constant_labels)
+            .without_resource_constant_labels(&self.without_resource_constant_labels);
+        if self.generated_help {
+            builder = builder.with_generated_help();
+        }
+
+        if let Some(limit) = self.cardinality_limit {
+            builder = builder.with_cardinality_limit(limit);
+        }
+        for (instrument, limit) in &self.metric_cardinality_limits {
+            builder = builder.with_metric_cardinality_limit(instrument, *limit);
+        }
+        if let Some(limit) = self.label_limit {
+            builder = builder.with_label_limit(limit);
+        }
+        if let Some(limit) = self.label_name_length_limit {
+            builder = builder.with_label_name_length_limit(limit);
+        }
+        if let Some(limit) = self.label_value_length_limit {
+            builder = builder.with_label_value_length_limit(limit);
+        }
+
+        for pattern in &self.include {
+            builder = builder.with_include(MetricSelector::new().name(pattern));
+        }
+        for pattern in &self.exclude {
+            builder = builder.with_exclude(MetricSelector::new().name(pattern));
+        }
+        for regex in &self.drop_labels {
+            builder = builder.with_relabel_config(RelabelConfig::label_drop().regex(regex)?);
+        }
+        for regex in &self.keep_labels {
+            builder = builder.with_relabel_config(RelabelConfig::label_keep().regex(regex)?);
+        }
+
+        // An override replaces the previous one of the same instrument, so the
+        // flags of an instrument are merged in a single override
+        let mut overrides = BTreeMap::new();
+        let mut set = |instrument: &str, apply: &dyn Fn(MetadataOverride) -> MetadataOverride| {
+            let metadata = overrides
+                .remove(instrument)
+                .unwrap_or_else(|| MetadataOverride::new(instrument));
+            overrides.insert(instrument.to_owned(), apply(metadata));
+        };
+        for (instrument, name) in &self.renames {
+            set(instrument, &|metadata| metadata.name(name));
+        }
+        for (instrument, description) in &self.descriptions {
+            set(instrument, &|metadata| metadata.description(description));
+        }
+        for (instrument, unit) in &self.units {
+            set(instrument, &|metadata| metadata.unit(unit));
+        }
+        for metadata in overrides.into_values() {
+            builder = builder.with_metadata_override(metadata);
+        }
+
+        if let Some(bytes) = self.size_budget {
+            builder = builder.with_size_budget(bytes);
+        }
+        for (scope, priority) in &self.scope_priorities {
+            builder = builder.with_scope_priority(scope, *priority);
+        }
+        for (pattern, priority) in &self.name_priorities {
+            builder = builder.with_name_priority(pattern, *priority);
+        }
+
+        Ok(builder)
+    }
+}
+
+#[derive(Debug, Args)]
+struct LintArgs {
+    /// The exposition files to check, or `-` for the standard input
+    #[arg(default_value = "-")]
+    files: Vec<PathBuf>,
+
+    /// The format of the expositions, guessed from their content by default
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+
+    /// Disable a rule, like `missing-help`
+    #[arg(long = "disable", value_name = "RULE", value_parser = parse_rule)]
+    disabled: Vec<LintRule>,
+
+    /// Report the families with more series than this
+    #[arg(long, value_name = "SERIES")]
+    series_budget: Option<usize>,
+}
+
+#[derive(Debug, Args)]
+struct DiffArgs {
+    /// The first scrape, or `-` for the standard input
+    old: PathBuf,
+
+    /// The second scrape, or `-` for the standard input
+    new: PathBuf,
+
+    /// The format of the scrapes, guessed from their content by default
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+}
+
+#[derive(Debug, Args)]
+struct TopArgs {
+    /// The URL of the endpoint, like `http://localhost:9464/metrics`
+    url: top::Endpoint,
+
+    /// The time between scrapes, in seconds
+    #[arg(long, default_value = "5", value_parser = parse_interval)]
+    interval: Duration,
+
+    /// The number of series to show
+    #[arg(long, default_value_t = 20)]
+    limit: usize,
+
+    /// Stop after showing this many refreshes
+    #[arg(long)]
+    iterations: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum InputFormat {
+    /// OTLP JSON, one `MetricsData` message per line
+    Json,
+
+    /// A single OTLP protobuf `MetricsData` message
+    Protobuf,
+}
+
+impl InputFormat {
+    /// Guess the encoding of a file from its extension
+    fn guess(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("pb" | "binpb" | "protobuf") => Self::Protobuf,
+            _ => Self::Json,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    /// The Prometheus text format, version 0.0.4
+    Text,
+
+    /// The `OpenMetrics` text format
+    #[value(name = "openmetrics")]
+    OpenMetrics,
+}
+
+impl Format {
+    /// The format of an exposition, `OpenMetrics` if it ends with `# EOF`
+    fn guess(exposition: &str) -> Self {
+        if exposition.trim_end().ends_with("# EOF") {
+            Self::OpenMetrics
+        } else {
+            Self::Text
+        }
+    }
+}
+
+impl From<Format> for ExpositionFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Text => Self::Text,
+            Format::OpenMetrics => Self::OpenMetrics,
+        }
+    }
+}
+
+fn parse_pair<T>(pair: &str) -> Result<(String, T), String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let (key, value) = pair
+        .split_once('=')
+        .ok_or_else(|| format!("expected `KEY=VALUE`, got `{pair}`"))?;
+    let value = value
+        .parse()
+        .map_err(|error| format!("invalid value `{value}`: {error}"))?;
+    Ok((key.to_owned(), value))
+}
+
+fn parse_rule(name: &str) -> Result<LintRule, String> {
+    LintRule::from_name(name).ok_or_else(|| {
+        let names: Vec<&str> = LintRule::ALL.iter().map(|rule| rule.name()).collect();
+        format!("unknown rule, expected one of {}", names.join(", "))
+    })
+}
+
+fn parse_interval(value: &str) -> Result<Duration, String> {
+    let seconds: f64 = value.parse().map_err(|error| format!("{error}"))?;
+    Duration::try_from_secs_f64(seconds).map_err(|error| format!("{error}"))
+}
+
+/// Read a whole file, or the standard input for `-`
+fn read_input(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    if path == Path::new("-") {
+        std::io::stdin().read_to_end(&mut bytes)?;
+    } else {
+        File::open(path)
+            .map_err(|error| format!("failed to open {}: {error}", path.display()))?
+            .read_to_end(&mut bytes)?;
+    }
+    Ok(bytes)
+}
+
+/// Read an exposition, and the format it is in
+fn read_exposition(path: &Path, format: Option<Format>) -> Result<(String, Format), Error> {
+    let exposition = String::from_utf8(read_input(path)?)
+        .map_err(|_| format!("{} is not valid UTF-8", path.display()))?;
+    let format = format.unwrap_or_else(|| Format::guess(&exposition));
+    Ok((exposition, format))
+}
+
+fn convert(args: &ConvertArgs) -> Result<ExitCode, Error> {
+    let builder = args.exporter.builder()?;
+
+    let input = read_input(&args.input)?;
+    let metrics = match args
+        .input_format
+        .unwrap_or_else(|| InputFormat::guess(&args.input))
+    {
+        InputFormat::Json => OtlpMetrics::from_json_lines(input.as_slice())?,
+        InputFormat::Protobuf => OtlpMetrics::from_protobuf(&input)?,
+    };
+    if metrics.rejected_data_points() > 0 {
+        eprintln!(
+            "warning: {} data points were left out, because they are exponential histograms, \
+             summaries, delta histogram points whose bounds changed or have no value",
+            metrics.rejected_data_points()
+        );
+    }
+
+    let mut exposition = Vec::new();
+    metrics.export_with(&builder, &mut exposition)?;
+    if let Format::OpenMetrics = args.output_format {
+        let families = parse_exposition(std::str::from_utf8(&exposition)?, ExpositionFormat::Text)?;
+        exposition.clear();
+        openmetrics::render(&families, &mut exposition)?;
+    }
+
+    match &args.output {
+        Some(path) => std::fs::write(path, exposition)
+            .map_err(|error| format!("failed to write {}: {error}", path.display()))?,
+        None => std::io::stdout().lock().write_all(&exposition)?,
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn lint(args: &LintArgs) -> Result<ExitCode, Error> {
+    let mut linter = Linter::new();
+    for rule in &args.disabled {
+        linter = linter.without_rule(*rule);
+    }
+    if let Some(budget) = args.series_budget {
+        linter = linter.with_series_budget(budget);
+    }
+
+    let mut found = false;
+    for path in &args.files {
+        let (exposition, format) = read_exposition(path, args.format)?;
+        let findings = linter
+            .lint_exposition(exposition.as_bytes(), format.into())
+            .map_err(|error| format!("{}: {error}", path.display()))?;
+
+        for finding in &findings {
+            println!("{}: {finding}", path.display());
+        }
+        found |= !findings.is_empty();
+    }
+
+    Ok(if found {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn diff(args: &DiffArgs) -> Result<ExitCode, Error> {
+    let stdin = Path::new("-");
+    if args.old == stdin && args.new == stdin {
+        return Err("only one of the scrapes can be read from the standard input".into());
+    }
+
+    let mut scrapes = Vec::new();
+    for path in [&args.old, &args.new] {
+        let (exposition, format) = read_exposition(path, args.format)?;
+        let families = parse_exposition(&exposition, format.into())
+            .map_err(|error| format!("{}: {error}", path.display()))?;
+        scrapes.push(families);
+    }
+
+    let changes = diff::diff(&scrapes[0], &scrapes[1]);
+    let mut stdout = std::io::stdout().lock();
+    for change in &changes {
+        writeln!(stdout, "{change}")?;
+    }
+    writeln!(stdout, "{}", diff::summary(&changes))?;
+
+    Ok(if changes.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn top(args: &TopArgs) -> Result<ExitCode, Error> {
+    let mut previous = top::scrape(&args.url)?;
+    let mut refreshes = 0;
+    while args
+        .iterations
+        .is_none_or(|iterations| refreshes < iterations)
+    {
+        std::thread::sleep(args.interval);
+        let snapshot = top::scrape(&args.url)?;
+        top::render(
+            &previous.diff(&snapshot),
+            args.limit,
+            &mut std::io::stdout().lock(),
+        )?;
+        previous = snapshot;
+        refreshes += 1;
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result = match &cli.command {
+        Command::Convert(args) => convert(args),
+        Command::Lint(args) => lint(args),
+        Command::Diff(args) => diff(args),
+        Command::Top(args) => top(args),
+    };
+
+    result.unwrap_or_else(|error| {
+        eprintln!("error: {error}");
+        ExitCode::from(2)
+    })
+}
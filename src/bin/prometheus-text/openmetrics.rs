@@ -0,0 +1,162 @@
+//! Renders parsed families in the `OpenMetrics` text format
+
+use std::io::Write;
+
+use opentelemetry_prometheus_text_exporter::{MetricFamily, MetricType};
+
+/// Write a sample value, with the spelling of infinities and `NaN` of the
+/// exposition formats
+pub(crate) fn write_value<W: Write>(writer: &mut W, value: f64) -> std::io::Result<()> {
+    if value.is_nan() {
+        write!(writer, "NaN")
+    } else if value.is_infinite() {
+        write!(writer, "{}Inf", if value > 0.0 { "+" } else { "-" })
+    } else {
+        write!(writer, "{value}")
+    }
+}
+
+/// Write a label set, if it isn't empty, escaping the values
+pub(crate) fn write_labels<W: Write>(
+    writer: &mut W,
+    labels: &[(String, String)],
+) -> std::io::Result<()> {
+    if labels.is_empty() {
+        return Ok(());
+    }
+
+    write!(writer, "{{")?;
+    for (index, (name, value)) in labels.iter().enumerate() {
+        if index > 0 {
+            write!(writer, ",")?;
+        }
+        let value = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        write!(writer, "{name}=\"{value}\"")?;
+    }
+    write!(writer, "}}")
+}
+
+/// Write a timestamp in milliseconds as seconds
+fn write_timestamp<W: Write>(writer: &mut W, milliseconds: i64) -> std::io::Result<()> {
+    let (seconds, milliseconds) = (milliseconds.div_euclid(1000), milliseconds.rem_euclid(1000));
+    if milliseconds == 0 {
+        write!(writer, " {seconds}")
+    } else {
+        write!(
+            writer,
+            " {seconds}.{}",
+            format!("{milliseconds:03}").trim_end_matches('0')
+        )
+    }
+}
+
+/// Render families parsed from the text format in `OpenMetrics`.
+///
+/// Counters are named without their `_total` suffix, which their samples get
+/// if they don't have it, and units are only kept when the name ends with
+/// them, like `OpenMetrics` requires.
+pub(crate) fn render<W: Write>(families: &[MetricFamily], writer: &mut W) -> std::io::Result<()> {
+    for family in families {
+        let counter = family.metric_type == MetricType::Counter;
+        let name = if counter {
+            family.name.strip_suffix("_total").unwrap_or(&family.name)
+        } else {
+            &family.name
+        };
+
+        writeln!(writer, "# TYPE {name} {}", family.metric_type)?;
+        if let Some(help) = &family.help {
+            let help = help
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            writeln!(writer, "# HELP {name} {help}")?;
+        }
+        if let Some(unit) = &family.unit
+            && !unit.is_empty()
+            && name.ends_with(&format!("_{unit}"))
+        {
+            writeln!(writer, "# UNIT {name} {unit}")?;
+        }
+
+        for sample in &family.samples {
+            if counter {
+                write!(writer, "{name}_total")?;
+            } else {
+                write!(writer, "{}", sample.name)?;
+            }
+            write_labels(writer, &sample.labels)?;
+            write!(writer, " ")?;
+            write_value(writer, sample.value)?;
+            if let Some(timestamp) = sample.timestamp {
+                write_timestamp(writer, timestamp)?;
+            }
+            if let Some(exemplar) = &sample.exemplar {
+                write!(writer, " # ")?;
+                // An exemplar always has braces, even without labels
+                if exemplar.labels.is_empty() {
+                    write!(writer, "{{}}")?;
+                }
+                write_labels(writer, &exemplar.labels)?;
+                write!(writer, " ")?;
+                write_value(writer, exemplar.value)?;
+                if let Some(timestamp) = exemplar.timestamp {
+                    write_timestamp(writer, timestamp)?;
+                }
+            }
+            writeln!(writer)?;
+        }
+    }
+
+    writeln!(writer, "# EOF")
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry_prometheus_text_exporter::{ExpositionFormat, parse_exposition};
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let families = parse_exposition(
+            "# HELP jobs_seconds_total Time \"spent\"\n\
+             # TYPE jobs_seconds_total counter\n\
+             # UNIT jobs_seconds_total seconds\n\
+             jobs_seconds_total{queue=\"a\\nb\"} 1.5 1700000000250\n\
+             # TYPE jobs counter\n\
+             jobs 3\n\
+             # TYPE size histogram\n\
+             # UNIT size bytes\n\
+             size_bucket{le=\"+Inf\"} 1\n\
+             size_sum 2\n\
+             size_count 1\n",
+            ExpositionFormat::Text,
+        )
+        .unwrap();
+
+        let mut output = Vec::new();
+        render(&families, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "# TYPE jobs_seconds counter\n\
+             # HELP jobs_seconds Time \\\"spent\\\"\n\
+             # UNIT jobs_seconds seconds\n\
+             jobs_seconds_total{queue=\"a\\nb\"} 1.5 1700000000.25\n\
+             # TYPE jobs counter\n\
+             jobs_total 3\n\
+             # TYPE size histogram\n\
+             size_bucket{le=\"+Inf\"} 1\n\
+             size_sum 2\n\
+             size_count 1\n\
+             # EOF\n"
+        );
+
+        // The output is valid OpenMetrics
+        parse_exposition(&output, ExpositionFormat::OpenMetrics).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+//! Scrapes an endpoint periodically, showing the series changing the most
+
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Read, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::time::Duration;
+
+use opentelemetry_prometheus_text_exporter::{Delta, ExpositionFormat, Snapshot, SnapshotDiff};
+
+use crate::Error;
+
+/// How long to wait for an endpoint to respond
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A plain HTTP endpoint to scrape
+#[derive(Debug, Clone)]
+pub(crate) struct Endpoint {
+    address: String,
+    path: String,
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            return Err("only http:// endpoints are supported".to_owned());
+        };
+        let (authority, path) = rest
+            .find('/')
+            .map_or((rest, "/metrics"), |index| (&rest[..index], &rest[index..]));
+        if authority.is_empty() {
+            return Err("missing host".to_owned());
+        }
+
+        let address = if authority.rsplit_once(':').is_some_and(|(_, port)| {
+            !port.is_empty() && port.bytes().all(|byte| byte.is_ascii_digit())
+        }) {
+            authority.to_owned()
+        } else {
+            format!("{authority}:80")
+        };
+        Ok(Self {
+            address,
+            path: path.to_owned(),
+        })
+    }
+}
+
+/// Scrape the endpoint with an HTTP/1.0 request, which gets the whole
+/// exposition without chunked encoding
+pub(crate) fn scrape(endpoint: &Endpoint) -> Result<Snapshot, Error> {
+    let mut stream = TcpStream::connect(&endpoint.address)
+        .map_err(|error| format!("failed to connect to {}: {error}", endpoint.address))?;
+    stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nAccept: text/plain;version=0.0.4\r\n\r\n",
+        endpoint.path, endpoint.address
+    );
+    stream.write_all(request.as_bytes())?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8(response)
+        .map_err(|_| format!("the response of {} is not valid UTF-8", endpoint.address))?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| format!("invalid HTTP response from {}", endpoint.address))?;
+
+    let mut lines = head.lines();
+    let status = lines.next().unwrap_or_default();
+    if status.split(' ').nth(1) != Some("200") {
+        return Err(format!("scraping {} failed: {status}", endpoint.address).into());
+    }
+    let format = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        .and_then(|(_, value)| ExpositionFormat::from_content_type(value.trim()))
+        .unwrap_or(ExpositionFormat::Text);
+
+    Ok(Snapshot::parse(body, format)?)
+}
+
+/// Describe how a series changed
+fn change(delta: &Delta) -> String {
+    let mut change = String::new();
+    match delta {
+        Delta::Counter {
+            increase, reset, ..
+        } => {
+            let _ = write!(change, "+{increase}");
+            if *reset {
+                change.push_str(" (reset)");
+            }
+        }
+        Delta::Gauge { old, new } => {
+            let _ = write!(change, "{old} -> {new}");
+        }
+        Delta::Histogram(histogram) => {
+            if let (Some(median), Some(p99)) = (histogram.quantile(0.5), histogram.quantile(0.99)) {
+                let _ = write!(change, "p50 {median:.3} p99 {p99:.3}");
+            }
+            if histogram.reset() {
+                change.push_str(" (reset)");
+            }
+        }
+    }
+    change
+}
+
+/// Render the series which changed the most between two scrapes, clearing
+/// the screen first when writing to a terminal
+pub(crate) fn render<W: Write>(
+    diff: &SnapshotDiff,
+    limit: usize,
+    writer: &mut W,
+) -> Result<(), Error> {
+    if std::io::stdout().is_terminal() {
+        write!(writer, "\x1b[2J\x1b[H")?;
+    }
+
+    let mut series: Vec<_> = diff
+        .series()
+        .iter()
+        .filter(|series| series.rate() != 0.0 && series.rate().is_finite())
+        .collect();
+    series.sort_by(|a, b| b.rate().abs().total_cmp(&a.rate().abs()));
+
+    writeln!(
+        writer,
+        "{} series changed in {:.1}s, {} added, {} removed",
+        series.len(),
+        diff.elapsed().as_secs_f64(),
+        diff.added().len(),
+        diff.removed().len()
+    )?;
+    writeln!(writer, "{:>12}  {:<28}  SERIES", "RATE/S", "CHANGE")?;
+    for series in series.into_iter().take(limit) {
+        writeln!(
+            writer,
+            "{:>12.3}  {:<28}  {}",
+            series.rate(),
+            change(series.delta()),
+            series.id()
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint() {
+        let endpoint: Endpoint = "http://localhost:9464".parse().unwrap();
+        assert_eq!(endpoint.address, "localhost:9464");
+        assert_eq!(endpoint.path, "/metrics");
+
+        let endpoint: Endpoint = "http://[::1]/stats/prometheus".parse().unwrap();
+        assert_eq!(endpoint.address, "[::1]:80");
+        assert_eq!(endpoint.path, "/stats/prometheus");
+
+        assert!("https://localhost:9464".parse::<Endpoint>().is_err());
+        assert!("http:///metrics".parse::<Endpoint>().is_err());
+    }
+}
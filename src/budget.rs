@@ -0,0 +1,464 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::{Mutex, PoisonError};
+
+use opentelemetry::InstrumentationScope;
+
+use crate::glob::glob_match;
+use crate::serialize::{Family, PrometheusSerializer, write_label_value};
+
+const DROPPED_FAMILIES: &str = "otel_prometheus_exporter_budget_dropped_families_total";
+const DROPPED_FAMILIES_HELP: &str =
+    "Number of times a family was left out of the exposition to fit in its size budget";
+
+/// Default priority of `target_info`, which is small and needed to join the
+/// resource attributes to all the other families
+const TARGET_INFO_PRIORITY: i32 = i32::MAX;
+
+/// Maximum number of families in the counter of dropped families, the ones
+/// dropped the longest time ago are forgotten first
+const MAX_REPORTED_FAMILIES: usize = 100;
+
+/// The families a priority applies to
+#[derive(Debug, Clone)]
+pub(crate) enum PriorityRule {
+    /// Families of the instrumentation scope with this name
+    Scope(String),
+
+    /// Families with a name matching this glob pattern, after all the
+    /// transformations
+    Name(String),
+}
+
+impl PriorityRule {
+    fn matches(&self, candidate: &Candidate<'_>) -> bool {
+        match self {
+            Self::Scope(scope) => candidate.scope == Some(scope.as_str()),
+            Self::Name(pattern) => glob_match(pattern, &candidate.name),
+        }
+    }
+}
+
+/// A rendered family, which may be dropped to fit in the budget
+pub(crate) struct Candidate<'a> {
+    /// The name of the family, after all the transformations
+    pub name: Cow<'a, str>,
+
+    /// The name of the instrumentation scope of the family, which only
+    /// `target_info` has not
+    pub scope: Option<&'a str>,
+
+    /// The size of the rendered family, in bytes
+    pub size: usize,
+}
+
+/// Maximum size of the exposition, and which families to drop first to stay
+/// under it
+#[derive(Debug)]
+pub(crate) struct SizeBudget {
+    limit: usize,
+
+    /// Priorities of the families, the first matching rule applies
+    rules: Vec<(PriorityRule, i32)>,
+
+    /// The families dropped so far
+    dropped: Mutex<History>,
+}
+
+/// The families dropped across scrapes
+#[derive(Debug, Default)]
+struct History {
+    /// Number of exports which went through the budget
+    scrapes: u64,
+
+    /// Number of times each family was dropped, and the last export it was
+    families: BTreeMap<String, Dropped>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Dropped {
+    count: u64,
+    last_scrape: u64,
+}
+
+impl History {
+    /// Forget the family dropped the longest time ago, if it was not dropped
+    /// by the current export, returning the size it took in the report
+    fn forget_oldest(&mut self) -> Option<usize> {
+        let (name, dropped) = self
+            .families
+            .iter()
+            .filter(|(_, dropped)| dropped.last_scrape < self.scrapes)
+            .min_by_key(|(_, dropped)| dropped.last_scrape)
+            .map(|(name, dropped)| (name.clone(), *dropped))?;
+        self.families.remove(&name);
+
+        let mut size = sample_size(&name, dropped.count);
+        if self.families.is_empty() {
+            size += counter_header_size();
+        }
+        Some(size)
+    }
+}
+
+impl SizeBudget {
+    pub fn new(limit: usize, rules: Vec<(PriorityRule, i32)>) -> Self {
+        Self {
+            limit,
+            rules,
+            dropped: Mutex::default(),
+        }
+    }
+
+    fn priority(&self, candidate: &Candidate<'_>) -> i32 {
+        let default = if candidate.scope.is_none() {
+            TARGET_INFO_PRIORITY
+        } else {
+            0
+        };
+        self.rules
+            .iter()
+            .find(|(rule, _)| rule.matches(candidate))
+            .map_or(default, |(_, priority)| *priority)
+    }
+
+    /// Choose the families to drop for the exposition to fit in the budget,
+    /// and write the report about them.
+    ///
+    /// Families with the lowest priority are dropped first, and among those,
+    /// the last rendered ones first. The report is counted in the budget: it
+    /// is made of a comment naming the families dropped from this exposition,
+    /// and a counter of the families dropped so far. The counter keeps at
+    /// most [`MAX_REPORTED_FAMILIES`] families besides the ones dropped from
+    /// this exposition, and forgets the ones dropped the longest time ago
+    /// when it doesn't fit in the budget.
+    ///
+    /// Returns whether each candidate is kept.
+    pub fn select<W: Write>(
+        &self,
+        candidates: &[Candidate<'_>],
+        report: &mut W,
+    ) -> std::io::Result<Vec<bool>> {
+        let mut history = self.dropped.lock().unwrap_or_else(PoisonError::into_inner);
+        history.scrapes += 1;
+        while history.families.len() > MAX_REPORTED_FAMILIES {
+            history.forget_oldest();
+        }
+
+        let mut kept = vec![true; candidates.len()];
+        let mut size: usize = candidates.iter().map(|candidate| candidate.size).sum();
+        let mut report_size = counter_size(&history.families);
+
+        let comment_prefix = format!("# Over the size budget of {} bytes, dropped:", self.limit);
+        let mut dropped = Vec::new();
+
+        if size + report_size > self.limit {
+            let priorities: Vec<i32> = candidates.iter().map(|c| self.priority(c)).collect();
+            let mut order: Vec<usize> = (0..candidates.len())
+                .filter(|&index| candidates[index].size > 0)
+                .collect();
+            order.sort_by_key(|&index| (priorities[index], Reverse(index)));
+
+            for index in order {
+                if size + report_size <= self.limit {
+                    break;
+                }
+
+                let candidate = &candidates[index];
+                kept[index] = false;
+                size -= candidate.size;
+
+                if dropped.is_empty() {
+                    // The prefix and the line feed of the comment
+                    report_size += comment_prefix.len() + 1;
+                }
+                report_size += 1 + candidate.name.len();
+
+                if history.families.is_empty() {
+                    report_size += counter_header_size();
+                }
+                let scrape = history.scrapes;
+                let entry = history
+                    .families
+                    .entry(candidate.name.to_string())
+                    .or_default();
+                if entry.count > 0 {
+                    report_size -= sample_size(&candidate.name, entry.count);
+                }
+                entry.count += 1;
+                entry.last_scrape = scrape;
+                report_size += sample_size(&candidate.name, entry.count);
+
+                dropped.push(candidate.name.as_ref());
+            }
+        }
+
+        // When dropping the families is not enough, the families dropped by
+        // previous exports take too much room in the counter
+        while size + report_size > self.limit
+            && let Some(forgotten) = history.forget_oldest()
+        {
+            report_size -= forgotten;
+        }
+
+        if !dropped.is_empty() {
+            write!(report, "{comment_prefix}")?;
+            for name in &dropped {
+                write!(report, " {name}")?;
+            }
+            writeln!(report)?;
+        }
+
+        if !history.families.is_empty() {
+            write_counter_header(report)?;
+            for (name, dropped) in &history.families {
+                write_sample(report, name, dropped.count)?;
+            }
+        }
+
+        Ok(kept)
+    }
+
+    /// Render all the families in the given buffer, then write the ones
+    /// fitting in the budget, followed by the report about the dropped ones.
+    ///
+    /// Calls `on_written` with each family written and its size.
+    pub fn write_families<W: Write>(
+        &self,
+        serializer: &PrometheusSerializer,
+        families: &[Family<'_>],
+        mut rendered: Vec<u8>,
+        writer: &mut W,
+        mut on_written: impl FnMut(&Family<'_>, usize),
+    ) -> std::io::Result<()> {
+        let mut ranges = Vec::with_capacity(families.len());
+        for family in families {
+            let start = rendered.len();
+            serializer.serialize_family(family, &mut rendered)?;
+            ranges.push(start..rendered.len());
+        }
+
+        let candidates: Vec<_> = families
+            .iter()
+            .zip(&ranges)
+            .map(|(family, range)| Candidate {
+                name: serializer
+                    .family_stats(family)
+                    .map(|stats| stats.name)
+                    .unwrap_or_default(),
+                scope: family.scope().map(InstrumentationScope::name),
+                size: range.len(),
+            })
+            .collect();
+
+        let mut report = Vec::new();
+        let kept = self.select(&candidates, &mut report)?;
+
+        for ((family, range), kept) in families.iter().zip(ranges).zip(kept) {
+            if kept {
+                writer.write_all(&rendered[range.clone()])?;
+                on_written(family, range.len());
+            }
+        }
+
+        writer.write_all(&report)
+    }
+}
+
+fn write_counter_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writeln!(writer, "# TYPE {DROPPED_FAMILIES} counter")?;
+    writeln!(writer, "# HELP {DROPPED_FAMILIES} {DROPPED_FAMILIES_HELP}")
+}
+
+fn write_sample<W: Write>(writer: &mut W, name: &str, count: u64) -> std::io::Result<()> {
+    write!(writer, "{DROPPED_FAMILIES}{{family=")?;
+    write_label_value(writer, name)?;
+    writeln!(writer, "}} {count}")
+}
+
+fn counter_header_size() -> usize {
+    let mut buffer = Vec::new();
+    write_counter_header(&mut buffer).expect("writing to a Vec never fails");
+    buffer.len()
+}
+
+fn sample_size(name: &str, count: u64) -> usize {
+    let mut buffer = Vec::new();
+    write_sample(&mut buffer, name, count).expect("writing to a Vec never fails");
+    buffer.len()
+}
+
+/// Size of the counter of dropped families, as it would be rendered
+fn counter_size(families: &BTreeMap<String, Dropped>) -> usize {
+    if families.is_empty() {
+        return 0;
+    }
+
+    counter_header_size()
+        + families
+            .iter()
+            .map(|(name, dropped)| sample_size(name, dropped.count))
+            .sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(name: &'static str, scope: &'static str, size: usize) -> Candidate<'static> {
+        Candidate {
+            name: Cow::Borrowed(name),
+            scope: Some(scope),
+            size,
+        }
+    }
+
+    #[test]
+    fn test_within_budget() {
+        let budget = SizeBudget::new(100, Vec::new());
+        let mut report = Vec::new();
+        let kept = budget
+            .select(
+                &[candidate("a", "app", 50), candidate("b", "app", 50)],
+                &mut report,
+            )
+            .unwrap();
+        assert_eq!(kept, [true, true]);
+        assert!(report.is_empty());
+    }
+
+    #[test]
+    fn test_drops_lowest_priority_first() {
+        let budget = SizeBudget::new(
+            2000,
+            vec![
+                (PriorityRule::Name("important_*".to_owned()), 10),
+                (PriorityRule::Scope("noisy".to_owned()), -10),
+            ],
+        );
+        let candidates = [
+            candidate("important_requests", "noisy", 800),
+            candidate("noisy_pool_size", "noisy", 800),
+            candidate("app_requests", "app", 700),
+            candidate("app_errors", "app", 300),
+        ];
+
+        let mut report = Vec::new();
+        let kept = budget.select(&candidates, &mut report).unwrap();
+        // The noisy scope goes first, then the last rendered family among the
+        // ones of default priority
+        assert_eq!(kept, [true, false, true, false]);
+
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with(
+            "# Over the size budget of 2000 bytes, dropped: noisy_pool_size app_errors\n"
+        ));
+        assert!(report.contains(&format!(
+            "# TYPE {DROPPED_FAMILIES} counter\n# HELP {DROPPED_FAMILIES} "
+        )));
+        assert!(report.contains(&format!("{DROPPED_FAMILIES}{{family=\"app_errors\"}} 1\n")));
+        assert!(report.contains(&format!(
+            "{DROPPED_FAMILIES}{{family=\"noisy_pool_size\"}} 1\n"
+        )));
+        assert!(1500 + report.len() <= 2000);
+    }
+
+    #[test]
+    fn test_family_label_is_escaped() {
+        let budget = SizeBudget::new(1, Vec::new());
+        let mut report = Vec::new();
+        budget
+            .select(&[candidate("a\tb\\c", "app", 10)], &mut report)
+            .unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.ends_with(&format!("{DROPPED_FAMILIES}{{family=\"a\tb\\\\c\"}} 1\n")));
+    }
+
+    #[test]
+    fn test_target_info_is_dropped_last() {
+        let budget = SizeBudget::new(850, Vec::new());
+        let target_info = Candidate {
+            name: Cow::Borrowed("target_info"),
+            scope: None,
+            size: 100,
+        };
+        let kept = budget
+            .select(
+                &[candidate("app_requests", "app", 800), target_info],
+                &mut Vec::new(),
+            )
+            .unwrap();
+        assert_eq!(kept, [false, true]);
+    }
+
+    #[test]
+    fn test_counter_is_kept_across_scrapes() {
+        let budget = SizeBudget::new(750, Vec::new());
+        let candidates = [candidate("a", "app", 400), candidate("b", "app", 400)];
+
+        for _ in 0..2 {
+            budget.select(&candidates, &mut Vec::new()).unwrap();
+        }
+
+        // The family is not dropped anymore, but the counter is still reported
+        let mut report = Vec::new();
+        let kept = budget
+            .select(&[candidate("a", "app", 400)], &mut report)
+            .unwrap();
+        assert_eq!(kept, [true]);
+        let report = String::from_utf8(report).unwrap();
+        assert!(!report.contains("dropped:"));
+        assert!(report.ends_with(&format!("{DROPPED_FAMILIES}{{family=\"b\"}} 2\n")));
+    }
+
+    #[test]
+    fn test_counter_is_capped() {
+        let budget = SizeBudget::new(1_000_000, Vec::new());
+        let names: Vec<String> = (0..MAX_REPORTED_FAMILIES + 10)
+            .map(|index| format!("family_{index}"))
+            .collect();
+        for name in &names {
+            let candidate = Candidate {
+                name: Cow::Borrowed(name),
+                scope: Some("app"),
+                size: 2_000_000,
+            };
+            budget.select(&[candidate], &mut Vec::new()).unwrap();
+        }
+
+        let mut report = Vec::new();
+        budget.select(&[], &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        // The families dropped the longest time ago are forgotten
+        assert_eq!(
+            report.matches(DROPPED_FAMILIES).count(),
+            2 + MAX_REPORTED_FAMILIES
+        );
+        assert!(!report.contains("\"family_9\""));
+        assert!(report.contains("\"family_10\""));
+    }
+
+    #[test]
+    fn test_counter_fits_in_budget() {
+        let budget = SizeBudget::new(400, Vec::new());
+        for name in ["a", "b", "c"] {
+            budget
+                .select(&[candidate(name, "app", 1000)], &mut Vec::new())
+                .unwrap();
+        }
+
+        // The counter of the three families would not fit anymore, so the
+        // oldest ones are forgotten
+        let mut report = Vec::new();
+        let kept = budget
+            .select(&[candidate("d", "app", 1000)], &mut report)
+            .unwrap();
+        assert_eq!(kept, [false]);
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.len() <= 400, "{report}");
+        assert!(report.contains(&format!("{DROPPED_FAMILIES}{{family=\"d\"}} 1\n")));
+        assert!(!report.contains("family=\"a\""));
+    }
+}
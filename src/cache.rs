@@ -0,0 +1,295 @@
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// A rendered exposition, cheap to clone and share between scrapes.
+///
+/// With the `bytes` feature, it converts to [`bytes::Bytes`] without copying.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Exposition {
+    data: Arc<[u8]>,
+    stale: bool,
+}
+
+impl Exposition {
+    /// The rendered exposition
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The length of the rendered exposition, in bytes
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Whether the rendered exposition is empty
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Whether this is an older exposition, served because a fresh one could
+    /// not be collected in time
+    #[must_use]
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    pub(crate) fn into_stale(self) -> Self {
+        Self {
+            stale: true,
+            ..self
+        }
+    }
+}
+
+impl std::fmt::Debug for Exposition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Exposition")
+            .field("len", &self.data.len())
+            .field("stale", &self.stale)
+            .finish()
+    }
+}
+
+impl From<Vec<u8>> for Exposition {
+    fn from(data: Vec<u8>) -> Self {
+        Self {
+            data: data.into(),
+            stale: false,
+        }
+    }
+}
+
+impl AsRef<[u8]> for Exposition {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl std::ops::Deref for Exposition {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl From<Exposition> for bytes::Bytes {
+    fn from(exposition: Exposition) -> Self {
+        bytes::Bytes::from_owner(exposition)
+    }
+}
+
+/// Shares a single in-flight render between concurrent callers, and keeps the
+/// result around for a minimum interval.
+#[derive(Debug)]
+pub(crate) struct ScrapeCache {
+    min_interval: Duration,
+    state: Mutex<CacheState>,
+    done: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    /// The last successful render
+    entry: Option<CacheEntry>,
+
+    /// Whether a render is currently in progress
+    in_flight: bool,
+
+    /// Incremented every time a render finishes, successfully or not
+    generation: u64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    exposition: Exposition,
+
+    /// When the render started
+    started_at: Instant,
+
+    /// The generation at which the render finished
+    generation: u64,
+}
+
+impl ScrapeCache {
+    pub fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            state: Mutex::default(),
+            done: Condvar::new(),
+        }
+    }
+
+    /// Get the cached exposition, or render a new one.
+    ///
+    /// If a render is already in progress, this waits for it and returns its
+    /// result instead of starting another one. If it fails, one of the waiting
+    /// callers starts a new render. Stale expositions are returned, but not
+    /// cached.
+    pub fn get_or_render(
+        &self,
+        render: impl FnOnce() -> std::io::Result<Exposition>,
+    ) -> std::io::Result<Exposition> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut waited_for = None;
+        loop {
+            if let Some(entry) = &state.entry {
+                // Either the entry is recent enough, or it is the result of the
+                // render we waited for
+                let rendered_while_waiting =
+                    waited_for.is_some_and(|generation| entry.generation > generation);
+                if rendered_while_waiting || entry.started_at.elapsed() < self.min_interval {
+                    return Ok(entry.exposition.clone());
+                }
+            }
+
+            if !state.in_flight {
+                break;
+            }
+
+            waited_for = Some(state.generation);
+            state = self
+                .done
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+
+        state.in_flight = true;
+        drop(state);
+
+        // Make sure waiters are woken up even if the render panics
+        let guard = InFlightGuard {
+            cache: self,
+            started_at: Instant::now(),
+        };
+        let result = render();
+        guard.finish(result.as_ref().ok());
+
+        result
+    }
+}
+
+/// Marks the in-flight render as finished, even if it panics
+struct InFlightGuard<'a> {
+    cache: &'a ScrapeCache,
+    started_at: Instant,
+}
+
+impl InFlightGuard<'_> {
+    fn finish(self, exposition: Option<&Exposition>) {
+        self.finish_ref(exposition);
+        std::mem::forget(self);
+    }
+
+    fn finish_ref(&self, exposition: Option<&Exposition>) {
+        let mut state = self
+            .cache
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        state.in_flight = false;
+        state.generation += 1;
+        // A stale exposition is already older than the interval
+        if let Some(exposition) = exposition.filter(|exposition| !exposition.is_stale()) {
+            state.entry = Some(CacheEntry {
+                exposition: exposition.clone(),
+                started_at: self.started_at,
+                generation: state.generation,
+            });
+        }
+        self.cache.done.notify_all();
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.finish_ref(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn test_caches_for_min_interval() {
+        let cache = ScrapeCache::new(Duration::from_mins(1));
+        let renders = AtomicUsize::new(0);
+        let render = || {
+            let n = renders.fetch_add(1, Ordering::SeqCst);
+            Ok(Exposition::from(format!("render {n}").into_bytes()))
+        };
+
+        let first = cache.get_or_render(render).unwrap();
+        let second = cache.get_or_render(render).unwrap();
+        assert_eq!(first.as_bytes(), b"render 0");
+        assert_eq!(second, first);
+        assert_eq!(renders.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_renders_again_without_interval() {
+        let cache = ScrapeCache::new(Duration::ZERO);
+        let renders = AtomicUsize::new(0);
+        let render = || {
+            let n = renders.fetch_add(1, Ordering::SeqCst);
+            Ok(Exposition::from(format!("render {n}").into_bytes()))
+        };
+
+        cache.get_or_render(render).unwrap();
+        let second = cache.get_or_render(render).unwrap();
+        assert_eq!(second.as_bytes(), b"render 1");
+    }
+
+    #[test]
+    fn test_errors_are_not_cached() {
+        let cache = ScrapeCache::new(Duration::from_mins(1));
+        let result = cache.get_or_render(|| Err(std::io::Error::other("failed")));
+        assert!(result.is_err());
+
+        let exposition = cache
+            .get_or_render(|| Ok(Exposition::from(b"ok".to_vec())))
+            .unwrap();
+        assert_eq!(exposition.as_bytes(), b"ok");
+    }
+
+    #[test]
+    fn test_coalesces_concurrent_renders() {
+        const CALLERS: usize = 8;
+
+        let cache = ScrapeCache::new(Duration::ZERO);
+        let renders = AtomicUsize::new(0);
+        let barrier = Barrier::new(CALLERS);
+
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..CALLERS)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        cache
+                            .get_or_render(|| {
+                                renders.fetch_add(1, Ordering::SeqCst);
+                                // Give the other callers time to pile up
+                                std::thread::sleep(Duration::from_millis(100));
+                                Ok(Exposition::from(b"shared".to_vec()))
+                            })
+                            .unwrap()
+                    })
+                })
+                .collect();
+
+            for handle in handles {
+                assert_eq!(handle.join().unwrap().as_bytes(), b"shared");
+            }
+        });
+
+        assert!(renders.load(Ordering::SeqCst) < CALLERS);
+    }
+}
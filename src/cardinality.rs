@@ -0,0 +1,249 @@
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, PoisonError};
+
+use opentelemetry::{InstrumentationScope, Key, KeyValue, Value};
+use opentelemetry_sdk::Resource;
+
+/// Remembers the series admitted under the cardinality limit of every metric
+/// between scrapes, so that the same ones keep being rendered as-is while the
+/// newcomers are folded in the overflow series.
+///
+/// Metrics are identified by their name, scope and resource, so that the
+/// parts of a family merged from several resources are admitted separately.
+#[derive(Debug, Default)]
+pub(crate) struct AdmittedSeries {
+    metrics: Mutex<HashMap<u64, Admitted>>,
+}
+
+/// The series admitted for a metric
+#[derive(Debug, Default)]
+struct Admitted {
+    /// The order in which each series was admitted, by key
+    ranks: HashMap<u64, u64>,
+    next_rank: u64,
+
+    /// The series and limit of the last call, and which series were kept,
+    /// so that rendering a metric and counting its series agree without
+    /// admitting them twice
+    last: Option<(u64, Vec<bool>)>,
+}
+
+impl AdmittedSeries {
+    /// Tell which data points of a metric are rendered as-is, given the key of
+    /// their attributes, or [`None`] for the overflow data point of the SDK.
+    ///
+    /// The series which were admitted first are kept. Newcomers are admitted
+    /// while there is room for them, and series which are gone from the
+    /// metric are forgotten, making room for others. The limit includes the
+    /// overflow series, so when it is reached, one less series is kept to make
+    /// room for it. The overflow data point of the SDK is never kept, as it is
+    /// merged in the overflow series.
+    pub fn kept(&self, metric: u64, series: &[Option<u64>], limit: usize) -> Vec<bool> {
+        let limit = limit.max(1);
+        let mut metrics = self.metrics.lock().unwrap_or_else(PoisonError::into_inner);
+        let admitted = metrics.entry(metric).or_default();
+
+        let mut hasher = DefaultHasher::new();
+        series.hash(&mut hasher);
+        limit.hash(&mut hasher);
+        let fingerprint = hasher.finish();
+        if let Some((last, kept)) = &admitted.last
+            && *last == fingerprint
+        {
+            return kept.clone();
+        }
+
+        let present: HashSet<u64> = series.iter().flatten().copied().collect();
+        admitted.ranks.retain(|key, _| present.contains(key));
+        for &key in series.iter().flatten() {
+            if admitted.ranks.len() >= limit {
+                break;
+            }
+            if let Entry::Vacant(entry) = admitted.ranks.entry(key) {
+                entry.insert(admitted.next_rank);
+                admitted.next_rank += 1;
+            }
+        }
+
+        let overflows = series.len() > limit;
+        let capacity = if overflows { limit - 1 } else { limit };
+        let mut ranks: Vec<u64> = present
+            .iter()
+            .filter_map(|key| admitted.ranks.get(key))
+            .copied()
+            .collect();
+        ranks.sort_unstable();
+        let threshold = ranks.get(capacity).copied().unwrap_or(u64::MAX);
+
+        let kept: Vec<bool> = series
+            .iter()
+            .map(|key| {
+                key.and_then(|key| admitted.ranks.get(&key))
+                    .is_some_and(|&rank| rank < threshold)
+            })
+            .collect();
+        admitted.last = Some((fingerprint, kept.clone()));
+        kept
+    }
+}
+
+/// Identify a metric by its name, scope and resource
+pub(crate) fn metric_key(name: &str, scope: &InstrumentationScope, resource: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    scope.hash(&mut hasher);
+    resource.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Identify a resource by its attributes, whatever their order
+pub(crate) fn resource_key(resource: &Resource) -> u64 {
+    resource.iter().fold(0, |key, (name, value)| {
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        value.as_str().hash(&mut hasher);
+        key.wrapping_add(hasher.finish())
+    })
+}
+
+/// Identify the series of a metric by its attributes, or return [`None`] for
+/// the overflow data point of the SDK
+pub(crate) fn attributes_key<'a>(attributes: impl Iterator<Item = &'a KeyValue>) -> Option<u64> {
+    let mut hasher = DefaultHasher::new();
+    let mut attributes = attributes.peekable();
+    let mut only_overflow = attributes.peek().is_some();
+    for attribute in attributes {
+        only_overflow &= is_overflow_attribute(attribute);
+        attribute.hash(&mut hasher);
+    }
+
+    (!only_overflow).then(|| hasher.finish())
+}
+
+/// Whether the attribute is the one of the overflow series, as defined by the
+/// OpenTelemetry specification
+fn is_overflow_attribute(attribute: &KeyValue) -> bool {
+    attribute.key == Key::from_static_str("otel.metric.overflow")
+        && attribute.value == Value::Bool(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kept(admitted: &AdmittedSeries, series: &[Option<u64>], limit: usize) -> Vec<u64> {
+        series
+            .iter()
+            .zip(admitted.kept(1, series, limit))
+            .filter(|(_, kept)| *kept)
+            .filter_map(|(key, _)| *key)
+            .collect()
+    }
+
+    #[test]
+    fn test_admitted_series_are_kept() {
+        let admitted = AdmittedSeries::default();
+        assert_eq!(kept(&admitted, &[Some(1), Some(2)], 3), [1, 2]);
+
+        // Newcomers are folded, wherever they come in the data points
+        assert_eq!(
+            kept(&admitted, &[Some(4), Some(3), Some(2), Some(1)], 3),
+            [2, 1]
+        );
+        assert_eq!(
+            kept(&admitted, &[Some(3), Some(1), Some(2), Some(4)], 3),
+            [1, 2]
+        );
+    }
+
+    #[test]
+    fn test_series_fit_in_the_limit() {
+        let admitted = AdmittedSeries::default();
+        assert_eq!(kept(&admitted, &[Some(1), Some(2), Some(3)], 3), [1, 2, 3]);
+
+        // Once over the limit, the last admitted series makes room for the
+        // overflow one
+        assert_eq!(
+            kept(&admitted, &[Some(1), Some(2), Some(3), Some(4)], 3),
+            [1, 2]
+        );
+    }
+
+    #[test]
+    fn test_gone_series_are_forgotten() {
+        let admitted = AdmittedSeries::default();
+        assert_eq!(kept(&admitted, &[Some(1), Some(2)], 2), [1, 2]);
+        assert_eq!(kept(&admitted, &[Some(2), Some(3)], 2), [2, 3]);
+    }
+
+    #[test]
+    fn test_metrics_of_several_resources_are_admitted_separately() {
+        let admitted = AdmittedSeries::default();
+        let scope = InstrumentationScope::builder("scope").build();
+        let first = metric_key(
+            "requests",
+            &scope,
+            resource_key(
+                &Resource::builder_empty()
+                    .with_attribute(KeyValue::new("host", "a"))
+                    .build(),
+            ),
+        );
+        let second = metric_key(
+            "requests",
+            &scope,
+            resource_key(
+                &Resource::builder_empty()
+                    .with_attribute(KeyValue::new("host", "b"))
+                    .build(),
+            ),
+        );
+        assert_ne!(first, second);
+
+        // Rendering the parts one after the other keeps the series admitted
+        // for each of them
+        for _ in 0..2 {
+            assert_eq!(
+                admitted.kept(first, &[Some(1), Some(2), Some(3)], 2),
+                [true, false, false]
+            );
+            assert_eq!(
+                admitted.kept(second, &[Some(4), Some(5), Some(6)], 2),
+                [true, false, false]
+            );
+        }
+    }
+
+    #[test]
+    fn test_same_series_are_admitted_once() {
+        let admitted = AdmittedSeries::default();
+        assert_eq!(kept(&admitted, &[Some(1), Some(2)], 3), [1, 2]);
+        assert_eq!(kept(&admitted, &[Some(1), Some(2)], 3), [1, 2]);
+        assert_eq!(kept(&admitted, &[Some(2), Some(3), Some(4)], 3), [2, 3, 4]);
+    }
+
+    #[test]
+    fn test_resource_key_ignores_the_order_of_the_attributes() {
+        let first = Resource::builder_empty()
+            .with_attributes([KeyValue::new("a", "1"), KeyValue::new("b", "2")])
+            .build();
+        let second = Resource::builder_empty()
+            .with_attributes([KeyValue::new("b", "2"), KeyValue::new("a", "1")])
+            .build();
+        assert_eq!(resource_key(&first), resource_key(&second));
+    }
+
+    #[test]
+    fn test_sdk_overflow_is_folded() {
+        let admitted = AdmittedSeries::default();
+        let overflow = KeyValue::new("otel.metric.overflow", true);
+        assert_eq!(attributes_key(std::iter::once(&overflow)), None);
+        assert!(attributes_key(std::iter::empty()).is_some());
+
+        let series = [Some(1), None, Some(2)];
+        assert_eq!(admitted.kept(1, &series, 3), [true, false, true]);
+        assert_eq!(admitted.kept(1, &series, 2), [true, false, false]);
+    }
+}
@@ -0,0 +1,144 @@
+use serde::Deserialize;
+
+use crate::exporter::{ExporterBuilder, PrometheusExporter};
+
+/// The Prometheus exporter section of the `OpenTelemetry` declarative
+/// configuration, found under
+/// `meter_provider.readers[].pull.exporter.prometheus`.
+///
+/// It can be deserialized from the YAML or JSON configuration file with any
+/// serde format. Missing and `null` fields keep the default of the exporter.
+///
+/// # Example
+///
+/// ```rust
+/// use opentelemetry_prometheus_text_exporter::PrometheusConfig;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let config: PrometheusConfig = serde_json::from_str(
+///     r#"{
+///         "host": "0.0.0.0",
+///         "port": 9464,
+///         "without_scope_info": true,
+///         "with_resource_constant_labels": {
+///             "included": ["service.*"],
+///             "excluded": ["service.instance.id"]
+///         }
+///     }"#,
+/// )?;
+///
+/// // Builder methods called afterwards take precedence
+/// let exporter = config.builder().with_self_metrics().build();
+/// assert_eq!(exporter.listen_address(), ("0.0.0.0", 9464));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub struct PrometheusConfig {
+    /// The address the metrics are served on, see
+    /// [`ExporterBuilder::with_host`]
+    pub host: Option<String>,
+
+    /// The port the metrics are served on, see [`ExporterBuilder::with_port`]
+    pub port: Option<u16>,
+
+    /// Disables the unit suffixes, see [`ExporterBuilder::without_units`]
+    pub without_units: Option<bool>,
+
+    /// Disables the `_total` suffixes, see
+    /// [`ExporterBuilder::without_counter_suffixes`]
+    pub without_type_suffix: Option<bool>,
+
+    /// Disables the scope labels and `otel_scope_info`, see
+    /// [`ExporterBuilder::without_scope_info`]
+    pub without_scope_info: Option<bool>,
+
+    /// Disables `target_info`, see [`ExporterBuilder::without_target_info`]
+    pub without_target_info: Option<bool>,
+
+    /// The resource attributes added as labels to every series, see
+    /// [`ExporterBuilder::with_resource_constant_labels`]
+    pub with_resource_constant_labels: Option<IncludeExclude>,
+
+    /// How instrument names are translated to Prometheus names
+    pub translation_strategy: Option<TranslationStrategy>,
+}
+
+/// Glob patterns of the keys to include and exclude
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub struct IncludeExclude {
+    /// The keys to include, none if missing
+    pub included: Option<Vec<String>>,
+
+    /// The keys to exclude, even if they are included
+    pub excluded: Option<Vec<String>>,
+}
+
+/// How instrument names are translated to Prometheus names.
+///
+/// Names are always escaped to the legacy Prometheus character set, so the
+/// `NoUTF8EscapingWithSuffixes` and `NoTranslation` strategies of the
+/// specification are rejected when deserializing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub enum TranslationStrategy {
+    /// Escape the names, and add the unit and `_total` suffixes
+    #[default]
+    UnderscoreEscapingWithSuffixes,
+
+    /// Escape the names, without the unit and `_total` suffixes
+    UnderscoreEscapingWithoutSuffixes,
+}
+
+impl PrometheusConfig {
+    /// Creates a builder with this configuration.
+    ///
+    /// The methods called on the returned builder take precedence over the
+    /// configuration, like [`ExporterBuilder::with_units`] adding back the
+    /// unit suffixes disabled by `without_units`.
+    #[must_use]
+    pub fn builder(&self) -> ExporterBuilder {
+        let mut builder = PrometheusExporter::builder();
+
+        if let Some(host) = &self.host {
+            builder = builder.with_host(host.clone());
+        }
+        if let Some(port) = self.port {
+            builder = builder.with_port(port);
+        }
+
+        if self.translation_strategy == Some(TranslationStrategy::UnderscoreEscapingWithoutSuffixes)
+        {
+            builder = builder.without_units().without_counter_suffixes();
+        }
+
+        if self.without_units == Some(true) {
+            builder = builder.without_units();
+        }
+        if self.without_type_suffix == Some(true) {
+            builder = builder.without_counter_suffixes();
+        }
+        if self.without_scope_info == Some(true) {
+            builder = builder.without_scope_info();
+        }
+        if self.without_target_info == Some(true) {
+            builder = builder.without_target_info();
+        }
+
+        if let Some(labels) = &self.with_resource_constant_labels {
+            builder = builder
+                .with_resource_constant_labels(labels.included.iter().flatten().cloned())
+                .without_resource_constant_labels(labels.excluded.iter().flatten().cloned());
+        }
+
+        builder
+    }
+
+    /// Creates an exporter with this configuration
+    #[must_use]
+    pub fn build(&self) -> PrometheusExporter {
+        self.builder().build()
+    }
+}
@@ -0,0 +1,242 @@
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::time::Instant;
+
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::metrics::v1::MetricsData;
+use prost::Message as _;
+
+use crate::exporter::ExporterBuilder;
+use crate::otlp::{OtlpStore, decode_json};
+use crate::serialize::PrometheusSerializer;
+
+/// Metrics decoded from OTLP JSON or protobuf, like the files written by the
+/// file exporter of the OpenTelemetry Collector.
+///
+/// Each line of a JSON file is a `MetricsData` message, and the lines are
+/// merged in order like the exports received by an [`OtlpGateway`]: the last
+/// value of each stream is kept, and delta sums and histograms are accumulated
+/// into cumulative ones.
+///
+/// The metrics are then rendered with the same translation rules as the
+/// in-process exporter.
+///
+/// # Example
+///
+/// ```rust
+/// use opentelemetry_prometheus_text_exporter::{ExporterBuilder, OtlpMetrics};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let file = br#"{"resourceMetrics":[{"scopeMetrics":[{"metrics":[{"name":"jobs","gauge":{"dataPoints":[{"asInt":"3"}]}}]}]}]}"#;
+/// let metrics = OtlpMetrics::from_json_lines(&file[..])?;
+///
+/// let mut output = Vec::new();
+/// metrics.export_with(&ExporterBuilder::default().without_scope_info(), &mut output)?;
+/// assert_eq!(String::from_utf8(output)?, "# TYPE jobs gauge\njobs 3\n\n");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`OtlpGateway`]: crate::OtlpGateway
+#[derive(Debug, Default)]
+pub struct OtlpMetrics {
+    store: OtlpStore,
+    rejected_data_points: u64,
+}
+
+impl OtlpMetrics {
+    /// Decode OTLP JSON lines, each of them being a `MetricsData` message.
+    ///
+    /// Empty lines are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails, or if a line is not a valid
+    /// `MetricsData` message.
+    pub fn from_json_lines<R: BufRead>(reader: R) -> Result<Self, DecodeError> {
+        let mut metrics = Self::default();
+
+        for (index, line) in reader.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.map_err(|error| DecodeError {
+                line: line_number,
+                kind: DecodeErrorKind::Read,
+                message: error.to_string(),
+            })?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let data: MetricsData = decode_json(line.as_bytes())
+                .map_err(|error| DecodeError::json(line_number, &error))?;
+            metrics.merge(data);
+        }
+
+        Ok(metrics)
+    }
+
+    /// Decode a single OTLP JSON `MetricsData` message, which may span
+    /// several lines.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message is invalid.
+    pub fn from_json(json: &[u8]) -> Result<Self, DecodeError> {
+        let data: MetricsData =
+            decode_json(json).map_err(|error| DecodeError::json(error.line(), &error))?;
+
+        let mut metrics = Self::default();
+        metrics.merge(data);
+        Ok(metrics)
+    }
+
+    /// Decode a single OTLP protobuf `MetricsData` message, or an
+    /// `ExportMetricsServiceRequest`, which has the same encoding
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message is invalid.
+    pub fn from_protobuf(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let data = MetricsData::decode(bytes).map_err(|error| DecodeError {
+            line: 1,
+            kind: DecodeErrorKind::Protobuf,
+            message: error.to_string(),
+        })?;
+
+        let mut metrics = Self::default();
+        metrics.merge(data);
+        Ok(metrics)
+    }
+
+    fn merge(&mut self, data: MetricsData) {
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: data.resource_metrics,
+        };
+        self.rejected_data_points += self.store.ingest(request, Instant::now());
+    }
+
+    /// The number of data points which are not rendered, because they are
+    /// exponential histograms, summaries, delta histogram points whose bounds
+    /// changed, or have no value
+    #[must_use]
+    pub fn rejected_data_points(&self) -> u64 {
+        self.rejected_data_points
+    }
+
+    /// Render the metrics with the default translation options
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    pub fn export<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.export_with(&ExporterBuilder::default(), writer)
+    }
+
+    /// Render the metrics with the naming and filtering options, and the size
+    /// budget, of the given builder
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    pub fn export_with<W: Write>(
+        &self,
+        builder: &ExporterBuilder,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        let serializer = PrometheusSerializer::with_config(builder.serializer_config());
+        if let Some(budget) = builder.size_budget() {
+            let families = serializer.merge_families(self.store.families());
+            return budget.write_families(&serializer, &families, Vec::new(), writer, |_, _| {});
+        }
+
+        for family in serializer.merge_families(self.store.families()) {
+            serializer.serialize_family(&family, writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Error returned when decoding invalid OTLP JSON or protobuf with
+/// [`OtlpMetrics`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    line: usize,
+    kind: DecodeErrorKind,
+    message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecodeErrorKind {
+    /// The line could not be read
+    Read,
+
+    /// The line is not valid OTLP JSON, with the column of the error if it
+    /// is a syntax error
+    Json { column: Option<usize> },
+
+    /// The payload is not a valid OTLP protobuf message
+    Protobuf,
+}
+
+impl DecodeError {
+    fn json(line: usize, error: &serde_json::Error) -> Self {
+        // The position is reported separately, and only known for syntax
+        // errors
+        let message = error.to_string();
+        let location = format!(" at line {} column {}", error.line(), error.column());
+        let message = message.strip_suffix(&location).unwrap_or(&message);
+
+        Self {
+            line: line.max(1),
+            kind: DecodeErrorKind::Json {
+                column: (error.column() > 0).then_some(error.column()),
+            },
+            message: message.to_owned(),
+        }
+    }
+
+    /// The line of the input where the error was found, starting at 1, or 1
+    /// for protobuf payloads
+    #[must_use]
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The column of the line where the error was found, starting at 1, if
+    /// the line is not valid JSON
+    #[must_use]
+    pub fn column(&self) -> Option<usize> {
+        match self.kind {
+            DecodeErrorKind::Json { column } => column,
+            DecodeErrorKind::Read | DecodeErrorKind::Protobuf => None,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            DecodeErrorKind::Read => {
+                write!(f, "failed to read line {}: {}", self.line, self.message)
+            }
+            DecodeErrorKind::Json {
+                column: Some(column),
+            } => write!(
+                f,
+                "invalid OTLP JSON at line {}, column {column}: {}",
+                self.line, self.message
+            ),
+            DecodeErrorKind::Protobuf => write!(f, "invalid OTLP protobuf: {}", self.message),
+            DecodeErrorKind::Json { column: None } => {
+                write!(
+                    f,
+                    "invalid OTLP JSON at line {}: {}",
+                    self.line, self.message
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
@@ -0,0 +1,284 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, SendError, Sender};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::cache::Exposition;
+
+/// Parses the value of the `X-Prometheus-Scrape-Timeout-Seconds` header sent by
+/// Prometheus on every scrape.
+///
+/// Returns [`None`] if the value is not a valid, positive number of seconds.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// use opentelemetry_prometheus_text_exporter::parse_scrape_timeout_header;
+///
+/// assert_eq!(
+///     parse_scrape_timeout_header("9.5"),
+///     Some(Duration::from_millis(9500))
+/// );
+/// assert_eq!(parse_scrape_timeout_header("soon"), None);
+/// ```
+#[must_use]
+pub fn parse_scrape_timeout_header(value: &str) -> Option<Duration> {
+    let seconds: f64 = value.trim().parse().ok()?;
+    if seconds > 0.0 {
+        Duration::try_from_secs_f64(seconds).ok()
+    } else {
+        None
+    }
+}
+
+/// A collection to run on the collector thread
+type Job = Box<dyn FnOnce() + Send>;
+
+/// State shared between exports made with a collection deadline
+#[derive(Debug, Default)]
+pub(crate) struct DeadlineState {
+    /// The last collection started, which may still be running
+    pending: Mutex<Option<Arc<Collection>>>,
+
+    /// The last successful render, served when stale results are allowed
+    last_good: Mutex<Option<Exposition>>,
+
+    /// Sends the collections to the collector thread, once it is started
+    collector: Mutex<Option<Sender<Job>>>,
+}
+
+impl DeadlineState {
+    /// Join the pending collection if it is still running, or start a new one
+    /// on the collector thread.
+    ///
+    /// The collector thread is started by the first collection, and stops
+    /// once the state is dropped. Collections run one at a time, so a single
+    /// thread is enough.
+    pub fn start_or_join<F>(self: &Arc<Self>, render: F) -> std::io::Result<Arc<Collection>>
+    where
+        F: FnOnce() -> std::io::Result<Exposition> + Send + 'static,
+    {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(collection) = &*pending
+            && !collection.is_finished()
+        {
+            return Ok(collection.clone());
+        }
+
+        let collection = Arc::new(Collection::default());
+        let state = Arc::downgrade(self);
+        let job_collection = Arc::clone(&collection);
+        let job: Job = Box::new(move || {
+            // A panicking collection fails instead of being waited for forever
+            let result = panic::catch_unwind(AssertUnwindSafe(render))
+                .unwrap_or_else(|_| Err(std::io::Error::other("the metrics collection panicked")));
+            if let Ok(exposition) = &result
+                && let Some(state) = state.upgrade()
+            {
+                *state
+                    .last_good
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner) = Some(exposition.clone());
+            }
+            job_collection.finish(result);
+        });
+        self.send(job)?;
+
+        *pending = Some(collection.clone());
+        Ok(collection)
+    }
+
+    /// Send a job to the collector thread, starting it if needed
+    fn send(&self, job: Job) -> std::io::Result<()> {
+        let mut collector = self
+            .collector
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let job = match &*collector {
+            Some(sender) => match sender.send(job) {
+                Ok(()) => return Ok(()),
+                // The thread is gone, which only happens if a job panicked
+                // outside of the collection
+                Err(SendError(job)) => job,
+            },
+            None => job,
+        };
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        std::thread::Builder::new()
+            .name("otel-prometheus-collect".to_owned())
+            .spawn(move || {
+                for job in receiver {
+                    job();
+                }
+            })?;
+        sender
+            .send(job)
+            .map_err(|_| std::io::Error::other("the collector thread stopped"))?;
+        *collector = Some(sender);
+        Ok(())
+    }
+
+    /// The last successful render, marked as stale
+    pub fn last_good(&self) -> Option<Exposition> {
+        self.last_good
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .map(|exposition| exposition.clone().into_stale())
+    }
+}
+
+/// A collection running on the collector thread
+#[derive(Debug, Default)]
+pub(crate) struct Collection {
+    /// The outcome of the collection, once it finished. Errors are kept as
+    /// their kind and message, so that every waiter can get a copy.
+    result: Mutex<Option<Result<Exposition, (std::io::ErrorKind, String)>>>,
+    done: Condvar,
+}
+
+impl Collection {
+    fn is_finished(&self) -> bool {
+        self.result
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some()
+    }
+
+    fn finish(&self, result: std::io::Result<Exposition>) {
+        let result = result.map_err(|e| (e.kind(), e.to_string()));
+        *self.result.lock().unwrap_or_else(PoisonError::into_inner) = Some(result);
+        self.done.notify_all();
+    }
+
+    /// Wait for the collection to finish, for at most the given timeout.
+    ///
+    /// Returns [`None`] if the collection didn't finish in time.
+    pub fn wait(&self, timeout: Duration) -> Option<std::io::Result<Exposition>> {
+        let result = self.result.lock().unwrap_or_else(PoisonError::into_inner);
+        let (result, _) = self
+            .done
+            .wait_timeout_while(result, timeout, |result| result.is_none())
+            .unwrap_or_else(PoisonError::into_inner);
+
+        result.as_ref().map(|result| {
+            result
+                .clone()
+                .map_err(|(kind, message)| std::io::Error::new(kind, message))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_scrape_timeout_header() {
+        assert_eq!(
+            parse_scrape_timeout_header("10"),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            parse_scrape_timeout_header(" 0.25 "),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(parse_scrape_timeout_header("0"), None);
+        assert_eq!(parse_scrape_timeout_header("-1"), None);
+        assert_eq!(parse_scrape_timeout_header("NaN"), None);
+        assert_eq!(parse_scrape_timeout_header("inf"), None);
+        assert_eq!(parse_scrape_timeout_header(""), None);
+    }
+
+    #[test]
+    fn test_slow_collection_is_joined() {
+        let state = Arc::new(DeadlineState::default());
+        let (unblock, blocked) = mpsc::channel::<()>();
+
+        let collection = state
+            .start_or_join(move || {
+                blocked.recv().unwrap();
+                Ok(Exposition::from(b"slow".to_vec()))
+            })
+            .unwrap();
+        assert!(collection.wait(Duration::from_millis(10)).is_none());
+        assert!(state.last_good().is_none());
+
+        // The next export joins the collection still in progress
+        let joined = state
+            .start_or_join(|| unreachable!("the pending collection should be joined"))
+            .unwrap();
+        assert!(Arc::ptr_eq(&collection, &joined));
+
+        unblock.send(()).unwrap();
+        let exposition = joined.wait(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!(exposition.as_bytes(), b"slow");
+        assert!(!exposition.is_stale());
+
+        let last_good = state.last_good().unwrap();
+        assert_eq!(last_good.as_bytes(), b"slow");
+        assert!(last_good.is_stale());
+    }
+
+    #[test]
+    fn test_errors_are_shared() {
+        let state = Arc::new(DeadlineState::default());
+        let collection = state
+            .start_or_join(|| Err(std::io::Error::other("collection failed")))
+            .unwrap();
+
+        for _ in 0..2 {
+            let error = collection
+                .wait(Duration::from_secs(5))
+                .unwrap()
+                .unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::Other);
+            assert_eq!(error.to_string(), "collection failed");
+        }
+    }
+
+    #[test]
+    fn test_collections_share_a_thread() {
+        let state = Arc::new(DeadlineState::default());
+        let (sender, threads) = mpsc::channel();
+
+        for _ in 0..3 {
+            let sender = sender.clone();
+            let collection = state
+                .start_or_join(move || {
+                    sender.send(std::thread::current().id()).unwrap();
+                    Ok(Exposition::from(Vec::new()))
+                })
+                .unwrap();
+            collection.wait(Duration::from_secs(5)).unwrap().unwrap();
+        }
+
+        let threads: Vec<_> = threads.try_iter().collect();
+        assert_eq!(threads.len(), 3);
+        assert!(threads.iter().all(|thread| *thread == threads[0]));
+        assert_ne!(threads[0], std::thread::current().id());
+    }
+
+    #[test]
+    fn test_panicking_collection_fails() {
+        let state = Arc::new(DeadlineState::default());
+        let collection = state.start_or_join(|| panic!("callback panicked")).unwrap();
+        let error = collection
+            .wait(Duration::from_secs(5))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "the metrics collection panicked");
+
+        // The collector thread keeps running
+        let collection = state
+            .start_or_join(|| Ok(Exposition::from(b"ok".to_vec())))
+            .unwrap();
+        let exposition = collection.wait(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!(exposition.as_bytes(), b"ok");
+    }
+}
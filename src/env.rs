@@ -0,0 +1,275 @@
+use std::env::VarError;
+use std::fmt;
+
+use crate::exporter::ExporterBuilder;
+
+/// The address the Prometheus HTTP server listens on
+pub(crate) const HOST: &str = "OTEL_EXPORTER_PROMETHEUS_HOST";
+
+/// The port the Prometheus HTTP server listens on
+pub(crate) const PORT: &str = "OTEL_EXPORTER_PROMETHEUS_PORT";
+
+/// Disables the unit suffixes, like [`ExporterBuilder::without_units`]
+pub(crate) const WITHOUT_UNITS: &str = "OTEL_EXPORTER_PROMETHEUS_WITHOUT_UNITS";
+
+/// Disables the `_total` suffixes, like
+/// [`ExporterBuilder::without_counter_suffixes`]
+pub(crate) const WITHOUT_TYPE_SUFFIX: &str = "OTEL_EXPORTER_PROMETHEUS_WITHOUT_TYPE_SUFFIX";
+
+/// Disables the scope labels, like [`ExporterBuilder::without_scope_info`]
+pub(crate) const WITHOUT_SCOPE_INFO: &str = "OTEL_EXPORTER_PROMETHEUS_WITHOUT_SCOPE_INFO";
+
+/// Disables `target_info`, like [`ExporterBuilder::without_target_info`]
+pub(crate) const WITHOUT_TARGET_INFO: &str = "OTEL_EXPORTER_PROMETHEUS_WITHOUT_TARGET_INFO";
+
+/// How instrument names are translated to Prometheus names
+pub(crate) const TRANSLATION_STRATEGY: &str = "OTEL_EXPORTER_PROMETHEUS_TRANSLATION_STRATEGY";
+
+/// Error returned by [`ExporterBuilder::from_env`] when an environment
+/// variable has an invalid value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FromEnvError {
+    variable: &'static str,
+    value: String,
+    reason: String,
+}
+
+impl FromEnvError {
+    fn new(variable: &'static str, value: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            variable,
+            value: value.into(),
+            reason: reason.into(),
+        }
+    }
+
+    /// The name of the environment variable with an invalid value
+    #[must_use]
+    pub fn variable(&self) -> &'static str {
+        self.variable
+    }
+
+    /// The invalid value
+    #[must_use]
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl fmt::Display for FromEnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid value {:?} for {}: {}",
+            self.value, self.variable, self.reason
+        )
+    }
+}
+
+impl std::error::Error for FromEnvError {}
+
+/// Read an environment variable, treating empty values like missing ones
+fn read<F>(lookup: &F, variable: &'static str) -> Result<Option<String>, FromEnvError>
+where
+    F: Fn(&'static str) -> Result<String, VarError>,
+{
+    match lookup(variable) {
+        Ok(value) if value.trim().is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value.trim().to_owned())),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(VarError::NotUnicode(value)) => Err(FromEnvError::new(
+            variable,
+            value.to_string_lossy(),
+            "the value is not valid unicode",
+        )),
+    }
+}
+
+/// Read a boolean environment variable, which is either `true` or `false`
+fn read_bool<F>(lookup: &F, variable: &'static str) -> Result<bool, FromEnvError>
+where
+    F: Fn(&'static str) -> Result<String, VarError>,
+{
+    match read(lookup, variable)? {
+        None => Ok(false),
+        Some(value) if value.eq_ignore_ascii_case("true") => Ok(true),
+        Some(value) if value.eq_ignore_ascii_case("false") => Ok(false),
+        Some(value) => Err(FromEnvError::new(
+            variable,
+            value,
+            "expected `true` or `false`",
+        )),
+    }
+}
+
+/// Configure the builder from the environment variables read with the given
+/// function
+pub(crate) fn configure<F>(
+    mut builder: ExporterBuilder,
+    lookup: F,
+) -> Result<ExporterBuilder, FromEnvError>
+where
+    F: Fn(&'static str) -> Result<String, VarError>,
+{
+    if let Some(host) = read(&lookup, HOST)? {
+        builder = builder.with_host(host);
+    }
+
+    if let Some(port) = read(&lookup, PORT)? {
+        let port = port.parse().map_err(|_| {
+            FromEnvError::new(PORT, port, "expected a port number between 0 and 65535")
+        })?;
+        builder = builder.with_port(port);
+    }
+
+    if let Some(strategy) = read(&lookup, TRANSLATION_STRATEGY)? {
+        match strategy.as_str() {
+            "UnderscoreEscapingWithSuffixes" => {}
+            "UnderscoreEscapingWithoutSuffixes" => {
+                builder = builder.without_units().without_counter_suffixes();
+            }
+            "NoUTF8EscapingWithSuffixes" | "NoTranslation" => {
+                return Err(FromEnvError::new(
+                    TRANSLATION_STRATEGY,
+                    strategy,
+                    "names are always escaped to the legacy Prometheus character set",
+                ));
+            }
+            _ => {
+                return Err(FromEnvError::new(
+                    TRANSLATION_STRATEGY,
+                    strategy,
+                    "expected one of `UnderscoreEscapingWithSuffixes`, \
+                     `UnderscoreEscapingWithoutSuffixes`, `NoUTF8EscapingWithSuffixes` or \
+                     `NoTranslation`",
+                ));
+            }
+        }
+    }
+
+    if read_bool(&lookup, WITHOUT_UNITS)? {
+        builder = builder.without_units();
+    }
+    if read_bool(&lookup, WITHOUT_TYPE_SUFFIX)? {
+        builder = builder.without_counter_suffixes();
+    }
+    if read_bool(&lookup, WITHOUT_SCOPE_INFO)? {
+        builder = builder.without_scope_info();
+    }
+    if read_bool(&lookup, WITHOUT_TARGET_INFO)? {
+        builder = builder.without_target_info();
+    }
+
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn from_vars(vars: &[(&str, &str)]) -> Result<ExporterBuilder, FromEnvError> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+            .collect();
+        configure(ExporterBuilder::default(), |name| {
+            vars.get(name).cloned().ok_or(VarError::NotPresent)
+        })
+    }
+
+    #[test]
+    fn test_configure_from_vars() {
+        let exporter = from_vars(&[
+            (HOST, "0.0.0.0"),
+            (PORT, " 9100 "),
+            (WITHOUT_SCOPE_INFO, "TRUE"),
+            (WITHOUT_TARGET_INFO, "false"),
+            (WITHOUT_UNITS, ""),
+        ])
+        .unwrap()
+        .build();
+        assert_eq!(exporter.listen_address(), ("0.0.0.0", 9100));
+
+        let exporter = from_vars(&[]).unwrap().build();
+        assert_eq!(exporter.listen_address(), ("localhost", 9464));
+
+        // Explicit calls take precedence
+        let exporter = from_vars(&[(PORT, "9100")])
+            .unwrap()
+            .with_port(8080)
+            .build();
+        assert_eq!(exporter.listen_address(), ("localhost", 8080));
+    }
+
+    #[test]
+    fn test_builder_overrides_booleans() {
+        use opentelemetry::metrics::MeterProvider;
+        use opentelemetry_sdk::metrics::SdkMeterProvider;
+
+        let export = |builder: ExporterBuilder| {
+            let exporter = builder.build();
+            let provider = SdkMeterProvider::builder()
+                .with_reader(exporter.clone())
+                .build();
+            provider
+                .meter("test")
+                .u64_counter("requests")
+                .with_unit("ms")
+                .build()
+                .add(1, &[]);
+
+            let mut buffer = Vec::new();
+            exporter.export(&mut buffer).unwrap();
+            String::from_utf8(buffer).unwrap()
+        };
+
+        let vars = [
+            (WITHOUT_UNITS, "true"),
+            (WITHOUT_TYPE_SUFFIX, "true"),
+            (WITHOUT_SCOPE_INFO, "true"),
+            (WITHOUT_TARGET_INFO, "true"),
+        ];
+        let output = export(from_vars(&vars).unwrap());
+        assert!(output.contains("\nrequests 1\n"));
+        assert!(!output.contains("target_info"));
+
+        let output = export(
+            from_vars(&vars)
+                .unwrap()
+                .with_units()
+                .with_counter_suffixes()
+                .with_scope_info()
+                .with_target_info(),
+        );
+        assert!(output.contains("\nrequests_milliseconds_total{otel_scope_name=\"test\"} 1\n"));
+        assert!(output.contains("# TYPE target_info gauge\n"));
+
+        let builder = from_vars(&[(TRANSLATION_STRATEGY, "UnderscoreEscapingWithoutSuffixes")])
+            .unwrap()
+            .with_counter_suffixes();
+        let output = export(builder);
+        assert!(output.contains("\nrequests_total{otel_scope_name=\"test\"} 1\n"));
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let error = from_vars(&[(PORT, "70000")]).unwrap_err();
+        assert_eq!(error.variable(), PORT);
+        assert_eq!(error.value(), "70000");
+        assert_eq!(
+            error.to_string(),
+            "invalid value \"70000\" for OTEL_EXPORTER_PROMETHEUS_PORT: expected a port number \
+             between 0 and 65535"
+        );
+
+        let error = from_vars(&[(WITHOUT_UNITS, "yes")]).unwrap_err();
+        assert_eq!(error.variable(), WITHOUT_UNITS);
+
+        let error = from_vars(&[(TRANSLATION_STRATEGY, "NoTranslation")]).unwrap_err();
+        assert_eq!(error.variable(), TRANSLATION_STRATEGY);
+        let error = from_vars(&[(TRANSLATION_STRATEGY, "Underscores")]).unwrap_err();
+        assert_eq!(error.variable(), TRANSLATION_STRATEGY);
+    }
+}
@@ -1,26 +1,160 @@
-use std::sync::{Arc, Weak};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, TryLockError, Weak};
+use std::time::Duration;
 
+use opentelemetry::KeyValue;
 use opentelemetry_sdk::error::OTelSdkResult;
 use opentelemetry_sdk::metrics::data::ResourceMetrics;
 use opentelemetry_sdk::metrics::reader::MetricReader;
-use opentelemetry_sdk::metrics::{ManualReader, ManualReaderBuilder, Pipeline};
+use opentelemetry_sdk::metrics::{
+    InstrumentKind, ManualReader, ManualReaderBuilder, Pipeline, Temporality,
+};
 
-use crate::serialize::PrometheusSerializer;
+use crate::budget::{PriorityRule, SizeBudget};
+use crate::cache::{Exposition, ScrapeCache};
+use crate::deadline::DeadlineState;
+use crate::env::FromEnvError;
+use crate::filter::{MetricFilter, MetricSelector};
+use crate::glob::glob_match;
+use crate::idle::IdleTimeout;
+use crate::limits::LabelLimits;
+use crate::metadata::{MetadataOverride, MetadataOverrides};
+use crate::parse::ExpositionFormat;
+use crate::producer::MetricProducer;
+use crate::relabel::RelabelConfig;
+use crate::selector::SeriesSelector;
+use crate::self_metrics::{CountingWriter, ExportErrorKind, ScrapeRecorder, SelfMetrics};
+use crate::serialize::{Family, PrometheusSerializer, sanitize_name};
+use crate::snapshot::Snapshot;
+
+/// The default address of the Prometheus HTTP server, from the `OpenTelemetry`
+/// specification
+const DEFAULT_HOST: &str = "localhost";
+
+/// The default port of the Prometheus HTTP server, from the `OpenTelemetry`
+/// specification
+const DEFAULT_PORT: u16 = 9464;
 
 /// Configuration for the Prometheus exporter
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Default)]
 pub(crate) struct ExporterConfig {
     pub disable_target_info: bool,
     pub without_units: bool,
     pub without_counter_suffixes: bool,
     pub disable_scope_info: bool,
+
+    /// The maximum number of series of each metric, overflow series included
+    pub cardinality_limit: Option<usize>,
+
+    /// Per-metric overrides of the cardinality limit, by instrument name
+    pub metric_cardinality_limits: HashMap<String, usize>,
+
+    /// Which metrics are rendered
+    pub filter: MetricFilter,
+
+    /// Relabeling rules applied to every series
+    pub relabel: Vec<RelabelConfig>,
+
+    /// Prefix of all the metric names, already sanitized
+    pub namespace: Option<String>,
+
+    /// Labels added to every series, with sanitized names
+    pub const_labels: Vec<(String, String)>,
+
+    /// Overrides of the metadata of some instruments
+    pub metadata_overrides: MetadataOverrides,
+
+    /// Whether to generate a `# HELP` line for instruments without a
+    /// description
+    pub generate_help: bool,
+
+    /// Limits on the names and labels of every series
+    pub label_limits: LabelLimits,
+
+    /// When to leave out the series which stopped changing
+    pub idle_timeout: IdleTimeout,
+
+    /// Which resource attributes are added as labels to every series
+    pub resource_labels: ResourceLabels,
+}
+
+/// Glob patterns of the resource attributes added as labels to every series
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ResourceLabels {
+    pub included: Vec<String>,
+    pub excluded: Vec<String>,
+}
+
+impl ResourceLabels {
+    /// Whether any resource attribute may be added
+    pub fn is_set(&self) -> bool {
+        !self.included.is_empty()
+    }
+
+    /// Whether the resource attribute with the given key is added
+    pub fn includes(&self, key: &str) -> bool {
+        self.included.iter().any(|pattern| glob_match(pattern, key))
+            && !self.excluded.iter().any(|pattern| glob_match(pattern, key))
+    }
+}
+
+impl ExporterConfig {
+    /// The cardinality limit applying to the given instrument
+    pub fn cardinality_limit(&self, name: &str) -> Option<usize> {
+        self.metric_cardinality_limits
+            .get(name)
+            .copied()
+            .or(self.cardinality_limit)
+    }
 }
 
 /// Prometheus metrics exporter, using the text exposition format
 #[derive(Clone, Debug)]
 pub struct PrometheusExporter {
     inner: Arc<ManualReader>,
+    producers: Arc<[Box<dyn MetricProducer>]>,
+    delta_conversion: bool,
     serializer: PrometheusSerializer,
+    pool: Arc<ExportPool>,
+    cache: Arc<ScrapeCache>,
+    collect_timeout: Option<Duration>,
+    stale_on_timeout: bool,
+    deadline: Arc<DeadlineState>,
+    self_metrics: Option<Arc<SelfMetrics>>,
+    budget: Option<Arc<SizeBudget>>,
+    host: Arc<str>,
+    port: u16,
+}
+
+/// Allocations reused between exports
+#[derive(Debug, Default)]
+struct ExportPool {
+    /// Metrics collected during the last export, reused by the next one
+    rms: Mutex<Vec<ResourceMetrics>>,
+
+    /// Output buffer of [`PrometheusExporter::export_bytes`]
+    #[cfg(feature = "bytes")]
+    bytes: Mutex<bytes::BytesMut>,
+
+    /// Size of the last exposition rendered in a buffer, used to pre-reserve
+    /// capacity for the next one
+    last_size: AtomicUsize,
+}
+
+impl ExportPool {
+    /// Capacity to reserve for the next exposition
+    ///
+    /// This adds some headroom to the previous size so that a slight growth
+    /// doesn't trigger a reallocation.
+    fn predicted_size(&self) -> usize {
+        let last_size = self.last_size.load(Ordering::Relaxed);
+        last_size + last_size / 8
+    }
+
+    fn record_size(&self, size: usize) {
+        self.last_size.store(size, Ordering::Relaxed);
+    }
 }
 
 impl MetricReader for PrometheusExporter {
@@ -36,15 +170,23 @@ impl MetricReader for PrometheusExporter {
         self.inner.force_flush()
     }
 
-    fn shutdown_with_timeout(&self, timeout: std::time::Duration) -> OTelSdkResult {
+    fn shutdown_with_timeout(&self, timeout: Duration) -> OTelSdkResult {
         self.inner.shutdown_with_timeout(timeout)
     }
 
-    fn temporality(
-        &self,
-        kind: opentelemetry_sdk::metrics::InstrumentKind,
-    ) -> opentelemetry_sdk::metrics::Temporality {
-        self.inner.temporality(kind)
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        match kind {
+            // Those are rendered as Prometheus counters and histograms, which
+            // must be cumulative
+            InstrumentKind::Counter
+            | InstrumentKind::ObservableCounter
+            | InstrumentKind::Histogram
+                if !self.delta_conversion =>
+            {
+                Temporality::Cumulative
+            }
+            _ => self.inner.temporality(kind),
+        }
     }
 }
 
@@ -61,19 +203,538 @@ impl PrometheusExporter {
         ExporterBuilder::default()
     }
 
+    /// The address the HTTP server serving the metrics should listen on.
+    ///
+    /// The exporter doesn't serve the metrics itself, but carries the address
+    /// set with [`ExporterBuilder::with_host`] and
+    /// [`ExporterBuilder::with_port`], or read by
+    /// [`ExporterBuilder::from_env`]. It defaults to `localhost:9464`, and
+    /// can be passed to [`std::net::TcpListener::bind`] as is.
+    #[must_use]
+    pub fn listen_address(&self) -> (&str, u16) {
+        (&self.host, self.port)
+    }
+
     /// Export the collected metrics to the given writer.
     ///
     /// # Errors
     ///
     /// Returns an error if the writer fails to write the metrics.
     pub fn export<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
-        let mut rm = ResourceMetrics::default();
-        self.inner.collect(&mut rm).map_err(std::io::Error::other)?;
-        self.serializer.serialize(&rm, writer)?;
+        self.write_exposition(writer)
+    }
+
+    /// Export the collected series matching one of the given selectors to the
+    /// given writer.
+    ///
+    /// This is meant to serve the `name[]` or `match[]` query parameters of a
+    /// scrape endpoint, to only render the series a caller is interested in.
+    /// All the series are rendered if there are no selectors.
+    ///
+    /// The size budget and the exporter metrics don't apply to filtered
+    /// exports, and filtered exports are not counted in the exporter metrics.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metrics could not be collected, or if the writer
+    /// fails to write the metrics.
+    pub fn export_filtered<W: std::io::Write>(
+        &self,
+        selectors: &[SeriesSelector],
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        self.with_collected(|rms| {
+            #[cfg(feature = "tracing")]
+            let _span = tracing::debug_span!("otel_prometheus_exporter.serialize").entered();
+
+            for family in self.serializer.families(rms) {
+                self.serializer
+                    .serialize_selected(&family, selectors, writer)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Export the collected metrics to the given buffer.
+    ///
+    /// The buffer is cleared first, then enough capacity is reserved for an
+    /// exposition slightly larger than the previous one, so that reusing the
+    /// same buffer between scrapes avoids most reallocations.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metrics could not be collected.
+    pub fn export_to_vec(&self, buffer: &mut Vec<u8>) -> std::io::Result<()> {
+        buffer.clear();
+        buffer.reserve(self.pool.predicted_size());
+        self.write_exposition(buffer)?;
+        self.pool.record_size(buffer.len());
+        Ok(())
+    }
+
+    /// Export the collected metrics and parse them in a [`Snapshot`], to
+    /// query them or compare them with a later one using [`Snapshot::diff`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metrics could not be collected, or with the
+    /// [`InvalidData`] kind if the exposition can't be parsed.
+    ///
+    /// [`InvalidData`]: std::io::ErrorKind::InvalidData
+    pub fn snapshot(&self) -> std::io::Result<Snapshot> {
+        let mut buffer = Vec::new();
+        self.write_exposition(&mut buffer)?;
+        let exposition = String::from_utf8(buffer)
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+        Snapshot::parse(&exposition, ExpositionFormat::Text)
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+    }
+
+    /// Export the collected metrics, sharing the result between concurrent
+    /// callers and caching it for the configured minimum interval.
+    ///
+    /// If an export is already in progress, this waits for it and returns its
+    /// result instead of collecting the metrics again. The result is then
+    /// served to all callers until the interval set with
+    /// [`ExporterBuilder::with_min_scrape_interval`] has elapsed since the
+    /// start of the collection.
+    ///
+    /// The collection is bounded by the timeout set with
+    /// [`ExporterBuilder::with_collect_timeout`], like with
+    /// [`export_with_timeout()`](Self::export_with_timeout). A stale
+    /// exposition served on timeout is not cached, so the next export tries
+    /// again.
+    ///
+    /// There is a single cached exposition, not one per negotiated format:
+    /// the exporter only renders the Prometheus text format, so it should be
+    /// served with the `text/plain; version=0.0.4` content type, even to
+    /// scrapers asking for `OpenMetrics` first.
+    ///
+    /// This blocks while waiting for an in-progress export.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metrics could not be collected, or in time.
+    /// Failed exports are not cached.
+    pub fn export_cached(&self) -> std::io::Result<Exposition> {
+        self.cache
+            .get_or_render(|| self.render_within(self.collect_timeout))
+    }
+
+    /// Export the collected metrics, giving up if the collection takes longer
+    /// than the given timeout.
+    ///
+    /// The timeout used is the shortest of the given one and the one set with
+    /// [`ExporterBuilder::with_collect_timeout`]. When scraped by Prometheus,
+    /// the timeout can be taken from the `X-Prometheus-Scrape-Timeout-Seconds`
+    /// header, using [`parse_scrape_timeout_header`]. Without any timeout, the
+    /// metrics are collected on the current thread.
+    ///
+    /// Otherwise, the collection runs on a collector thread, which the first
+    /// export with a timeout starts and the next ones reuse. It can't be
+    /// interrupted, so if it times out, it keeps running in the background and
+    /// the next export waits for it instead of starting another one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with the [`TimedOut`] kind if the collection didn't
+    /// finish in time, unless [`ExporterBuilder::with_stale_on_timeout`] is set
+    /// and a previous export succeeded. In that case, the last successful
+    /// exposition is returned, marked as [stale].
+    ///
+    /// Also returns an error if the metrics could not be collected.
+    ///
+    /// [`parse_scrape_timeout_header`]: crate::parse_scrape_timeout_header
+    /// [`TimedOut`]: std::io::ErrorKind::TimedOut
+    /// [stale]: Exposition::is_stale
+    pub fn export_with_timeout(&self, timeout: Option<Duration>) -> std::io::Result<Exposition> {
+        let timeout = match (timeout, self.collect_timeout) {
+            (Some(timeout), Some(collect_timeout)) => Some(timeout.min(collect_timeout)),
+            (timeout, collect_timeout) => timeout.or(collect_timeout),
+        };
+        self.render_within(timeout)
+    }
+
+    /// Render the collected metrics, on the collector thread if there is a
+    /// timeout, serving the last successful exposition on timeout if allowed.
+    fn render_within(&self, timeout: Option<Duration>) -> std::io::Result<Exposition> {
+        let Some(timeout) = timeout else {
+            return self.render();
+        };
+
+        let exporter = self.clone();
+        let collection = self.deadline.start_or_join(move || exporter.render())?;
+        if let Some(result) = collection.wait(timeout) {
+            return result;
+        }
+
+        if let Some(self_metrics) = &self.self_metrics {
+            self_metrics.record_error(ExportErrorKind::Timeout);
+        }
+
+        if self.stale_on_timeout
+            && let Some(exposition) = self.deadline.last_good()
+        {
+            return Ok(exposition);
+        }
+
+        Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!(
+                "metrics collection did not finish within {:.3}s",
+                timeout.as_secs_f64()
+            ),
+        ))
+    }
+
+    /// Export the collected metrics as cheaply cloneable [`Bytes`].
+    ///
+    /// The exposition is rendered in a buffer owned by the exporter. Once all
+    /// the [`Bytes`] returned by a previous call are dropped, its allocation is
+    /// reused for the next exposition.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metrics could not be collected.
+    ///
+    /// [`Bytes`]: bytes::Bytes
+    #[cfg(feature = "bytes")]
+    pub fn export_bytes(&self) -> std::io::Result<bytes::Bytes> {
+        let mut fresh = bytes::BytesMut::new();
+        let mut guard = try_lock(&self.pool.bytes);
+        let buffer = guard.as_deref_mut().unwrap_or(&mut fresh);
+        self.export_to_bytes_mut(buffer)
+    }
+
+    /// Export the collected metrics in the given [`BytesMut`], returning the
+    /// rendered exposition as [`Bytes`].
+    ///
+    /// The exposition is split off the buffer, which keeps its spare capacity.
+    /// Once the returned [`Bytes`] is dropped, the next call can reclaim the
+    /// whole allocation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metrics could not be collected.
+    ///
+    /// [`Bytes`]: bytes::Bytes
+    /// [`BytesMut`]: bytes::BytesMut
+    #[cfg(feature = "bytes")]
+    pub fn export_to_bytes_mut(
+        &self,
+        buffer: &mut bytes::BytesMut,
+    ) -> std::io::Result<bytes::Bytes> {
+        use bytes::BufMut as _;
+
+        buffer.clear();
+        buffer.reserve(self.pool.predicted_size());
+        let mut writer = buffer.writer();
+        self.write_exposition(&mut writer)?;
+        self.pool.record_size(buffer.len());
+        Ok(buffer.split().freeze())
+    }
+
+    /// Export the collected metrics to the given [`tokio`] writer.
+    ///
+    /// Metrics are collected synchronously, then written one family at a
+    /// time, so that large expositions start streaming before the whole
+    /// document is rendered. The writer is flushed once everything is written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metrics could not be collected, or if the writer
+    /// fails to write the metrics.
+    #[cfg(feature = "tokio")]
+    pub async fn export_async_tokio<W>(&self, writer: &mut W) -> std::io::Result<()>
+    where
+        W: tokio::io::AsyncWrite + Unpin + ?Sized,
+    {
+        self.export_async(&mut TokioSink(writer)).await
+    }
+
+    /// Export the collected metrics to the given [`futures`] writer.
+    ///
+    /// Metrics are collected synchronously, then written one family at a
+    /// time, so that large expositions start streaming before the whole
+    /// document is rendered. The writer is flushed once everything is written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metrics could not be collected, or if the writer
+    /// fails to write the metrics.
+    ///
+    /// [`futures`]: https://docs.rs/futures
+    #[cfg(feature = "futures")]
+    pub async fn export_async_futures<W>(&self, writer: &mut W) -> std::io::Result<()>
+    where
+        W: futures_util::io::AsyncWrite + Unpin + ?Sized,
+    {
+        self.export_async(&mut FuturesSink(writer)).await
+    }
+
+    /// Collect the metrics and write them to the given async writer, one
+    /// family at a time, followed by the exporter metrics if they are enabled.
+    #[cfg(any(feature = "tokio", feature = "futures"))]
+    async fn export_async(&self, writer: &mut impl AsyncSink) -> std::io::Result<()> {
+        let _scrape = self.serializer.start_scrape();
+        let mut recorder = self.self_metrics.as_deref().map(SelfMetrics::start_scrape);
+        let result = async {
+            let rms = self.collect_all()?;
+            if let Some(recorder) = &mut recorder {
+                recorder.collected();
+            }
+
+            let mut buffer = Vec::new();
+            if let Some(budget) = self.budget.as_deref() {
+                // Families can only be dropped once they are all rendered
+                self.write_within_budget(budget, &rms, &mut buffer, recorder.as_mut())?;
+                writer.write_all(&buffer).await?;
+                buffer.clear();
+            } else {
+                for family in self.serializer.families(&rms) {
+                    self.serialize_family(
+                        &family,
+                        &mut CountingWriter::new(&mut buffer),
+                        recorder.as_mut(),
+                    )?;
+                    writer.write_all(&buffer).await?;
+                    buffer.clear();
+                }
+            }
+
+            if let Some(recorder) = &recorder {
+                self.serializer
+                    .serialize_self_metrics(&recorder.stats(), &mut buffer)?;
+                writer.write_all(&buffer).await?;
+            }
+
+            writer.flush().await
+        }
+        .await;
+
+        if result.is_err()
+            && let Some(recorder) = &recorder
+        {
+            recorder.failed();
+        }
+
+        result
+    }
+
+    /// Collect the metrics and write them to the given writer, followed by the
+    /// exporter metrics if they are enabled.
+    fn write_exposition<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let _scrape = self.serializer.start_scrape();
+
+        if let Some(budget) = self.budget.as_deref() {
+            let mut recorder = self.self_metrics.as_deref().map(SelfMetrics::start_scrape);
+            let result = self.with_collected(|rms| {
+                if let Some(recorder) = &mut recorder {
+                    recorder.collected();
+                }
+
+                self.write_within_budget(budget, rms, writer, recorder.as_mut())?;
+                if let Some(recorder) = &recorder {
+                    self.serializer
+                        .serialize_self_metrics(&recorder.stats(), writer)?;
+                }
+                Ok(())
+            });
+
+            if result.is_err()
+                && let Some(recorder) = &recorder
+            {
+                recorder.failed();
+            }
+
+            return result;
+        }
+
+        let Some(self_metrics) = self.self_metrics.as_deref() else {
+            return self.with_collected(|rms| {
+                #[cfg(feature = "tracing")]
+                let _span = tracing::debug_span!("otel_prometheus_exporter.serialize").entered();
+
+                self.serializer.serialize(rms, writer)
+            });
+        };
+
+        let mut recorder = self_metrics.start_scrape();
+        let result = self.with_collected(|rms| {
+            recorder.collected();
+
+            let mut writer = CountingWriter::new(writer);
+            for family in self.serializer.families(rms) {
+                self.serialize_family(&family, &mut writer, Some(&mut recorder))?;
+            }
+
+            self.serializer
+                .serialize_self_metrics(&recorder.stats(), &mut writer)
+        });
+
+        if result.is_err() {
+            recorder.failed();
+        }
+
+        result
+    }
+
+    /// Serialize a single family, recording it in the statistics of the
+    /// current scrape if there is one.
+    fn serialize_family<W: std::io::Write>(
+        &self,
+        family: &Family<'_>,
+        writer: &mut CountingWriter<'_, W>,
+        recorder: Option<&mut ScrapeRecorder<'_>>,
+    ) -> std::io::Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("otel_prometheus_exporter.serialize_family").entered();
+
+        self.serializer.serialize_family(family, writer)?;
+        let bytes = writer.take_count();
+        if let Some(recorder) = recorder {
+            recorder.record_family(&self.serializer, family, bytes);
+        }
+
+        Ok(())
+    }
+
+    /// Render all the families, then write the ones fitting in the size
+    /// budget, followed by the report about the dropped ones.
+    fn write_within_budget<W: std::io::Write>(
+        &self,
+        budget: &SizeBudget,
+        rms: &[ResourceMetrics],
+        writer: &mut W,
+        mut recorder: Option<&mut ScrapeRecorder<'_>>,
+    ) -> std::io::Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("otel_prometheus_exporter.serialize").entered();
+
+        let families = self.serializer.families(rms);
+        let rendered = Vec::with_capacity(self.pool.predicted_size());
+        budget.write_families(
+            &self.serializer,
+            &families,
+            rendered,
+            writer,
+            |family, size| {
+                if let Some(recorder) = recorder.as_deref_mut() {
+                    recorder.record_family(&self.serializer, family, size);
+                }
+            },
+        )
+    }
+
+    /// Render the collected metrics in a new [`Exposition`].
+    fn render(&self) -> std::io::Result<Exposition> {
+        let mut buffer = Vec::new();
+        self.export_to_vec(&mut buffer)?;
+        Ok(Exposition::from(buffer))
+    }
+
+    /// The serializer rendering the metrics of this exporter
+    pub(crate) fn serializer(&self) -> &PrometheusSerializer {
+        &self.serializer
+    }
+
+    /// Collect the metrics from the reader and all the producers, reusing the
+    /// pooled allocation if it is available.
+    fn with_collected<T>(
+        &self,
+        f: impl FnOnce(&[ResourceMetrics]) -> std::io::Result<T>,
+    ) -> std::io::Result<T> {
+        // If another export is in progress, collect in a fresh allocation
+        // instead of waiting for it
+        let mut fresh = Vec::new();
+        let mut guard = try_lock(&self.pool.rms);
+        let rms = guard.as_deref_mut().unwrap_or(&mut fresh);
+        self.collect_into(rms)?;
+        f(rms)
+    }
+
+    /// Collect the metrics from the reader and all the producers in a fresh
+    /// allocation.
+    pub(crate) fn collect_all(&self) -> std::io::Result<Vec<ResourceMetrics>> {
+        let mut rms = Vec::new();
+        self.collect_into(&mut rms)?;
+        Ok(rms)
+    }
+
+    /// Collect the metrics from the reader and all the producers.
+    ///
+    /// The SDK reuses the existing allocations of the given
+    /// [`ResourceMetrics`].
+    fn collect_into(&self, rms: &mut Vec<ResourceMetrics>) -> std::io::Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("otel_prometheus_exporter.collect").entered();
+
+        rms.resize_with(1 + self.producers.len(), ResourceMetrics::default);
+        let (rm, produced) = rms
+            .split_first_mut()
+            .expect("there is always at least one ResourceMetrics");
+
+        self.inner.collect(rm).map_err(std::io::Error::other)?;
+
+        for (producer, rm) in self.producers.iter().zip(produced) {
+            producer.produce(rm).map_err(std::io::Error::other)?;
+        }
+
         Ok(())
     }
 }
 
+/// Lock the given mutex if it is not already locked.
+///
+/// A poisoned mutex is still locked, as it only holds reusable allocations.
+fn try_lock<T>(mutex: &Mutex<T>) -> Option<std::sync::MutexGuard<'_, T>> {
+    match mutex.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+/// An async writer the exposition is written to, whichever runtime it
+/// belongs to
+#[cfg(any(feature = "tokio", feature = "futures"))]
+trait AsyncSink {
+    async fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()>;
+
+    async fn flush(&mut self) -> std::io::Result<()>;
+}
+
+/// A [`tokio`] writer
+#[cfg(feature = "tokio")]
+struct TokioSink<'a, W: ?Sized>(&'a mut W);
+
+#[cfg(feature = "tokio")]
+impl<W: tokio::io::AsyncWrite + Unpin + ?Sized> AsyncSink for TokioSink<'_, W> {
+    async fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        tokio::io::AsyncWriteExt::write_all(self.0, buf).await
+    }
+
+    async fn flush(&mut self) -> std::io::Result<()> {
+        tokio::io::AsyncWriteExt::flush(self.0).await
+    }
+}
+
+/// A [`futures`](https://docs.rs/futures) writer
+#[cfg(feature = "futures")]
+struct FuturesSink<'a, W: ?Sized>(&'a mut W);
+
+#[cfg(feature = "futures")]
+impl<W: futures_util::io::AsyncWrite + Unpin + ?Sized> AsyncSink for FuturesSink<'_, W> {
+    async fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        futures_util::io::AsyncWriteExt::write_all(self.0, buf).await
+    }
+
+    async fn flush(&mut self) -> std::io::Result<()> {
+        futures_util::io::AsyncWriteExt::flush(self.0).await
+    }
+}
+
 impl Default for PrometheusExporter {
     fn default() -> Self {
         Self::new()
@@ -100,6 +761,17 @@ impl Default for PrometheusExporter {
 ///   - Example: `http.requests` becomes `http_requests` instead of
 ///     `http_requests_total`
 ///
+/// ## Environment
+/// - [`from_env()`]: Reads the configuration from the standard
+///   `OTEL_EXPORTER_PROMETHEUS_*` environment variables
+/// - [`with_host()`] and [`with_port()`]: Set the address the metrics are
+///   served on, returned by [`PrometheusExporter::listen_address`]
+/// - [`with_units()`], [`with_counter_suffixes()`], [`with_target_info()`] and
+///   [`with_scope_info()`]: Enable again what the environment disables
+/// - With the `serde` feature, `PrometheusConfig::builder()` creates a builder
+///   from the Prometheus exporter section of the `OpenTelemetry` declarative
+///   configuration
+///
 /// ## Resource Information
 /// - [`without_target_info()`]: Disables the `target_info` metric that contains
 ///   resource attributes
@@ -111,6 +783,73 @@ impl Default for PrometheusExporter {
 ///     not added
 ///   - Also disables the `otel_scope_info` metric
 ///
+/// ## Reader Configuration
+/// - [`with_temporality()`]: Sets the temporality preference of the underlying
+///   reader
+///   - Counters and histograms stay cumulative unless
+///     [`with_delta_conversion()`] is also set
+/// - [`with_producer()`]: Registers an additional [`MetricProducer`] collected
+///   on every export
+///
+/// ## Caching
+/// - [`with_min_scrape_interval()`]: Serves the same exposition from
+///   [`PrometheusExporter::export_cached`] for a minimum interval
+///
+/// ## Cardinality Limits
+/// - [`with_cardinality_limit()`]: Caps the number of series of every metric
+/// - [`with_metric_cardinality_limit()`]: Overrides the cap for a single metric
+///   - Series beyond the limit are folded in a single series labeled
+///     `otel_metric_overflow="true"`
+///
+/// ## Naming and Labels
+/// - [`with_namespace()`]: Prefixes the name of every metric
+/// - [`with_const_labels()`]: Adds labels to every series
+/// - [`with_resource_constant_labels()`]: Adds the resource attributes matching
+///   glob patterns as labels to every series, except the ones matching
+///   [`without_resource_constant_labels()`]
+///
+/// ## Metadata
+/// - [`with_metadata_override()`]: Overrides the description, unit or name of
+///   an instrument
+/// - [`with_generated_help()`]: Generates a description for the instruments
+///   without one
+///
+/// ## Label Limits
+/// - [`with_label_limit()`]: Leaves out the series with too many labels
+/// - [`with_label_name_length_limit()`] and
+///   [`with_label_value_length_limit()`]: Cut the label names and values longer
+///   than a number of bytes
+///
+/// ## Idle Series
+/// - [`with_idle_series_timeout()`] and [`with_idle_series_scrapes()`]: Leave
+///   out the series whose value stopped changing
+///
+/// ## Filtering
+/// - [`with_include()`]: Only renders the metrics matching one of the given
+///   [`MetricSelector`]s
+/// - [`with_exclude()`]: Leaves out the metrics matching the given
+///   [`MetricSelector`]
+///
+/// ## Relabeling
+/// - [`with_relabel_config()`]: Rewrites the labels of every series, like
+///   Prometheus `metric_relabel_configs`
+///
+/// ## Size Budget
+/// - [`with_size_budget()`]: Drops families to keep the exposition under a size
+///   in bytes
+/// - [`with_scope_priority()`] and [`with_name_priority()`]: Set which families
+///   are dropped first
+///
+/// ## Self-Observability
+/// - [`with_self_metrics()`]: Appends metrics about the exporter itself to the
+///   exposition
+///
+/// ## Collection Deadline
+/// - [`with_collect_timeout()`]: Bounds the collection time of
+///   [`PrometheusExporter::export_with_timeout`]
+/// - [`with_stale_on_timeout()`]: Serves the last successful exposition when
+///   the collection times out
+///
 /// # Example Usage
 ///
 /// ```rust
@@ -136,16 +875,71 @@ impl Default for PrometheusExporter {
 /// # }
 /// ```
 ///
+/// [`from_env()`]: ExporterBuilder::from_env
+/// [`with_host()`]: ExporterBuilder::with_host
+/// [`with_port()`]: ExporterBuilder::with_port
 /// [`without_units()`]: ExporterBuilder::without_units
 /// [`without_counter_suffixes()`]: ExporterBuilder::without_counter_suffixes
 /// [`without_target_info()`]: ExporterBuilder::without_target_info
 /// [`without_scope_info()`]: ExporterBuilder::without_scope_info
+/// [`with_units()`]: ExporterBuilder::with_units
+/// [`with_counter_suffixes()`]: ExporterBuilder::with_counter_suffixes
+/// [`with_target_info()`]: ExporterBuilder::with_target_info
+/// [`with_scope_info()`]: ExporterBuilder::with_scope_info
+/// [`with_temporality()`]: ExporterBuilder::with_temporality
+/// [`with_delta_conversion()`]: ExporterBuilder::with_delta_conversion
+/// [`with_producer()`]: ExporterBuilder::with_producer
+/// [`with_min_scrape_interval()`]: ExporterBuilder::with_min_scrape_interval
+/// [`with_collect_timeout()`]: ExporterBuilder::with_collect_timeout
+/// [`with_stale_on_timeout()`]: ExporterBuilder::with_stale_on_timeout
+/// [`with_self_metrics()`]: ExporterBuilder::with_self_metrics
+/// [`with_cardinality_limit()`]: ExporterBuilder::with_cardinality_limit
+/// [`with_metric_cardinality_limit()`]: ExporterBuilder::with_metric_cardinality_limit
+/// [`with_namespace()`]: ExporterBuilder::with_namespace
+/// [`with_const_labels()`]: ExporterBuilder::with_const_labels
+/// [`with_resource_constant_labels()`]: ExporterBuilder::with_resource_constant_labels
+/// [`without_resource_constant_labels()`]: ExporterBuilder::without_resource_constant_labels
+/// [`with_metadata_override()`]: ExporterBuilder::with_metadata_override
+/// [`with_generated_help()`]: ExporterBuilder::with_generated_help
+/// [`with_label_limit()`]: ExporterBuilder::with_label_limit
+/// [`with_label_name_length_limit()`]: ExporterBuilder::with_label_name_length_limit
+/// [`with_label_value_length_limit()`]: ExporterBuilder::with_label_value_length_limit
+/// [`with_idle_series_timeout()`]: ExporterBuilder::with_idle_series_timeout
+/// [`with_idle_series_scrapes()`]: ExporterBuilder::with_idle_series_scrapes
+/// [`with_include()`]: ExporterBuilder::with_include
+/// [`with_exclude()`]: ExporterBuilder::with_exclude
+/// [`with_relabel_config()`]: ExporterBuilder::with_relabel_config
+/// [`with_size_budget()`]: ExporterBuilder::with_size_budget
+/// [`with_scope_priority()`]: ExporterBuilder::with_scope_priority
+/// [`with_name_priority()`]: ExporterBuilder::with_name_priority
 #[derive(Default)]
 pub struct ExporterBuilder {
     disable_target_info: bool,
     without_units: bool,
     without_counter_suffixes: bool,
     disable_scope_info: bool,
+    temporality: Temporality,
+    delta_conversion: bool,
+    producers: Vec<Box<dyn MetricProducer>>,
+    min_scrape_interval: Duration,
+    collect_timeout: Option<Duration>,
+    stale_on_timeout: bool,
+    self_metrics: bool,
+    cardinality_limit: Option<usize>,
+    metric_cardinality_limits: HashMap<String, usize>,
+    filter: MetricFilter,
+    relabel: Vec<RelabelConfig>,
+    namespace: Option<String>,
+    const_labels: Vec<KeyValue>,
+    metadata_overrides: MetadataOverrides,
+    generate_help: bool,
+    label_limits: LabelLimits,
+    idle_timeout: IdleTimeout,
+    resource_labels: ResourceLabels,
+    size_budget: Option<usize>,
+    priorities: Vec<(PriorityRule, i32)>,
+    host: Option<String>,
+    port: Option<u16>,
     reader: ManualReaderBuilder,
 }
 
@@ -156,11 +950,94 @@ impl std::fmt::Debug for ExporterBuilder {
             .field("without_units", &self.without_units)
             .field("without_counter_suffixes", &self.without_counter_suffixes)
             .field("disable_scope_info", &self.disable_scope_info)
+            .field("temporality", &self.temporality)
+            .field("delta_conversion", &self.delta_conversion)
+            .field("producers", &self.producers)
+            .field("min_scrape_interval", &self.min_scrape_interval)
+            .field("collect_timeout", &self.collect_timeout)
+            .field("stale_on_timeout", &self.stale_on_timeout)
+            .field("self_metrics", &self.self_metrics)
+            .field("cardinality_limit", &self.cardinality_limit)
+            .field("metric_cardinality_limits", &self.metric_cardinality_limits)
+            .field("filter", &self.filter)
+            .field("relabel", &self.relabel)
+            .field("namespace", &self.namespace)
+            .field("const_labels", &self.const_labels)
+            .field("metadata_overrides", &self.metadata_overrides)
+            .field("generate_help", &self.generate_help)
+            .field("label_limits", &self.label_limits)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("resource_labels", &self.resource_labels)
+            .field("size_budget", &self.size_budget)
+            .field("priorities", &self.priorities)
+            .field("host", &self.host)
+            .field("port", &self.port)
             .finish_non_exhaustive()
     }
 }
 
 impl ExporterBuilder {
+    /// Creates a builder configured from the standard
+    /// `OTEL_EXPORTER_PROMETHEUS_*` environment variables.
+    ///
+    /// The following variables are read, and ignored when missing or empty:
+    ///
+    /// | Variable | Effect |
+    /// |----------|--------|
+    /// | `OTEL_EXPORTER_PROMETHEUS_HOST` | [`with_host()`](Self::with_host) |
+    /// | `OTEL_EXPORTER_PROMETHEUS_PORT` | [`with_port()`](Self::with_port) |
+    /// | `OTEL_EXPORTER_PROMETHEUS_WITHOUT_UNITS` | [`without_units()`](Self::without_units) when `true` |
+    /// | `OTEL_EXPORTER_PROMETHEUS_WITHOUT_TYPE_SUFFIX` | [`without_counter_suffixes()`](Self::without_counter_suffixes) when `true` |
+    /// | `OTEL_EXPORTER_PROMETHEUS_WITHOUT_SCOPE_INFO` | [`without_scope_info()`](Self::without_scope_info) when `true` |
+    /// | `OTEL_EXPORTER_PROMETHEUS_WITHOUT_TARGET_INFO` | [`without_target_info()`](Self::without_target_info) when `true` |
+    /// | `OTEL_EXPORTER_PROMETHEUS_TRANSLATION_STRATEGY` | `UnderscoreEscapingWithSuffixes`, or `UnderscoreEscapingWithoutSuffixes` for both [`without_units()`](Self::without_units) and [`without_counter_suffixes()`](Self::without_counter_suffixes) |
+    ///
+    /// Booleans are either `true` or `false`, in any case. The methods called
+    /// on the returned builder take precedence over the environment, like
+    /// [`with_units()`](Self::with_units) adding back the unit suffixes
+    /// disabled by `OTEL_EXPORTER_PROMETHEUS_WITHOUT_UNITS`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the variable if one has an invalid value,
+    /// like a port out of range, or a translation strategy keeping UTF-8
+    /// names, which the exporter doesn't support.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use opentelemetry_prometheus_text_exporter::ExporterBuilder;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let exporter = ExporterBuilder::from_env()?.with_self_metrics().build();
+    /// let listener = std::net::TcpListener::bind(exporter.listen_address());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_env() -> Result<Self, FromEnvError> {
+        crate::env::configure(Self::default(), std::env::var)
+    }
+
+    /// Sets the address the metrics are served on, `localhost` by default.
+    ///
+    /// The exporter doesn't serve the metrics itself: the address is returned
+    /// by [`PrometheusExporter::listen_address`] for the HTTP server.
+    #[must_use]
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Sets the port the metrics are served on, `9464` by default.
+    ///
+    /// Like [`with_host()`](Self::with_host), it is only returned by
+    /// [`PrometheusExporter::listen_address`].
+    #[must_use]
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
     /// Disables exporter's addition of unit suffixes to metric names.
     ///
     /// By default, metric names include a unit suffix to follow Prometheus
@@ -176,6 +1053,16 @@ impl ExporterBuilder {
         self
     }
 
+    /// Adds the unit suffixes to metric names, which is the default.
+    ///
+    /// This undoes [`without_units()`](Self::without_units), like when it is
+    /// set by [`from_env()`](Self::from_env).
+    #[must_use]
+    pub fn with_units(mut self) -> Self {
+        self.without_units = false;
+        self
+    }
+
     /// Disables exporter's addition `_total` suffixes on counters.
     ///
     /// By default, metric names include a `_total` suffix to follow Prometheus
@@ -188,6 +1075,17 @@ impl ExporterBuilder {
         self
     }
 
+    /// Adds the `_total` suffixes to counters, which is the default.
+    ///
+    /// This undoes
+    /// [`without_counter_suffixes()`](Self::without_counter_suffixes), like
+    /// when it is set by [`from_env()`](Self::from_env).
+    #[must_use]
+    pub fn with_counter_suffixes(mut self) -> Self {
+        self.without_counter_suffixes = false;
+        self
+    }
+
     /// Configures the exporter to not export the resource `target_info` metric.
     ///
     /// If not specified, the exporter will create a `target_info` metric
@@ -200,6 +1098,16 @@ impl ExporterBuilder {
         self
     }
 
+    /// Exports the resource `target_info` metric, which is the default.
+    ///
+    /// This undoes [`without_target_info()`](Self::without_target_info), like
+    /// when it is set by [`from_env()`](Self::from_env).
+    #[must_use]
+    pub fn with_target_info(mut self) -> Self {
+        self.disable_target_info = false;
+        self
+    }
+
     /// Configures the exporter to not export the `otel_scope_info` metric.
     ///
     /// If not specified, the exporter will create a `otel_scope_info` metric
@@ -211,20 +1119,507 @@ impl ExporterBuilder {
         self
     }
 
+    /// Exports the `otel_scope_info` metric and the scope labels, which is the
+    /// default.
+    ///
+    /// This undoes [`without_scope_info()`](Self::without_scope_info), like
+    /// when it is set by [`from_env()`](Self::from_env).
+    #[must_use]
+    pub fn with_scope_info(mut self) -> Self {
+        self.disable_scope_info = false;
+        self
+    }
+
+    /// Sets the temporality preference of the underlying reader.
+    ///
+    /// Prometheus expects cumulative values, so counters, observable counters
+    /// and histograms are always collected with cumulative temporality, unless
+    /// [`with_delta_conversion()`](Self::with_delta_conversion) is also set.
+    /// The preference still applies to the other instrument kinds, as far as
+    /// the SDK allows it.
+    #[must_use]
+    pub fn with_temporality(mut self, temporality: Temporality) -> Self {
+        self.temporality = temporality;
+        self
+    }
+
+    /// Lets the temporality preference apply to counters and histograms too.
+    ///
+    /// Only set this if delta values are converted to cumulative ones before
+    /// they reach Prometheus, for example by an aggregating proxy. The exporter
+    /// itself renders delta sums as gauges, and delta histograms as-is.
+    #[must_use]
+    pub fn with_delta_conversion(mut self) -> Self {
+        self.delta_conversion = true;
+        self
+    }
+
+    /// Registers an additional [`MetricProducer`].
+    ///
+    /// Producers are collected on every export, after the exporter's own
+    /// reader, and their metrics are rendered in the same exposition. This
+    /// can be called multiple times to register several producers.
+    ///
+    /// Metrics named like one of the reader or of another producer are
+    /// rendered in the same family, and left out if they have another type.
+    /// A resource shared with the reader is rendered once in `target_info`.
+    #[must_use]
+    pub fn with_producer(mut self, producer: impl MetricProducer) -> Self {
+        self.producers.push(Box::new(producer));
+        self
+    }
+
+    /// Sets the minimum interval between two collections made by
+    /// [`PrometheusExporter::export_cached`].
+    ///
+    /// Scrapes within that interval are served the same exposition, which
+    /// avoids collecting and serializing the same data several times when
+    /// multiple scrapers hit the same target. Concurrent scrapes always share
+    /// a single collection, even without an interval.
+    #[must_use]
+    pub fn with_min_scrape_interval(mut self, interval: Duration) -> Self {
+        self.min_scrape_interval = interval;
+        self
+    }
+
+    /// Sets the maximum time [`PrometheusExporter::export_with_timeout`] and
+    /// [`PrometheusExporter::export_cached`] wait for the metrics to be
+    /// collected.
+    ///
+    /// A slow observable instrument callback can otherwise block the export
+    /// until the scraper gives up.
+    #[must_use]
+    pub fn with_collect_timeout(mut self, timeout: Duration) -> Self {
+        self.collect_timeout = Some(timeout);
+        self
+    }
+
+    /// Configures [`PrometheusExporter::export_with_timeout`] and
+    /// [`PrometheusExporter::export_cached`] to serve the last successful
+    /// exposition, marked as [stale], when the collection times out.
+    ///
+    /// Without this, a timeout is returned as an error.
+    ///
+    /// [stale]: Exposition::is_stale
+    #[must_use]
+    pub fn with_stale_on_timeout(mut self) -> Self {
+        self.stale_on_timeout = true;
+        self
+    }
+
+    /// Appends metrics about the exporter itself after the user metrics.
+    ///
+    /// Those cover the duration of the scrape, split between collection and
+    /// serialization, the size of the exposition, the number of families and
+    /// series, metrics left out or named like another one of a different type,
+    /// and the number of failed exports by kind of error. They are all
+    /// prefixed with `otel_prometheus_exporter_`, for example
+    /// `otel_prometheus_exporter_scrape_duration_seconds`.
+    #[must_use]
+    pub fn with_self_metrics(mut self) -> Self {
+        self.self_metrics = true;
+        self
+    }
+
+    /// Caps the number of series rendered for each metric.
+    ///
+    /// When a metric has more data points than the limit, the series admitted
+    /// first are rendered as-is, and the newcomers are folded in a single
+    /// series labeled `otel_metric_overflow="true"`. The admitted series are
+    /// remembered between scrapes, so the same ones keep being rendered, and
+    /// a series which is gone makes room for a newcomer. The limit applies to
+    /// each resource separately, like the reader and the
+    /// [producers](Self::with_producer) rendering the same metric.
+    ///
+    /// The overflow series sums the values of sums, and the count, sum and
+    /// buckets of histograms, keeping only the bounds shared by all the
+    /// folded data points. It takes the last folded value of gauges, which
+    /// can't be summed. The overflow data point of the SDK, when it applies a
+    /// cardinality limit of its own, is merged in it.
+    ///
+    /// The limit includes the overflow series, so at most `limit` series are
+    /// rendered per metric. A limit of `0` is treated as `1`.
+    ///
+    /// With [`with_self_metrics()`](Self::with_self_metrics), the number of
+    /// folded series of each metric is reported in
+    /// `otel_prometheus_exporter_overflowed_series`.
+    #[must_use]
+    pub fn with_cardinality_limit(mut self, limit: usize) -> Self {
+        self.cardinality_limit = Some(limit);
+        self
+    }
+
+    /// Overrides the cardinality limit of a single metric, identified by its
+    /// instrument name, for example `http.server.request.duration`.
+    ///
+    /// This applies even without a global limit set with
+    /// [`with_cardinality_limit()`](Self::with_cardinality_limit).
+    #[must_use]
+    pub fn with_metric_cardinality_limit(mut self, name: impl Into<String>, limit: usize) -> Self {
+        self.metric_cardinality_limits.insert(name.into(), limit);
+        self
+    }
+
+    /// Prefixes the name of every metric with the given namespace.
+    ///
+    /// The namespace is sanitized and joined to the sanitized metric name with
+    /// an underscore, before the unit and `_total` suffixes are added. For
+    /// example, with the `myapp` namespace, the counter `http.requests` becomes
+    /// `myapp_http_requests_total`. It doesn't apply to `target_info`, the
+    /// scope labels or the exporter metrics.
+    #[must_use]
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Adds constant labels to every series, including `target_info`.
+    ///
+    /// The label names are sanitized like attribute names, and the labels are
+    /// written after the attributes and scope labels. When a constant label
+    /// has the same name as another label of a series, their values are
+    /// merged, separated by `;`. Collisions are only looked for when constant
+    /// or resource labels are added, so that other series are written as-is.
+    ///
+    /// This can be called multiple times to add more labels.
+    #[must_use]
+    pub fn with_const_labels(mut self, labels: impl IntoIterator<Item = KeyValue>) -> Self {
+        self.const_labels.extend(labels);
+        self
+    }
+
+    /// Adds the resource attributes with a key matching one of the given glob
+    /// patterns as labels to every series.
+    ///
+    /// Patterns apply to the attribute keys before they are sanitized, like
+    /// `service.*`, where `*` matches any sequence of characters and `?` a
+    /// single one. This can be called multiple times to add more patterns.
+    /// The attributes are still rendered in `target_info`, which doesn't get
+    /// them as labels.
+    ///
+    /// This helps when the resource attributes are needed to query the
+    /// metrics, without joining them with `target_info`.
+    #[must_use]
+    pub fn with_resource_constant_labels<I>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.resource_labels
+            .included
+            .extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Doesn't add the resource attributes with a key matching one of the
+    /// given glob patterns as labels, even if they match the patterns of
+    /// [`with_resource_constant_labels()`](Self::with_resource_constant_labels).
+    #[must_use]
+    pub fn without_resource_constant_labels<I>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.resource_labels
+            .excluded
+            .extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Overrides the description, unit or Prometheus name of an instrument.
+    ///
+    /// The override applies to the instruments with its name, from its
+    /// instrumentation scope if it has one, or from any scope otherwise. An
+    /// override replaces a previous one for the same instrument and scope.
+    /// See [`MetadataOverride`] for what can be overridden.
+    #[must_use]
+    pub fn with_metadata_override(mut self, metadata: MetadataOverride) -> Self {
+        self.metadata_overrides.insert(metadata);
+        self
+    }
+
+    /// Generates a `# HELP` line for the instruments without a description.
+    ///
+    /// By default, the `# HELP` line is left out when an instrument has an
+    /// empty description, which some linters complain about. With this option
+    /// set, the description is generated from the instrument name, for example
+    /// `OpenTelemetry instrument http.server.duration`.
+    #[must_use]
+    pub fn with_generated_help(mut self) -> Self {
+        self.generate_help = true;
+        self
+    }
+
+    /// Limits the number of labels of every series.
+    ///
+    /// Like the `label_limit` of a Prometheus scrape configuration, except
+    /// that instead of failing the scrape, the series with more labels are
+    /// left out. Every label counts, including the `le` label of histogram
+    /// buckets, the scope labels and the constant labels.
+    ///
+    /// Series left out because they don't fit in the label limits are counted
+    /// in the `otel_prometheus_exporter_label_limit_dropped_series_total`
+    /// exporter metric.
+    #[must_use]
+    pub fn with_label_limit(mut self, limit: usize) -> Self {
+        self.label_limits.labels = Some(limit);
+        self
+    }
+
+    /// Limits the length of label names, in bytes.
+    ///
+    /// Like the `label_name_length_limit` of a Prometheus scrape
+    /// configuration, except that instead of failing the scrape, longer names
+    /// are cut. The series with two label names which are the same once cut
+    /// are left out, and the `le` and scope labels are never cut.
+    ///
+    /// Series cut to fit in the label limits are counted in the
+    /// `otel_prometheus_exporter_truncated_series_total` exporter metric.
+    #[must_use]
+    pub fn with_label_name_length_limit(mut self, limit: usize) -> Self {
+        self.label_limits.name_length = Some(limit);
+        self
+    }
+
+    /// Limits the length of label values and metric names, in bytes.
+    ///
+    /// Like the `label_value_length_limit` of a Prometheus scrape
+    /// configuration, except that instead of failing the scrape, longer values
+    /// are cut on a character boundary and end with a hash of the full value
+    /// and `...`, within the limit. This keeps a long attribute, like a full
+    /// SQL statement, from being copied in every sample, while values sharing
+    /// the same beginning stay different series.
+    ///
+    /// Metric names are cut without the hash nor the marker so that they stay
+    /// valid, keeping the `_total` suffix of counters and leaving room for the
+    /// `_bucket` suffix of histograms.
+    #[must_use]
+    pub fn with_label_value_length_limit(mut self, limit: usize) -> Self {
+        self.label_limits.value_length = Some(limit);
+        self
+    }
+
+    /// Leaves out the series whose value didn't change for the given
+    /// duration.
+    ///
+    /// The SDK keeps exporting cumulative series forever, even when their
+    /// attributes are long gone, like the address of a pod which was replaced.
+    /// With this option set, the exporter remembers the last value of every
+    /// series between scrapes, and leaves out the ones which didn't change for
+    /// a while. A series is rendered again as soon as its value changes. The
+    /// same series of several resources, like the reader and a
+    /// [producer](Self::with_producer), are tracked separately.
+    ///
+    /// Histograms are left out when their count didn't change. Series are
+    /// left out after the [cardinality limit](Self::with_cardinality_limit) is
+    /// applied, and the exporter metrics count them in the number of series.
+    /// The number of series left out of a scrape is reported in the
+    /// `otel_prometheus_exporter_idle_series` exporter metric.
+    ///
+    /// Every export counts as a scrape, except the ones starting within the
+    /// [minimum scrape interval](Self::with_min_scrape_interval) of the
+    /// previous one, which count as the same scrape. Exports overlapping each
+    /// other keep the series they share.
+    ///
+    /// [`PrometheusExporter::export_filtered`] renders the idle series too, and
+    /// doesn't count as a scrape.
+    #[must_use]
+    pub fn with_idle_series_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout.duration = Some(timeout);
+        self
+    }
+
+    /// Leaves out the series whose value didn't change for the given number
+    /// of scrapes.
+    ///
+    /// This works like [`with_idle_series_timeout()`], counting scrapes
+    /// instead of time. When both are set, series are left out as soon as one
+    /// of them is reached.
+    ///
+    /// [`with_idle_series_timeout()`]: Self::with_idle_series_timeout
+    #[must_use]
+    pub fn with_idle_series_scrapes(mut self, scrapes: u32) -> Self {
+        self.idle_timeout.scrapes = Some(scrapes);
+        self
+    }
+
+    /// Only renders the metrics matching the given selector.
+    ///
+    /// This can be called multiple times, in which case the metrics matching
+    /// any of the selectors are rendered. Without any include rule, all the
+    /// metrics are rendered, except the ones matching an
+    /// [exclude rule](Self::with_exclude).
+    #[must_use]
+    pub fn with_include(mut self, selector: MetricSelector) -> Self {
+        self.filter.include.push(selector);
+        self
+    }
+
+    /// Leaves out the metrics matching the given selector.
+    ///
+    /// This can be called multiple times, in which case the metrics matching
+    /// any of the selectors are left out. Exclude rules take precedence over
+    /// [include rules](Self::with_include).
+    #[must_use]
+    pub fn with_exclude(mut self, selector: MetricSelector) -> Self {
+        self.filter.exclude.push(selector);
+        self
+    }
+
+    /// Adds a relabeling rule, applied to the labels of every series before it
+    /// is written.
+    ///
+    /// This works like Prometheus `metric_relabel_configs`: rules are applied
+    /// in the order they are added, and can rename series, rewrite or remove
+    /// labels, or drop series altogether. See [`RelabelConfig`] for the
+    /// supported actions.
+    ///
+    /// The metadata of a family is named after its first series left by the
+    /// rules, without its `_bucket`, `_sum` or `_count` suffix. Rules renaming
+    /// series should therefore rename all the series of a family the same way.
+    /// The [cardinality limit](Self::with_cardinality_limit) and the exporter
+    /// metrics count the series before relabeling.
+    #[must_use]
+    pub fn with_relabel_config(mut self, rule: RelabelConfig) -> Self {
+        self.relabel.push(rule);
+        self
+    }
+
+    /// Keeps the exposition under the given size, in bytes.
+    ///
+    /// Prometheus fails the whole scrape when the exposition is larger than
+    /// its `body_size_limit`. When the rendered families would go over the
+    /// budget, the ones with the lowest priority are left out, and a comment
+    /// naming them is added, along with the
+    /// `otel_prometheus_exporter_budget_dropped_families_total` counter. Both
+    /// are counted in the budget, but the exporter metrics enabled with
+    /// [`with_self_metrics()`](Self::with_self_metrics) are not: they are
+    /// written after the budgeted families, so the budget has to leave room
+    /// for them under the `body_size_limit`. The counter
+    /// keeps the 100 families dropped the most recently, and forgets the
+    /// oldest ones sooner if it doesn't fit in the budget anymore.
+    ///
+    /// Families are dropped whole, so the exposition stays valid. With a
+    /// budget, the async exports render all the families before writing
+    /// them.
+    #[must_use]
+    pub fn with_size_budget(mut self, bytes: usize) -> Self {
+        self.size_budget = Some(bytes);
+        self
+    }
+
+    /// Sets the priority of the families of the given instrumentation scope,
+    /// when dropping families to fit in the [size budget].
+    ///
+    /// Families have a priority of `0` by default, except `target_info` which
+    /// has the highest one, and the ones with the lowest priority are dropped
+    /// first. Among families of the same priority,
+    /// the last rendered ones are dropped first. When several rules match a
+    /// family, the first one registered applies.
+    ///
+    /// [size budget]: Self::with_size_budget
+    #[must_use]
+    pub fn with_scope_priority(mut self, scope: impl Into<String>, priority: i32) -> Self {
+        self.priorities
+            .push((PriorityRule::Scope(scope.into()), priority));
+        self
+    }
+
+    /// Sets the priority of the families with a name matching the given glob
+    /// pattern, when dropping families to fit in the [size budget].
+    ///
+    /// The pattern applies to the Prometheus name of the family, for example
+    /// `http_server_*`, where `*` matches any sequence of characters and `?`
+    /// a single one. See [`with_scope_priority()`](Self::with_scope_priority)
+    /// for how priorities apply.
+    ///
+    /// [size budget]: Self::with_size_budget
+    #[must_use]
+    pub fn with_name_priority(mut self, pattern: impl Into<String>, priority: i32) -> Self {
+        self.priorities
+            .push((PriorityRule::Name(pattern.into()), priority));
+        self
+    }
+
     /// Creates a new [`PrometheusExporter`] from this configuration.
     #[must_use]
     pub fn build(self) -> PrometheusExporter {
-        let inner = Arc::new(self.reader.build());
+        let config = self.serializer_config();
+        let serializer = PrometheusSerializer::with_config(config);
+        let budget = self.size_budget().map(Arc::new);
+        let inner = Arc::new(self.reader.with_temporality(self.temporality).build());
+
+        PrometheusExporter {
+            inner,
+            producers: self.producers.into(),
+            delta_conversion: self.delta_conversion,
+            serializer,
+            pool: Arc::default(),
+            cache: Arc::new(ScrapeCache::new(self.min_scrape_interval)),
+            collect_timeout: self.collect_timeout,
+            stale_on_timeout: self.stale_on_timeout,
+            deadline: Arc::default(),
+            self_metrics: self.self_metrics.then(Arc::default),
+            budget,
+            host: self.host.as_deref().unwrap_or(DEFAULT_HOST).into(),
+            port: self.port.unwrap_or(DEFAULT_PORT),
+        }
+    }
+
+    /// Creates a new [`OtlpGateway`] rendering the metrics pushed over OTLP
+    /// with the translation options of this configuration.
+    ///
+    /// The options about the collection of the metrics, the HTTP server and
+    /// the scrape handling don't apply.
+    ///
+    /// [`OtlpGateway`]: crate::OtlpGateway
+    #[cfg(feature = "otlp")]
+    #[must_use]
+    pub fn build_gateway(self) -> crate::OtlpGateway {
+        let serializer = PrometheusSerializer::with_config(self.serializer_config());
+        crate::OtlpGateway::with_serializer(serializer)
+    }
 
-        let config = ExporterConfig {
+    /// The size budget, if one is set
+    pub(crate) fn size_budget(&self) -> Option<SizeBudget> {
+        self.size_budget
+            .map(|limit| SizeBudget::new(limit, self.priorities.clone()))
+    }
+
+    /// The configuration of the serializer, from the translation options
+    pub(crate) fn serializer_config(&self) -> ExporterConfig {
+        ExporterConfig {
             disable_target_info: self.disable_target_info,
             without_units: self.without_units,
             without_counter_suffixes: self.without_counter_suffixes,
             disable_scope_info: self.disable_scope_info,
-        };
-
-        let serializer = PrometheusSerializer::with_config(config);
-
-        PrometheusExporter { inner, serializer }
+            cardinality_limit: self.cardinality_limit,
+            metric_cardinality_limits: self.metric_cardinality_limits.clone(),
+            filter: self.filter.clone(),
+            relabel: self.relabel.clone(),
+            namespace: self
+                .namespace
+                .as_deref()
+                .map(|namespace| sanitize_name(namespace).trim_end_matches('_').to_owned())
+                .filter(|namespace| !namespace.is_empty()),
+            const_labels: self
+                .const_labels
+                .iter()
+                .map(|kv| {
+                    (
+                        sanitize_name(kv.key.as_str()).into_owned(),
+                        kv.value.to_string(),
+                    )
+                })
+                .collect(),
+            metadata_overrides: self.metadata_overrides.clone(),
+            generate_help: self.generate_help,
+            label_limits: self.label_limits,
+            idle_timeout: IdleTimeout {
+                min_interval: self.min_scrape_interval,
+                ..self.idle_timeout
+            },
+            resource_labels: self.resource_labels.clone(),
+        }
     }
 }
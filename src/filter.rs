@@ -0,0 +1,252 @@
+use opentelemetry::InstrumentationScope;
+use opentelemetry_sdk::metrics::data::{AggregatedMetrics, MetricData};
+use regex::Regex;
+
+use crate::glob::glob_match;
+
+/// The type of instrument a metric comes from.
+///
+/// The SDK doesn't tell synchronous and observable instruments apart, so this
+/// is inferred from the aggregated data: an observable counter is a
+/// [`Counter`](Self::Counter), an observable gauge a [`Gauge`](Self::Gauge),
+/// and so on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstrumentType {
+    /// A monotonic sum, from a counter or an observable counter
+    Counter,
+
+    /// A non-monotonic sum, from an up-down counter or an observable one
+    UpDownCounter,
+
+    /// A gauge or an observable gauge
+    Gauge,
+
+    /// A histogram with explicit buckets
+    Histogram,
+
+    /// A histogram with exponential buckets
+    ExponentialHistogram,
+}
+
+impl InstrumentType {
+    pub(crate) fn of(data: &AggregatedMetrics) -> Self {
+        fn of_data<T>(data: &MetricData<T>) -> InstrumentType {
+            match data {
+                MetricData::Gauge(_) => InstrumentType::Gauge,
+                MetricData::Sum(sum) if sum.is_monotonic() => InstrumentType::Counter,
+                MetricData::Sum(_) => InstrumentType::UpDownCounter,
+                MetricData::Histogram(_) => InstrumentType::Histogram,
+                MetricData::ExponentialHistogram(_) => InstrumentType::ExponentialHistogram,
+            }
+        }
+
+        match data {
+            AggregatedMetrics::F64(data) => of_data(data),
+            AggregatedMetrics::U64(data) => of_data(data),
+            AggregatedMetrics::I64(data) => of_data(data),
+        }
+    }
+}
+
+/// A pattern matching the whole of a name
+#[derive(Debug, Clone)]
+enum Pattern {
+    Glob(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn regex(pattern: &str) -> Result<Self, regex::Error> {
+        // Like in Prometheus, the regex has to match the whole value
+        Regex::new(&format!("^(?:{pattern})$")).map(Self::Regex)
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Self::Glob(pattern) => glob_match(pattern, value),
+            Self::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+/// Selects metrics by name, instrumentation scope and instrument type.
+///
+/// A metric is selected if it matches all the criteria set on the selector. A
+/// selector without any criteria selects every metric.
+///
+/// Glob patterns match the whole value, `*` matching any sequence of
+/// characters and `?` a single one. Regular expressions also have to match the
+/// whole value, like in Prometheus.
+///
+/// # Example
+///
+/// ```rust
+/// use opentelemetry_prometheus_text_exporter::{InstrumentType, MetricSelector};
+///
+/// # fn main() -> Result<(), regex::Error> {
+/// // Histograms of the `hyper` scopes
+/// let selector = MetricSelector::new()
+///     .scope_name("hyper*")
+///     .instrument_type(InstrumentType::Histogram);
+///
+/// // Metrics exported as `http_server_*_seconds`
+/// let selector = MetricSelector::new().prometheus_name_regex("http_server_.*_seconds")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MetricSelector {
+    name: Option<Pattern>,
+    prometheus_name: Option<Pattern>,
+    scope_name: Option<Pattern>,
+    scope_version: Option<Pattern>,
+    instrument_type: Option<InstrumentType>,
+}
+
+impl MetricSelector {
+    /// Create a selector matching every metric
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Select metrics with an OpenTelemetry instrument name matching the given
+    /// glob pattern, for example `http.server.*`.
+    #[must_use]
+    pub fn name(mut self, pattern: impl Into<String>) -> Self {
+        self.name = Some(Pattern::Glob(pattern.into()));
+        self
+    }
+
+    /// Select metrics with an OpenTelemetry instrument name matching the given
+    /// regular expression.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the regular expression is invalid.
+    pub fn name_regex(mut self, regex: &str) -> Result<Self, regex::Error> {
+        self.name = Some(Pattern::regex(regex)?);
+        Ok(self)
+    }
+
+    /// Select metrics with a Prometheus name matching the given glob pattern,
+    /// for example `http_server_*_total`.
+    ///
+    /// The Prometheus name is the name of the rendered family, including the
+    /// unit and `_total` suffixes.
+    #[must_use]
+    pub fn prometheus_name(mut self, pattern: impl Into<String>) -> Self {
+        self.prometheus_name = Some(Pattern::Glob(pattern.into()));
+        self
+    }
+
+    /// Select metrics with a Prometheus name matching the given regular
+    /// expression.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the regular expression is invalid.
+    pub fn prometheus_name_regex(mut self, regex: &str) -> Result<Self, regex::Error> {
+        self.prometheus_name = Some(Pattern::regex(regex)?);
+        Ok(self)
+    }
+
+    /// Select metrics from an instrumentation scope with a name matching the
+    /// given glob pattern.
+    #[must_use]
+    pub fn scope_name(mut self, pattern: impl Into<String>) -> Self {
+        self.scope_name = Some(Pattern::Glob(pattern.into()));
+        self
+    }
+
+    /// Select metrics from an instrumentation scope with a version matching
+    /// the given glob pattern. Scopes without a version have an empty one.
+    #[must_use]
+    pub fn scope_version(mut self, pattern: impl Into<String>) -> Self {
+        self.scope_version = Some(Pattern::Glob(pattern.into()));
+        self
+    }
+
+    /// Select metrics from the given type of instrument.
+    #[must_use]
+    pub fn instrument_type(mut self, instrument_type: InstrumentType) -> Self {
+        self.instrument_type = Some(instrument_type);
+        self
+    }
+
+    /// Whether the metric with the given instrument name and type, rendered
+    /// with the given Prometheus name, is selected
+    pub(crate) fn matches(
+        &self,
+        name: &str,
+        instrument_type: InstrumentType,
+        scope: &InstrumentationScope,
+        prometheus_name: &str,
+    ) -> bool {
+        self.name
+            .as_ref()
+            .is_none_or(|pattern| pattern.matches(name))
+            && self
+                .prometheus_name
+                .as_ref()
+                .is_none_or(|pattern| pattern.matches(prometheus_name))
+            && self
+                .scope_name
+                .as_ref()
+                .is_none_or(|pattern| pattern.matches(scope.name()))
+            && self
+                .scope_version
+                .as_ref()
+                .is_none_or(|pattern| pattern.matches(scope.version().unwrap_or_default()))
+            && self
+                .instrument_type
+                .is_none_or(|expected| expected == instrument_type)
+    }
+}
+
+/// Include and exclude rules deciding which metrics are rendered
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MetricFilter {
+    pub include: Vec<MetricSelector>,
+    pub exclude: Vec<MetricSelector>,
+}
+
+impl MetricFilter {
+    /// Whether the metric is rendered: it has to match one of the include
+    /// rules, if there are any, and none of the exclude rules.
+    pub fn allows(
+        &self,
+        name: &str,
+        instrument_type: InstrumentType,
+        scope: &InstrumentationScope,
+        prometheus_name: &str,
+    ) -> bool {
+        let included = self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|selector| selector.matches(name, instrument_type, scope, prometheus_name));
+
+        included
+            && !self
+                .exclude
+                .iter()
+                .any(|selector| selector.matches(name, instrument_type, scope, prometheus_name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regex_matches_whole_value() {
+        let pattern = Pattern::regex("http_.*|rpc").unwrap();
+        assert!(pattern.matches("http_requests_total"));
+        assert!(pattern.matches("rpc"));
+        assert!(!pattern.matches("rpc_requests_total"));
+        assert!(!pattern.matches("my_http_requests_total"));
+
+        assert!(Pattern::regex("(unclosed").is_err());
+    }
+}
@@ -0,0 +1,326 @@
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use opentelemetry_proto::tonic::collector::metrics::v1::{
+    ExportMetricsPartialSuccess, ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+};
+use prost::Message as _;
+
+use crate::exporter::ExporterBuilder;
+use crate::otlp::{OtlpStore, decode_json};
+use crate::serialize::PrometheusSerializer;
+
+/// Resources which didn't push metrics for that long are forgotten, by default
+const DEFAULT_RESOURCE_TTL: Duration = Duration::from_mins(5);
+
+/// Renders the metrics pushed over OTLP in the Prometheus text format.
+///
+/// The gateway is meant for processes which can't be scraped, like short-lived
+/// jobs or sidecars: they push their metrics with an OTLP exporter, and the
+/// gateway keeps the latest state of every stream, by resource,
+/// instrumentation scope and attributes, until Prometheus scrapes it.
+///
+/// The metrics are translated like with the in-process exporter, using the
+/// naming and filtering options of the [`ExporterBuilder`] it was built with.
+/// Delta sums and histograms are accumulated, so they render as cumulative
+/// ones. Exponential histograms, summaries and delta histogram points whose
+/// bounds changed are not rendered, and are reported as rejected data points
+/// in the OTLP response.
+///
+/// A resource which didn't push metrics for some time, 5 minutes by default,
+/// is forgotten with all its series, like the series of an in-process exporter
+/// disappear with its process. Data points flagged as having no recorded value
+/// remove the series right away.
+///
+/// There is no HTTP server built in: the handler of `POST /v1/metrics` passes
+/// the request body to [`ingest()`](Self::ingest), and the handler of the
+/// scrape endpoint calls [`export()`](Self::export).
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// use opentelemetry_prometheus_text_exporter::ExporterBuilder;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let gateway = ExporterBuilder::default()
+///     .without_scope_info()
+///     .build_gateway()
+///     .with_resource_ttl(Duration::from_secs(60));
+///
+/// // In the handler of `POST /v1/metrics`
+/// # let body = br#"{"resourceMetrics":[]}"#;
+/// let response = gateway.ingest("application/json", body)?;
+///
+/// // In the handler of the scrape endpoint
+/// let mut output = Vec::new();
+/// gateway.export(&mut output)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct OtlpGateway {
+    serializer: PrometheusSerializer,
+    store: Arc<Mutex<OtlpStore>>,
+    resource_ttl: Duration,
+}
+
+impl Default for OtlpGateway {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OtlpGateway {
+    /// Create a gateway with the default translation options
+    #[must_use]
+    pub fn new() -> Self {
+        ExporterBuilder::default().build_gateway()
+    }
+
+    pub(crate) fn with_serializer(serializer: PrometheusSerializer) -> Self {
+        Self {
+            serializer,
+            store: Arc::default(),
+            resource_ttl: DEFAULT_RESOURCE_TTL,
+        }
+    }
+
+    /// Forget the resources which didn't push metrics for the given time,
+    /// instead of 5 minutes.
+    ///
+    /// This should be longer than the export interval of the OTLP exporters,
+    /// plus some margin for delayed pushes.
+    #[must_use]
+    pub fn with_resource_ttl(mut self, ttl: Duration) -> Self {
+        self.resource_ttl = ttl;
+        self
+    }
+
+    /// Merge the metrics of an OTLP/HTTP export request, with the given
+    /// `Content-Type`.
+    ///
+    /// Both `application/x-protobuf` and `application/json` payloads are
+    /// accepted. Returns the body of the response to send back, encoded like
+    /// the request.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the content type is not supported, which should be
+    /// answered with a `415 Unsupported Media Type` status, or if the payload
+    /// is invalid, which should be answered with a `400 Bad Request` status.
+    pub fn ingest(&self, content_type: &str, body: &[u8]) -> Result<Vec<u8>, IngestError> {
+        let media_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        match media_type.as_str() {
+            "application/x-protobuf" => {
+                let request = ExportMetricsServiceRequest::decode(body)
+                    .map_err(|error| IngestError::invalid_payload(&error))?;
+                Ok(self.ingest_request(request).encode_to_vec())
+            }
+            "application/json" => {
+                let request: ExportMetricsServiceRequest =
+                    decode_json(body).map_err(|error| IngestError::invalid_payload(&error))?;
+                let response = self.ingest_request(request);
+                // A fully successful response is an empty object, as unset
+                // fields are left out of the OTLP JSON encoding
+                if response.partial_success.is_none() {
+                    return Ok(b"{}".to_vec());
+                }
+                // The response only has plain fields, which always serialize
+                Ok(serde_json::to_vec(&response).unwrap_or_default())
+            }
+            _ => Err(IngestError {
+                kind: IngestErrorKind::UnsupportedContentType(content_type.to_owned()),
+            }),
+        }
+    }
+
+    /// Merge the metrics of a decoded OTLP export request, for example one
+    /// received by an OTLP/gRPC service
+    pub fn ingest_request(
+        &self,
+        request: ExportMetricsServiceRequest,
+    ) -> ExportMetricsServiceResponse {
+        let mut store = self.store.lock().unwrap_or_else(PoisonError::into_inner);
+        let rejected = store.ingest(request, Instant::now());
+
+        ExportMetricsServiceResponse {
+            partial_success: (rejected > 0).then(|| ExportMetricsPartialSuccess {
+                rejected_data_points: i64::try_from(rejected).unwrap_or(i64::MAX),
+                error_message: "exponential histograms, summaries, data points without a \
+                                value and delta histogram points whose bounds changed are not \
+                                supported"
+                    .to_owned(),
+            }),
+        }
+    }
+
+    /// Export the metrics pushed to the gateway to the given writer, after
+    /// forgetting the resources which didn't push metrics for too long.
+    ///
+    /// The metrics are rendered in a buffer first, so that a slow writer
+    /// doesn't block the pushes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    pub fn export<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut buffer = Vec::new();
+        {
+            let mut store = self.store.lock().unwrap_or_else(PoisonError::into_inner);
+            store.expire(self.resource_ttl, Instant::now());
+
+            let _scrape = self.serializer.start_scrape();
+            for family in self.serializer.merge_families(store.families()) {
+                self.serializer.serialize_family(&family, &mut buffer)?;
+            }
+        }
+
+        writer.write_all(&buffer)
+    }
+}
+
+/// Error returned by [`OtlpGateway::ingest`] when a request can't be decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IngestError {
+    kind: IngestErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum IngestErrorKind {
+    UnsupportedContentType(String),
+    InvalidPayload(String),
+}
+
+impl IngestError {
+    fn invalid_payload(error: &dyn std::error::Error) -> Self {
+        Self {
+            kind: IngestErrorKind::InvalidPayload(error.to_string()),
+        }
+    }
+
+    /// Whether the request has a content type other than protobuf or JSON
+    #[must_use]
+    pub fn is_unsupported_content_type(&self) -> bool {
+        matches!(self.kind, IngestErrorKind::UnsupportedContentType(_))
+    }
+}
+
+impl fmt::Display for IngestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            IngestErrorKind::UnsupportedContentType(content_type) => write!(
+                f,
+                "unsupported content type {content_type:?}, expected \
+                 `application/x-protobuf` or `application/json`"
+            ),
+            IngestErrorKind::InvalidPayload(message) => {
+                write!(f, "invalid OTLP metrics payload: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for IngestError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ingest_json() {
+        let gateway = ExporterBuilder::default().build_gateway();
+
+        // Written like the OTLP JSON encoding, with 64-bit integers as strings
+        let body = r#"{
+            "resourceMetrics": [{
+                "resource": {
+                    "attributes": [{"key": "service.name", "value": {"stringValue": "job"}}]
+                },
+                "scopeMetrics": [{
+                    "scope": {"name": "batch"},
+                    "metrics": [{
+                        "name": "rows.written",
+                        "sum": {
+                            "aggregationTemporality": 2,
+                            "isMonotonic": true,
+                            "dataPoints": [{
+                                "attributes": [{"key": "table", "value": {"stringValue": "users"}}],
+                                "timeUnixNano": "1700000000000000000",
+                                "asInt": "9007199254740993"
+                            }]
+                        }
+                    }]
+                }]
+            }]
+        }"#;
+        let response = gateway.ingest("application/json", body.as_bytes()).unwrap();
+        assert_eq!(response, b"{}");
+
+        let mut output = Vec::new();
+        gateway.export(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains(
+                "rows_written_total{table=\"users\",otel_scope_name=\"batch\"} 9007199254740993\n"
+            ),
+            "{output}"
+        );
+
+        let summary = r#"{"resourceMetrics": [{"scopeMetrics": [{"metrics": [{
+            "name": "latency",
+            "summary": {"dataPoints": [{}]}
+        }]}]}]}"#;
+        let response = gateway
+            .ingest("application/json", summary.as_bytes())
+            .unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(
+            response.starts_with(r#"{"partialSuccess":{"rejectedDataPoints":1,"#),
+            "{response}"
+        );
+    }
+    #[test]
+    fn test_export_releases_the_store_before_writing() {
+        /// Checks that the store isn't locked while it is written to
+        struct Writer<'a> {
+            gateway: &'a OtlpGateway,
+            written: usize,
+        }
+
+        impl Write for Writer<'_> {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                assert!(self.gateway.store.try_lock().is_ok());
+                self.written += buf.len();
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let gateway = ExporterBuilder::default().build_gateway();
+        let body = r#"{"resourceMetrics": [{"scopeMetrics": [{"metrics": [{
+            "name": "jobs",
+            "gauge": {"dataPoints": [{"asDouble": 1}]}
+        }]}]}]}"#;
+        gateway.ingest("application/json", body.as_bytes()).unwrap();
+
+        let mut writer = Writer {
+            gateway: &gateway,
+            written: 0,
+        };
+        gateway.export(&mut writer).unwrap();
+        assert!(writer.written > 0);
+    }
+}
@@ -0,0 +1,65 @@
+/// Matches a name against a glob pattern, where `*` matches any sequence of
+/// characters, including an empty one, and `?` matches a single character.
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` in the pattern, and of the name when it was met
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => {
+                // Let the last `*` match one more character, if there was one
+                let Some((star, matched)) = backtrack else {
+                    return false;
+                };
+                backtrack = Some((star, matched + 1));
+                p = star + 1;
+                n = matched + 1;
+            }
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("http_requests_total", "http_requests_total"));
+        assert!(!glob_match("http_requests_total", "http_requests"));
+        assert!(glob_match("http_*", "http_requests_total"));
+        assert!(glob_match("*_total", "http_requests_total"));
+        assert!(glob_match("*requests*", "http_requests_total"));
+        assert!(glob_match("http_*_total", "http_requests_total"));
+        assert!(!glob_match("http_*_total", "http_requests_seconds"));
+        assert!(glob_match("rpc_?", "rpc_a"));
+        assert!(!glob_match("rpc_?", "rpc_ab"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+    }
+
+    #[test]
+    fn test_glob_match_non_ascii() {
+        assert!(glob_match("caf?_total", "café_total"));
+        assert!(!glob_match("caf?_total", "cafe\u{301}_total"));
+        assert!(glob_match("??", "日本"));
+        assert!(!glob_match("???", "日本"));
+        assert!(glob_match("*本", "日本"));
+        assert!(glob_match("日*", "日本"));
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::{BTreeMap, HashMap, btree_map};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use opentelemetry::{InstrumentationScope, KeyValue};
+
+/// How long a series can keep the same value before it is left out
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct IdleTimeout {
+    /// The time since the value last changed
+    pub duration: Option<Duration>,
+
+    /// The number of scrapes since the value last changed
+    pub scrapes: Option<u32>,
+
+    /// Scrapes starting within this interval of the previous one count as
+    /// the same scrape, like the ones sharing a cached exposition
+    pub min_interval: Duration,
+}
+
+impl IdleTimeout {
+    pub fn is_set(&self) -> bool {
+        self.duration.is_some() || self.scrapes.is_some()
+    }
+}
+
+/// The last value seen for a series
+#[derive(Debug)]
+struct SeriesState {
+    value: u64,
+    changed_at: Instant,
+
+    /// The number of scrapes which saw the same value since it last changed
+    unchanged_scrapes: u32,
+
+    /// The last scrape which saw the series
+    scrape: u64,
+    idle: bool,
+}
+
+#[derive(Debug, Default)]
+struct IdleState {
+    /// The number of scrapes started
+    scrape: u64,
+
+    /// When the current scrape started
+    started_at: Option<Instant>,
+
+    /// The number of exports still rendering each scrape
+    in_flight: BTreeMap<u64, usize>,
+
+    series: HashMap<u64, SeriesState>,
+}
+
+/// Remembers the last value of every series between scrapes, to leave out the
+/// ones which stopped changing
+#[derive(Debug)]
+pub(crate) struct IdleSeries {
+    timeout: IdleTimeout,
+    state: Mutex<IdleState>,
+
+    /// The number of series left out of the last scrape
+    suppressed: AtomicU64,
+}
+
+impl IdleSeries {
+    pub fn new(timeout: IdleTimeout) -> Self {
+        Self {
+            timeout,
+            state: Mutex::default(),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Start a new scrape, forgetting the series which were not in the
+    /// previous one, or join the current one if it started within the
+    /// minimum interval.
+    ///
+    /// The scrape lasts until the returned token is dropped. The series of
+    /// the scrapes still in progress are kept, as they may not have been
+    /// seen yet.
+    pub fn start_scrape(&self) -> ScrapeToken<'_> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let joined = state
+            .started_at
+            .is_some_and(|started_at| now.duration_since(started_at) < self.timeout.min_interval);
+        if !joined {
+            let previous = state.scrape;
+            state.scrape += 1;
+            state.started_at = Some(now);
+
+            let oldest = state
+                .in_flight
+                .keys()
+                .next()
+                .map_or(previous, |&scrape| scrape.saturating_sub(1).min(previous));
+            state.series.retain(|_, series| series.scrape >= oldest);
+            self.suppressed.store(0, Ordering::Relaxed);
+        }
+
+        let scrape = state.scrape;
+        *state.in_flight.entry(scrape).or_default() += 1;
+        ScrapeToken { idle: self, scrape }
+    }
+
+    /// Record the value of a series in the current scrape, and tell whether
+    /// it should be left out because it didn't change for too long.
+    ///
+    /// The value only has to change when the series does, like the bits of a
+    /// float or the count of a histogram.
+    pub fn is_idle(&self, key: u64, value: u64) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let scrape = state.scrape;
+
+        let series = match state.series.entry(key) {
+            Entry::Vacant(entry) => {
+                entry.insert(SeriesState {
+                    value,
+                    changed_at: now,
+                    unchanged_scrapes: 0,
+                    scrape,
+                    idle: false,
+                });
+                return false;
+            }
+            Entry::Occupied(entry) => entry.into_mut(),
+        };
+
+        // The series may be rendered more than once in the same scrape
+        if series.scrape != scrape {
+            if series.value == value {
+                series.unchanged_scrapes = series.unchanged_scrapes.saturating_add(1);
+            } else {
+                series.value = value;
+                series.changed_at = now;
+                series.unchanged_scrapes = 0;
+            }
+
+            series.scrape = scrape;
+            series.idle = self
+                .timeout
+                .duration
+                .is_some_and(|duration| now.duration_since(series.changed_at) >= duration)
+                || self
+                    .timeout
+                    .scrapes
+                    .is_some_and(|scrapes| series.unchanged_scrapes >= scrapes);
+
+            if series.idle {
+                self.suppressed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        series.idle
+    }
+
+    /// The number of series left out of the current scrape so far
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed.load(Ordering::Relaxed)
+    }
+}
+
+/// A scrape in progress, which finishes when dropped
+#[derive(Debug)]
+pub(crate) struct ScrapeToken<'a> {
+    idle: &'a IdleSeries,
+    scrape: u64,
+}
+
+impl Drop for ScrapeToken<'_> {
+    fn drop(&mut self) {
+        let mut state = self
+            .idle
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let btree_map::Entry::Occupied(mut exports) = state.in_flight.entry(self.scrape) {
+            *exports.get_mut() -= 1;
+            if *exports.get() == 0 {
+                exports.remove();
+            }
+        }
+    }
+}
+
+/// Identify a series by its family name, its instrumentation scope, the key
+/// of its resource and its attributes
+pub(crate) fn series_key<'a>(
+    name: &str,
+    scope: &InstrumentationScope,
+    resource: u64,
+    attributes: impl Iterator<Item = &'a KeyValue>,
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    scope.hash(&mut hasher);
+    resource.hash(&mut hasher);
+    for attribute in attributes {
+        attribute.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_after_unchanged_scrapes() {
+        let idle = IdleSeries::new(IdleTimeout {
+            duration: None,
+            scrapes: Some(2),
+            min_interval: Duration::ZERO,
+        });
+
+        let scrape = |value| {
+            let _scrape = idle.start_scrape();
+            idle.is_idle(1, value)
+        };
+        assert!(!scrape(10));
+        assert!(!scrape(10));
+        assert!(scrape(10));
+        assert_eq!(idle.suppressed(), 1);
+
+        // It comes back as soon as it changes
+        assert!(!scrape(11));
+        assert_eq!(idle.suppressed(), 0);
+    }
+
+    #[test]
+    fn test_idle_after_duration() {
+        let idle = IdleSeries::new(IdleTimeout {
+            duration: Some(Duration::ZERO),
+            scrapes: None,
+            min_interval: Duration::ZERO,
+        });
+
+        idle.start_scrape();
+        assert!(!idle.is_idle(1, 10));
+        idle.start_scrape();
+        assert!(idle.is_idle(1, 10));
+    }
+
+    #[test]
+    fn test_forgets_series_missing_from_a_scrape() {
+        let idle = IdleSeries::new(IdleTimeout {
+            duration: None,
+            scrapes: Some(1),
+            min_interval: Duration::ZERO,
+        });
+
+        idle.start_scrape();
+        assert!(!idle.is_idle(1, 10));
+        idle.start_scrape();
+        idle.start_scrape();
+        // Seen as a new series
+        assert!(!idle.is_idle(1, 10));
+    }
+
+    #[test]
+    fn test_series_of_several_resources_are_tracked_separately() {
+        let idle = IdleSeries::new(IdleTimeout {
+            duration: None,
+            scrapes: Some(1),
+            min_interval: Duration::ZERO,
+        });
+        let scope = InstrumentationScope::builder("scope").build();
+        let attributes = [KeyValue::new("user", "a")];
+        let main = series_key("requests", &scope, 1, attributes.iter());
+        let plugin = series_key("requests", &scope, 2, attributes.iter());
+        assert_ne!(main, plugin);
+
+        drop(idle.start_scrape());
+        assert!(!idle.is_idle(main, 10));
+        assert!(!idle.is_idle(plugin, 10));
+
+        // The series of the plugin changing doesn't keep the one of the main
+        // resource alive
+        drop(idle.start_scrape());
+        assert!(idle.is_idle(main, 10));
+        assert!(!idle.is_idle(plugin, 11));
+    }
+
+    #[test]
+    fn test_overlapping_scrapes_keep_their_series() {
+        let idle = IdleSeries::new(IdleTimeout {
+            duration: None,
+            scrapes: Some(1),
+            min_interval: Duration::ZERO,
+        });
+
+        drop(idle.start_scrape());
+        assert!(!idle.is_idle(1, 10));
+        assert!(!idle.is_idle(2, 10));
+
+        // A slow export renders the first series, then another export starts
+        // and renders the second one before the slow export is done
+        let slow = idle.start_scrape();
+        assert!(idle.is_idle(1, 10));
+        drop(idle.start_scrape());
+        assert!(idle.is_idle(2, 10));
+
+        // The slow export still sees the second series
+        assert!(idle.is_idle(2, 10));
+        drop(slow);
+    }
+
+    #[test]
+    fn test_scrapes_within_the_interval_count_once() {
+        let idle = IdleSeries::new(IdleTimeout {
+            duration: None,
+            scrapes: Some(1),
+            min_interval: Duration::from_secs(30),
+        });
+
+        for _ in 0..3 {
+            let _scrape = idle.start_scrape();
+            assert!(!idle.is_idle(1, 10));
+        }
+    }
+}
@@ -1,5 +1,56 @@
 #![doc = include_str!("../README.md")]
 
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod budget;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod cache;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod cardinality;
+#[cfg(feature = "serde")]
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod config;
+#[cfg(feature = "otlp")]
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod convert;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod deadline;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod env;
 #[deny(
     clippy::all,
     clippy::pedantic,
@@ -11,6 +62,150 @@
     reason = "The configuration struct has many boolean fields, this is intentional"
 )]
 pub(crate) mod exporter;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod filter;
+#[cfg(feature = "otlp")]
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod gateway;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod glob;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod idle;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod limits;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod lint;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod metadata;
+#[cfg(feature = "otlp")]
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod otlp;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod parse;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod producer;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod registry;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod relabel;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod selector;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod self_metrics;
 pub(crate) mod serialize;
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod snapshot;
+#[cfg(feature = "testing")]
+#[deny(
+    clippy::all,
+    clippy::pedantic,
+    rustdoc::broken_intra_doc_links,
+    missing_docs
+)]
+pub(crate) mod testing;
 
+pub use self::cache::Exposition;
+#[cfg(feature = "serde")]
+pub use self::config::{IncludeExclude, PrometheusConfig, TranslationStrategy};
+#[cfg(feature = "otlp")]
+pub use self::convert::{DecodeError, OtlpMetrics};
+pub use self::deadline::parse_scrape_timeout_header;
+pub use self::env::FromEnvError;
 pub use self::exporter::{ExporterBuilder, PrometheusExporter};
+pub use self::filter::{InstrumentType, MetricSelector};
+#[cfg(feature = "otlp")]
+pub use self::gateway::{IngestError, OtlpGateway};
+pub use self::lint::{LintFinding, LintRule, Linter};
+pub use self::metadata::MetadataOverride;
+pub use self::parse::{
+    Exemplar, ExpositionFormat, ExpositionParser, MetricFamily, MetricType, ParseExpositionError,
+    Sample, parse_exposition,
+};
+pub use self::producer::MetricProducer;
+pub use self::registry::PrometheusRegistry;
+pub use self::relabel::RelabelConfig;
+pub use self::selector::{ParseSelectorError, SeriesSelector};
+pub use self::snapshot::{
+    Delta, HistogramDelta, HistogramQuery, SeriesDelta, SeriesId, SeriesQuery, Snapshot,
+    SnapshotDiff,
+};
+#[cfg(feature = "testing")]
+#[doc(hidden)]
+pub use self::testing::__assert_exposition_eq;
+#[cfg(feature = "testing")]
+pub use self::testing::normalize_exposition;
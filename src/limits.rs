@@ -0,0 +1,267 @@
+use std::borrow::Cow;
+
+/// Appended to the label values cut to fit in their length limit
+pub(crate) const TRUNCATION_MARKER: &str = "...";
+
+/// The length of the hash of the full value, written before the truncation
+/// marker so that values sharing the same beginning stay apart
+const HASH_LEN: usize = 8;
+
+/// The labels of a series once the limits are applied, with their names and
+/// values cut
+pub(crate) type LimitedLabels<'a> = Vec<(&'a str, Cow<'a, str>)>;
+
+/// Limits on the labels of every series, mirroring the `label_limit`,
+/// `label_name_length_limit` and `label_value_length_limit` of a Prometheus
+/// scrape configuration
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LabelLimits {
+    /// The maximum number of labels of a series
+    pub labels: Option<usize>,
+
+    /// The maximum length of a label name, in bytes
+    pub name_length: Option<usize>,
+
+    /// The maximum length of a label value, in bytes, which also applies to
+    /// metric names
+    pub value_length: Option<usize>,
+}
+
+impl LabelLimits {
+    /// Whether any limit is set
+    pub fn is_set(&self) -> bool {
+        self.labels.is_some() || self.name_length.is_some() || self.value_length.is_some()
+    }
+
+    /// Cut a metric name to fit in the value length limit.
+    ///
+    /// The given suffix, like the `_total` of counters, is kept at the end of
+    /// the name, and room is left for the suffixes appended to the series,
+    /// like the `_bucket` of histograms. Names don't get the truncation
+    /// marker, so that they stay valid, and keep at least one character
+    /// before the suffix.
+    pub fn metric_name<'a>(
+        &self,
+        name: Cow<'a, str>,
+        suffix: &str,
+        series_suffix_len: usize,
+    ) -> (Cow<'a, str>, bool) {
+        let Some(limit) = self.value_length else {
+            return (name, false);
+        };
+        if name.len() + series_suffix_len <= limit {
+            return (name, false);
+        }
+
+        let stem = name.strip_suffix(suffix).unwrap_or(&name);
+        let suffix = &name[stem.len()..];
+        let stem_limit = limit
+            .saturating_sub(suffix.len() + series_suffix_len)
+            .max(1);
+        let len = floor_char_boundary(stem, stem_limit);
+        if len == stem.len() {
+            return (name, false);
+        }
+
+        (Cow::Owned(format!("{}{suffix}", &stem[..len])), true)
+    }
+
+    /// Cut a label value to fit in the value length limit.
+    ///
+    /// A hash of the full value comes after the part which is kept, so that
+    /// values sharing the same beginning stay different, followed by the
+    /// truncation marker if there is room for it.
+    pub fn label_value<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        match self.value_length {
+            Some(limit) if value.len() > limit => {
+                let hash = format!("{:08x}", fnv1a(value));
+                if limit < HASH_LEN {
+                    return Cow::Owned(hash[..limit].to_owned());
+                }
+
+                let marker = if limit >= HASH_LEN + TRUNCATION_MARKER.len() {
+                    TRUNCATION_MARKER
+                } else {
+                    ""
+                };
+                let len = floor_char_boundary(value, limit - HASH_LEN - marker.len());
+                Cow::Owned(format!("{}{hash}{marker}", &value[..len]))
+            }
+            _ => Cow::Borrowed(value),
+        }
+    }
+
+    /// Apply the limits to the labels of a series.
+    ///
+    /// Returns [`None`] if the series doesn't fit in the limits, because it
+    /// has more labels than allowed or because two label names are the same
+    /// once cut. Otherwise, returns the labels with their names and values
+    /// cut, and whether some of them were. The `le` label of histogram
+    /// buckets and the scope labels are never cut.
+    pub fn apply<'a>(
+        &self,
+        labels: impl Iterator<Item = (&'a str, &'a str)>,
+    ) -> Option<(LimitedLabels<'a>, bool)> {
+        let mut limited: LimitedLabels<'a> = Vec::new();
+        let mut names_cut = false;
+        let mut values_cut = false;
+
+        for (name, value) in labels {
+            if self.labels.is_some_and(|limit| limited.len() >= limit) {
+                return None;
+            }
+
+            if is_protected(name) {
+                limited.push((name, Cow::Borrowed(value)));
+                continue;
+            }
+
+            let limited_name = match self.name_length {
+                Some(limit) => &name[..floor_char_boundary(name, limit)],
+                None => name,
+            };
+            let limited_value = self.label_value(value);
+            names_cut |= limited_name.len() != name.len();
+            values_cut |= limited_value != value;
+            limited.push((limited_name, limited_value));
+        }
+
+        if names_cut
+            && limited
+                .iter()
+                .enumerate()
+                .any(|(index, (name, _))| limited[..index].iter().any(|(other, _)| other == name))
+        {
+            return None;
+        }
+
+        Some((limited, names_cut || values_cut))
+    }
+}
+
+/// Whether the label is left as-is by the limits, as the series would be
+/// invalid or clash with each other without it
+fn is_protected(name: &str) -> bool {
+    name == "le" || name.starts_with("otel_scope_")
+}
+
+/// A hash of the value which stays the same across runs and platforms
+fn fnv1a(value: &str) -> u32 {
+    value.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// The largest length of at most `limit` bytes at which the string can be cut
+/// without splitting a character
+fn floor_char_boundary(value: &str, limit: usize) -> usize {
+    if limit >= value.len() {
+        return value.len();
+    }
+
+    (0..=limit)
+        .rev()
+        .find(|&index| value.is_char_boundary(index))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_value_is_cut_with_a_hash() {
+        let limits = LabelLimits {
+            value_length: Some(16),
+            ..LabelLimits::default()
+        };
+
+        assert_eq!(limits.label_value("short"), "short");
+        assert_eq!(limits.label_value("exactly 16 bytes"), "exactly 16 bytes");
+
+        // Values sharing the same beginning stay apart
+        let first = limits.label_value("SELECT * FROM users");
+        let second = limits.label_value("SELECT * FROM orders");
+        assert_eq!(first.len(), 16);
+        assert!(first.starts_with("SELEC") && first.ends_with("..."));
+        assert_ne!(first, second);
+
+        // `é` takes two bytes, and can't be split
+        assert!(limits.label_value("ééééééé").starts_with("éé"));
+
+        let limits = LabelLimits {
+            value_length: Some(4),
+            ..LabelLimits::default()
+        };
+        assert_eq!(limits.label_value("abcde").len(), 4);
+        assert_ne!(limits.label_value("abcde"), limits.label_value("abcdf"));
+    }
+
+    #[test]
+    fn test_metric_name_keeps_the_suffix() {
+        let limits = LabelLimits {
+            value_length: Some(16),
+            ..LabelLimits::default()
+        };
+
+        let (name, truncated) =
+            limits.metric_name(Cow::Borrowed("http_duration_seconds"), "", "_bucket".len());
+        assert_eq!(name, "http_dura");
+        assert!(truncated);
+
+        let (name, truncated) =
+            limits.metric_name(Cow::Borrowed("requests_count_total"), "_total", 0);
+        assert_eq!(name, "requests_c_total");
+        assert!(truncated);
+
+        let (name, truncated) = limits.metric_name(Cow::Borrowed("http_requests"), "", 0);
+        assert_eq!(name, "http_requests");
+        assert!(!truncated);
+    }
+
+    #[test]
+    fn test_series_over_the_label_limit_are_left_out() {
+        let limits = LabelLimits {
+            labels: Some(2),
+            ..LabelLimits::default()
+        };
+
+        let labels = [("method", "GET"), ("le", "1")];
+        assert!(limits.apply(labels.into_iter()).is_some());
+
+        let labels = [("method", "GET"), ("path", "/"), ("le", "1")];
+        assert!(limits.apply(labels.into_iter()).is_none());
+    }
+
+    #[test]
+    fn test_protected_labels_are_not_cut() {
+        let limits = LabelLimits {
+            name_length: Some(4),
+            value_length: Some(1),
+            ..LabelLimits::default()
+        };
+
+        let labels = [("le", "+Inf"), ("otel_scope_name", "db"), ("method", "GET")];
+        let (limited, truncated) = limits.apply(labels.into_iter()).unwrap();
+        assert_eq!(limited[0], ("le", Cow::Borrowed("+Inf")));
+        assert_eq!(limited[1], ("otel_scope_name", Cow::Borrowed("db")));
+        assert_eq!(limited[2].0, "meth");
+        assert!(truncated);
+    }
+
+    #[test]
+    fn test_series_with_colliding_names_are_left_out() {
+        let limits = LabelLimits {
+            name_length: Some(4),
+            ..LabelLimits::default()
+        };
+
+        let labels = [("host_1", "a"), ("host_2", "b")];
+        assert!(limits.apply(labels.into_iter()).is_none());
+
+        let labels = [("host", "a"), ("port", "80")];
+        let (limited, truncated) = limits.apply(labels.into_iter()).unwrap();
+        assert_eq!(limited.len(), 2);
+        assert!(!truncated);
+    }
+}
@@ -0,0 +1,517 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io::BufRead;
+
+use crate::parse::{
+    ExpositionFormat, ExpositionParser, MetricFamily, MetricType, ParseExpositionError,
+};
+
+/// Units which have a base unit Prometheus prefers, with that base unit
+const NON_BASE_UNITS: &[(&str, &str)] = &[
+    ("nanoseconds", "seconds"),
+    ("microseconds", "seconds"),
+    ("milliseconds", "seconds"),
+    ("minutes", "seconds"),
+    ("hours", "seconds"),
+    ("days", "seconds"),
+    ("bits", "bytes"),
+    ("kilobytes", "bytes"),
+    ("megabytes", "bytes"),
+    ("gigabytes", "bytes"),
+    ("kibibytes", "bytes"),
+    ("mebibytes", "bytes"),
+    ("gibibytes", "bytes"),
+    ("fahrenheit", "celsius"),
+    ("percent", "ratio"),
+];
+
+/// The suffixes of the samples of histograms and summaries
+const RESERVED_SUFFIXES: &[&str] = &["_bucket", "_count", "_sum"];
+
+/// A rule checked by the [`Linter`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LintRule {
+    /// Counters end with `_total`
+    CounterSuffix,
+
+    /// Only counters end with `_total`
+    TotalSuffix,
+
+    /// Only histograms and summaries end with `_bucket`, `_count` or `_sum`
+    ReservedSuffix,
+
+    /// Names use base units, like seconds instead of milliseconds
+    BaseUnit,
+
+    /// Families have a non-empty `HELP` text
+    MissingHelp,
+
+    /// Samples don't have an `le` label outside of histograms, a `quantile`
+    /// label outside of summaries, or labels starting with `__`
+    ReservedLabel,
+
+    /// Metric and label names are in `snake_case`, not `camelCase`
+    SnakeCase,
+
+    /// Families don't have more series than the budget set with
+    /// [`Linter::with_series_budget`]
+    SeriesBudget,
+}
+
+impl LintRule {
+    /// All the rules, in the order their findings are reported
+    pub const ALL: [Self; 8] = [
+        Self::CounterSuffix,
+        Self::TotalSuffix,
+        Self::ReservedSuffix,
+        Self::BaseUnit,
+        Self::MissingHelp,
+        Self::ReservedLabel,
+        Self::SnakeCase,
+        Self::SeriesBudget,
+    ];
+
+    /// The name of the rule, like `counter-suffix`
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::CounterSuffix => "counter-suffix",
+            Self::TotalSuffix => "total-suffix",
+            Self::ReservedSuffix => "reserved-suffix",
+            Self::BaseUnit => "base-unit",
+            Self::MissingHelp => "missing-help",
+            Self::ReservedLabel => "reserved-label",
+            Self::SnakeCase => "snake-case",
+            Self::SeriesBudget => "series-budget",
+        }
+    }
+
+    /// The rule with the given name, if any
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|rule| rule.name() == name)
+    }
+}
+
+impl fmt::Display for LintRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A problem found by the [`Linter`] in a metric family
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintFinding {
+    rule: LintRule,
+    family: String,
+    message: String,
+}
+
+impl LintFinding {
+    /// The rule which found the problem
+    #[must_use]
+    pub fn rule(&self) -> LintRule {
+        self.rule
+    }
+
+    /// The name of the family with the problem
+    #[must_use]
+    pub fn family(&self) -> &str {
+        &self.family
+    }
+
+    /// A description of the problem
+    #[must_use]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} ({})", self.family, self.message, self.rule)
+    }
+}
+
+/// Checks the naming conventions of Prometheus metrics, like
+/// `promtool check metrics` does.
+///
+/// The linter runs on parsed metric families, or directly on an exposition,
+/// like the output of the exporter, and reports the problems it finds as
+/// [`LintFinding`]s, so that CI can fail when instrumentation regresses. All
+/// the [rules](LintRule) are enabled by default, except the series budget,
+/// which needs a limit.
+///
+/// # Example
+///
+/// ```rust
+/// use opentelemetry_prometheus_text_exporter::{ExpositionFormat, LintRule, Linter};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let exposition = "# HELP duration_milliseconds Request duration\nduration_milliseconds 12\n";
+///
+/// let linter = Linter::new().with_series_budget(100);
+/// let findings = linter.lint_exposition(exposition.as_bytes(), ExpositionFormat::Text)?;
+/// assert_eq!(findings.len(), 1);
+/// assert_eq!(findings[0].rule(), LintRule::BaseUnit);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Linter {
+    disabled: HashSet<LintRule>,
+    series_budget: Option<usize>,
+}
+
+impl Linter {
+    /// Create a linter with all the rules enabled
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Disable a rule
+    #[must_use]
+    pub fn without_rule(mut self, rule: LintRule) -> Self {
+        self.disabled.insert(rule);
+        self
+    }
+
+    /// Report the families with more series than the given budget
+    ///
+    /// The buckets, sum and count of a histogram or summary with the same
+    /// labels are a single series.
+    #[must_use]
+    pub fn with_series_budget(mut self, budget: usize) -> Self {
+        self.series_budget = Some(budget);
+        self
+    }
+
+    /// Check the given metric families
+    #[must_use]
+    pub fn lint(&self, families: &[MetricFamily]) -> Vec<LintFinding> {
+        let mut findings = Vec::new();
+        for family in families {
+            self.lint_family(family, &mut findings);
+        }
+        findings
+    }
+
+    /// Parse an exposition in the given format and check its families, one
+    /// at a time
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the exposition is invalid.
+    pub fn lint_exposition<R: BufRead>(
+        &self,
+        reader: R,
+        format: ExpositionFormat,
+    ) -> Result<Vec<LintFinding>, ParseExpositionError> {
+        let mut findings = Vec::new();
+        for family in ExpositionParser::new(reader, format) {
+            self.lint_family(&family?, &mut findings);
+        }
+        Ok(findings)
+    }
+
+    fn lint_family(&self, family: &MetricFamily, findings: &mut Vec<LintFinding>) {
+        for rule in LintRule::ALL {
+            if self.disabled.contains(&rule) {
+                continue;
+            }
+
+            let mut report = |message: String| {
+                findings.push(LintFinding {
+                    rule,
+                    family: family.name.clone(),
+                    message,
+                });
+            };
+
+            match rule {
+                LintRule::CounterSuffix => check_counter_suffix(family, &mut report),
+                LintRule::TotalSuffix => {
+                    if family.metric_type != MetricType::Counter && family.name.ends_with("_total")
+                    {
+                        report("only counters should end with `_total`".to_owned());
+                    }
+                }
+                LintRule::ReservedSuffix => check_reserved_suffix(family, &mut report),
+                LintRule::BaseUnit => check_base_unit(family, &mut report),
+                LintRule::MissingHelp => {
+                    if family.help.as_deref().is_none_or(str::is_empty) {
+                        report("no help text".to_owned());
+                    }
+                }
+                LintRule::ReservedLabel => check_reserved_labels(family, &mut report),
+                LintRule::SnakeCase => check_snake_case(family, &mut report),
+                LintRule::SeriesBudget => {
+                    if let Some(budget) = self.series_budget {
+                        let series = count_series(family);
+                        if series > budget {
+                            report(format!("{series} series, over the budget of {budget}"));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn check_counter_suffix(family: &MetricFamily, report: &mut impl FnMut(String)) {
+    if family.metric_type != MetricType::Counter {
+        return;
+    }
+
+    // In OpenMetrics, the family name doesn't have the suffix, but the
+    // samples always do
+    let missing = family
+        .samples
+        .iter()
+        .any(|sample| !sample.name.ends_with("_total") && !sample.name.ends_with("_created"));
+    if missing || (family.samples.is_empty() && !family.name.ends_with("_total")) {
+        report("counters should end with `_total`".to_owned());
+    }
+}
+
+fn check_reserved_suffix(family: &MetricFamily, report: &mut impl FnMut(String)) {
+    if matches!(
+        family.metric_type,
+        MetricType::Histogram | MetricType::GaugeHistogram | MetricType::Summary
+    ) {
+        return;
+    }
+
+    if let Some(suffix) = RESERVED_SUFFIXES
+        .iter()
+        .find(|suffix| family.name.ends_with(*suffix))
+    {
+        report(format!(
+            "only histograms and summaries should end with `{suffix}`"
+        ));
+    }
+}
+
+fn check_base_unit(family: &MetricFamily, report: &mut impl FnMut(String)) {
+    let words = family
+        .name
+        .split('_')
+        .chain(family.unit.as_deref())
+        .collect::<HashSet<_>>();
+
+    for (unit, base) in NON_BASE_UNITS {
+        if words.contains(unit) {
+            report(format!("use the base unit `{base}` instead of `{unit}`"));
+        }
+    }
+}
+
+fn check_reserved_labels(family: &MetricFamily, report: &mut impl FnMut(String)) {
+    let histogram = matches!(
+        family.metric_type,
+        MetricType::Histogram | MetricType::GaugeHistogram
+    );
+    let summary = family.metric_type == MetricType::Summary;
+
+    let mut reported = HashSet::new();
+    for sample in &family.samples {
+        for (label, _) in &sample.labels {
+            let reserved = (label == "le" && !histogram)
+                || (label == "quantile" && !summary)
+                || label.starts_with("__");
+            if reserved && reported.insert(label.as_str()) {
+                report(format!("the label `{label}` is reserved"));
+            }
+        }
+    }
+}
+
+/// Count the series of the family, where the samples of a histogram or a
+/// summary with the same labels but their `le` or `quantile` label are a
+/// single series, whatever their suffix.
+fn count_series(family: &MetricFamily) -> usize {
+    let bucket_label = match family.metric_type {
+        MetricType::Histogram | MetricType::GaugeHistogram => Some("le"),
+        MetricType::Summary => Some("quantile"),
+        _ => None,
+    };
+
+    family
+        .samples
+        .iter()
+        .map(|sample| {
+            let mut labels: Vec<_> = sample
+                .labels
+                .iter()
+                .filter(|(name, _)| Some(name.as_str()) != bucket_label)
+                .collect();
+            labels.sort_unstable();
+            labels
+        })
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+fn check_snake_case(family: &MetricFamily, report: &mut impl FnMut(String)) {
+    let is_camel_case = |name: &str| name.chars().any(|c| c.is_ascii_uppercase());
+
+    if is_camel_case(&family.name) {
+        report("the metric name should be in `snake_case`".to_owned());
+    }
+
+    let mut reported = HashSet::new();
+    for sample in &family.samples {
+        for (label, _) in &sample.labels {
+            if is_camel_case(label) && reported.insert(label.as_str()) {
+                report(format!("the label `{label}` should be in `snake_case`"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint(linter: &Linter, exposition: &str) -> Vec<(LintRule, String)> {
+        linter
+            .lint_exposition(exposition.as_bytes(), ExpositionFormat::Text)
+            .unwrap()
+            .into_iter()
+            .map(|finding| (finding.rule(), finding.family().to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        let exposition = "\
+# HELP jobs Jobs processed
+# TYPE jobs counter
+jobs 3
+# HELP queue_total Jobs in the queue
+# TYPE queue_total gauge
+queue_total 2
+# HELP latency_count Latency
+# TYPE latency_count gauge
+latency_count 1
+# HELP latency_milliseconds Latency
+# TYPE latency_milliseconds histogram
+latency_milliseconds_bucket{le=\"+Inf\"} 1
+latency_milliseconds_sum 1
+latency_milliseconds_count 1
+# TYPE up gauge
+up 1
+# HELP free Free memory
+# TYPE free gauge
+# UNIT free kilobytes
+free{le=\"1\",__meta=\"a\"} 1
+# HELP requestsInFlight Requests
+# TYPE requestsInFlight gauge
+requestsInFlight{httpMethod=\"GET\"} 1
+requestsInFlight{httpMethod=\"POST\"} 1
+";
+
+        assert_eq!(
+            lint(&Linter::new().with_series_budget(1), exposition),
+            vec![
+                (LintRule::CounterSuffix, "jobs".to_owned()),
+                (LintRule::TotalSuffix, "queue_total".to_owned()),
+                (LintRule::ReservedSuffix, "latency_count".to_owned()),
+                (LintRule::BaseUnit, "latency_milliseconds".to_owned()),
+                (LintRule::MissingHelp, "up".to_owned()),
+                (LintRule::BaseUnit, "free".to_owned()),
+                (LintRule::ReservedLabel, "free".to_owned()),
+                (LintRule::ReservedLabel, "free".to_owned()),
+                (LintRule::SnakeCase, "requestsInFlight".to_owned()),
+                (LintRule::SnakeCase, "requestsInFlight".to_owned()),
+                (LintRule::SeriesBudget, "requestsInFlight".to_owned()),
+            ]
+        );
+
+        // Without a budget, and with rules disabled
+        let linter = Linter::new()
+            .without_rule(LintRule::SnakeCase)
+            .without_rule(LintRule::ReservedLabel);
+        let findings = lint(&linter, exposition);
+        assert_eq!(findings.len(), 6);
+        assert!(findings.iter().all(|(rule, _)| !matches!(
+            rule,
+            LintRule::SnakeCase | LintRule::ReservedLabel | LintRule::SeriesBudget
+        )));
+    }
+
+    #[test]
+    fn test_series_budget() {
+        let exposition = "\
+# HELP latency_seconds Latency
+# TYPE latency_seconds histogram
+latency_seconds_bucket{method=\"GET\",le=\"0.1\"} 1
+latency_seconds_bucket{method=\"GET\",le=\"+Inf\"} 1
+latency_seconds_sum{method=\"GET\"} 0.05
+latency_seconds_count{method=\"GET\"} 1
+latency_seconds_bucket{le=\"+Inf\",method=\"POST\"} 1
+latency_seconds_sum{method=\"POST\"} 0.2
+latency_seconds_count{method=\"POST\"} 1
+# HELP rpc_seconds Latency
+# TYPE rpc_seconds summary
+rpc_seconds{quantile=\"0.5\"} 0.1
+rpc_seconds{quantile=\"0.9\"} 0.2
+rpc_seconds_sum 1
+rpc_seconds_count 5
+# HELP temperature_celsius Temperature
+# TYPE temperature_celsius gauge
+temperature_celsius{le=\"1\"} 1
+temperature_celsius{le=\"2\"} 2
+";
+
+        let linter = Linter::new()
+            .without_rule(LintRule::ReservedLabel)
+            .with_series_budget(1);
+        assert_eq!(
+            lint(&linter, exposition),
+            vec![
+                (LintRule::SeriesBudget, "latency_seconds".to_owned()),
+                (LintRule::SeriesBudget, "temperature_celsius".to_owned()),
+            ]
+        );
+        assert!(lint(&linter.with_series_budget(2), exposition).is_empty());
+    }
+
+    #[test]
+    fn test_open_metrics_counters() {
+        let exposition = "\
+# TYPE jobs counter
+# HELP jobs Jobs processed
+jobs_total 3
+# EOF
+";
+        let findings = Linter::new()
+            .lint_exposition(exposition.as_bytes(), ExpositionFormat::OpenMetrics)
+            .unwrap();
+        assert_eq!(findings, Vec::new());
+    }
+
+    #[test]
+    fn test_finding_display() {
+        let findings = Linter::new()
+            .lint_exposition(
+                &b"# HELP up Up\nup{__name=\"a\"} 1\n"[..],
+                ExpositionFormat::Text,
+            )
+            .unwrap();
+        assert_eq!(
+            findings[0].to_string(),
+            "up: the label `__name` is reserved (reserved-label)"
+        );
+        assert_eq!(findings[0].message(), "the label `__name` is reserved");
+    }
+
+    #[test]
+    fn test_rule_names() {
+        for rule in LintRule::ALL {
+            assert_eq!(LintRule::from_name(rule.name()), Some(rule));
+        }
+        assert_eq!(LintRule::from_name("unknown"), None);
+    }
+}
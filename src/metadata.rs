@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+/// Overrides the metadata of an instrument: its description, unit, or
+/// Prometheus name.
+///
+/// This helps with instruments of third-party libraries having an empty
+/// description, a wrong unit or an awkward name. Overrides apply to the
+/// instruments with the given name, from any instrumentation scope unless one
+/// is set with [`scope()`](Self::scope).
+///
+/// # Example
+///
+/// ```rust
+/// use opentelemetry_prometheus_text_exporter::MetadataOverride;
+///
+/// // The library records seconds, but declares milliseconds
+/// let fix = MetadataOverride::new("db.query.duration")
+///     .scope("legacy-db")
+///     .unit("s")
+///     .description("Duration of the database queries");
+/// ```
+#[derive(Debug, Clone)]
+pub struct MetadataOverride {
+    instrument: String,
+    scope: Option<String>,
+    name: Option<String>,
+    description: Option<String>,
+    unit: Option<String>,
+}
+
+impl MetadataOverride {
+    /// Create an override for the instruments with the given name
+    #[must_use]
+    pub fn new(instrument: impl Into<String>) -> Self {
+        Self {
+            instrument: instrument.into(),
+            scope: None,
+            name: None,
+            description: None,
+            unit: None,
+        }
+    }
+
+    /// Only override the instrument of the instrumentation scope with this
+    /// name.
+    ///
+    /// An override for a specific scope takes precedence over one for any
+    /// scope.
+    #[must_use]
+    pub fn scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    /// Set the Prometheus name of the family.
+    ///
+    /// The name is sanitized, but used as is otherwise: the namespace, the
+    /// unit suffix and the `_total` suffix are not added to it.
+    #[must_use]
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Set the description written in the `# HELP` line
+    #[must_use]
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Set the unit of the instrument, in the `OpenTelemetry` notation like
+    /// `s` or `By`.
+    ///
+    /// It is converted like the unit of any instrument, for the name suffix
+    /// and the `# UNIT` line, unless units are disabled.
+    #[must_use]
+    pub fn unit(mut self, unit: impl Into<String>) -> Self {
+        self.unit = Some(unit.into());
+        self
+    }
+
+    /// The Prometheus name of the family, if overridden
+    pub(crate) fn prometheus_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The description of the instrument, if overridden
+    pub(crate) fn description_override(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// The unit of the instrument, if overridden
+    pub(crate) fn unit_override(&self) -> Option<&str> {
+        self.unit.as_deref()
+    }
+}
+
+/// The metadata overrides, by instrument name
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MetadataOverrides {
+    by_instrument: HashMap<String, Vec<MetadataOverride>>,
+}
+
+impl MetadataOverrides {
+    /// Add an override, replacing the one for the same instrument and scope
+    pub fn insert(&mut self, metadata: MetadataOverride) {
+        let overrides = self
+            .by_instrument
+            .entry(metadata.instrument.clone())
+            .or_default();
+        overrides.retain(|existing| existing.scope != metadata.scope);
+        overrides.push(metadata);
+    }
+
+    /// The override applying to the given instrument of the given scope
+    pub fn get(&self, instrument: &str, scope: &str) -> Option<&MetadataOverride> {
+        let overrides = self.by_instrument.get(instrument)?;
+        overrides
+            .iter()
+            .find(|metadata| metadata.scope.as_deref() == Some(scope))
+            .or_else(|| overrides.iter().find(|metadata| metadata.scope.is_none()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scoped_override_takes_precedence() {
+        let mut overrides = MetadataOverrides::default();
+        overrides.insert(MetadataOverride::new("db.duration").unit("ms"));
+        overrides.insert(
+            MetadataOverride::new("db.duration")
+                .scope("legacy")
+                .unit("s"),
+        );
+        overrides.insert(MetadataOverride::new("db.duration").unit("us"));
+
+        let unit = |scope| {
+            overrides
+                .get("db.duration", scope)
+                .and_then(MetadataOverride::unit_override)
+        };
+        assert_eq!(unit("legacy"), Some("s"));
+        assert_eq!(unit("app"), Some("us"));
+        assert!(overrides.get("db.queries", "legacy").is_none());
+    }
+}
@@ -0,0 +1,949 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::ops::Add;
+use std::time::{Duration, Instant};
+
+use opentelemetry::{Array, InstrumentationScope, KeyValue, StringValue, Value};
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{self as proto_common, any_value};
+use opentelemetry_proto::tonic::metrics::v1::{
+    self as proto, AggregationTemporality, DataPointFlags, metric, number_data_point,
+};
+use opentelemetry_sdk::Resource;
+
+use crate::serialize::{Family, HistogramPoint, MetricRef, NumberPoint, Numeric};
+
+/// A number pushed over OTLP, which is either an integer or a float
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum OtlpNumber {
+    Int(i64),
+    Double(f64),
+}
+
+impl OtlpNumber {
+    #[allow(clippy::cast_precision_loss)]
+    fn as_f64(self) -> f64 {
+        match self {
+            Self::Int(value) => value as f64,
+            Self::Double(value) => value,
+        }
+    }
+}
+
+impl Add for OtlpNumber {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => Self::Int(a.saturating_add(b)),
+            (a, b) => Self::Double(a.as_f64() + b.as_f64()),
+        }
+    }
+}
+
+impl Numeric for OtlpNumber {
+    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        match self {
+            Self::Int(value) => value.serialize(writer),
+            Self::Double(value) => value.serialize(writer),
+        }
+    }
+
+    fn fingerprint(self) -> u64 {
+        match self {
+            Self::Int(value) => value.fingerprint(),
+            Self::Double(value) => value.fingerprint(),
+        }
+    }
+}
+
+/// The latest value of a gauge or sum stream
+#[derive(Debug, Clone)]
+pub(crate) struct OtlpNumberPoint {
+    attributes: Vec<KeyValue>,
+    value: OtlpNumber,
+}
+
+impl NumberPoint for OtlpNumberPoint {
+    type Value = OtlpNumber;
+
+    fn attributes(&self) -> impl Iterator<Item = &KeyValue> {
+        self.attributes.iter()
+    }
+
+    fn value(&self) -> OtlpNumber {
+        self.value
+    }
+}
+
+/// The latest value of a histogram stream
+#[derive(Debug, Clone)]
+pub(crate) struct OtlpHistogramPoint {
+    attributes: Vec<KeyValue>,
+    bounds: Vec<f64>,
+    bucket_counts: Vec<u64>,
+    count: u64,
+
+    /// The sum of the values, zero if the producer didn't record it
+    sum: f64,
+}
+
+impl HistogramPoint for OtlpHistogramPoint {
+    type Value = f64;
+
+    fn attributes(&self) -> impl Iterator<Item = &KeyValue> {
+        self.attributes.iter()
+    }
+
+    fn bounds(&self) -> impl Iterator<Item = f64> {
+        self.bounds.iter().copied()
+    }
+
+    fn bucket_counts(&self) -> impl Iterator<Item = u64> {
+        self.bucket_counts.iter().copied()
+    }
+
+    fn count(&self) -> u64 {
+        self.count
+    }
+
+    fn sum(&self) -> f64 {
+        self.sum
+    }
+}
+
+/// The cumulative data points of a metric, whatever the temporality it was
+/// pushed with
+#[derive(Debug, Clone)]
+pub(crate) enum OtlpData {
+    Gauge(Vec<OtlpNumberPoint>),
+    Sum {
+        monotonic: bool,
+        points: Vec<OtlpNumberPoint>,
+    },
+    Histogram(Vec<OtlpHistogramPoint>),
+}
+
+impl OtlpData {
+    /// Data of the same kind, without data points
+    fn empty(&self) -> Self {
+        match self {
+            Self::Gauge(_) => Self::Gauge(Vec::new()),
+            Self::Sum { monotonic, .. } => Self::Sum {
+                monotonic: *monotonic,
+                points: Vec::new(),
+            },
+            Self::Histogram(_) => Self::Histogram(Vec::new()),
+        }
+    }
+
+    /// Whether the data points of the other data can be merged in this one
+    fn same_kind(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Gauge(_), Self::Gauge(_)) | (Self::Histogram(_), Self::Histogram(_)) => true,
+            (Self::Sum { monotonic: a, .. }, Self::Sum { monotonic: b, .. }) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// A metric stream pushed over OTLP, with the latest state of its data points
+#[derive(Debug, Clone)]
+pub(crate) struct OtlpMetric {
+    pub name: String,
+    pub description: String,
+    pub unit: String,
+    pub data: OtlpData,
+
+    /// The position of the data points, by attributes
+    index: HashMap<Vec<KeyValue>, usize>,
+}
+
+impl OtlpMetric {
+    fn new(name: String, description: String, unit: String, data: OtlpData) -> Self {
+        Self {
+            name,
+            description,
+            unit,
+            data,
+            index: HashMap::new(),
+        }
+    }
+
+    /// Merge the data points pushed for this stream.
+    ///
+    /// Cumulative and gauge data points replace the ones with the same
+    /// attributes, and delta ones are added to them. A delta histogram point
+    /// with other bounds than the accumulated one can't be added to it, so it
+    /// is rejected and counted in the returned number: the accumulated state
+    /// is kept until a cumulative point replaces it.
+    fn merge(&mut self, update: Update) -> u64 {
+        let mut rejected = 0;
+        // A metric which changed type starts over
+        if !self.data.same_kind(&update.data) {
+            self.data = update.data.empty();
+            self.index.clear();
+        }
+
+        let index = &mut self.index;
+        match (&mut self.data, update.data) {
+            (OtlpData::Gauge(points), OtlpData::Gauge(updates))
+            | (
+                OtlpData::Sum { points, .. },
+                OtlpData::Sum {
+                    points: updates, ..
+                },
+            ) => {
+                merge_points(index, points, updates, &update.removed, |stored, point| {
+                    if update.delta {
+                        stored.value = stored.value + point.value;
+                    } else {
+                        stored.value = point.value;
+                    }
+                });
+            }
+            (OtlpData::Histogram(points), OtlpData::Histogram(updates)) => {
+                merge_points(index, points, updates, &update.removed, |stored, point| {
+                    if !update.delta {
+                        *stored = point;
+                    } else if stored.bounds == point.bounds {
+                        stored.count += point.count;
+                        stored.sum += point.sum;
+                        for (total, count) in
+                            stored.bucket_counts.iter_mut().zip(point.bucket_counts)
+                        {
+                            *total += count;
+                        }
+                    } else {
+                        rejected += 1;
+                    }
+                });
+            }
+            _ => unreachable!("the data was reset to the kind of the update"),
+        }
+
+        rejected
+    }
+}
+
+/// Points which can be looked up by attributes
+trait Keyed {
+    fn key(&self) -> &[KeyValue];
+}
+
+impl Keyed for OtlpNumberPoint {
+    fn key(&self) -> &[KeyValue] {
+        &self.attributes
+    }
+}
+
+impl Keyed for OtlpHistogramPoint {
+    fn key(&self) -> &[KeyValue] {
+        &self.attributes
+    }
+}
+
+/// Insert or update the given data points, then remove the ones marked as
+/// having no recorded value
+fn merge_points<P: Keyed>(
+    index: &mut HashMap<Vec<KeyValue>, usize>,
+    points: &mut Vec<P>,
+    updates: Vec<P>,
+    removed: &[Vec<KeyValue>],
+    mut update: impl FnMut(&mut P, P),
+) {
+    for point in updates {
+        if let Some(&position) = index.get(point.key()) {
+            update(&mut points[position], point);
+        } else {
+            index.insert(point.key().to_vec(), points.len());
+            points.push(point);
+        }
+    }
+
+    for attributes in removed {
+        if let Some(position) = index.remove(attributes) {
+            points.swap_remove(position);
+            if let Some(moved) = points.get(position) {
+                index.insert(moved.key().to_vec(), position);
+            }
+        }
+    }
+}
+
+/// The data points of a metric in a single export request
+struct Update {
+    data: OtlpData,
+
+    /// Whether the data points are deltas to add to the stored ones
+    delta: bool,
+
+    /// The attributes of the data points flagged as having no recorded value,
+    /// which are removed
+    removed: Vec<Vec<KeyValue>>,
+}
+
+/// The metrics of an instrumentation scope of a resource
+#[derive(Debug)]
+struct ScopeState {
+    scope: InstrumentationScope,
+    metrics: Vec<OtlpMetric>,
+}
+
+/// The metrics pushed by a resource
+#[derive(Debug)]
+struct ResourceState {
+    /// The attributes of the resource, sorted, identifying it
+    key: Vec<KeyValue>,
+    resource: Resource,
+    scopes: Vec<ScopeState>,
+
+    /// When the resource last pushed metrics
+    updated_at: Instant,
+}
+
+/// The latest cumulative state of the metrics pushed over OTLP, by resource,
+/// instrumentation scope and metric name
+#[derive(Debug, Default)]
+pub(crate) struct OtlpStore {
+    resources: Vec<ResourceState>,
+}
+
+impl OtlpStore {
+    /// Merge the metrics of an export request.
+    ///
+    /// Returns the number of data points which were rejected, because they
+    /// have an unsupported type or no value, or are delta histogram points
+    /// whose bounds changed.
+    pub fn ingest(&mut self, request: ExportMetricsServiceRequest, now: Instant) -> u64 {
+        let mut rejected = 0;
+
+        for resource_metrics in request.resource_metrics {
+            let key = sorted_attributes(
+                resource_metrics
+                    .resource
+                    .map(|resource| resource.attributes)
+                    .unwrap_or_default(),
+            );
+            let position =
+                if let Some(position) = self.resources.iter().position(|state| state.key == key) {
+                    position
+                } else {
+                    self.resources.push(ResourceState {
+                        resource: Resource::builder_empty()
+                            .with_attributes(key.iter().cloned())
+                            .build(),
+                        key,
+                        scopes: Vec::new(),
+                        updated_at: now,
+                    });
+                    self.resources.len() - 1
+                };
+            let state = &mut self.resources[position];
+            state.updated_at = now;
+
+            for scope_metrics in resource_metrics.scope_metrics {
+                let scope = convert_scope(scope_metrics.scope, scope_metrics.schema_url);
+                let position =
+                    if let Some(position) = state.scopes.iter().position(|s| s.scope == scope) {
+                        position
+                    } else {
+                        state.scopes.push(ScopeState {
+                            scope,
+                            metrics: Vec::new(),
+                        });
+                        state.scopes.len() - 1
+                    };
+                let scope_state = &mut state.scopes[position];
+
+                for metric in scope_metrics.metrics {
+                    let Some(update) = convert_data(metric.data, &mut rejected) else {
+                        continue;
+                    };
+
+                    if let Some(stored) = scope_state
+                        .metrics
+                        .iter_mut()
+                        .find(|stored| stored.name == metric.name)
+                    {
+                        stored.description = metric.description;
+                        stored.unit = metric.unit;
+                        rejected += stored.merge(update);
+                    } else {
+                        let mut stored = OtlpMetric::new(
+                            metric.name,
+                            metric.description,
+                            metric.unit,
+                            update.data.empty(),
+                        );
+                        rejected += stored.merge(update);
+                        scope_state.metrics.push(stored);
+                    }
+                }
+            }
+        }
+
+        rejected
+    }
+
+    /// Forget the resources which didn't push metrics within the given time
+    pub fn expire(&mut self, ttl: Duration, now: Instant) {
+        self.resources
+            .retain(|state| now.saturating_duration_since(state.updated_at) < ttl);
+    }
+
+    /// List the families to render, in order, like
+    /// [`PrometheusSerializer::families`](crate::serialize::PrometheusSerializer::families),
+    /// before they are merged by
+    /// [`PrometheusSerializer::merge_families`](crate::serialize::PrometheusSerializer::merge_families)
+    pub fn families(&self) -> impl Iterator<Item = Family<'_>> {
+        self.resources
+            .iter()
+            .flat_map(|state| {
+                state.scopes.iter().flat_map(move |scope_state| {
+                    scope_state
+                        .metrics
+                        .iter()
+                        .map(move |metric| Family::Metric {
+                            metric: MetricRef::Otlp(metric),
+                            scope: &scope_state.scope,
+                            resource: &state.resource,
+                            metadata: None,
+                        })
+                })
+            })
+            .chain(std::iter::once(Family::TargetInfo {
+                resources: self.resources.iter().map(|state| &state.resource).collect(),
+            }))
+    }
+}
+
+/// Decode an OTLP JSON message.
+///
+/// The OTLP JSON encoding represents 64-bit integers as strings, which the
+/// generated types don't accept for the values of number data points, so
+/// those are converted to JSON numbers first.
+pub(crate) fn decode_json<T: serde::de::DeserializeOwned>(json: &[u8]) -> serde_json::Result<T> {
+    let mut value: serde_json::Value = serde_json::from_slice(json)?;
+    normalize_json_numbers(&mut value);
+    serde_json::from_value(value)
+}
+
+fn normalize_json_numbers(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                match (key.as_str(), &*value) {
+                    ("asInt", serde_json::Value::String(number)) => {
+                        if let Ok(number) = number.parse::<i64>() {
+                            *value = serde_json::Value::from(number);
+                        }
+                    }
+                    _ => normalize_json_numbers(value),
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(normalize_json_numbers),
+        _ => {}
+    }
+}
+
+/// Whether the data point is a staleness marker, without a recorded value
+fn no_recorded_value(flags: u32) -> bool {
+    flags & DataPointFlags::NoRecordedValueMask as u32 != 0
+}
+
+/// Convert the data points of a metric, counting the rejected ones.
+///
+/// Returns [`None`] if the metric has an unsupported type.
+fn convert_data(data: Option<metric::Data>, rejected: &mut u64) -> Option<Update> {
+    let mut removed = Vec::new();
+    let mut convert_number = |point: proto::NumberDataPoint| {
+        let attributes = sorted_attributes(point.attributes);
+        if no_recorded_value(point.flags) {
+            removed.push(attributes);
+            return None;
+        }
+
+        let value = match point.value {
+            Some(number_data_point::Value::AsInt(value)) => OtlpNumber::Int(value),
+            Some(number_data_point::Value::AsDouble(value)) => OtlpNumber::Double(value),
+            None => {
+                *rejected += 1;
+                return None;
+            }
+        };
+        Some(OtlpNumberPoint { attributes, value })
+    };
+
+    let (data, delta) = match data? {
+        metric::Data::Gauge(gauge) => {
+            let points = gauge
+                .data_points
+                .into_iter()
+                .filter_map(&mut convert_number)
+                .collect();
+            (OtlpData::Gauge(points), false)
+        }
+        metric::Data::Sum(sum) => {
+            let points = sum
+                .data_points
+                .into_iter()
+                .filter_map(&mut convert_number)
+                .collect();
+            let data = OtlpData::Sum {
+                monotonic: sum.is_monotonic,
+                points,
+            };
+            (data, is_delta(sum.aggregation_temporality))
+        }
+        metric::Data::Histogram(histogram) => {
+            let points = histogram
+                .data_points
+                .into_iter()
+                .filter_map(|point| {
+                    let attributes = sorted_attributes(point.attributes);
+                    if no_recorded_value(point.flags) {
+                        removed.push(attributes);
+                        return None;
+                    }
+
+                    // There is one more bucket than bounds, for the `+Inf` one
+                    if point.bucket_counts.len() != point.explicit_bounds.len() + 1 {
+                        *rejected += 1;
+                        return None;
+                    }
+
+                    Some(OtlpHistogramPoint {
+                        attributes,
+                        bounds: point.explicit_bounds,
+                        bucket_counts: point.bucket_counts,
+                        count: point.count,
+                        sum: point.sum.unwrap_or_default(),
+                    })
+                })
+                .collect();
+            (
+                OtlpData::Histogram(points),
+                is_delta(histogram.aggregation_temporality),
+            )
+        }
+
+        // Exponential histograms and summaries are not rendered, like with
+        // the in-process exporter
+        metric::Data::ExponentialHistogram(histogram) => {
+            *rejected += histogram.data_points.len() as u64;
+            return None;
+        }
+        metric::Data::Summary(summary) => {
+            *rejected += summary.data_points.len() as u64;
+            return None;
+        }
+    };
+
+    Some(Update {
+        data,
+        delta,
+        removed,
+    })
+}
+
+fn is_delta(temporality: i32) -> bool {
+    temporality == AggregationTemporality::Delta as i32
+}
+
+/// Convert an OTLP instrumentation scope, with the schema URL of its metrics
+fn convert_scope(
+    scope: Option<proto_common::InstrumentationScope>,
+    schema_url: String,
+) -> InstrumentationScope {
+    let scope = scope.unwrap_or_default();
+    let mut builder = InstrumentationScope::builder(scope.name)
+        .with_attributes(scope.attributes.into_iter().filter_map(convert_attribute));
+    if !scope.version.is_empty() {
+        builder = builder.with_version(scope.version);
+    }
+    if !schema_url.is_empty() {
+        builder = builder.with_schema_url(schema_url);
+    }
+    builder.build()
+}
+
+/// Convert OTLP attributes, sorted by key with the duplicates removed, like
+/// the SDK does
+fn sorted_attributes(attributes: Vec<proto_common::KeyValue>) -> Vec<KeyValue> {
+    let mut attributes: Vec<_> = attributes
+        .into_iter()
+        .filter_map(convert_attribute)
+        .collect();
+    // The last value of a duplicated key wins
+    attributes.reverse();
+    attributes.sort_by(|a, b| a.key.cmp(&b.key));
+    attributes.dedup_by(|a, b| a.key == b.key);
+    attributes
+}
+
+/// Convert an OTLP attribute, leaving it out if it has no value
+fn convert_attribute(attribute: proto_common::KeyValue) -> Option<KeyValue> {
+    let value = convert_value(attribute.value?.value?)?;
+    Some(KeyValue::new(attribute.key, value))
+}
+
+/// Convert an OTLP attribute value.
+///
+/// Arrays of a single primitive type are kept as arrays, and the other complex
+/// values are rendered as JSON strings.
+fn convert_value(value: any_value::Value) -> Option<Value> {
+    let value = match value {
+        any_value::Value::StringValue(value) => Value::from(value),
+        any_value::Value::BoolValue(value) => Value::Bool(value),
+        any_value::Value::IntValue(value) => Value::I64(value),
+        any_value::Value::DoubleValue(value) => Value::F64(value),
+        any_value::Value::ArrayValue(array) => {
+            let values: Vec<_> = array
+                .values
+                .into_iter()
+                .filter_map(|value| value.value)
+                .collect();
+            match convert_array(&values) {
+                Some(array) => Value::Array(array),
+                None => Value::from(json_array(values).to_string()),
+            }
+        }
+        value @ (any_value::Value::KvlistValue(_) | any_value::Value::BytesValue(_)) => {
+            Value::from(to_json(value).to_string())
+        }
+        any_value::Value::StringValueStrindex(_) => return None,
+    };
+    Some(value)
+}
+
+/// Convert an array of a single primitive type
+fn convert_array(values: &[any_value::Value]) -> Option<Array> {
+    let array = match values.first()? {
+        any_value::Value::BoolValue(_) => Array::Bool(
+            values
+                .iter()
+                .map(|value| match value {
+                    any_value::Value::BoolValue(value) => Some(*value),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?,
+        ),
+        any_value::Value::IntValue(_) => Array::I64(
+            values
+                .iter()
+                .map(|value| match value {
+                    any_value::Value::IntValue(value) => Some(*value),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?,
+        ),
+        any_value::Value::DoubleValue(_) => Array::F64(
+            values
+                .iter()
+                .map(|value| match value {
+                    any_value::Value::DoubleValue(value) => Some(*value),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?,
+        ),
+        any_value::Value::StringValue(_) => Array::String(
+            values
+                .iter()
+                .map(|value| match value {
+                    any_value::Value::StringValue(value) => Some(StringValue::from(value.clone())),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?,
+        ),
+        _ => return None,
+    };
+    Some(array)
+}
+
+fn json_array(values: Vec<any_value::Value>) -> serde_json::Value {
+    serde_json::Value::Array(values.into_iter().map(to_json).collect())
+}
+
+/// Render an OTLP attribute value as JSON, with bytes as hexadecimal strings
+fn to_json(value: any_value::Value) -> serde_json::Value {
+    match value {
+        any_value::Value::StringValue(value) => serde_json::Value::String(value),
+        any_value::Value::BoolValue(value) => serde_json::Value::Bool(value),
+        any_value::Value::IntValue(value) => serde_json::Value::from(value),
+        any_value::Value::DoubleValue(value) => serde_json::Value::from(value),
+        any_value::Value::ArrayValue(array) => json_array(
+            array
+                .values
+                .into_iter()
+                .filter_map(|value| value.value)
+                .collect(),
+        ),
+        any_value::Value::KvlistValue(list) => serde_json::Value::Object(
+            list.values
+                .into_iter()
+                .filter_map(|kv| Some((kv.key, to_json(kv.value?.value?))))
+                .collect(),
+        ),
+        any_value::Value::BytesValue(bytes) => {
+            let mut hex = String::with_capacity(bytes.len() * 2);
+            for byte in bytes {
+                let _ = write!(hex, "{byte:02x}");
+            }
+            serde_json::Value::String(hex)
+        }
+        any_value::Value::StringValueStrindex(_) => serde_json::Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry_proto::tonic::common::v1::AnyValue;
+    use opentelemetry_proto::tonic::metrics::v1::{
+        ExponentialHistogram, ExponentialHistogramDataPoint, Gauge, Histogram, HistogramDataPoint,
+        Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics, Sum,
+    };
+
+    use super::*;
+    use crate::exporter::ExporterConfig;
+    use crate::serialize::PrometheusSerializer;
+
+    fn attribute(key: &str, value: any_value::Value) -> proto_common::KeyValue {
+        proto_common::KeyValue {
+            key: key.to_owned(),
+            value: Some(AnyValue { value: Some(value) }),
+            ..Default::default()
+        }
+    }
+
+    fn point(queue: &str, value: i64, flags: u32) -> NumberDataPoint {
+        NumberDataPoint {
+            attributes: vec![attribute(
+                "queue",
+                any_value::Value::StringValue(queue.to_owned()),
+            )],
+            value: Some(number_data_point::Value::AsInt(value)),
+            flags,
+            ..Default::default()
+        }
+    }
+
+    fn request(data: metric::Data) -> ExportMetricsServiceRequest {
+        ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                scope_metrics: vec![ScopeMetrics {
+                    metrics: vec![Metric {
+                        name: "jobs".to_owned(),
+                        data: Some(data),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn delta_sum(points: Vec<NumberDataPoint>) -> metric::Data {
+        metric::Data::Sum(Sum {
+            data_points: points,
+            aggregation_temporality: AggregationTemporality::Delta as i32,
+            is_monotonic: true,
+        })
+    }
+
+    fn render(store: &OtlpStore) -> String {
+        let serializer = PrometheusSerializer::new();
+        let mut output = Vec::new();
+        for family in serializer.merge_families(store.families()) {
+            serializer.serialize_family(&family, &mut output).unwrap();
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_delta_sums_accumulate() {
+        let mut store = OtlpStore::default();
+        let now = Instant::now();
+
+        store.ingest(request(delta_sum(vec![point("a", 2, 0)])), now);
+        store.ingest(
+            request(delta_sum(vec![point("a", 3, 0), point("b", 1, 0)])),
+            now,
+        );
+        assert_eq!(
+            render(&store),
+            "# TYPE jobs_total counter\njobs_total{queue=\"a\"} 5\njobs_total{queue=\"b\"} 1\n\n"
+        );
+
+        // A data point without recorded value removes the series
+        let no_recorded_value = DataPointFlags::NoRecordedValueMask as u32;
+        store.ingest(
+            request(delta_sum(vec![point("a", 0, no_recorded_value)])),
+            now,
+        );
+        assert_eq!(
+            render(&store),
+            "# TYPE jobs_total counter\njobs_total{queue=\"b\"} 1\n\n"
+        );
+
+        // A metric changing type starts over
+        let gauge = metric::Data::Gauge(Gauge {
+            data_points: vec![point("c", 7, 0)],
+        });
+        store.ingest(request(gauge), now);
+        assert_eq!(render(&store), "# TYPE jobs gauge\njobs{queue=\"c\"} 7\n\n");
+    }
+
+    #[test]
+    fn test_expire_resources() {
+        let mut store = OtlpStore::default();
+        let now = Instant::now();
+        store.ingest(request(delta_sum(vec![point("a", 1, 0)])), now);
+
+        store.expire(Duration::from_mins(1), now + Duration::from_secs(30));
+        assert!(!render(&store).is_empty());
+        store.expire(Duration::from_mins(1), now + Duration::from_mins(1));
+        assert_eq!(render(&store), "");
+    }
+
+    #[test]
+    fn test_unsupported_data_points_are_rejected() {
+        let mut store = OtlpStore::default();
+        let histogram = metric::Data::ExponentialHistogram(ExponentialHistogram {
+            data_points: vec![
+                ExponentialHistogramDataPoint::default(),
+                ExponentialHistogramDataPoint::default(),
+            ],
+            aggregation_temporality: AggregationTemporality::Cumulative as i32,
+        });
+        assert_eq!(store.ingest(request(histogram), Instant::now()), 2);
+
+        let mut without_value = point("a", 0, 0);
+        without_value.value = None;
+        let gauge = metric::Data::Gauge(Gauge {
+            data_points: vec![without_value],
+        });
+        assert_eq!(store.ingest(request(gauge), Instant::now()), 1);
+    }
+
+    #[test]
+    fn test_delta_histograms_with_other_bounds_are_rejected() {
+        fn delta_histogram(bounds: Vec<f64>, bucket_counts: Vec<u64>) -> metric::Data {
+            metric::Data::Histogram(Histogram {
+                data_points: vec![HistogramDataPoint {
+                    count: bucket_counts.iter().sum(),
+                    sum: Some(1.0),
+                    bucket_counts,
+                    explicit_bounds: bounds,
+                    ..Default::default()
+                }],
+                aggregation_temporality: AggregationTemporality::Delta as i32,
+            })
+        }
+
+        let mut store = OtlpStore::default();
+        let now = Instant::now();
+        let expected = "# TYPE jobs histogram\n\
+                        jobs_count 4\n\
+                        jobs_sum 2\n\
+                        jobs_bucket{le=\"1\"} 2\n\
+                        jobs_bucket{le=\"+Inf\"} 4\n\n";
+
+        store.ingest(request(delta_histogram(vec![1.0], vec![1, 1])), now);
+        store.ingest(request(delta_histogram(vec![1.0], vec![1, 1])), now);
+        assert_eq!(render(&store), expected);
+
+        // The accumulated state is kept rather than replaced by a single delta
+        assert_eq!(
+            store.ingest(request(delta_histogram(vec![2.0], vec![5, 0])), now),
+            1
+        );
+        assert_eq!(render(&store), expected);
+    }
+
+    #[test]
+    fn test_overflowed_histograms_keep_the_shared_bounds() {
+        fn histogram_point(
+            queue: &str,
+            bounds: Vec<f64>,
+            bucket_counts: Vec<u64>,
+        ) -> HistogramDataPoint {
+            HistogramDataPoint {
+                attributes: vec![attribute(
+                    "queue",
+                    any_value::Value::StringValue(queue.to_owned()),
+                )],
+                count: bucket_counts.iter().sum(),
+                sum: Some(1.0),
+                bucket_counts,
+                explicit_bounds: bounds,
+                ..Default::default()
+            }
+        }
+
+        let mut store = OtlpStore::default();
+        let histogram = metric::Data::Histogram(Histogram {
+            data_points: vec![
+                histogram_point("a", vec![1.0, 2.0], vec![1, 1, 1]),
+                histogram_point("b", vec![2.0, 5.0], vec![2, 0, 1]),
+            ],
+            aggregation_temporality: AggregationTemporality::Cumulative as i32,
+        });
+        store.ingest(request(histogram), Instant::now());
+
+        let serializer = PrometheusSerializer::with_config(ExporterConfig {
+            cardinality_limit: Some(1),
+            ..ExporterConfig::default()
+        });
+        let mut output = Vec::new();
+        for family in serializer.merge_families(store.families()) {
+            serializer.serialize_family(&family, &mut output).unwrap();
+        }
+
+        // Only the bound shared by both data points is kept
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "# TYPE jobs histogram\n\
+             jobs_count{otel_metric_overflow=\"true\"} 6\n\
+             jobs_sum{otel_metric_overflow=\"true\"} 2\n\
+             jobs_bucket{otel_metric_overflow=\"true\",le=\"2\"} 4\n\
+             jobs_bucket{otel_metric_overflow=\"true\",le=\"+Inf\"} 6\n\n"
+        );
+    }
+
+    #[test]
+    fn test_convert_attributes() {
+        let attributes = sorted_attributes(vec![
+            attribute("b", any_value::Value::IntValue(1)),
+            attribute("a", any_value::Value::BoolValue(true)),
+            attribute("b", any_value::Value::IntValue(2)),
+            attribute(
+                "list",
+                any_value::Value::ArrayValue(proto_common::ArrayValue {
+                    values: vec![
+                        AnyValue {
+                            value: Some(any_value::Value::IntValue(1)),
+                        },
+                        AnyValue {
+                            value: Some(any_value::Value::StringValue("x".to_owned())),
+                        },
+                    ],
+                }),
+            ),
+            attribute("bytes", any_value::Value::BytesValue(vec![0xca, 0xfe])),
+        ]);
+
+        assert_eq!(
+            attributes,
+            [
+                KeyValue::new("a", true),
+                KeyValue::new("b", 2),
+                KeyValue::new("bytes", "\"cafe\""),
+                KeyValue::new("list", "[1,\"x\"]"),
+            ]
+        );
+    }
+}
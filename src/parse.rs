@@ -0,0 +1,1275 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::BufRead;
+
+/// Exemplars can't have more than this many characters in their label names
+/// and values, in `OpenMetrics`
+const MAX_EXEMPLAR_LABELS_LENGTH: usize = 128;
+
+/// The text formats of a scrape response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpositionFormat {
+    /// The Prometheus text format, version 0.0.4, which the exporter renders
+    Text,
+
+    /// The `OpenMetrics` text format, version 1.0.0
+    OpenMetrics,
+}
+
+impl ExpositionFormat {
+    /// The format of a scrape response with the given `Content-Type`, if it
+    /// is one of the text formats
+    #[must_use]
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let media_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        match media_type.as_str() {
+            "text/plain" => Some(Self::Text),
+            "application/openmetrics-text" => Some(Self::OpenMetrics),
+            _ => None,
+        }
+    }
+}
+
+/// The type of a metric family, from its `TYPE` line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricType {
+    /// A monotonic counter
+    Counter,
+
+    /// A gauge
+    Gauge,
+
+    /// A histogram with cumulative buckets
+    Histogram,
+
+    /// A histogram of gauge values, only in `OpenMetrics`
+    GaugeHistogram,
+
+    /// A summary with quantiles
+    Summary,
+
+    /// A set of boolean states, only in `OpenMetrics`
+    StateSet,
+
+    /// Labels describing an entity, only in `OpenMetrics`
+    Info,
+
+    /// A family without a `TYPE` line, or typed as `untyped` in the text
+    /// format or `unknown` in `OpenMetrics`
+    Unknown,
+}
+
+impl MetricType {
+    fn parse(name: &str, format: ExpositionFormat) -> Option<Self> {
+        let metric_type = match (name, format) {
+            ("counter", _) => Self::Counter,
+            ("gauge", _) => Self::Gauge,
+            ("histogram", _) => Self::Histogram,
+            ("summary", _) => Self::Summary,
+            ("untyped", ExpositionFormat::Text) | ("unknown", ExpositionFormat::OpenMetrics) => {
+                Self::Unknown
+            }
+            ("gaugehistogram", ExpositionFormat::OpenMetrics) => Self::GaugeHistogram,
+            ("stateset", ExpositionFormat::OpenMetrics) => Self::StateSet,
+            ("info", ExpositionFormat::OpenMetrics) => Self::Info,
+            _ => return None,
+        };
+
+        Some(metric_type)
+    }
+
+    /// The suffixes of the sample names of a family of this type
+    fn suffixes(self, format: ExpositionFormat) -> &'static [&'static str] {
+        match (self, format) {
+            (Self::Counter, ExpositionFormat::OpenMetrics) => &["_total", "_created"],
+            (Self::Histogram, ExpositionFormat::Text) => &["_bucket", "_sum", "_count"],
+            (Self::Histogram, ExpositionFormat::OpenMetrics) => {
+                &["_bucket", "_sum", "_count", "_created"]
+            }
+            (Self::GaugeHistogram, _) => &["_bucket", "_gsum", "_gcount"],
+            (Self::Summary, ExpositionFormat::Text) => &["", "_sum", "_count"],
+            (Self::Summary, ExpositionFormat::OpenMetrics) => &["", "_sum", "_count", "_created"],
+            (Self::Info, _) => &["_info"],
+            (Self::Counter | Self::Gauge | Self::StateSet | Self::Unknown, _) => &[""],
+        }
+    }
+}
+
+impl fmt::Display for MetricType {
+    /// Formats the type like in an `OpenMetrics` `TYPE` line
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+            Self::Histogram => "histogram",
+            Self::GaugeHistogram => "gaugehistogram",
+            Self::Summary => "summary",
+            Self::StateSet => "stateset",
+            Self::Info => "info",
+            Self::Unknown => "unknown",
+        })
+    }
+}
+
+/// A metric family parsed from an exposition, with its metadata and samples
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricFamily {
+    /// The name of the family, which is the name of its samples without their
+    /// suffixes, like `_bucket` or `_count`
+    pub name: String,
+
+    /// The type of the family, or [`MetricType::Unknown`] without a `TYPE`
+    /// line
+    pub metric_type: MetricType,
+
+    /// The unescaped text of the `HELP` line, if any
+    pub help: Option<String>,
+
+    /// The unit of the `UNIT` line, if any
+    pub unit: Option<String>,
+
+    /// The samples of the family, in order
+    pub samples: Vec<Sample>,
+}
+
+/// A sample of a metric family
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// The name of the sample, including its suffix
+    pub name: String,
+
+    /// The labels of the sample, unescaped, in the order they were written
+    pub labels: Vec<(String, String)>,
+
+    /// The value of the sample
+    pub value: f64,
+
+    /// The timestamp of the sample, in milliseconds since the Unix epoch
+    pub timestamp: Option<i64>,
+
+    /// The exemplar of the sample, only in `OpenMetrics`
+    pub exemplar: Option<Exemplar>,
+}
+
+impl Sample {
+    /// The value of the label with the given name, if the sample has it
+    #[must_use]
+    pub fn label(&self, name: &str) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(label, _)| label == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// An exemplar attached to a counter or histogram bucket sample
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exemplar {
+    /// The labels of the exemplar, unescaped, in the order they were written
+    pub labels: Vec<(String, String)>,
+
+    /// The value of the exemplar
+    pub value: f64,
+
+    /// The timestamp of the exemplar, in milliseconds since the Unix epoch
+    pub timestamp: Option<i64>,
+}
+
+/// Parse a whole exposition in the given format
+///
+/// # Errors
+///
+/// Returns the first error found in the exposition.
+pub fn parse_exposition(
+    input: &str,
+    format: ExpositionFormat,
+) -> Result<Vec<MetricFamily>, ParseExpositionError> {
+    ExpositionParser::new(input.as_bytes(), format).collect()
+}
+
+/// A streaming parser of the Prometheus text format and of `OpenMetrics`.
+///
+/// The parser reads the exposition line by line, and yields each metric
+/// family once all its samples are read, so that large expositions don't have
+/// to be loaded at once. It stops after the first error.
+///
+/// It is meant to validate expositions, so on top of the syntax it rejects
+/// what Prometheus rejects, like duplicate label names, invalid escape
+/// sequences, histogram buckets without an `le` label, or a missing `# EOF` in
+/// `OpenMetrics`. It also requires the samples of a family to be grouped
+/// together, its metadata to be written once and before its samples, and
+/// rejects duplicate series.
+///
+/// Names have to be in the legacy Prometheus character set: quoted UTF-8
+/// names are not supported.
+///
+/// # Example
+///
+/// ```rust
+/// use opentelemetry_prometheus_text_exporter::{ExpositionFormat, ExpositionParser, MetricType};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let exposition = "# TYPE http_requests_total counter\n\
+///                   http_requests_total{method=\"GET\"} 3\n";
+///
+/// for family in ExpositionParser::new(exposition.as_bytes(), ExpositionFormat::Text) {
+///     let family = family?;
+///     assert_eq!(family.name, "http_requests_total");
+///     assert_eq!(family.metric_type, MetricType::Counter);
+///     assert_eq!(family.samples[0].label("method"), Some("GET"));
+///     assert_eq!(family.samples[0].value, 3.0);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ExpositionParser<R> {
+    reader: R,
+    format: ExpositionFormat,
+    buffer: String,
+    line_number: usize,
+    current: Option<FamilyState>,
+    /// The names and types of the families already read
+    seen: HashMap<String, MetricType>,
+    eof: bool,
+    done: bool,
+}
+
+/// The family being read, with what is needed to validate its next lines
+#[derive(Debug)]
+struct FamilyState {
+    family: MetricFamily,
+    has_type: bool,
+    series: HashSet<(String, Vec<(String, String)>)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Metadata {
+    Help,
+    Type,
+    Unit,
+}
+
+impl Metadata {
+    fn keyword(self) -> &'static str {
+        match self {
+            Self::Help => "HELP",
+            Self::Type => "TYPE",
+            Self::Unit => "UNIT",
+        }
+    }
+}
+
+impl<R: BufRead> ExpositionParser<R> {
+    /// Create a parser reading an exposition in the given format
+    pub fn new(reader: R, format: ExpositionFormat) -> Self {
+        Self {
+            reader,
+            format,
+            buffer: String::new(),
+            line_number: 0,
+            current: None,
+            seen: HashMap::new(),
+            eof: false,
+            done: false,
+        }
+    }
+
+    /// Read lines until a family is complete, or the end of the input
+    fn advance(&mut self) -> Result<Option<MetricFamily>, ParseExpositionError> {
+        loop {
+            self.buffer.clear();
+            let read = self
+                .reader
+                .read_line(&mut self.buffer)
+                .map_err(|error| ParseExpositionError::read(self.line_number + 1, &error))?;
+
+            if read == 0 {
+                if self.format == ExpositionFormat::OpenMetrics && !self.eof {
+                    return Err(ParseExpositionError::syntax(
+                        self.line_number + 1,
+                        1,
+                        "missing `# EOF` at the end of the exposition",
+                    ));
+                }
+                return Ok(self.current.take().map(|state| state.family));
+            }
+
+            self.line_number += 1;
+            if self.eof {
+                return Err(ParseExpositionError::syntax(
+                    self.line_number,
+                    1,
+                    "unexpected content after `# EOF`",
+                ));
+            }
+
+            let buffer = std::mem::take(&mut self.buffer);
+            let line = buffer.strip_suffix('\n').unwrap_or(&buffer);
+            let finished = self.parse_line(line);
+            self.buffer = buffer;
+
+            if let Some(family) = finished? {
+                return Ok(Some(family));
+            }
+        }
+    }
+
+    /// Parse a line, returning the previous family if the line starts a new
+    /// one
+    fn parse_line(&mut self, line: &str) -> Result<Option<MetricFamily>, ParseExpositionError> {
+        let mut cursor = Cursor::new(line, self.line_number, self.format);
+
+        match self.format {
+            ExpositionFormat::Text => {
+                cursor.skip_whitespace();
+                if cursor.rest().is_empty() {
+                    return Ok(None);
+                }
+            }
+            ExpositionFormat::OpenMetrics => {
+                if line.is_empty() {
+                    return Err(cursor.error("empty lines are not allowed in OpenMetrics"));
+                }
+            }
+        }
+
+        if cursor.eat("#") {
+            self.comment(cursor)
+        } else {
+            self.sample(cursor)
+        }
+    }
+
+    fn comment(
+        &mut self,
+        mut cursor: Cursor<'_>,
+    ) -> Result<Option<MetricFamily>, ParseExpositionError> {
+        let open_metrics = self.format == ExpositionFormat::OpenMetrics;
+
+        if open_metrics {
+            if cursor.rest() == " EOF" {
+                self.eof = true;
+                return Ok(self.current.take().map(|state| state.family));
+            }
+            if !cursor.eat(" ") {
+                return Err(cursor.error("expected a space after `#`"));
+            }
+        } else if !cursor.skip_whitespace() {
+            // Any other comment is ignored
+            return Ok(None);
+        }
+
+        let metadata = if cursor.eat("HELP") {
+            Metadata::Help
+        } else if cursor.eat("TYPE") {
+            Metadata::Type
+        } else if cursor.eat("UNIT") {
+            Metadata::Unit
+        } else if open_metrics {
+            return Err(cursor.error("expected `HELP`, `TYPE`, `UNIT` or `EOF`"));
+        } else {
+            return Ok(None);
+        };
+
+        if !cursor.separator() {
+            if open_metrics {
+                return Err(
+                    cursor.error(format!("expected a space after `{}`", metadata.keyword()))
+                );
+            }
+            return Ok(None);
+        }
+
+        let name_position = cursor.position;
+        let name = cursor
+            .metric_name()
+            .ok_or_else(|| cursor.error("expected a metric name"))?;
+
+        let text = if cursor.rest().is_empty() {
+            None
+        } else if cursor.separator() {
+            Some(cursor)
+        } else {
+            return Err(cursor.error(format!(
+                "expected a space after the metric name of the `{}` line",
+                metadata.keyword()
+            )));
+        };
+
+        self.metadata(metadata, name, &cursor.at(name_position), text)
+    }
+
+    /// Set the metadata of a family, from the text after its name
+    fn metadata(
+        &mut self,
+        metadata: Metadata,
+        name: &str,
+        at_name: &Cursor<'_>,
+        text: Option<Cursor<'_>>,
+    ) -> Result<Option<MetricFamily>, ParseExpositionError> {
+        let finished = self.switch_family(name, at_name)?;
+        let state = self
+            .current
+            .as_mut()
+            .expect("switch_family always sets the current family");
+        if !state.family.samples.is_empty() {
+            return Err(at_name.error(format!(
+                "`{}` line for `{name}` after its samples",
+                metadata.keyword()
+            )));
+        }
+
+        match metadata {
+            Metadata::Help => {
+                if state.family.help.is_some() {
+                    return Err(at_name.error(format!("second `HELP` line for `{name}`")));
+                }
+                let help = match text {
+                    Some(text) => text.help_text()?,
+                    None => String::new(),
+                };
+                state.family.help = Some(help);
+            }
+            Metadata::Type => {
+                if state.has_type {
+                    return Err(at_name.error(format!("second `TYPE` line for `{name}`")));
+                }
+                let text = text.ok_or_else(|| {
+                    at_name
+                        .at(at_name.line.len())
+                        .error("expected a metric type")
+                })?;
+                let type_name = text.rest();
+                let type_name = match self.format {
+                    ExpositionFormat::Text => type_name.trim_end(),
+                    ExpositionFormat::OpenMetrics => type_name,
+                };
+                let metric_type = MetricType::parse(type_name, self.format)
+                    .ok_or_else(|| text.error(format!("unknown metric type `{type_name}`")))?;
+
+                state.family.metric_type = metric_type;
+                state.has_type = true;
+                self.seen.insert(name.to_owned(), metric_type);
+            }
+            Metadata::Unit => {
+                if state.family.unit.is_some() {
+                    return Err(at_name.error(format!("second `UNIT` line for `{name}`")));
+                }
+                let unit = text.map_or("", |text| text.rest());
+                let unit = match self.format {
+                    ExpositionFormat::Text => unit.trim_end(),
+                    ExpositionFormat::OpenMetrics => {
+                        if !unit.is_empty() && !name.ends_with(&format!("_{unit}")) {
+                            return Err(at_name.error(format!(
+                                "the name of `{name}` doesn't end with its unit `{unit}`"
+                            )));
+                        }
+                        unit
+                    }
+                };
+                state.family.unit = Some(unit.to_owned());
+            }
+        }
+
+        Ok(finished)
+    }
+
+    fn sample(
+        &mut self,
+        mut cursor: Cursor<'_>,
+    ) -> Result<Option<MetricFamily>, ParseExpositionError> {
+        let open_metrics = self.format == ExpositionFormat::OpenMetrics;
+
+        let name_position = cursor.position;
+        let name = cursor
+            .metric_name()
+            .ok_or_else(|| cursor.error("expected a metric name"))?;
+
+        let belongs = self.current.as_ref().is_some_and(|state| {
+            let family = &state.family;
+            name.strip_prefix(family.name.as_str())
+                .is_some_and(|suffix| family.metric_type.suffixes(self.format).contains(&suffix))
+        });
+        let finished = if belongs {
+            None
+        } else if let Some(state) = self
+            .current
+            .as_ref()
+            .filter(|state| state.family.name == name)
+        {
+            let expected: Vec<String> = state
+                .family
+                .metric_type
+                .suffixes(self.format)
+                .iter()
+                .map(|suffix| format!("`{name}{suffix}`"))
+                .collect();
+            return Err(cursor.at(name_position).error(format!(
+                "invalid sample name for the family `{name}`, expected {}",
+                expected.join(" or ")
+            )));
+        } else {
+            self.switch_family(name, &cursor.at(name_position))?
+        };
+
+        let mut separated = cursor.skip_whitespace();
+        let labels = if cursor.eat("{") {
+            let labels = cursor.label_set()?;
+            separated = cursor.skip_whitespace();
+            labels
+        } else {
+            Vec::new()
+        };
+        if !(separated || (open_metrics && cursor.eat(" "))) {
+            return Err(cursor.error("expected a space before the value"));
+        }
+
+        let value = cursor.value()?;
+
+        let mut timestamp = None;
+        let mut exemplar = None;
+        if open_metrics {
+            if !cursor.rest().starts_with(" # ") && cursor.eat(" ") {
+                timestamp = Some(cursor.timestamp()?);
+            }
+            let exemplar_position = cursor.position;
+            if cursor.eat(" # ") {
+                exemplar = Some((exemplar_position, cursor.exemplar()?));
+            }
+        } else if cursor.skip_whitespace() && !cursor.rest().is_empty() {
+            timestamp = Some(cursor.timestamp()?);
+            cursor.skip_whitespace();
+        }
+
+        if !cursor.rest().is_empty() {
+            return Err(cursor.error("unexpected content at the end of the sample"));
+        }
+
+        let state = self
+            .current
+            .as_mut()
+            .expect("the current family is set for every sample");
+        let at_name = cursor.at(name_position);
+        let at_exemplar = exemplar.as_ref().map(|(position, _)| cursor.at(*position));
+        state.check_sample(name, &labels, value, &at_name, at_exemplar.as_ref())?;
+
+        let mut key = labels.clone();
+        key.sort();
+        if !state.series.insert((name.to_owned(), key)) {
+            return Err(at_name.error(format!("duplicate series for `{name}`")));
+        }
+
+        state.family.samples.push(Sample {
+            name: name.to_owned(),
+            labels,
+            value,
+            timestamp,
+            exemplar: exemplar.map(|(_, exemplar)| exemplar),
+        });
+
+        Ok(finished)
+    }
+
+    /// Make the family with the given name the current one, returning the
+    /// previous family if it changed
+    fn switch_family(
+        &mut self,
+        name: &str,
+        cursor: &Cursor<'_>,
+    ) -> Result<Option<MetricFamily>, ParseExpositionError> {
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|state| state.family.name == name)
+        {
+            return Ok(None);
+        }
+
+        // Also catch samples of a typed family read before, like a
+        // `_bucket` of a histogram
+        let seen_before = self.seen.contains_key(name)
+            || self.seen.iter().any(|(family, metric_type)| {
+                name.strip_prefix(family.as_str()).is_some_and(|suffix| {
+                    !suffix.is_empty() && metric_type.suffixes(self.format).contains(&suffix)
+                })
+            });
+        if seen_before {
+            return Err(cursor.error(format!(
+                "the lines of the family of `{name}` are not grouped together"
+            )));
+        }
+
+        self.seen.insert(name.to_owned(), MetricType::Unknown);
+        let previous = self.current.replace(FamilyState {
+            family: MetricFamily {
+                name: name.to_owned(),
+                metric_type: MetricType::Unknown,
+                help: None,
+                unit: None,
+                samples: Vec::new(),
+            },
+            has_type: false,
+            series: HashSet::new(),
+        });
+
+        Ok(previous.map(|state| state.family))
+    }
+}
+
+impl FamilyState {
+    /// Check that a sample is valid for the type of the family
+    #[allow(
+        clippy::float_cmp,
+        reason = "The values of states and info samples are exactly 0 or 1"
+    )]
+    fn check_sample(
+        &self,
+        name: &str,
+        labels: &[(String, String)],
+        value: f64,
+        at_name: &Cursor<'_>,
+        at_exemplar: Option<&Cursor<'_>>,
+    ) -> Result<(), ParseExpositionError> {
+        let family = &self.family;
+        let suffix = &name[family.name.len()..];
+
+        match (family.metric_type, suffix) {
+            (MetricType::Histogram | MetricType::GaugeHistogram, "_bucket") => {
+                require_number_label(at_name, labels, "le", name)?;
+            }
+            (MetricType::Summary, "") => {
+                require_number_label(at_name, labels, "quantile", name)?;
+            }
+            (MetricType::StateSet, _) => {
+                if !labels.iter().any(|(label, _)| *label == family.name) {
+                    return Err(at_name.error(format!(
+                        "the samples of the state set `{}` need a `{}` label",
+                        family.name, family.name
+                    )));
+                }
+                if value != 0.0 && value != 1.0 {
+                    return Err(at_name.error("the value of a state must be 0 or 1"));
+                }
+            }
+            (MetricType::Info, _) if value != 1.0 => {
+                return Err(at_name.error("the value of an info sample must be 1"));
+            }
+            _ => {}
+        }
+
+        if let Some(at_exemplar) = at_exemplar {
+            let allowed = matches!(
+                (family.metric_type, suffix),
+                (MetricType::Counter, "_total")
+                    | (
+                        MetricType::Histogram | MetricType::GaugeHistogram,
+                        "_bucket"
+                    )
+            );
+            if !allowed {
+                return Err(at_exemplar
+                    .error("exemplars are only allowed on counter totals and histogram buckets"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Iterator for ExpositionParser<R> {
+    type Item = Result<MetricFamily, ParseExpositionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.advance() {
+            Ok(Some(family)) => Some(Ok(family)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Check that a sample has a label with a number, like the `le` label of
+/// histogram buckets
+fn require_number_label(
+    cursor: &Cursor<'_>,
+    labels: &[(String, String)],
+    label: &str,
+    name: &str,
+) -> Result<(), ParseExpositionError> {
+    let value = labels
+        .iter()
+        .find(|(name, _)| name == label)
+        .map(|(_, value)| value)
+        .ok_or_else(|| cursor.error(format!("`{name}` needs a `{label}` label")))?;
+
+    parse_float(value)
+        .map(|_| ())
+        .ok_or_else(|| cursor.error(format!("invalid `{label}` value `{value}`")))
+}
+
+/// Parse a float like Prometheus does, accepting `NaN`, `+Inf` and `-Inf`
+fn parse_float(value: &str) -> Option<f64> {
+    value.parse().ok()
+}
+
+/// A position in a line being parsed
+#[derive(Clone, Copy)]
+struct Cursor<'a> {
+    line: &'a str,
+    line_number: usize,
+    position: usize,
+    format: ExpositionFormat,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(line: &'a str, line_number: usize, format: ExpositionFormat) -> Self {
+        Self {
+            line,
+            line_number,
+            position: 0,
+            format,
+        }
+    }
+
+    /// A copy of the cursor at another position of the line
+    fn at(&self, position: usize) -> Self {
+        Self { position, ..*self }
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseExpositionError {
+        ParseExpositionError::syntax(
+            self.line_number,
+            self.line[..self.position].chars().count() + 1,
+            message,
+        )
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.line[self.position..]
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Skip spaces and tabs, only allowed around tokens in the text format,
+    /// returning whether there were some
+    fn skip_whitespace(&mut self) -> bool {
+        if self.format == ExpositionFormat::OpenMetrics {
+            return false;
+        }
+
+        let rest = self.rest();
+        let skipped = rest.len() - rest.trim_start_matches([' ', '\t']).len();
+        self.position += skipped;
+        skipped > 0
+    }
+
+    /// Skip the separator between two tokens: a single space in `OpenMetrics`,
+    /// any whitespace in the text format
+    fn separator(&mut self) -> bool {
+        match self.format {
+            ExpositionFormat::Text => self.skip_whitespace(),
+            ExpositionFormat::OpenMetrics => self.eat(" "),
+        }
+    }
+
+    fn identifier(&mut self, allow_colons: bool) -> Option<&'a str> {
+        let rest = self.rest();
+        let len = rest
+            .char_indices()
+            .take_while(|(index, c)| {
+                c.is_ascii_alphabetic()
+                    || *c == '_'
+                    || (allow_colons && *c == ':')
+                    || (*index > 0 && c.is_ascii_digit())
+            })
+            .count();
+        if len == 0 {
+            return None;
+        }
+
+        // Only ASCII characters were counted
+        self.position += len;
+        Some(&rest[..len])
+    }
+
+    fn metric_name(&mut self) -> Option<&'a str> {
+        self.identifier(true)
+    }
+
+    /// Read the next token, up to a space or the end of the line
+    fn token(&mut self) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find([' ', '\t']).unwrap_or(rest.len());
+        self.position += len;
+        &rest[..len]
+    }
+
+    fn value(&mut self) -> Result<f64, ParseExpositionError> {
+        let start = *self;
+        let token = self.token();
+        parse_float(token).ok_or_else(|| start.error(format!("invalid value `{token}`")))
+    }
+
+    /// Parse a timestamp, in milliseconds in the text format and in seconds
+    /// in `OpenMetrics`
+    fn timestamp(&mut self) -> Result<i64, ParseExpositionError> {
+        let start = *self;
+        let token = self.token();
+        let timestamp = match self.format {
+            ExpositionFormat::Text => token.parse().ok(),
+            ExpositionFormat::OpenMetrics => parse_float(token)
+                .filter(|seconds| seconds.is_finite())
+                .map(|seconds| {
+                    #[allow(
+                        clippy::cast_possible_truncation,
+                        reason = "Timestamps out of range saturate"
+                    )]
+                    let milliseconds = (seconds * 1000.0).round() as i64;
+                    milliseconds
+                }),
+        };
+
+        timestamp.ok_or_else(|| start.error(format!("invalid timestamp `{token}`")))
+    }
+
+    /// Parse the labels of a sample or an exemplar, after the opening brace
+    fn label_set(&mut self) -> Result<Vec<(String, String)>, ParseExpositionError> {
+        let mut labels: Vec<(String, String)> = Vec::new();
+
+        self.skip_whitespace();
+        if self.eat("}") {
+            return Ok(labels);
+        }
+
+        loop {
+            let name_position = self.position;
+            let name = self
+                .identifier(false)
+                .ok_or_else(|| self.error("expected a label name"))?;
+            if labels.iter().any(|(label, _)| label == name) {
+                return Err(self
+                    .at(name_position)
+                    .error(format!("duplicate label `{name}`")));
+            }
+
+            self.skip_whitespace();
+            if !self.eat("=") {
+                return Err(self.error("expected `=`"));
+            }
+            self.skip_whitespace();
+            let value = self.label_value()?;
+            labels.push((name.to_owned(), value));
+
+            self.skip_whitespace();
+            if self.eat("}") {
+                return Ok(labels);
+            }
+            if !self.eat(",") {
+                return Err(self.error("expected `,` or `}`"));
+            }
+
+            // The text format allows a trailing comma
+            self.skip_whitespace();
+            if self.format == ExpositionFormat::Text && self.eat("}") {
+                return Ok(labels);
+            }
+        }
+    }
+
+    /// Parse a quoted label value
+    fn label_value(&mut self) -> Result<String, ParseExpositionError> {
+        if !self.rest().starts_with('"') {
+            return Err(self.error("expected a quoted label value"));
+        }
+        let start = *self;
+        self.position += 1;
+
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += index + 1;
+                    return Ok(value);
+                }
+                '\\' => {
+                    let escaped = match chars.next() {
+                        Some((_, '\\')) => '\\',
+                        Some((_, '"')) => '"',
+                        Some((_, 'n')) => '\n',
+                        Some(_) => {
+                            return Err(self
+                                .at(self.position + index)
+                                .error("invalid escape sequence"));
+                        }
+                        None => break,
+                    };
+                    value.push(escaped);
+                }
+                c => value.push(c),
+            }
+        }
+
+        Err(start.error("unterminated label value"))
+    }
+
+    /// Parse the text of a `HELP` line, up to the end of the line
+    fn help_text(&self) -> Result<String, ParseExpositionError> {
+        let mut text = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((index, c)) = chars.next() {
+            if c != '\\' {
+                text.push(c);
+                continue;
+            }
+
+            match (chars.next(), self.format) {
+                (Some((_, '\\')), _) | (None, ExpositionFormat::Text) => text.push('\\'),
+                (Some((_, 'n')), _) => text.push('\n'),
+                (Some((_, '"')), ExpositionFormat::OpenMetrics) => text.push('"'),
+                // Prometheus keeps the other escape sequences of the text
+                // format as they are
+                (Some((_, c)), ExpositionFormat::Text) => {
+                    text.push('\\');
+                    text.push(c);
+                }
+                (_, ExpositionFormat::OpenMetrics) => {
+                    return Err(self
+                        .at(self.position + index)
+                        .error("invalid escape sequence"));
+                }
+            }
+        }
+
+        Ok(text)
+    }
+
+    /// Parse an exemplar, after the ` # ` separator
+    fn exemplar(&mut self) -> Result<Exemplar, ParseExpositionError> {
+        let labels_position = self.position;
+        if !self.eat("{") {
+            return Err(self.error("expected the labels of the exemplar"));
+        }
+        let labels = self.label_set()?;
+        let length: usize = labels
+            .iter()
+            .map(|(name, value)| name.chars().count() + value.chars().count())
+            .sum();
+        if length > MAX_EXEMPLAR_LABELS_LENGTH {
+            return Err(self.at(labels_position).error(format!(
+                "the labels of the exemplar are longer than {MAX_EXEMPLAR_LABELS_LENGTH} \
+                 characters"
+            )));
+        }
+
+        if !self.eat(" ") {
+            return Err(self.error("expected a space before the value of the exemplar"));
+        }
+        let value = self.value()?;
+        let timestamp = if self.eat(" ") {
+            Some(self.timestamp()?)
+        } else {
+            None
+        };
+
+        Ok(Exemplar {
+            labels,
+            value,
+            timestamp,
+        })
+    }
+}
+
+/// Error returned when parsing an invalid exposition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseExpositionError {
+    line: usize,
+    column: usize,
+    kind: ParseExpositionErrorKind,
+    message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParseExpositionErrorKind {
+    /// The line could not be read
+    Read,
+
+    /// The line is not valid
+    Syntax,
+}
+
+impl ParseExpositionError {
+    fn read(line: usize, error: &std::io::Error) -> Self {
+        Self {
+            line,
+            column: 1,
+            kind: ParseExpositionErrorKind::Read,
+            message: error.to_string(),
+        }
+    }
+
+    fn syntax(line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            column,
+            kind: ParseExpositionErrorKind::Syntax,
+            message: message.into(),
+        }
+    }
+
+    /// The line of the exposition where the error was found, starting at 1
+    #[must_use]
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The column of the line where the error was found, in characters and
+    /// starting at 1
+    #[must_use]
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
+impl fmt::Display for ParseExpositionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ParseExpositionErrorKind::Read => {
+                write!(f, "failed to read line {}: {}", self.line, self.message)
+            }
+            ParseExpositionErrorKind::Syntax => write!(
+                f,
+                "invalid exposition at line {}, column {}: {}",
+                self.line, self.column, self.message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseExpositionError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str, format: ExpositionFormat) -> Vec<MetricFamily> {
+        parse_exposition(input, format).unwrap()
+    }
+
+    fn error(input: &str, format: ExpositionFormat) -> (usize, usize, String) {
+        let error = parse_exposition(input, format).unwrap_err();
+        (error.line(), error.column(), error.message)
+    }
+
+    #[test]
+    fn test_text_format() {
+        let families = parse(
+            "# A comment\n\
+             # HELP http_requests_total Requests,\\n by \\\\method\\t.\n\
+             # TYPE http_requests_total counter\n\
+             http_requests_total{method=\"GET\",path=\"/a\\\"b\\\\\"} 3 1700000000000\n\
+             \n\
+             http_requests_total { method = \"POST\" , } \t 1.5e3\n\
+             # TYPE request_duration_seconds histogram\n\
+             # UNIT request_duration_seconds seconds\n\
+             request_duration_seconds_bucket{le=\"0.5\"} 1\n\
+             request_duration_seconds_bucket{le=\"+Inf\"} 2\n\
+             request_duration_seconds_sum 1.2\n\
+             request_duration_seconds_count 2\n\
+             up NaN\n\
+             down -Inf",
+            ExpositionFormat::Text,
+        );
+        assert_eq!(families.len(), 4);
+
+        let requests = &families[0];
+        assert_eq!(requests.name, "http_requests_total");
+        assert_eq!(requests.metric_type, MetricType::Counter);
+        assert_eq!(
+            requests.help.as_deref(),
+            Some("Requests,\n by \\method\\t.")
+        );
+        assert_eq!(requests.samples.len(), 2);
+        assert_eq!(requests.samples[0].label("path"), Some("/a\"b\\"));
+        assert_eq!(requests.samples[0].timestamp, Some(1_700_000_000_000));
+        assert_eq!(
+            requests.samples[1].labels,
+            vec![("method".to_owned(), "POST".to_owned())]
+        );
+        assert!((requests.samples[1].value - 1500.0).abs() < f64::EPSILON);
+
+        let duration = &families[1];
+        assert_eq!(duration.metric_type, MetricType::Histogram);
+        assert_eq!(duration.unit.as_deref(), Some("seconds"));
+        assert_eq!(duration.samples.len(), 4);
+        assert_eq!(duration.samples[3].name, "request_duration_seconds_count");
+
+        // Families without metadata are untyped, one per sample name
+        assert_eq!(families[2].name, "up");
+        assert_eq!(families[2].metric_type, MetricType::Unknown);
+        assert!(families[2].samples[0].value.is_nan());
+        assert!(families[3].samples[0].value.is_infinite());
+        assert!(families[3].samples[0].value.is_sign_negative());
+    }
+
+    #[test]
+    fn test_open_metrics_format() {
+        let families = parse(
+            "# TYPE jobs counter\n\
+             # HELP jobs Jobs \\\"run\\\".\n\
+             jobs_total{queue=\"a\"} 3 1700000000.5 # {trace_id=\"abc\"} 1 1700000000\n\
+             jobs_created{queue=\"a\"} 1700000000\n\
+             # TYPE build info\n\
+             build_info{version=\"1.0\"} 1\n\
+             # TYPE state stateset\n\
+             state{state=\"on\"} 1\n\
+             state{state=\"off\"} 0\n\
+             # EOF\n",
+            ExpositionFormat::OpenMetrics,
+        );
+        assert_eq!(families.len(), 3);
+
+        let jobs = &families[0];
+        assert_eq!(jobs.help.as_deref(), Some("Jobs \"run\"."));
+        assert_eq!(jobs.samples[0].timestamp, Some(1_700_000_000_500));
+        let exemplar = jobs.samples[0].exemplar.as_ref().unwrap();
+        assert_eq!(
+            exemplar.labels,
+            vec![("trace_id".to_owned(), "abc".to_owned())]
+        );
+        assert_eq!(exemplar.timestamp, Some(1_700_000_000_000));
+        assert_eq!(jobs.samples[1].name, "jobs_created");
+
+        assert_eq!(families[1].metric_type, MetricType::Info);
+        assert_eq!(families[2].samples.len(), 2);
+    }
+
+    #[test]
+    fn test_streaming() {
+        let input = "a 1\nb 2\n# TYPE c gauge\nc{\n";
+        let mut parser = ExpositionParser::new(input.as_bytes(), ExpositionFormat::Text);
+        assert_eq!(parser.next().unwrap().unwrap().name, "a");
+        assert_eq!(parser.next().unwrap().unwrap().name, "b");
+        assert_eq!(parser.next().unwrap().unwrap_err().line(), 4);
+        assert!(parser.next().is_none());
+    }
+
+    #[test]
+    fn test_text_errors() {
+        for (input, line, column) in [
+            ("1up 1", 1, 1),
+            ("up", 1, 3),
+            ("up{} x", 1, 6),
+            ("up{a=\"b\",a=\"c\"} 1", 1, 10),
+            ("up{a=\"\\t\"} 1", 1, 7),
+            ("up{a=\"b} 1", 1, 6),
+            ("up{a=b} 1", 1, 6),
+            ("up 1 1.5", 1, 6),
+            ("up 1 2 3", 1, 8),
+            ("up 1 # {a=\"b\"} 1", 1, 6),
+            ("# TYPE up gauges", 1, 11),
+            ("# TYPE up", 1, 10),
+            ("# TYPE up gauge\n# TYPE up gauge", 2, 8),
+            ("# HELP up a\n# HELP up b", 2, 8),
+            ("up 1\n# TYPE up gauge", 2, 8),
+            ("up 1\ndown 1\nup 2", 3, 1),
+            ("# TYPE h histogram\nh_sum 1\nx 1\nh_count 1", 4, 1),
+            ("# TYPE h histogram\nh_bucket 1", 2, 1),
+            ("# TYPE h histogram\nh_bucket{le=\"x\"} 1", 2, 1),
+            ("# TYPE s summary\ns 1", 2, 1),
+            ("up{a=\"b\"} 1\nup{a=\"b\"} 2", 2, 1),
+        ] {
+            let (actual_line, actual_column, message) = error(input, ExpositionFormat::Text);
+            assert_eq!(
+                (actual_line, actual_column),
+                (line, column),
+                "{input:?}: {message}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_open_metrics_errors() {
+        for (input, line, column) in [
+            ("up 1\n", 2, 1),
+            ("up 1\n# EOF\n\n", 3, 1),
+            ("up 1\n\n# EOF", 2, 1),
+            ("# a comment\n# EOF", 1, 3),
+            (" up 1\n# EOF", 1, 1),
+            ("up  1\n# EOF", 1, 4),
+            ("up{a=\"b\",} 1\n# EOF", 1, 10),
+            ("# TYPE up untyped\n# EOF", 1, 11),
+            (
+                "# TYPE up_seconds gauge\n# UNIT up_seconds bytes\n# EOF",
+                2,
+                8,
+            ),
+            ("# TYPE c counter\nc 1\n# EOF", 2, 1),
+            ("# TYPE g gauge\ng 1 # {a=\"b\"} 1\n# EOF", 2, 4),
+            ("# TYPE i info\ni_info 2\n# EOF", 2, 1),
+            ("# TYPE s stateset\ns{a=\"b\"} 1\n# EOF", 2, 1),
+            ("# HELP up \\t\n# EOF", 1, 11),
+        ] {
+            let (actual_line, actual_column, message) = error(input, ExpositionFormat::OpenMetrics);
+            assert_eq!(
+                (actual_line, actual_column),
+                (line, column),
+                "{input:?}: {message}"
+            );
+        }
+
+        let labels = format!("{{trace_id=\"{}\"}}", "a".repeat(121));
+        let input = format!("# TYPE c counter\nc_total 1 # {labels} 1\n# EOF");
+        let (_, _, message) = error(&input, ExpositionFormat::OpenMetrics);
+        assert!(message.contains("longer than 128"), "{message}");
+    }
+
+    #[test]
+    fn test_error_display() {
+        let error = parse_exposition("up{a=\"b\",a=\"c\"} 1", ExpositionFormat::Text).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid exposition at line 1, column 10: duplicate label `a`"
+        );
+    }
+
+    #[test]
+    fn test_format_from_content_type() {
+        assert_eq!(
+            ExpositionFormat::from_content_type("text/plain; version=0.0.4; charset=utf-8"),
+            Some(ExpositionFormat::Text)
+        );
+        assert_eq!(
+            ExpositionFormat::from_content_type("application/openmetrics-text; version=1.0.0"),
+            Some(ExpositionFormat::OpenMetrics)
+        );
+        assert_eq!(
+            ExpositionFormat::from_content_type("application/json"),
+            None
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::metrics::ManualReader;
+use opentelemetry_sdk::metrics::data::ResourceMetrics;
+use opentelemetry_sdk::metrics::reader::MetricReader;
+
+/// A source of metrics that are not recorded through the exporter's own
+/// [`SdkMeterProvider`] pipeline.
+///
+/// Producers are collected on every export, right after the exporter's own
+/// reader, and their metrics are rendered in the same exposition. This is the
+/// way to bridge metrics coming from another meter provider, or from any other
+/// source able to fill a [`ResourceMetrics`].
+///
+/// [`ManualReader`] implements this trait, so a reader registered on a
+/// separate provider can be used as a producer directly.
+///
+/// [`SdkMeterProvider`]: opentelemetry_sdk::metrics::SdkMeterProvider
+pub trait MetricProducer: std::fmt::Debug + Send + Sync + 'static {
+    /// Fill the given [`ResourceMetrics`] with the metrics of this producer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metrics could not be produced. This fails the
+    /// whole export.
+    fn produce(&self, rm: &mut ResourceMetrics) -> OTelSdkResult;
+}
+
+impl MetricProducer for ManualReader {
+    fn produce(&self, rm: &mut ResourceMetrics) -> OTelSdkResult {
+        self.collect(rm)
+    }
+}
+
+impl<T: MetricProducer + ?Sized> MetricProducer for Arc<T> {
+    fn produce(&self, rm: &mut ResourceMetrics) -> OTelSdkResult {
+        (**self).produce(rm)
+    }
+}
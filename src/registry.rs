@@ -0,0 +1,242 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write;
+
+use opentelemetry::Key;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::metrics::data::ResourceMetrics;
+
+use crate::exporter::PrometheusExporter;
+use crate::serialize::{Family, RenderOptions, sanitize_name};
+
+/// Serves the metrics of several exporters in a single exposition.
+///
+/// Each exporter is registered with its own `SdkMeterProvider`, for example
+/// one per tenant, and is collected on every export of the registry. The
+/// families with the same name are merged, so that the exposition stays valid,
+/// and rendered with the metadata of the first exporter having them. Families
+/// with the same name but a different type than the first one are left out.
+///
+/// The series of each exporter are told apart with a discriminator label. By
+/// default, those are the `job` and `instance` labels, from the
+/// `service.namespace`, `service.name` and `service.instance.id` attributes of
+/// the resource, like the target labels Prometheus would give them. An
+/// exporter whose resource has no `service.instance.id`, like the default
+/// resource, gets the name under which it was registered as `instance`, so
+/// that its series stay apart from the ones of the other exporters. With
+/// [`with_discriminator_label()`](Self::with_discriminator_label), a single
+/// label with the name under which each exporter was registered is used
+/// instead. The discriminator labels are added to `target_info` too, which
+/// keeps one series per resource.
+///
+/// Each exporter renders its series with its own configuration, but the size
+/// budget, the scrape cache and the exporter metrics don't apply. In
+/// particular, the families left out, because they have an unsupported type,
+/// are filtered out or conflict with the family of another exporter, are not
+/// counted in `otel_prometheus_exporter_dropped_metrics` nor
+/// `otel_prometheus_exporter_conflicting_metrics`.
+///
+/// # Example
+///
+/// ```rust
+/// use opentelemetry_prometheus_text_exporter::{PrometheusExporter, PrometheusRegistry};
+/// use opentelemetry_sdk::metrics::SdkMeterProvider;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let tenant_exporter = PrometheusExporter::new();
+/// let tenant_provider = SdkMeterProvider::builder()
+///     .with_reader(tenant_exporter.clone())
+///     .build();
+///
+/// let plugins_exporter = PrometheusExporter::new();
+/// let plugins_provider = SdkMeterProvider::builder()
+///     .with_reader(plugins_exporter.clone())
+///     .build();
+///
+/// let registry = PrometheusRegistry::new()
+///     .with_exporter("tenant-a", tenant_exporter)
+///     .with_exporter("plugins", plugins_exporter)
+///     .with_discriminator_label("provider");
+///
+/// let mut output = Vec::new();
+/// registry.export(&mut output)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PrometheusRegistry {
+    exporters: Vec<(String, PrometheusExporter)>,
+    discriminator_label: Option<String>,
+}
+
+/// The labels telling apart the series of the exporters of a registry
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) enum Discriminator<'a> {
+    /// No label, outside of a registry
+    #[default]
+    None,
+
+    /// A label with the same value on all the series of an exporter
+    Label(&'a str, &'a str),
+
+    /// The `job` and `instance` labels, from the attributes of the resource,
+    /// with the name of the exporter as `instance` if it has no instance id
+    JobInstance(&'a str),
+}
+
+impl Discriminator<'_> {
+    /// The labels to add to the series of the given resource
+    pub fn labels(&self, resource: &Resource) -> Vec<(String, String)> {
+        match *self {
+            Self::None => Vec::new(),
+            Self::Label(name, value) => vec![(name.to_owned(), value.to_owned())],
+            Self::JobInstance(exporter) => {
+                let attribute = |key: &'static str| {
+                    resource
+                        .get(&Key::from_static_str(key))
+                        .map(|value| value.to_string())
+                };
+
+                let mut labels = Vec::with_capacity(2);
+                if let Some(name) = attribute("service.name") {
+                    let job = match attribute("service.namespace") {
+                        Some(namespace) => format!("{namespace}/{name}"),
+                        None => name,
+                    };
+                    labels.push(("job".to_owned(), job));
+                }
+                let instance =
+                    attribute("service.instance.id").unwrap_or_else(|| exporter.to_owned());
+                labels.push(("instance".to_owned(), instance));
+                labels
+            }
+        }
+    }
+}
+
+/// The parts of a family merged from several exporters
+struct MergedFamily<'a> {
+    prometheus_type: &'static str,
+    parts: Vec<(usize, Family<'a>)>,
+}
+
+impl PrometheusRegistry {
+    /// Create an empty registry
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers an exporter under the given name.
+    ///
+    /// Exporters are rendered in the order they are registered.
+    #[must_use]
+    pub fn with_exporter(mut self, name: impl Into<String>, exporter: PrometheusExporter) -> Self {
+        self.exporters.push((name.into(), exporter));
+        self
+    }
+
+    /// Tells apart the series of each exporter with a label of the given
+    /// name, set to the name under which the exporter was registered, instead
+    /// of the `job` and `instance` labels.
+    ///
+    /// The name is sanitized like the other label names. The label replaces
+    /// the attributes, resource and constant labels of the same name.
+    #[must_use]
+    pub fn with_discriminator_label(mut self, label: impl AsRef<str>) -> Self {
+        self.discriminator_label = Some(sanitize_name(label.as_ref()).into_owned());
+        self
+    }
+
+    /// Collect all the exporters, and export their merged metrics to the
+    /// given writer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metrics of an exporter could not be collected,
+    /// or if the writer fails to write the metrics.
+    pub fn export<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut scrapes = Vec::with_capacity(self.exporters.len());
+        let collected = self
+            .exporters
+            .iter()
+            .map(|(_, exporter)| {
+                scrapes.push(exporter.serializer().start_scrape());
+                exporter.collect_all()
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        for merged in self.merge(&collected) {
+            let mut written: Option<Cow<'_, str>> = None;
+            let mut is_target_info = false;
+            for (index, family) in &merged.parts {
+                let (name, exporter) = &self.exporters[*index];
+                let options = RenderOptions {
+                    discriminator: match &self.discriminator_label {
+                        Some(label) => Discriminator::Label(label, name),
+                        None => Discriminator::JobInstance(name),
+                    },
+                    written_family: written.as_deref(),
+                    ..RenderOptions::default()
+                };
+                let name = exporter
+                    .serializer()
+                    .serialize_part(family, &options, writer)?;
+                if written.is_none() {
+                    written = name;
+                }
+                is_target_info = matches!(family, Family::TargetInfo { .. });
+            }
+
+            // Like in the exposition of a single exporter, `target_info`
+            // comes last without an empty line after it
+            if written.is_some() && !is_target_info {
+                writeln!(writer)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Group the families of all the exporters by name, in the order they
+    /// first appear, with `target_info` last
+    fn merge<'a>(&self, collected: &'a [Vec<ResourceMetrics>]) -> Vec<MergedFamily<'a>> {
+        let mut merged: Vec<MergedFamily<'a>> = Vec::new();
+        let mut by_name: HashMap<Cow<'a, str>, usize> = HashMap::new();
+        let mut target_info = MergedFamily {
+            prometheus_type: "gauge",
+            parts: Vec::new(),
+        };
+
+        for (index, ((_, exporter), rms)) in self.exporters.iter().zip(collected).enumerate() {
+            for family in exporter.serializer().families(rms) {
+                if matches!(family, Family::TargetInfo { .. }) {
+                    target_info.parts.push((index, family));
+                    continue;
+                }
+
+                // The metrics of unsupported types or filtered out would
+                // render nothing
+                let Ok((name, prometheus_type)) = exporter.serializer().family_type(&family) else {
+                    continue;
+                };
+
+                if let Some(&position) = by_name.get(&name) {
+                    let existing = &mut merged[position];
+                    if existing.prometheus_type == prometheus_type {
+                        existing.parts.push((index, family));
+                    }
+                } else {
+                    by_name.insert(name, merged.len());
+                    merged.push(MergedFamily {
+                        prometheus_type,
+                        parts: vec![(index, family)],
+                    });
+                }
+            }
+        }
+
+        merged.push(target_info);
+        merged
+    }
+}
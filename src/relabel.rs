@@ -0,0 +1,522 @@
+use std::num::NonZeroU64;
+
+use md5::{Digest, Md5};
+use regex::Regex;
+
+/// The label holding the name of a series during relabeling
+pub(crate) const NAME_LABEL: &str = "__name__";
+
+/// What a relabeling rule does, along with the parameters specific to it
+#[derive(Debug, Clone)]
+enum Action {
+    Replace {
+        target_label: String,
+    },
+    Keep,
+    Drop,
+    LabelDrop,
+    LabelKeep,
+    LabelMap,
+    HashMod {
+        target_label: String,
+        modulus: NonZeroU64,
+    },
+    Lowercase {
+        target_label: String,
+    },
+}
+
+/// A relabeling rule, working like the ones of Prometheus `relabel_configs`
+/// and `metric_relabel_configs`.
+///
+/// Rules are applied in order to the labels of every series, right before it
+/// is written. The labels include the name of the series in `__name__`, the
+/// instrumentation scope labels and, for histogram buckets, the `le` label.
+/// The series name includes the `_bucket`, `_sum` and `_count` suffixes of
+/// histograms.
+///
+/// Each rule is built from its action, like [`RelabelConfig::replace`], then
+/// configured with the same parameters as in Prometheus, which have the same
+/// defaults: no source labels, a `;` separator, a `(.*)` regex and a `$1`
+/// replacement.
+///
+/// Rules setting a label whose name isn't valid, once expanded, leave the
+/// labels as they are. After all the rules are applied, labels with an empty
+/// value and labels starting with `__` are removed. Series without a name, or
+/// renamed to an invalid metric name, are dropped.
+///
+/// A family is named after its first series, once relabeled. The series
+/// renamed after another family, like a histogram `_bucket` sample renamed
+/// without its suffix, are dropped, as they would be written under the
+/// metadata of the wrong family.
+///
+/// # Example
+///
+/// ```rust
+/// use opentelemetry_prometheus_text_exporter::RelabelConfig;
+///
+/// # fn main() -> Result<(), regex::Error> {
+/// // Scrub user IDs from URL paths
+/// let scrub = RelabelConfig::replace("http_route")
+///     .source_labels(["http_route"])
+///     .regex("/users/[^/]+(/.*)?")?
+///     .replacement("/users/:id$1");
+///
+/// // Drop the series of the health check endpoint
+/// let drop = RelabelConfig::drop()
+///     .source_labels(["__name__", "http_route"])
+///     .regex("http_.*;/health")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RelabelConfig {
+    action: Action,
+    source_labels: Vec<String>,
+    separator: String,
+    regex: Regex,
+    replacement: String,
+}
+
+impl RelabelConfig {
+    fn new(action: Action) -> Self {
+        Self {
+            action,
+            source_labels: Vec::new(),
+            separator: ";".to_owned(),
+            regex: anchored("(.*)").expect("the default regex is valid"),
+            replacement: "$1".to_owned(),
+        }
+    }
+
+    /// The `replace` action: if the regex matches the concatenated source
+    /// labels, set the target label to the replacement, expanded with the
+    /// capture groups of the regex. The target label can also refer to capture
+    /// groups. Setting a label to an empty value removes it.
+    #[must_use]
+    pub fn replace(target_label: impl Into<String>) -> Self {
+        Self::new(Action::Replace {
+            target_label: target_label.into(),
+        })
+    }
+
+    /// The `keep` action: drop the series for which the regex doesn't match
+    /// the concatenated source labels.
+    #[must_use]
+    pub fn keep() -> Self {
+        Self::new(Action::Keep)
+    }
+
+    /// The `drop` action: drop the series for which the regex matches the
+    /// concatenated source labels.
+    #[must_use]
+    pub fn drop() -> Self {
+        Self::new(Action::Drop)
+    }
+
+    /// The `labeldrop` action: remove the labels with a name matching the
+    /// regex.
+    #[must_use]
+    pub fn label_drop() -> Self {
+        Self::new(Action::LabelDrop)
+    }
+
+    /// The `labelkeep` action: remove the labels with a name not matching the
+    /// regex.
+    #[must_use]
+    pub fn label_keep() -> Self {
+        Self::new(Action::LabelKeep)
+    }
+
+    /// The `labelmap` action: copy the labels with a name matching the regex
+    /// to the label named after the replacement, expanded with the capture
+    /// groups of the regex.
+    #[must_use]
+    pub fn label_map() -> Self {
+        Self::new(Action::LabelMap)
+    }
+
+    /// The `hashmod` action: set the target label to the hash of the
+    /// concatenated source labels, modulo the given modulus. This uses the
+    /// same hash as Prometheus, so it can be used to shard series the same
+    /// way.
+    #[must_use]
+    pub fn hash_mod(target_label: impl Into<String>, modulus: NonZeroU64) -> Self {
+        Self::new(Action::HashMod {
+            target_label: target_label.into(),
+            modulus,
+        })
+    }
+
+    /// The `lowercase` action: set the target label to the concatenated
+    /// source labels, in lowercase.
+    #[must_use]
+    pub fn lowercase(target_label: impl Into<String>) -> Self {
+        Self::new(Action::Lowercase {
+            target_label: target_label.into(),
+        })
+    }
+
+    /// Set the labels whose values are concatenated, separated by the
+    /// [separator](Self::separator), and matched against the regex. Missing
+    /// labels have an empty value.
+    #[must_use]
+    pub fn source_labels<I>(mut self, labels: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.source_labels = labels.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set the separator placed between the values of the source labels.
+    /// Defaults to `;`.
+    #[must_use]
+    pub fn separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = separator.into();
+        self
+    }
+
+    /// Set the regular expression matched against the concatenated source
+    /// labels, or against label names for the `labeldrop`, `labelkeep` and
+    /// `labelmap` actions. Like in Prometheus, it has to match the whole
+    /// value. Defaults to `(.*)`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the regular expression is invalid.
+    pub fn regex(mut self, regex: &str) -> Result<Self, regex::Error> {
+        self.regex = anchored(regex)?;
+        Ok(self)
+    }
+
+    /// Set the replacement of the `replace` and `labelmap` actions, which can
+    /// refer to the capture groups of the regex, like `$1` or `${name}`.
+    /// Defaults to `$1`.
+    #[must_use]
+    pub fn replacement(mut self, replacement: impl Into<String>) -> Self {
+        self.replacement = replacement.into();
+        self
+    }
+
+    /// The concatenated values of the source labels
+    fn source_value(&self, labels: &LabelSet) -> String {
+        let mut value = String::new();
+        for (index, label) in self.source_labels.iter().enumerate() {
+            if index > 0 {
+                value.push_str(&self.separator);
+            }
+            value.push_str(labels.get(label));
+        }
+        value
+    }
+
+    /// Apply the rule to the labels of a series.
+    ///
+    /// Returns `false` if the series is dropped.
+    fn apply(&self, labels: &mut LabelSet) -> bool {
+        match &self.action {
+            Action::Replace { target_label } => {
+                let value = self.source_value(labels);
+                let Some(captures) = self.regex.captures(&value) else {
+                    return true;
+                };
+
+                let mut target = String::new();
+                captures.expand(target_label, &mut target);
+                if !is_valid_label_name(&target) {
+                    return true;
+                }
+
+                let mut replacement = String::new();
+                captures.expand(&self.replacement, &mut replacement);
+                labels.set(&target, replacement);
+            }
+            Action::Keep => return self.regex.is_match(&self.source_value(labels)),
+            Action::Drop => return !self.regex.is_match(&self.source_value(labels)),
+            Action::LabelDrop => labels.labels.retain(|(name, _)| !self.regex.is_match(name)),
+            Action::LabelKeep => labels.labels.retain(|(name, _)| self.regex.is_match(name)),
+            Action::LabelMap => {
+                let mut mapped = Vec::new();
+                for (name, value) in &labels.labels {
+                    if let Some(captures) = self.regex.captures(name) {
+                        let mut target = String::new();
+                        captures.expand(&self.replacement, &mut target);
+                        if is_valid_label_name(&target) {
+                            mapped.push((target, value.clone()));
+                        }
+                    }
+                }
+
+                for (name, value) in mapped {
+                    labels.set(&name, value);
+                }
+            }
+            Action::HashMod {
+                target_label,
+                modulus,
+            } => {
+                if !is_valid_label_name(target_label) {
+                    return true;
+                }
+
+                let hash = Md5::digest(self.source_value(labels).as_bytes());
+                let hash = u64::from_be_bytes(hash[8..].try_into().expect("MD5 is 16 bytes"));
+                labels.set(target_label, (hash % modulus.get()).to_string());
+            }
+            Action::Lowercase { target_label } => {
+                if !is_valid_label_name(target_label) {
+                    return true;
+                }
+
+                let value = self.source_value(labels).to_lowercase();
+                labels.set(target_label, value);
+            }
+        }
+
+        true
+    }
+}
+
+/// Compile a regex which has to match the whole value
+fn anchored(regex: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{regex})$"))
+}
+
+/// Whether the name is a valid metric name, in the text format
+fn is_valid_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The labels of a series being relabeled, in the order they were added
+#[derive(Debug, Default)]
+pub(crate) struct LabelSet {
+    labels: Vec<(String, String)>,
+}
+
+impl LabelSet {
+    /// Create a label set for a series with the given name
+    pub fn with_name(name: String) -> Self {
+        Self {
+            labels: vec![(NAME_LABEL.to_owned(), name)],
+        }
+    }
+
+    /// Add a label, without checking if one with the same name exists
+    pub fn push(&mut self, name: &str, value: &str) {
+        self.labels.push((name.to_owned(), value.to_owned()));
+    }
+
+    /// The value of a label, or an empty string if it is missing
+    pub fn get(&self, name: &str) -> &str {
+        self.labels
+            .iter()
+            .find(|(label, _)| label == name)
+            .map_or("", |(_, value)| value)
+    }
+
+    /// Set the value of a label, or remove it if the value is empty
+    fn set(&mut self, name: &str, value: String) {
+        if value.is_empty() {
+            self.labels.retain(|(label, _)| label != name);
+        } else if let Some((_, existing)) = self.labels.iter_mut().find(|(label, _)| label == name)
+        {
+            *existing = value;
+        } else {
+            self.labels.push((name.to_owned(), value));
+        }
+    }
+
+    /// Apply the rules in order, and remove the labels starting with `__`.
+    ///
+    /// Returns the name of the series, or [`None`] if it is dropped. Series
+    /// renamed to an invalid metric name are dropped.
+    pub fn relabel(&mut self, rules: &[RelabelConfig]) -> Option<String> {
+        if !rules.iter().all(|rule| rule.apply(self)) {
+            return None;
+        }
+
+        let name = self.get(NAME_LABEL).to_owned();
+        self.labels
+            .retain(|(label, value)| !label.starts_with("__") && !value.is_empty());
+        is_valid_metric_name(&name).then_some(name)
+    }
+
+    /// Iterate over the labels
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> + Clone {
+        self.labels
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> LabelSet {
+        let mut labels = LabelSet::with_name("http_requests_total".to_owned());
+        for (name, value) in pairs {
+            labels.push(name, value);
+        }
+        labels
+    }
+
+    fn collect(labels: &LabelSet) -> Vec<(&str, &str)> {
+        labels.iter().collect()
+    }
+
+    #[test]
+    fn test_replace() {
+        let rule = RelabelConfig::replace("route")
+            .source_labels(["route"])
+            .regex("/users/[^/]+(/.*)?")
+            .unwrap()
+            .replacement("/users/:id$1");
+
+        let mut set = labels(&[("route", "/users/42/posts")]);
+        assert_eq!(
+            set.relabel(std::slice::from_ref(&rule)).as_deref(),
+            Some("http_requests_total")
+        );
+        assert_eq!(collect(&set), [("route", "/users/:id/posts")]);
+
+        // The regex has to match the whole value
+        let mut set = labels(&[("route", "/api/users/42")]);
+        set.relabel(&[rule]);
+        assert_eq!(collect(&set), [("route", "/api/users/42")]);
+    }
+
+    #[test]
+    fn test_replace_name() {
+        let rule = RelabelConfig::replace("__name__")
+            .source_labels(["__name__"])
+            .regex("http_(.*)")
+            .unwrap()
+            .replacement("legacy_http_$1");
+
+        let mut set = labels(&[]);
+        assert_eq!(
+            set.relabel(&[rule]).as_deref(),
+            Some("legacy_http_requests_total")
+        );
+    }
+
+    #[test]
+    fn test_invalid_names_drop_the_series() {
+        let rule = RelabelConfig::replace("__name__").replacement("http-requests");
+        assert!(labels(&[]).relabel(&[rule]).is_none());
+
+        let rule = RelabelConfig::replace("__name__").replacement("http:requests");
+        assert_eq!(
+            labels(&[]).relabel(&[rule]).as_deref(),
+            Some("http:requests")
+        );
+    }
+
+    #[test]
+    fn test_invalid_target_labels_are_ignored() {
+        let rules = [
+            RelabelConfig::label_map()
+                .regex("(method)")
+                .unwrap()
+                .replacement("http-$1"),
+            RelabelConfig::hash_mod("bad-name", NonZeroU64::new(10).unwrap())
+                .source_labels(["method"]),
+            RelabelConfig::lowercase("").source_labels(["method"]),
+        ];
+        let mut set = labels(&[("method", "GET")]);
+        set.relabel(&rules).unwrap();
+        assert_eq!(collect(&set), [("method", "GET")]);
+    }
+
+    #[test]
+    fn test_keep_and_drop() {
+        let keep = RelabelConfig::keep()
+            .source_labels(["method", "status"])
+            .regex("GET;2..")
+            .unwrap();
+        assert!(
+            labels(&[("method", "GET"), ("status", "200")])
+                .relabel(std::slice::from_ref(&keep))
+                .is_some()
+        );
+        assert!(
+            labels(&[("method", "GET"), ("status", "500")])
+                .relabel(std::slice::from_ref(&keep))
+                .is_none()
+        );
+        assert!(labels(&[]).relabel(&[keep]).is_none());
+
+        let drop = RelabelConfig::drop()
+            .source_labels(["__name__"])
+            .regex("http_.*")
+            .unwrap();
+        assert!(labels(&[]).relabel(&[drop]).is_none());
+    }
+
+    #[test]
+    fn test_label_drop_keep_map() {
+        let mut set = labels(&[
+            ("otel_scope_name", "app"),
+            ("otel_scope_version", "1.0"),
+            ("method", "GET"),
+        ]);
+        let rules = [
+            RelabelConfig::label_map()
+                .regex("otel_scope_(.*)")
+                .unwrap()
+                .replacement("scope_$1"),
+            RelabelConfig::label_drop().regex("otel_.*").unwrap(),
+            RelabelConfig::label_keep()
+                .regex("__name__|scope_name|method")
+                .unwrap(),
+        ];
+        set.relabel(&rules).unwrap();
+        assert_eq!(collect(&set), [("method", "GET"), ("scope_name", "app")]);
+
+        // Dropping the name drops the series
+        let rule = RelabelConfig::label_drop().regex("__name__").unwrap();
+        assert!(labels(&[]).relabel(&[rule]).is_none());
+    }
+
+    #[test]
+    fn test_hash_mod() {
+        // Prometheus takes the last 8 bytes of the MD5 as a big-endian integer
+        let rule = RelabelConfig::hash_mod("shard", NonZeroU64::new(1000).unwrap())
+            .source_labels(["instance"]);
+        let mut set = labels(&[("instance", "localhost:9090")]);
+        set.relabel(&[rule]).unwrap();
+        let shard: u64 = set.get("shard").parse().unwrap();
+        let hash = Md5::digest(b"localhost:9090");
+        assert_eq!(
+            shard,
+            u64::from_be_bytes(hash[8..].try_into().unwrap()) % 1000
+        );
+    }
+
+    #[test]
+    fn test_lowercase_and_empty_values() {
+        let rules = [
+            RelabelConfig::lowercase("method").source_labels(["method"]),
+            // Setting an empty value removes the label
+            RelabelConfig::replace("status").replacement(""),
+        ];
+        let mut set = labels(&[("method", "GET"), ("status", "200")]);
+        set.relabel(&rules).unwrap();
+        assert_eq!(collect(&set), [("method", "get")]);
+    }
+}
@@ -0,0 +1,383 @@
+use std::fmt;
+use std::str::FromStr;
+
+use regex::Regex;
+
+use crate::relabel::NAME_LABEL;
+
+/// How a label is compared to the value of a matcher
+#[derive(Debug, Clone)]
+enum MatchOp {
+    Equal(String),
+    NotEqual(String),
+    Regex(Regex),
+    NotRegex(Regex),
+}
+
+impl MatchOp {
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Self::Equal(expected) => value == expected,
+            Self::NotEqual(expected) => value != expected,
+            Self::Regex(regex) => regex.is_match(value),
+            Self::NotRegex(regex) => !regex.is_match(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LabelMatcher {
+    name: String,
+    op: MatchOp,
+}
+
+/// Selects series by name and labels, like a series selector of `PromQL`.
+///
+/// Selectors are parsed from a metric name, like `http_requests_total`, or a
+/// `PromQL` series selector, like `http_requests_total{method="GET"}` or
+/// `{__name__=~"http_.*",status!~"5.."}`. The `=`, `!=`, `=~` and `!~` matchers
+/// are supported, and a missing label has an empty value.
+///
+/// Names are matched against the name of the family, so selecting a histogram
+/// selects all its `_bucket`, `_sum` and `_count` series. Labels are matched
+/// against the labels as they are rendered, after relabeling.
+///
+/// # Example
+///
+/// ```rust
+/// use opentelemetry_prometheus_text_exporter::SeriesSelector;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let selector: SeriesSelector = "http_requests_total".parse()?;
+/// let selector: SeriesSelector = r#"{__name__=~"http_.*",method="GET"}"#.parse()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SeriesSelector {
+    matchers: Vec<LabelMatcher>,
+}
+
+impl SeriesSelector {
+    /// Parse a metric name or a series selector
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the selector is invalid, or has no matcher at all.
+    pub fn parse(selector: &str) -> Result<Self, ParseSelectorError> {
+        Parser::new(selector).parse()
+    }
+
+    /// Whether a family with the given name may have series matching the
+    /// selector
+    pub(crate) fn matches_name(&self, name: &str) -> bool {
+        self.matchers
+            .iter()
+            .filter(|matcher| matcher.name == NAME_LABEL)
+            .all(|matcher| matcher.op.matches(name))
+    }
+
+    /// Whether the series of the given family, with the given labels, is
+    /// selected
+    pub(crate) fn matches<'a, I>(&self, name: &str, labels: &I) -> bool
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)> + Clone,
+    {
+        self.matchers.iter().all(|matcher| {
+            let value = if matcher.name == NAME_LABEL {
+                name
+            } else {
+                labels
+                    .clone()
+                    .into_iter()
+                    .find(|(label, _)| *label == matcher.name)
+                    .map_or("", |(_, value)| value)
+            };
+            matcher.op.matches(value)
+        })
+    }
+}
+
+impl FromStr for SeriesSelector {
+    type Err = ParseSelectorError;
+
+    fn from_str(selector: &str) -> Result<Self, Self::Err> {
+        Self::parse(selector)
+    }
+}
+
+/// Error returned when parsing an invalid [`SeriesSelector`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSelectorError {
+    message: String,
+    position: usize,
+}
+
+impl ParseSelectorError {
+    /// The byte offset in the selector where the error was found
+    #[must_use]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl fmt::Display for ParseSelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid series selector at position {}: {}",
+            self.position, self.message
+        )
+    }
+}
+
+impl std::error::Error for ParseSelectorError {}
+
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input, position: 0 }
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseSelectorError {
+        ParseSelectorError {
+            message: message.into(),
+            position: self.position,
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Parse an identifier, allowing colons in metric names
+    fn identifier(&mut self, allow_colons: bool) -> Option<&'a str> {
+        let rest = self.rest();
+        let valid = |(index, c): &(usize, char)| {
+            c.is_ascii_alphabetic()
+                || *c == '_'
+                || (allow_colons && *c == ':')
+                || (*index > 0 && c.is_ascii_digit())
+        };
+        let len = rest
+            .char_indices()
+            .take_while(valid)
+            .last()
+            .map_or(0, |(index, c)| index + c.len_utf8());
+        if len == 0 {
+            return None;
+        }
+
+        self.position += len;
+        Some(&rest[..len])
+    }
+
+    /// Parse a quoted string, with the escape sequences of `PromQL`
+    fn string(&mut self) -> Result<String, ParseSelectorError> {
+        let Some(quote @ ('"' | '\'' | '`')) = self.peek() else {
+            return Err(self.error("expected a quoted string"));
+        };
+        let start = self.position;
+        self.position += 1;
+
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                c if c == quote => {
+                    self.position += index + 1;
+                    return Ok(value);
+                }
+                // Raw strings don't have escape sequences
+                '\\' if quote != '`' => {
+                    let escaped = match chars.next() {
+                        Some((_, 'n')) => '\n',
+                        Some((_, 't')) => '\t',
+                        Some((_, 'r')) => '\r',
+                        Some((_, c @ ('\\' | '"' | '\''))) => c,
+                        Some((offset, _)) => {
+                            self.position += offset;
+                            return Err(self.error("unknown escape sequence"));
+                        }
+                        None => break,
+                    };
+                    value.push(escaped);
+                }
+                c => value.push(c),
+            }
+        }
+
+        self.position = start;
+        Err(self.error("unterminated string"))
+    }
+
+    fn matcher(&mut self) -> Result<LabelMatcher, ParseSelectorError> {
+        let name = self
+            .identifier(false)
+            .ok_or_else(|| self.error("expected a label name"))?
+            .to_owned();
+
+        self.skip_whitespace();
+        let op = if self.eat("=~") {
+            "=~"
+        } else if self.eat("!~") {
+            "!~"
+        } else if self.eat("!=") {
+            "!="
+        } else if self.eat("=") {
+            "="
+        } else {
+            return Err(self.error("expected one of `=`, `!=`, `=~` or `!~`"));
+        };
+
+        self.skip_whitespace();
+        let value_position = self.position;
+        let value = self.string()?;
+        let regex = || {
+            // Like in `PromQL`, the regex has to match the whole value
+            Regex::new(&format!("^(?:{value})$")).map_err(|error| ParseSelectorError {
+                message: error.to_string(),
+                position: value_position,
+            })
+        };
+
+        let op = match op {
+            "=" => MatchOp::Equal(value),
+            "!=" => MatchOp::NotEqual(value),
+            "=~" => MatchOp::Regex(regex()?),
+            _ => MatchOp::NotRegex(regex()?),
+        };
+
+        Ok(LabelMatcher { name, op })
+    }
+
+    fn parse(mut self) -> Result<SeriesSelector, ParseSelectorError> {
+        let mut matchers = Vec::new();
+
+        self.skip_whitespace();
+        if let Some(name) = self.identifier(true) {
+            matchers.push(LabelMatcher {
+                name: NAME_LABEL.to_owned(),
+                op: MatchOp::Equal(name.to_owned()),
+            });
+        }
+
+        self.skip_whitespace();
+        if self.eat("{") {
+            loop {
+                self.skip_whitespace();
+                if self.eat("}") {
+                    break;
+                }
+
+                matchers.push(self.matcher()?);
+
+                self.skip_whitespace();
+                if self.eat("}") {
+                    break;
+                }
+                if !self.eat(",") {
+                    return Err(self.error("expected `,` or `}`"));
+                }
+            }
+        }
+
+        self.skip_whitespace();
+        if self.position < self.input.len() {
+            return Err(self.error("unexpected character"));
+        }
+
+        if matchers.is_empty() {
+            return Err(self.error("a selector needs a metric name or a label matcher"));
+        }
+
+        Ok(SeriesSelector { matchers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(selector: &str) -> SeriesSelector {
+        SeriesSelector::parse(selector).unwrap()
+    }
+
+    #[test]
+    fn test_metric_name() {
+        let selector = parse("http_requests_total");
+        assert!(selector.matches_name("http_requests_total"));
+        assert!(!selector.matches_name("http_requests"));
+        assert!(selector.matches("http_requests_total", &[("method", "GET")]));
+
+        assert!(parse("job:requests:rate5m").matches_name("job:requests:rate5m"));
+    }
+
+    #[test]
+    fn test_label_matchers() {
+        let selector = parse(r#"{__name__=~"http_.*", method="GET", status!~"5..",}"#);
+        assert!(selector.matches_name("http_requests_total"));
+        assert!(!selector.matches_name("rpc_requests_total"));
+
+        assert!(selector.matches(
+            "http_requests_total",
+            &[("method", "GET"), ("status", "200")]
+        ));
+        assert!(!selector.matches(
+            "http_requests_total",
+            &[("method", "GET"), ("status", "503")]
+        ));
+        assert!(!selector.matches("http_requests_total", &[("method", "POST")]));
+
+        // Missing labels have an empty value
+        let selector = parse(r#"up{job!="", instance=''}"#);
+        assert!(selector.matches("up", &[("job", "api")]));
+        assert!(!selector.matches("up", &[("job", "api"), ("instance", "a")]));
+        assert!(!selector.matches("up", &std::iter::empty()));
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let selector = parse(r#"{path="a\"b\\c", raw=`\d+`}"#);
+        assert!(selector.matches("x", &[("path", r#"a"b\c"#), ("raw", r"\d+")]));
+    }
+
+    #[test]
+    fn test_invalid_selectors() {
+        for (selector, position) in [
+            ("", 0),
+            ("{}", 2),
+            ("up{", 3),
+            ("up{method}", 9),
+            (r#"up{method="GET""#, 15),
+            (r#"up{method="GET}"#, 10),
+            (r#"up{method=~"("}"#, 11),
+            ("up extra", 3),
+        ] {
+            let error = SeriesSelector::parse(selector).unwrap_err();
+            assert_eq!(error.position(), position, "{selector}: {error}");
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Metrics about the exporter itself.
+//!
+//! When enabled with [`ExporterBuilder::with_self_metrics`], the exporter
+//! appends a few families about the current scrape after the user metrics.
+//!
+//! [`ExporterBuilder::with_self_metrics`]: crate::ExporterBuilder::with_self_metrics
+
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::serialize::{Family, PrometheusSerializer, Skipped};
+
+/// The kinds of errors an export can fail with
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ExportErrorKind {
+    /// The metrics could not be collected
+    Collect,
+
+    /// The exposition could not be written
+    Write,
+
+    /// The collection did not finish in time
+    Timeout,
+}
+
+impl ExportErrorKind {
+    const ALL: [Self; 3] = [Self::Collect, Self::Write, Self::Timeout];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Collect => "collect",
+            Self::Write => "write",
+            Self::Timeout => "timeout",
+        }
+    }
+}
+
+/// Counters kept across scrapes
+#[derive(Debug, Default)]
+pub(crate) struct SelfMetrics {
+    scrapes: AtomicU64,
+    errors: [AtomicU64; ExportErrorKind::ALL.len()],
+}
+
+impl SelfMetrics {
+    /// Start recording a new scrape
+    pub fn start_scrape(&self) -> ScrapeRecorder<'_> {
+        self.scrapes.fetch_add(1, Ordering::Relaxed);
+        ScrapeRecorder {
+            metrics: self,
+            started_at: Instant::now(),
+            collect_duration: None,
+            bytes: 0,
+            series: 0,
+            families: 0,
+            dropped_unsupported: 0,
+            dropped_filtered: 0,
+            conflicting: 0,
+            overflows: Vec::new(),
+        }
+    }
+
+    /// Record a failed export
+    pub fn record_error(&self, kind: ExportErrorKind) {
+        self.errors[kind as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Records statistics about the scrape in progress
+#[derive(Debug)]
+pub(crate) struct ScrapeRecorder<'a> {
+    metrics: &'a SelfMetrics,
+    started_at: Instant,
+    collect_duration: Option<Duration>,
+    bytes: u64,
+    series: u64,
+    families: u64,
+    dropped_unsupported: u64,
+    dropped_filtered: u64,
+    conflicting: u64,
+    overflows: Vec<(String, u64)>,
+}
+
+impl ScrapeRecorder<'_> {
+    /// Mark the end of the collection
+    pub fn collected(&mut self) {
+        self.collect_duration = Some(self.started_at.elapsed());
+    }
+
+    /// Record a failed export. Errors happening before the end of the
+    /// collection are collection errors, the others are write errors.
+    pub fn failed(&self) {
+        let kind = if self.collect_duration.is_some() {
+            ExportErrorKind::Write
+        } else {
+            ExportErrorKind::Collect
+        };
+        self.metrics.record_error(kind);
+    }
+
+    /// Record a family rendered in the given number of bytes
+    pub fn record_family(
+        &mut self,
+        serializer: &PrometheusSerializer,
+        family: &Family<'_>,
+        bytes: usize,
+    ) {
+        self.bytes += bytes as u64;
+
+        let stats = match serializer.family_stats(family) {
+            Ok(stats) => stats,
+            Err(Skipped::UnsupportedType) => {
+                self.dropped_unsupported += 1;
+                return;
+            }
+            Err(Skipped::Filtered) => {
+                self.dropped_filtered += 1;
+                return;
+            }
+            Err(Skipped::Empty) => return,
+        };
+
+        self.families += 1;
+        self.series += stats.series as u64;
+
+        if stats.overflowed > 0 {
+            self.overflows
+                .push((stats.name.to_string(), stats.overflowed as u64));
+        }
+
+        self.conflicting += stats.conflicting as u64;
+    }
+
+    /// Get the statistics of the scrape so far
+    pub fn stats(&self) -> ScrapeStats {
+        let scrape_duration = self.started_at.elapsed();
+        let collect_duration = self.collect_duration.unwrap_or_default();
+
+        ScrapeStats {
+            scrape_duration,
+            collect_duration,
+            serialize_duration: scrape_duration.saturating_sub(collect_duration),
+            bytes: self.bytes,
+            families: self.families,
+            series: self.series,
+            dropped_unsupported: self.dropped_unsupported,
+            dropped_filtered: self.dropped_filtered,
+            conflicting: self.conflicting,
+            overflows: self.overflows.clone(),
+            scrapes: self.metrics.scrapes.load(Ordering::Relaxed),
+            errors: ExportErrorKind::ALL.map(|kind| {
+                (
+                    kind.as_str(),
+                    self.metrics.errors[kind as usize].load(Ordering::Relaxed),
+                )
+            }),
+        }
+    }
+}
+
+/// Statistics about a scrape, rendered by
+/// [`PrometheusSerializer::serialize_self_metrics`]
+#[derive(Debug)]
+pub(crate) struct ScrapeStats {
+    pub scrape_duration: Duration,
+    pub collect_duration: Duration,
+    pub serialize_duration: Duration,
+    pub bytes: u64,
+    pub families: u64,
+    pub series: u64,
+    pub dropped_unsupported: u64,
+    pub dropped_filtered: u64,
+    pub conflicting: u64,
+    /// The families which exceeded their cardinality limit, with the number of
+    /// series folded in their overflow series
+    pub overflows: Vec<(String, u64)>,
+    pub scrapes: u64,
+    pub errors: [(&'static str, u64); ExportErrorKind::ALL.len()],
+}
+
+/// Counts the bytes written to the inner writer
+pub(crate) struct CountingWriter<'a, W> {
+    inner: &'a mut W,
+    count: usize,
+}
+
+impl<'a, W: Write> CountingWriter<'a, W> {
+    pub fn new(inner: &'a mut W) -> Self {
+        Self { inner, count: 0 }
+    }
+
+    /// Get the number of bytes written since the last call, and reset it
+    pub fn take_count(&mut self) -> usize {
+        std::mem::take(&mut self.count)
+    }
+}
+
+impl<W: Write> Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_by_kind() {
+        let metrics = SelfMetrics::default();
+
+        let recorder = metrics.start_scrape();
+        recorder.failed();
+
+        let mut recorder = metrics.start_scrape();
+        recorder.collected();
+        recorder.failed();
+        metrics.record_error(ExportErrorKind::Timeout);
+
+        let stats = metrics.start_scrape().stats();
+        assert_eq!(stats.scrapes, 3);
+        assert_eq!(stats.errors, [("collect", 1), ("write", 1), ("timeout", 1)]);
+    }
+
+    #[test]
+    fn test_counting_writer() {
+        let mut output = Vec::new();
+        let mut writer = CountingWriter::new(&mut output);
+        write!(writer, "hello").unwrap();
+        assert_eq!(writer.take_count(), 5);
+        write!(writer, " world").unwrap();
+        assert_eq!(writer.take_count(), 6);
+        assert_eq!(output, b"hello world");
+    }
+}
@@ -22,345 +22,1853 @@
 //! - Instrumentation scope information is added as `otel_scope_*` labels
 
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::fmt::Write as _;
 use std::io::Write;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use opentelemetry::KeyValue;
+use opentelemetry::{InstrumentationScope, KeyValue};
 use opentelemetry_sdk::Resource;
 use opentelemetry_sdk::metrics::Temporality;
 use opentelemetry_sdk::metrics::data::{
-    AggregatedMetrics, Gauge, Histogram, Metric, MetricData, ResourceMetrics, Sum,
+    AggregatedMetrics, GaugeDataPoint, HistogramDataPoint, Metric, MetricData, ResourceMetrics,
+    SumDataPoint,
 };
 use smartstring::SmartString;
 
+use crate::cardinality::{AdmittedSeries, attributes_key, metric_key, resource_key};
 use crate::exporter::ExporterConfig;
+use crate::filter::InstrumentType;
+use crate::idle::{IdleSeries, ScrapeToken, series_key};
+use crate::limits::LimitedLabels;
+use crate::metadata::MetadataOverride;
+#[cfg(feature = "otlp")]
+use crate::otlp::{OtlpData, OtlpMetric};
+use crate::registry::Discriminator;
+use crate::relabel::LabelSet;
+use crate::selector::SeriesSelector;
+use crate::self_metrics::ScrapeStats;
 
 /// Prometheus format serializer with configurable options
 #[derive(Debug, Clone)]
 pub(crate) struct PrometheusSerializer {
     /// Configuration options for the serializer
     config: ExporterConfig,
+
+    /// Number of series written with their name or labels cut to fit in the
+    /// label limits, across all scrapes
+    truncated_series: Arc<AtomicU64>,
+
+    /// Number of series left out because they don't fit in the label limits,
+    /// across all scrapes
+    dropped_series: Arc<AtomicU64>,
+
+    /// The series rendered as-is under the cardinality limit of each metric,
+    /// across all scrapes
+    admitted: Arc<AdmittedSeries>,
+
+    /// The last values of the series, if idle series are left out
+    idle: Option<Arc<IdleSeries>>,
+}
+
+/// A single metric family of an exposition
+pub(crate) enum Family<'a> {
+    /// A metric, rendered with the labels of its instrumentation scope
+    Metric {
+        metric: MetricRef<'a>,
+        scope: &'a InstrumentationScope,
+        resource: &'a Resource,
+
+        /// The metadata of the family, or why it is skipped, computed once by
+        /// [`PrometheusSerializer::merge_families`]
+        metadata: Option<Result<FamilyMetadata<'a>, Skipped>>,
+    },
+
+    /// Metrics of several scopes or producers sharing the same name,
+    /// rendered as a single family
+    Merged {
+        parts: Vec<Family<'a>>,
+
+        /// The number of metrics dropped because they have the same name but
+        /// another type
+        conflicting: usize,
+    },
+
+    /// The `target_info` family, rendering all the resources at once
+    TargetInfo { resources: Vec<&'a Resource> },
+}
+
+impl<'a> Family<'a> {
+    /// The instrumentation scope of the family, or of its first part if it
+    /// is merged
+    pub fn scope(&self) -> Option<&'a InstrumentationScope> {
+        match self {
+            Self::Metric { scope, .. } => Some(scope),
+            Self::Merged { parts, .. } => parts.first().and_then(Family::scope),
+            Self::TargetInfo { .. } => None,
+        }
+    }
+}
+
+/// A metric to render, either collected from the SDK or pushed over OTLP
+#[derive(Clone, Copy)]
+pub(crate) enum MetricRef<'a> {
+    Sdk(&'a Metric),
+    #[cfg(feature = "otlp")]
+    Otlp(&'a OtlpMetric),
+}
+
+impl<'a> MetricRef<'a> {
+    fn name(self) -> &'a str {
+        match self {
+            Self::Sdk(metric) => metric.name(),
+            #[cfg(feature = "otlp")]
+            Self::Otlp(metric) => &metric.name,
+        }
+    }
+
+    fn description(self) -> &'a str {
+        match self {
+            Self::Sdk(metric) => metric.description(),
+            #[cfg(feature = "otlp")]
+            Self::Otlp(metric) => &metric.description,
+        }
+    }
+
+    fn unit(self) -> &'a str {
+        match self {
+            Self::Sdk(metric) => metric.unit(),
+            #[cfg(feature = "otlp")]
+            Self::Otlp(metric) => &metric.unit,
+        }
+    }
+
+    /// The Prometheus type of the metric, and whether it is a monotonic sum
+    /// rendered as a counter.
+    ///
+    /// Returns [`None`] if the metric has a type which can't be rendered.
+    fn prometheus_type(self) -> Option<(&'static str, bool)> {
+        match self {
+            Self::Sdk(metric) => get_prometheus_type_and_is_monotonic(metric.data()),
+            #[cfg(feature = "otlp")]
+            Self::Otlp(metric) => Some(match &metric.data {
+                OtlpData::Gauge(_)
+                | OtlpData::Sum {
+                    monotonic: false, ..
+                } => ("gauge", false),
+                OtlpData::Sum {
+                    monotonic: true, ..
+                } => ("counter", true),
+                OtlpData::Histogram(_) => ("histogram", false),
+            }),
+        }
+    }
+
+    fn instrument_type(self) -> InstrumentType {
+        match self {
+            Self::Sdk(metric) => InstrumentType::of(metric.data()),
+            #[cfg(feature = "otlp")]
+            Self::Otlp(metric) => match &metric.data {
+                OtlpData::Gauge(_) => InstrumentType::Gauge,
+                OtlpData::Sum {
+                    monotonic: true, ..
+                } => InstrumentType::Counter,
+                OtlpData::Sum {
+                    monotonic: false, ..
+                } => InstrumentType::UpDownCounter,
+                OtlpData::Histogram(_) => InstrumentType::Histogram,
+            },
+        }
+    }
+}
+
+/// How the series of a family are rendered
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RenderOptions<'a> {
+    /// The series to render, all of them if empty
+    pub selectors: &'a [SeriesSelector],
+
+    /// Whether to leave out the idle series, counting this as a scrape
+    pub track_idle: bool,
+
+    /// The labels telling apart the series of several exporters
+    pub discriminator: Discriminator<'a>,
+
+    /// The name of the family whose metadata was already written, along with
+    /// another part of a merged family
+    pub written_family: Option<&'a str>,
+}
+
+impl Default for RenderOptions<'_> {
+    fn default() -> Self {
+        Self {
+            selectors: &[],
+            track_idle: true,
+            discriminator: Discriminator::None,
+            written_family: None,
+        }
+    }
+}
+
+/// Name, type, description and unit of a metric family, after all the
+/// transformations
+#[derive(Debug, Clone)]
+pub(crate) struct FamilyMetadata<'a> {
+    name: Cow<'a, str>,
+    prometheus_type: &'static str,
+    description: Cow<'a, str>,
+    unit: Cow<'a, str>,
+
+    /// Whether the name was cut to fit in the label limits
+    truncated: bool,
+}
+
+/// Why a family is not rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Skipped {
+    /// The family has nothing to render, or is disabled
+    Empty,
+
+    /// The metric has a type which can't be rendered
+    UnsupportedType,
+
+    /// The metric was left out by the filter rules
+    Filtered,
+}
+
+/// Statistics about a family, as returned by
+/// [`PrometheusSerializer::family_stats`]
+pub(crate) struct FamilyStats<'a> {
+    /// The name of the family, after all the transformations
+    pub name: Cow<'a, str>,
+
+    /// The number of samples in the family
+    pub series: usize,
+
+    /// The number of data points folded in the overflow series, because the
+    /// metric exceeded its cardinality limit
+    pub overflowed: usize,
+
+    /// The number of metrics dropped because they have the same name as the
+    /// family but another type
+    pub conflicting: usize,
+}
+
+/// The name of the family with the given metadata, borrowed from it if it is
+fn family_name<'f>(metadata: Cow<'f, FamilyMetadata<'_>>) -> Cow<'f, str> {
+    match metadata {
+        Cow::Borrowed(metadata) => Cow::Borrowed(&metadata.name),
+        Cow::Owned(metadata) => Cow::Owned(metadata.name.into_owned()),
+    }
+}
+
+/// Where [`PrometheusSerializer::merge_families`] puts a family
+#[derive(Clone, Copy)]
+enum Placement {
+    /// In a new position
+    New,
+
+    /// With the family of the same name at the given position
+    Merged(usize),
+
+    /// Left out, as the family of the same name at the given position has
+    /// another type
+    Conflicting(usize),
 }
 
 impl PrometheusSerializer {
     /// Create a new serializer with default configuration
     pub fn new() -> Self {
-        Self {
-            config: ExporterConfig::default(),
-        }
+        Self::with_config(ExporterConfig::default())
     }
 
     /// Create a new serializer with the given configuration
     pub fn with_config(config: ExporterConfig) -> Self {
-        Self { config }
-    }
+        let idle = config
+            .idle_timeout
+            .is_set()
+            .then(|| Arc::new(IdleSeries::new(config.idle_timeout)));
 
-    /// Serialize ResourceMetrics to Prometheus format
-    pub fn serialize<W: Write>(&self, rm: &ResourceMetrics, writer: &mut W) -> std::io::Result<()> {
-        self.serialize_resource_metrics(rm, writer)
+        Self {
+            config,
+            truncated_series: Arc::default(),
+            dropped_series: Arc::default(),
+            admitted: Arc::default(),
+            idle,
+        }
     }
 
-    fn serialize_resource_metrics<W: Write>(
+    /// Serialize ResourceMetrics to Prometheus format
+    ///
+    /// Multiple ResourceMetrics can be rendered in a single exposition.
+    ///
+    /// The resources of all of them are rendered in a single `target_info`
+    /// family, after all the metrics.
+    pub fn serialize<W: Write>(
         &self,
-        rm: &ResourceMetrics,
+        rms: &[ResourceMetrics],
         writer: &mut W,
     ) -> std::io::Result<()> {
-        // Serialize all scope metrics first
-        for sm in rm.scope_metrics() {
-            self.serialize_scope_metrics(sm, writer)?;
+        for family in self.families(rms) {
+            self.serialize_family(&family, writer)?;
         }
 
-        // Serialize resource as target_info
-        self.serialize_resource(rm.resource(), writer)?;
-
         Ok(())
     }
 
-    fn serialize_resource<W: Write>(
+    /// List the families to render for the given ResourceMetrics, in order
+    ///
+    /// Metrics sharing the same name are merged, see
+    /// [`Self::merge_families`]. Each family can then be rendered separately
+    /// with [`Self::serialize_family`], which lets callers flush the output in
+    /// family-sized chunks.
+    pub fn families<'a>(&self, rms: &'a [ResourceMetrics]) -> Vec<Family<'a>> {
+        let metrics = rms.iter().flat_map(|rm| {
+            rm.scope_metrics().flat_map(move |scope_metrics| {
+                scope_metrics.metrics().map(move |metric| Family::Metric {
+                    metric: MetricRef::Sdk(metric),
+                    scope: scope_metrics.scope(),
+                    resource: rm.resource(),
+                    metadata: None,
+                })
+            })
+        });
+
+        self.merge_families(metrics.chain(std::iter::once(Family::TargetInfo {
+            resources: rms.iter().map(ResourceMetrics::resource).collect(),
+        })))
+    }
+
+    /// Merge the metrics rendered with the same name, like the ones of
+    /// several scopes or of a producer and the reader, in the position of the
+    /// first one.
+    ///
+    /// Prometheus rejects expositions with several families of the same name,
+    /// so metrics of another type than the first one with their name are
+    /// dropped, and counted as conflicting.
+    ///
+    /// The metadata of every metric is computed here and kept in its family,
+    /// so that rendering it doesn't compute it again.
+    pub fn merge_families<'a>(
         &self,
-        resource: &Resource,
+        families: impl IntoIterator<Item = Family<'a>>,
+    ) -> Vec<Family<'a>> {
+        let families: Vec<Family<'a>> = families
+            .into_iter()
+            .map(|mut family| {
+                if let Family::Metric {
+                    metric,
+                    scope,
+                    metadata,
+                    ..
+                } = &mut family
+                {
+                    *metadata = Some(self.selected_metadata(*metric, scope));
+                }
+                family
+            })
+            .collect();
+
+        // Where each family goes, found before moving them so that the names
+        // are borrowed from their metadata
+        let mut placements = Vec::with_capacity(families.len());
+        let mut by_name: HashMap<&str, (usize, &'static str)> = HashMap::new();
+        let mut count = 0;
+        for family in &families {
+            let Family::Metric {
+                metadata: Some(Ok(metadata)),
+                ..
+            } = family
+            else {
+                placements.push(Placement::New);
+                count += 1;
+                continue;
+            };
+
+            match by_name.entry(&metadata.name) {
+                Entry::Vacant(entry) => {
+                    entry.insert((count, metadata.prometheus_type));
+                    placements.push(Placement::New);
+                    count += 1;
+                }
+                Entry::Occupied(entry) => {
+                    let (position, prometheus_type) = *entry.get();
+                    placements.push(if metadata.prometheus_type == prometheus_type {
+                        Placement::Merged(position)
+                    } else {
+                        Placement::Conflicting(position)
+                    });
+                }
+            }
+        }
+
+        let mut merged: Vec<Family<'a>> = Vec::with_capacity(count);
+        for (family, placement) in families.into_iter().zip(placements) {
+            let position = match placement {
+                Placement::New => {
+                    merged.push(family);
+                    continue;
+                }
+                Placement::Merged(position) | Placement::Conflicting(position) => position,
+            };
+
+            let existing = &mut merged[position];
+            if let Family::Metric { .. } = existing {
+                let first = std::mem::replace(
+                    existing,
+                    Family::Merged {
+                        parts: Vec::new(),
+                        conflicting: 0,
+                    },
+                );
+                if let Family::Merged { parts, .. } = existing {
+                    parts.push(first);
+                }
+            }
+            if let Family::Merged { parts, conflicting } = existing {
+                if let Placement::Merged(_) = placement {
+                    parts.push(family);
+                } else {
+                    *conflicting += 1;
+                }
+            }
+        }
+
+        merged
+    }
+
+    /// Serialize a single family, as returned by [`Self::families`]
+    pub fn serialize_family<W: Write>(
+        &self,
+        family: &Family<'_>,
         writer: &mut W,
     ) -> std::io::Result<()> {
-        // Don't serialize empty resources or if target_info is disabled
-        if resource.is_empty() || self.config.disable_target_info {
-            return Ok(());
-        }
+        self.render_family(family, &RenderOptions::default(), writer)
+    }
 
-        write_type_comment(writer, "target_info", "gauge")?;
-        write_help_comment(writer, "target_info", "Target metadata")?;
+    /// Serialize the series of a family matching one of the given selectors,
+    /// or all of them if there are no selectors.
+    ///
+    /// Unlike [`Self::serialize_family`], idle series are rendered, and this
+    /// doesn't count as a scrape to tell whether series are idle.
+    pub fn serialize_selected<W: Write>(
+        &self,
+        family: &Family<'_>,
+        selectors: &[SeriesSelector],
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        let options = RenderOptions {
+            selectors,
+            track_idle: false,
+            ..RenderOptions::default()
+        };
+        self.render_family(family, &options, writer)
+    }
 
-        write!(writer, "target_info")?;
+    fn render_family<W: Write>(
+        &self,
+        family: &Family<'_>,
+        options: &RenderOptions<'_>,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        let written = self.serialize_part(family, options, writer)?;
 
-        let mut label_writer = LabelWriter::new(writer);
-        for (key, value) in resource.iter() {
-            let sanitized_key = sanitize_name(key.as_str());
-            let mut value_buf = SmartString::<smartstring::LazyCompact>::new();
-            write!(&mut value_buf, "{value}").map_err(std::io::Error::other)?;
-            label_writer.emit(&sanitized_key, &value_buf)?;
+        // Families are separated by an empty line, except `target_info` which
+        // comes last
+        if written.is_some() && !matches!(family, Family::TargetInfo { .. }) {
+            writeln!(writer)?;
         }
-        label_writer.finish()?;
-
-        writeln!(writer, " 1")?;
 
         Ok(())
     }
 
-    fn serialize_scope_metrics<W: Write>(
+    /// Serialize the series of a family, which may be a part of a family
+    /// merged from several exporters.
+    ///
+    /// The metadata of the family is written before its first series, unless
+    /// [`RenderOptions::written_family`] is set, in which case only the series
+    /// named after it are written. Returns the name of the family if any
+    /// series was written, which the relabeling rules may have changed.
+    pub fn serialize_part<'f, W: Write>(
+        &self,
+        family: &'f Family<'_>,
+        options: &RenderOptions<'_>,
+        writer: &mut W,
+    ) -> std::io::Result<Option<Cow<'f, str>>> {
+        match family {
+            Family::Metric {
+                metric,
+                scope,
+                resource,
+                metadata,
+            } => {
+                // Skip unsupported and filtered out metrics
+                let Ok(metadata) = self.cached_metadata(*metric, scope, metadata) else {
+                    return Ok(None);
+                };
+                self.serialize_metric(*metric, metadata, scope, resource, options, writer)
+            }
+            Family::Merged { parts, .. } => {
+                let mut written: Option<Cow<'f, str>> = None;
+                for part in parts {
+                    let options = RenderOptions {
+                        written_family: options.written_family.or(written.as_deref()),
+                        ..*options
+                    };
+                    let name = self.serialize_part(part, &options, writer)?;
+                    if written.is_none() {
+                        written = name;
+                    }
+                }
+                Ok(written)
+            }
+            Family::TargetInfo { resources } => {
+                let written =
+                    self.serialize_resources(unique_resources(resources), options, writer)?;
+                Ok(written.then_some(Cow::Borrowed("target_info")))
+            }
+        }
+    }
+
+    /// Start a new scrape, for the tracking of idle series, which lasts
+    /// until the returned token is dropped
+    pub fn start_scrape(&self) -> Option<ScrapeToken<'_>> {
+        self.idle.as_deref().map(IdleSeries::start_scrape)
+    }
+
+    /// Serialize the metrics about the exporter itself
+    ///
+    /// They are meant to be rendered after all the user metrics.
+    pub fn serialize_self_metrics<W: Write>(
         &self,
-        scope_metrics: &opentelemetry_sdk::metrics::data::ScopeMetrics,
+        stats: &ScrapeStats,
         writer: &mut W,
     ) -> std::io::Result<()> {
-        for metric in scope_metrics.metrics() {
-            self.serialize_metric(metric, scope_metrics, writer)?;
+        write_self_metric(
+            writer,
+            "otel_prometheus_exporter_scrape_duration_seconds",
+            "Time spent collecting and serializing the metrics of this scrape",
+            "gauge",
+            &[(None, stats.scrape_duration.as_secs_f64())],
+        )?;
+        write_self_metric(
+            writer,
+            "otel_prometheus_exporter_collect_duration_seconds",
+            "Time spent collecting the metrics of this scrape",
+            "gauge",
+            &[(None, stats.collect_duration.as_secs_f64())],
+        )?;
+        write_self_metric(
+            writer,
+            "otel_prometheus_exporter_serialize_duration_seconds",
+            "Time spent serializing the metrics of this scrape",
+            "gauge",
+            &[(None, stats.serialize_duration.as_secs_f64())],
+        )?;
+        write_self_metric(
+            writer,
+            "otel_prometheus_exporter_exposition_size_bytes",
+            "Size of the exposition of this scrape, excluding the exporter metrics",
+            "gauge",
+            &[(None, stats.bytes)],
+        )?;
+        write_self_metric(
+            writer,
+            "otel_prometheus_exporter_families",
+            "Number of metric families in this scrape, excluding the exporter metrics",
+            "gauge",
+            &[(None, stats.families)],
+        )?;
+        write_self_metric(
+            writer,
+            "otel_prometheus_exporter_series",
+            "Number of series in this scrape, excluding the exporter metrics",
+            "gauge",
+            &[(None, stats.series)],
+        )?;
+        write_self_metric(
+            writer,
+            "otel_prometheus_exporter_dropped_metrics",
+            "Number of metrics left out of this scrape",
+            "gauge",
+            &[
+                (
+                    Some(("reason", "unsupported_type")),
+                    stats.dropped_unsupported,
+                ),
+                (Some(("reason", "filtered")), stats.dropped_filtered),
+            ],
+        )?;
+        write_self_metric(
+            writer,
+            "otel_prometheus_exporter_conflicting_metrics",
+            "Number of metrics left out of this scrape because they are named like another one of a different type",
+            "gauge",
+            &[(None, stats.conflicting)],
+        )?;
+
+        if !stats.overflows.is_empty() {
+            let overflows: Vec<_> = stats
+                .overflows
+                .iter()
+                .map(|(name, count)| (Some(("metric", name.as_str())), *count))
+                .collect();
+            write_self_metric(
+                writer,
+                "otel_prometheus_exporter_overflowed_series",
+                "Number of series folded in the overflow series of each metric over its cardinality limit",
+                "gauge",
+                &overflows,
+            )?;
+        }
+
+        if let Some(idle) = &self.idle {
+            write_self_metric(
+                writer,
+                "otel_prometheus_exporter_idle_series",
+                "Number of series left out of this scrape because their value did not change for a while",
+                "gauge",
+                &[(None, idle.suppressed())],
+            )?;
         }
+
+        if self.config.label_limits.is_set() {
+            write_self_metric(
+                writer,
+                "otel_prometheus_exporter_truncated_series_total",
+                "Number of series written with their name or labels cut to fit in the label limits",
+                "counter",
+                &[(None, self.truncated_series.load(Ordering::Relaxed))],
+            )?;
+            write_self_metric(
+                writer,
+                "otel_prometheus_exporter_label_limit_dropped_series_total",
+                "Number of series left out because they don't fit in the label limits",
+                "counter",
+                &[(None, self.dropped_series.load(Ordering::Relaxed))],
+            )?;
+        }
+
+        write_self_metric(
+            writer,
+            "otel_prometheus_exporter_scrapes_total",
+            "Number of scrapes started",
+            "counter",
+            &[(None, stats.scrapes)],
+        )?;
+
+        let errors = stats
+            .errors
+            .map(|(kind, count)| (Some(("kind", kind)), count));
+        write_self_metric(
+            writer,
+            "otel_prometheus_exporter_errors_total",
+            "Number of failed exports, by kind of error",
+            "counter",
+            &errors,
+        )?;
+
         Ok(())
     }
 
-    fn serialize_metric<W: Write>(
+    fn serialize_resources<'a, W: Write>(
         &self,
-        metric: &Metric,
-        scope_metrics: &opentelemetry_sdk::metrics::data::ScopeMetrics,
+        resources: impl Iterator<Item = &'a Resource>,
+        options: &RenderOptions<'_>,
         writer: &mut W,
-    ) -> std::io::Result<()> {
-        let data = metric.data();
+    ) -> std::io::Result<bool> {
+        if self.config.disable_target_info || !self.may_select("target_info", options.selectors) {
+            return Ok(false);
+        }
 
-        let Some((prometheus_type, is_monotonic)) = get_prometheus_type_and_is_monotonic(data)
-        else {
-            return Ok(()); // Skip unsupported metrics
+        let mut family = FamilyWriter {
+            name: "target_info",
+            pending_header: options.written_family.is_none().then_some(FamilyHeader {
+                prometheus_type: "gauge",
+                description: "Target metadata",
+                unit: "",
+            }),
+            labels: LabelBuffer::default(),
+            selectors: options.selectors,
+            written_family: options.written_family,
+            renamed: None,
+            truncated_name: false,
+            track_idle: false,
+            resource: 0,
+            resource_labels: &[],
+            discriminator_labels: &[],
+            written: false,
         };
 
-        // Apply name transformations
-        let sanitized_name = sanitize_name(metric.name());
+        for resource in resources {
+            let discriminator_labels = options.discriminator.labels(resource);
+            let write_labels = |labels: &mut dyn LabelSink| {
+                for (key, value) in resource.iter() {
+                    let sanitized_key = sanitize_name(key.as_str());
+                    if discriminator_labels
+                        .iter()
+                        .any(|(name, _)| *name == sanitized_key)
+                    {
+                        continue;
+                    }
+                    let mut value_buf = SmartString::<smartstring::LazyCompact>::new();
+                    write!(&mut value_buf, "{value}").map_err(std::io::Error::other)?;
+                    labels.emit(&sanitized_key, &value_buf)?;
+                }
+                for (key, value) in &discriminator_labels {
+                    labels.emit(key, value)?;
+                }
+                Ok(())
+            };
+            self.write_sample(&mut family, "", write_labels, 1u64, writer)?;
+        }
+
+        Ok(family.written)
+    }
+
+    /// The attributes of the given resource added as labels to every series
+    fn resource_labels(&self, resource: &Resource) -> Vec<(String, String)> {
+        if !self.config.resource_labels.is_set() {
+            return Vec::new();
+        }
+
+        let mut labels: Vec<_> = resource
+            .iter()
+            .filter(|(key, _)| self.config.resource_labels.includes(key.as_str()))
+            .map(|(key, value)| (sanitize_name(key.as_str()).into_owned(), value.to_string()))
+            .collect();
+
+        // Resources don't keep their attributes in order
+        labels.sort_unstable();
+        labels
+    }
+
+    /// Compute the name, type, description and unit of the family rendering
+    /// the given metric, with the metadata overrides applied.
+    ///
+    /// Returns [`None`] if the metric has an unsupported type.
+    fn family_metadata<'a>(
+        &self,
+        metric: MetricRef<'a>,
+        scope: &InstrumentationScope,
+    ) -> Option<FamilyMetadata<'a>> {
+        let (prometheus_type, is_monotonic) = metric.prometheus_type()?;
+        let overrides = self
+            .config
+            .metadata_overrides
+            .get(metric.name(), scope.name());
 
         // Convert units only if not disabled
         let converted_unit = if self.config.without_units {
             Cow::Borrowed("")
+        } else if let Some(unit) = overrides.and_then(MetadataOverride::unit_override) {
+            Cow::Owned(convert_unit(unit).into_owned())
         } else {
             convert_unit(metric.unit())
         };
 
+        let description = match overrides.and_then(MetadataOverride::description_override) {
+            Some(description) => Cow::Owned(description.to_owned()),
+            None => Cow::Borrowed(metric.description()),
+        };
+        let description = if description.is_empty() && self.config.generate_help {
+            Cow::Owned(format!("OpenTelemetry instrument {}", metric.name()))
+        } else {
+            description
+        };
+
+        // An overridden name is used as is
+        let name = match overrides.and_then(MetadataOverride::prometheus_name) {
+            Some(name) => Cow::Owned(sanitize_name(name).into_owned()),
+            None => self.prometheus_name(metric, is_monotonic, &converted_unit),
+        };
+
+        // Keep the `_total` suffix of counters, and leave room for the
+        // `_bucket` suffix of the histogram series
+        let (suffix, series_suffix_len) = match prometheus_type {
+            "counter" => ("_total", 0),
+            "histogram" => ("", "_bucket".len()),
+            _ => ("", 0),
+        };
+        let (name, truncated) =
+            self.config
+                .label_limits
+                .metric_name(name, suffix, series_suffix_len);
+
+        Some(FamilyMetadata {
+            name,
+            prometheus_type,
+            description,
+            unit: converted_unit,
+            truncated,
+        })
+    }
+
+    /// Compute the Prometheus name of the given metric, from its name, unit
+    /// and type
+    fn prometheus_name<'a>(
+        &self,
+        metric: MetricRef<'a>,
+        is_monotonic: bool,
+        converted_unit: &str,
+    ) -> Cow<'a, str> {
+        // Apply name transformations
+        let sanitized_name = sanitize_name(metric.name());
+        let sanitized_name = match &self.config.namespace {
+            Some(namespace) => Cow::Owned(format!("{namespace}_{sanitized_name}")),
+            None => sanitized_name,
+        };
+
         // Add unit suffix if needed and not already present
         let final_name = if converted_unit.is_empty() {
             sanitized_name
         } else {
-            add_unit_suffix(sanitized_name.as_ref(), converted_unit.as_ref())
+            match add_unit_suffix(sanitized_name.as_ref(), converted_unit) {
+                Cow::Borrowed(_) => sanitized_name,
+                Cow::Owned(name) => Cow::Owned(name),
+            }
         };
 
         // Add _total suffix for monotonic sums if needed and not disabled
-        let final_name = if is_monotonic
-            && !self.config.without_counter_suffixes
-            && !final_name.ends_with("_total")
+        if is_monotonic && !self.config.without_counter_suffixes && !final_name.ends_with("_total")
         {
             Cow::Owned(format!("{final_name}_total"))
         } else {
             final_name
+        }
+    }
+
+    /// Compute the metadata of the family rendering the given metric, if it
+    /// passes the filter rules.
+    fn selected_metadata<'a>(
+        &self,
+        metric: MetricRef<'a>,
+        scope: &InstrumentationScope,
+    ) -> Result<FamilyMetadata<'a>, Skipped> {
+        let metadata = self
+            .family_metadata(metric, scope)
+            .ok_or(Skipped::UnsupportedType)?;
+
+        if self.config.filter.allows(
+            metric.name(),
+            metric.instrument_type(),
+            scope,
+            &metadata.name,
+        ) {
+            Ok(metadata)
+        } else {
+            Err(Skipped::Filtered)
+        }
+    }
+
+    /// The metadata of the family rendering the given metric, cached by
+    /// [`Self::merge_families`], or computed if it isn't.
+    fn cached_metadata<'a, 'f>(
+        &self,
+        metric: MetricRef<'a>,
+        scope: &InstrumentationScope,
+        cached: &'f Option<Result<FamilyMetadata<'a>, Skipped>>,
+    ) -> Result<Cow<'f, FamilyMetadata<'a>>, Skipped> {
+        match cached {
+            Some(Ok(metadata)) => Ok(Cow::Borrowed(metadata)),
+            Some(Err(reason)) => Err(*reason),
+            None => self.selected_metadata(metric, scope).map(Cow::Owned),
+        }
+    }
+
+    /// Compute the name and type of the given family, without counting its
+    /// series.
+    ///
+    /// # Errors
+    ///
+    /// Returns why the family is skipped if it would not be rendered at all.
+    pub fn family_type<'a>(
+        &self,
+        family: &Family<'a>,
+    ) -> Result<(Cow<'a, str>, &'static str), Skipped> {
+        match family {
+            Family::Metric {
+                metric,
+                scope,
+                metadata,
+                ..
+            } => {
+                let metadata = self.cached_metadata(*metric, scope, metadata)?;
+                Ok((metadata.name.clone(), metadata.prometheus_type))
+            }
+            Family::Merged { parts, .. } => parts
+                .first()
+                .map_or(Err(Skipped::Empty), |part| self.family_type(part)),
+            Family::TargetInfo { .. } => Ok((Cow::Borrowed("target_info"), "gauge")),
+        }
+    }
+
+    /// Compute the name and number of series of the given family, without
+    /// rendering it.
+    ///
+    /// # Errors
+    ///
+    /// Returns why the family is skipped if it would not be rendered at all.
+    pub fn family_stats<'f>(&self, family: &'f Family<'_>) -> Result<FamilyStats<'f>, Skipped> {
+        match family {
+            Family::Metric {
+                metric,
+                scope,
+                resource,
+                metadata,
+            } => {
+                let metadata = self.cached_metadata(*metric, scope, metadata)?;
+                let resource = resource_key(resource);
+                let (series, overflowed) =
+                    self.series_count(*metric, &metadata.name, scope, resource);
+                Ok(FamilyStats {
+                    name: family_name(metadata),
+                    series,
+                    overflowed,
+                    conflicting: 0,
+                })
+            }
+            Family::Merged { parts, conflicting } => {
+                let mut merged: Option<FamilyStats<'f>> = None;
+                let mut skipped = Skipped::Empty;
+                for part in parts {
+                    match (self.family_stats(part), &mut merged) {
+                        (Ok(stats), Some(merged)) => {
+                            merged.series += stats.series;
+                            merged.overflowed += stats.overflowed;
+                        }
+                        (Ok(stats), None) => merged = Some(stats),
+                        (Err(reason), _) => skipped = reason,
+                    }
+                }
+
+                let mut merged = merged.ok_or(skipped)?;
+                merged.conflicting = *conflicting;
+                Ok(merged)
+            }
+            Family::TargetInfo { resources } => {
+                if self.config.disable_target_info {
+                    return Err(Skipped::Empty);
+                }
+
+                let series = unique_resources(resources).count();
+                if series == 0 {
+                    return Err(Skipped::Empty);
+                }
+
+                Ok(FamilyStats {
+                    name: Cow::Borrowed("target_info"),
+                    series,
+                    overflowed: 0,
+                    conflicting: 0,
+                })
+            }
+        }
+    }
+
+    fn serialize_metric<'f, W: Write>(
+        &self,
+        metric: MetricRef<'_>,
+        metadata: Cow<'f, FamilyMetadata<'_>>,
+        scope: &InstrumentationScope,
+        resource: &Resource,
+        options: &RenderOptions<'_>,
+        writer: &mut W,
+    ) -> std::io::Result<Option<Cow<'f, str>>> {
+        let FamilyMetadata {
+            name: ref final_name,
+            prometheus_type,
+            ref description,
+            unit: ref converted_unit,
+            truncated,
+        } = *metadata;
+
+        if !self.may_select(final_name, options.selectors) {
+            return Ok(None);
+        }
+
+        // Without relabeling, the series are named after the metric, which
+        // has to be the one of the family already written
+        if self.config.relabel.is_empty()
+            && options
+                .written_family
+                .is_some_and(|written| written != final_name)
+        {
+            return Ok(None);
+        }
+
+        let limit = self.config.cardinality_limit(metric.name());
+        let resource_labels = self.resource_labels(resource);
+        let discriminator_labels = options.discriminator.labels(resource);
+
+        // The metadata is written along with the first series, as the
+        // relabeling rules may drop all of them
+        let mut family = FamilyWriter {
+            name: final_name.as_ref(),
+            pending_header: options.written_family.is_none().then_some(FamilyHeader {
+                prometheus_type,
+                description: description.as_ref(),
+                unit: converted_unit.as_ref(),
+            }),
+            labels: LabelBuffer::default(),
+            selectors: options.selectors,
+            written_family: options.written_family,
+            renamed: None,
+            truncated_name: truncated,
+            track_idle: options.track_idle,
+            resource: resource_key(resource),
+            resource_labels: &resource_labels,
+            discriminator_labels: &discriminator_labels,
+            written: false,
+        };
+
+        match metric {
+            MetricRef::Sdk(metric) => match metric.data() {
+                AggregatedMetrics::F64(MetricData::Gauge(gauge)) => {
+                    self.serialize_numbers(
+                        &mut family,
+                        || gauge.data_points(),
+                        limit,
+                        false,
+                        scope,
+                        writer,
+                    )?;
+                }
+                AggregatedMetrics::U64(MetricData::Gauge(gauge)) => {
+                    self.serialize_numbers(
+                        &mut family,
+                        || gauge.data_points(),
+                        limit,
+                        false,
+                        scope,
+                        writer,
+                    )?;
+                }
+                AggregatedMetrics::I64(MetricData::Gauge(gauge)) => {
+                    self.serialize_numbers(
+                        &mut family,
+                        || gauge.data_points(),
+                        limit,
+                        false,
+                        scope,
+                        writer,
+                    )?;
+                }
+
+                AggregatedMetrics::F64(MetricData::Sum(sum)) => {
+                    self.serialize_numbers(
+                        &mut family,
+                        || sum.data_points(),
+                        limit,
+                        true,
+                        scope,
+                        writer,
+                    )?;
+                }
+                AggregatedMetrics::U64(MetricData::Sum(sum)) => {
+                    self.serialize_numbers(
+                        &mut family,
+                        || sum.data_points(),
+                        limit,
+                        true,
+                        scope,
+                        writer,
+                    )?;
+                }
+                AggregatedMetrics::I64(MetricData::Sum(sum)) => {
+                    self.serialize_numbers(
+                        &mut family,
+                        || sum.data_points(),
+                        limit,
+                        true,
+                        scope,
+                        writer,
+                    )?;
+                }
+
+                AggregatedMetrics::F64(MetricData::Histogram(histogram)) => {
+                    self.serialize_histogram(
+                        &mut family,
+                        || histogram.data_points(),
+                        limit,
+                        scope,
+                        writer,
+                    )?;
+                }
+                AggregatedMetrics::U64(MetricData::Histogram(histogram)) => {
+                    self.serialize_histogram(
+                        &mut family,
+                        || histogram.data_points(),
+                        limit,
+                        scope,
+                        writer,
+                    )?;
+                }
+                AggregatedMetrics::I64(MetricData::Histogram(histogram)) => {
+                    self.serialize_histogram(
+                        &mut family,
+                        || histogram.data_points(),
+                        limit,
+                        scope,
+                        writer,
+                    )?;
+                }
+
+                // Skip exponential histograms
+                AggregatedMetrics::F64(MetricData::ExponentialHistogram(_))
+                | AggregatedMetrics::U64(MetricData::ExponentialHistogram(_))
+                | AggregatedMetrics::I64(MetricData::ExponentialHistogram(_)) => {}
+            },
+
+            #[cfg(feature = "otlp")]
+            MetricRef::Otlp(metric) => match &metric.data {
+                OtlpData::Gauge(points) => {
+                    let numbers = || points.iter();
+                    self.serialize_numbers(&mut family, numbers, limit, false, scope, writer)?;
+                }
+                OtlpData::Sum { points, .. } => {
+                    let numbers = || points.iter();
+                    self.serialize_numbers(&mut family, numbers, limit, true, scope, writer)?;
+                }
+                OtlpData::Histogram(points) => {
+                    self.serialize_histogram(&mut family, || points.iter(), limit, scope, writer)?;
+                }
+            },
+        }
+
+        let written = family.written;
+        let renamed = family.renamed.take();
+        Ok(written.then(|| renamed.map_or_else(|| family_name(metadata), Cow::Owned)))
+    }
+
+    /// Whether some series of the family with the given name may match one of
+    /// the selectors, before looking at their labels
+    fn may_select(&self, name: &str, selectors: &[SeriesSelector]) -> bool {
+        // Relabeling may rename the series, so they are only matched once
+        // relabeled
+        selectors.is_empty()
+            || !self.config.relabel.is_empty()
+            || selectors.iter().any(|selector| selector.matches_name(name))
+    }
+
+    fn write_scope_labels(
+        &self,
+        scope: &InstrumentationScope,
+        label_writer: &mut dyn LabelSink,
+    ) -> std::io::Result<()> {
+        if self.config.disable_scope_info {
+            return Ok(());
+        }
+
+        // Add scope name
+        if !scope.name().is_empty() {
+            label_writer.emit("otel_scope_name", scope.name())?;
+        }
+
+        // Add scope version
+        if let Some(version) = scope.version()
+            && !version.is_empty()
+        {
+            label_writer.emit("otel_scope_version", version)?;
+        }
+
+        // Add scope schema URL
+        if let Some(schema_url) = scope.schema_url()
+            && !schema_url.is_empty()
+        {
+            label_writer.emit("otel_scope_schema_url", schema_url)?;
+        }
+
+        // Add scope attributes (excluding name, version, schema_url to avoid conflicts)
+        for attr in scope.attributes() {
+            let key = attr.key.as_str();
+            if key != "name" && key != "version" && key != "schema_url" {
+                let sanitized_key = sanitize_name(key);
+                let value = format!("{}", attr.value);
+                let prefixed_key = format!("otel_scope_{}", sanitized_key.as_ref());
+                label_writer.emit(&prefixed_key, &value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Emits the labels of a series: its attributes, the `le` label of
+    /// histogram buckets, then the scope labels
+    fn write_labels<'a>(
+        &self,
+        attributes: impl Iterator<Item = &'a KeyValue>,
+        le_value: Option<&str>,
+        scope: &InstrumentationScope,
+        labels: &mut dyn LabelSink,
+    ) -> std::io::Result<()> {
+        write_attributes_as_labels(attributes, labels)?;
+        if let Some(le_value) = le_value {
+            labels.emit("le", le_value)?;
+        }
+        self.write_scope_labels(scope, labels)
+    }
+
+    /// Writes a sample of the family, unless the relabeling rules drop it or
+    /// it doesn't match the selectors.
+    ///
+    /// The metadata of the family is written before its first sample.
+    fn write_sample<T: Numeric, W: Write>(
+        &self,
+        family: &mut FamilyWriter<'_>,
+        suffix: &str,
+        write_labels: impl FnOnce(&mut dyn LabelSink) -> std::io::Result<()>,
+        value: T,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        let added_labels = !self.config.const_labels.is_empty()
+            || !family.resource_labels.is_empty()
+            || !family.discriminator_labels.is_empty();
+
+        // Without labels added to the series, nor rules looking at them, they
+        // are written as they come
+        if !added_labels
+            && self.config.relabel.is_empty()
+            && family.selectors.is_empty()
+            && !self.config.label_limits.is_set()
+        {
+            if let Some(header) = family.pending_header.take() {
+                header.write(family.name, writer)?;
+            }
+
+            write!(writer, "{}{suffix}", family.name)?;
+            let mut label_writer = LabelWriter::new(writer);
+            write_labels(&mut label_writer)?;
+            label_writer.finish()?;
+
+            write!(writer, " ")?;
+            value.serialize(writer)?;
+            writeln!(writer)?;
+            family.written = true;
+            return Ok(());
+        }
+
+        family.labels.clear();
+        write_labels(&mut family.labels)?;
+        if added_labels {
+            for (key, value) in self
+                .config
+                .const_labels
+                .iter()
+                .chain(family.resource_labels)
+            {
+                family.labels.emit(key, value)?;
+            }
+            family.labels.merge_collisions();
+            family.labels.replace(family.discriminator_labels);
+        }
+
+        if self.config.relabel.is_empty() {
+            if !family.selects(family.name, family.labels.iter()) {
+                return Ok(());
+            }
+            let Some((labels, truncated)) = self.limit_labels(family.labels.iter()) else {
+                return Ok(());
+            };
+
+            if let Some(header) = family.pending_header.take() {
+                header.write(family.name, writer)?;
+            }
+
+            write!(writer, "{}{suffix}", family.name)?;
+            write_limited_labels(&labels, writer)?;
+            self.record_truncation(family.truncated_name || truncated);
+        } else {
+            let mut labels = LabelSet::with_name(format!("{}{suffix}", family.name));
+            for (key, value) in family.labels.iter() {
+                labels.push(key, value);
+            }
+            let Some(name) = labels.relabel(&self.config.relabel) else {
+                return Ok(());
+            };
+
+            // The family is named after its first series, which may have been
+            // renamed. The series renamed after another family, or without
+            // the suffix of their sample, are left out as they would be
+            // written under the wrong metadata.
+            let Some(family_name) = name.strip_suffix(suffix) else {
+                return Ok(());
+            };
+            let expected = family.written_family.or(family.renamed.as_deref());
+            if expected.is_some_and(|expected| expected != family_name)
+                || !family.selects(family_name, labels.iter())
+            {
+                return Ok(());
+            }
+            let Some((labels, truncated)) = self.limit_labels(labels.iter()) else {
+                return Ok(());
+            };
+
+            if let Some(header) = family.pending_header.take() {
+                header.write(family_name, writer)?;
+            }
+
+            if family.renamed.is_none() {
+                family.renamed = Some(family_name.to_owned());
+            }
+
+            write!(writer, "{name}")?;
+            write_limited_labels(&labels, writer)?;
+            self.record_truncation(family.truncated_name || truncated);
+        }
+
+        write!(writer, " ")?;
+        value.serialize(writer)?;
+        writeln!(writer)?;
+        family.written = true;
+        Ok(())
+    }
+
+    /// Whether the series with the given attributes is left out, because its
+    /// value didn't change for a while
+    fn is_idle<'a>(
+        &self,
+        family: &FamilyWriter<'_>,
+        scope: &InstrumentationScope,
+        attributes: impl Iterator<Item = &'a KeyValue>,
+        fingerprint: u64,
+    ) -> bool {
+        match &self.idle {
+            Some(idle) if family.track_idle => {
+                let key = series_key(family.name, scope, family.resource, attributes);
+                idle.is_idle(key, fingerprint)
+            }
+            _ => false,
+        }
+    }
+
+    /// Applies the label limits to the labels of a series.
+    ///
+    /// Returns [`None`], and counts the series as dropped, if it doesn't fit
+    /// in the limits.
+    fn limit_labels<'a>(
+        &self,
+        labels: impl Iterator<Item = (&'a str, &'a str)>,
+    ) -> Option<(LimitedLabels<'a>, bool)> {
+        let limited = self.config.label_limits.apply(labels);
+        if limited.is_none() {
+            self.dropped_series.fetch_add(1, Ordering::Relaxed);
+        }
+        limited
+    }
+
+    /// Counts a series written with its name or labels cut to fit in the
+    /// label limits
+    fn record_truncation(&self, truncated: bool) {
+        if truncated {
+            self.truncated_series.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Tell which data points of a metric are rendered as-is under its
+    /// cardinality limit, or [`None`] if it has no limit
+    fn kept_points<'a, A>(
+        &self,
+        name: &str,
+        scope: &InstrumentationScope,
+        resource: u64,
+        attributes: impl Iterator<Item = A>,
+        limit: Option<usize>,
+    ) -> Option<Vec<bool>>
+    where
+        A: Iterator<Item = &'a KeyValue>,
+    {
+        let limit = limit?;
+        let metric = metric_key(name, scope, resource);
+        let series: Vec<_> = attributes.map(attributes_key).collect();
+        Some(self.admitted.kept(metric, &series, limit))
+    }
+
+    /// Counts the samples a metric renders to, and the number of data points
+    /// folded in the overflow series because of the cardinality limit.
+    ///
+    /// Histograms render a sample per bucket, plus the `+Inf` bucket, `_sum`
+    /// and `_count`.
+    fn series_count(
+        &self,
+        metric: MetricRef<'_>,
+        name: &str,
+        scope: &InstrumentationScope,
+        resource: u64,
+    ) -> (usize, usize) {
+        let limit = self.config.cardinality_limit(metric.name());
+        match metric {
+            MetricRef::Sdk(metric) => {
+                match metric.data() {
+                    AggregatedMetrics::F64(MetricData::Gauge(gauge)) => {
+                        self.number_series(|| gauge.data_points(), name, scope, resource, limit)
+                    }
+                    AggregatedMetrics::U64(MetricData::Gauge(gauge)) => {
+                        self.number_series(|| gauge.data_points(), name, scope, resource, limit)
+                    }
+                    AggregatedMetrics::I64(MetricData::Gauge(gauge)) => {
+                        self.number_series(|| gauge.data_points(), name, scope, resource, limit)
+                    }
+                    AggregatedMetrics::F64(MetricData::Sum(sum)) => {
+                        self.number_series(|| sum.data_points(), name, scope, resource, limit)
+                    }
+                    AggregatedMetrics::U64(MetricData::Sum(sum)) => {
+                        self.number_series(|| sum.data_points(), name, scope, resource, limit)
+                    }
+                    AggregatedMetrics::I64(MetricData::Sum(sum)) => {
+                        self.number_series(|| sum.data_points(), name, scope, resource, limit)
+                    }
+                    AggregatedMetrics::F64(MetricData::Histogram(histogram)) => self
+                        .histogram_series(|| histogram.data_points(), name, scope, resource, limit),
+                    AggregatedMetrics::U64(MetricData::Histogram(histogram)) => self
+                        .histogram_series(|| histogram.data_points(), name, scope, resource, limit),
+                    AggregatedMetrics::I64(MetricData::Histogram(histogram)) => self
+                        .histogram_series(|| histogram.data_points(), name, scope, resource, limit),
+                    AggregatedMetrics::F64(MetricData::ExponentialHistogram(_))
+                    | AggregatedMetrics::U64(MetricData::ExponentialHistogram(_))
+                    | AggregatedMetrics::I64(MetricData::ExponentialHistogram(_)) => (0, 0),
+                }
+            }
+
+            #[cfg(feature = "otlp")]
+            MetricRef::Otlp(metric) => match &metric.data {
+                OtlpData::Gauge(points) | OtlpData::Sum { points, .. } => {
+                    self.number_series(|| points.iter(), name, scope, resource, limit)
+                }
+                OtlpData::Histogram(points) => {
+                    self.histogram_series(|| points.iter(), name, scope, resource, limit)
+                }
+            },
+        }
+    }
+
+    fn number_series<'p, P: NumberPoint + 'p, I: Iterator<Item = &'p P>>(
+        &self,
+        data_points: impl Fn() -> I,
+        name: &str,
+        scope: &InstrumentationScope,
+        resource: u64,
+        limit: Option<usize>,
+    ) -> (usize, usize) {
+        let total = data_points().count();
+        let attributes = data_points().map(NumberPoint::attributes);
+        let Some(kept) = self.kept_points(name, scope, resource, attributes, limit) else {
+            return (total, 0);
         };
 
-        // Write metadata
-        write_type_comment(writer, final_name.as_ref(), prometheus_type)?;
-        write_help_comment(writer, final_name.as_ref(), metric.description())?;
-        write_unit_comment(writer, final_name.as_ref(), converted_unit.as_ref())?;
+        let series = kept.iter().filter(|kept| **kept).count();
+        let overflowed = data_points()
+            .zip(&kept)
+            .filter(|(data_point, kept)| {
+                !**kept && attributes_key(data_point.attributes()).is_some()
+            })
+            .count();
+        // All the folded data points render to a single overflow series
+        (series + usize::from(series < total), overflowed)
+    }
 
-        match data {
-            AggregatedMetrics::F64(MetricData::Gauge(gauge)) => {
-                self.serialize_gauge(final_name.as_ref(), gauge, scope_metrics, writer)?;
-            }
-            AggregatedMetrics::U64(MetricData::Gauge(gauge)) => {
-                self.serialize_gauge(final_name.as_ref(), gauge, scope_metrics, writer)?;
-            }
-            AggregatedMetrics::I64(MetricData::Gauge(gauge)) => {
-                self.serialize_gauge(final_name.as_ref(), gauge, scope_metrics, writer)?;
-            }
+    fn histogram_series<'p, P: HistogramPoint + 'p, I: Iterator<Item = &'p P>>(
+        &self,
+        data_points: impl Fn() -> I,
+        name: &str,
+        scope: &InstrumentationScope,
+        resource: u64,
+        limit: Option<usize>,
+    ) -> (usize, usize) {
+        let series_per_point = |buckets: usize| buckets + 3;
+        let attributes = data_points().map(HistogramPoint::attributes);
+        let Some(kept) = self.kept_points(name, scope, resource, attributes, limit) else {
+            let series = data_points()
+                .map(|data_point| series_per_point(data_point.bounds().count()))
+                .sum();
+            return (series, 0);
+        };
 
-            AggregatedMetrics::F64(MetricData::Sum(sum)) => {
-                self.serialize_sum(final_name.as_ref(), sum, scope_metrics, writer)?;
+        let mut series = 0;
+        let mut overflowed = 0;
+        let mut overflow: Option<HistogramOverflow<P::Value>> = None;
+        for (data_point, kept) in data_points().zip(kept) {
+            if kept {
+                series += series_per_point(data_point.bounds().count());
+                continue;
             }
-            AggregatedMetrics::U64(MetricData::Sum(sum)) => {
-                self.serialize_sum(final_name.as_ref(), sum, scope_metrics, writer)?;
+
+            if attributes_key(data_point.attributes()).is_some() {
+                overflowed += 1;
             }
-            AggregatedMetrics::I64(MetricData::Sum(sum)) => {
-                self.serialize_sum(final_name.as_ref(), sum, scope_metrics, writer)?;
+            match &mut overflow {
+                Some(overflow) => overflow.add(data_point),
+                None => overflow = Some(HistogramOverflow::new(data_point)),
             }
+        }
 
-            AggregatedMetrics::F64(MetricData::Histogram(histogram)) => {
-                self.serialize_histogram(final_name.as_ref(), histogram, scope_metrics, writer)?;
-            }
-            AggregatedMetrics::U64(MetricData::Histogram(histogram)) => {
-                self.serialize_histogram(final_name.as_ref(), histogram, scope_metrics, writer)?;
+        // All the folded data points render to a single overflow series
+        if let Some(overflow) = overflow {
+            series += series_per_point(overflow.cumulative_buckets.len());
+        }
+        (series, overflowed)
+    }
+
+    /// Writes the data points of a sum or a gauge, folding the ones beyond the
+    /// cardinality limit in the overflow series.
+    ///
+    /// The overflow series of a sum adds up the folded values, while the one
+    /// of a gauge takes the last one, as gauges can't be summed.
+    fn serialize_numbers<'p, P: NumberPoint + 'p, I, W: Write>(
+        &self,
+        family: &mut FamilyWriter<'_>,
+        data_points: impl Fn() -> I,
+        limit: Option<usize>,
+        additive: bool,
+        scope: &InstrumentationScope,
+        writer: &mut W,
+    ) -> std::io::Result<()>
+    where
+        I: Iterator<Item = &'p P>,
+    {
+        let kept = self.kept_points(
+            family.name,
+            scope,
+            family.resource,
+            data_points().map(NumberPoint::attributes),
+            limit,
+        );
+        let mut overflow: Option<P::Value> = None;
+
+        for (index, data_point) in data_points().enumerate() {
+            if kept.as_ref().is_some_and(|kept| !kept[index]) {
+                let value = data_point.value();
+                overflow = Some(match overflow {
+                    Some(total) if additive => total + value,
+                    _ => value,
+                });
+                continue;
             }
-            AggregatedMetrics::I64(MetricData::Histogram(histogram)) => {
-                self.serialize_histogram(final_name.as_ref(), histogram, scope_metrics, writer)?;
+
+            let fingerprint = data_point.value().fingerprint();
+            if self.is_idle(family, scope, data_point.attributes(), fingerprint) {
+                continue;
             }
 
-            // Skip exponential histograms
-            AggregatedMetrics::F64(MetricData::ExponentialHistogram(_))
-            | AggregatedMetrics::U64(MetricData::ExponentialHistogram(_))
-            | AggregatedMetrics::I64(MetricData::ExponentialHistogram(_)) => {}
+            let write_labels = |labels: &mut dyn LabelSink| {
+                self.write_labels(data_point.attributes(), None, scope, labels)
+            };
+            self.write_sample(family, "", write_labels, data_point.value(), writer)?;
         }
 
-        writeln!(writer)?;
+        if let Some(value) = overflow {
+            self.serialize_overflow_sample(family, value, scope, writer)?;
+        }
 
         Ok(())
     }
 
-    fn write_scope_labels<W: Write>(
+    /// Writes the sample of the overflow series, which aggregates all the
+    /// series beyond the cardinality limit
+    fn serialize_overflow_sample<T: Numeric, W: Write>(
         &self,
-        scope_metrics: &opentelemetry_sdk::metrics::data::ScopeMetrics,
-        label_writer: &mut LabelWriter<W>,
+        family: &mut FamilyWriter<'_>,
+        value: T,
+        scope: &InstrumentationScope,
+        writer: &mut W,
     ) -> std::io::Result<()> {
-        if self.config.disable_scope_info {
+        let overflow_attribute = overflow_attribute();
+        let attributes = std::iter::once(&overflow_attribute);
+        if self.is_idle(family, scope, attributes, value.fingerprint()) {
             return Ok(());
         }
-        let scope = scope_metrics.scope();
 
-        // Add scope name
-        if !scope.name().is_empty() {
-            label_writer.emit("otel_scope_name", scope.name())?;
-        }
+        let write_labels = |labels: &mut dyn LabelSink| {
+            self.write_labels(std::iter::once(&overflow_attribute), None, scope, labels)
+        };
+        self.write_sample(family, "", write_labels, value, writer)
+    }
 
-        // Add scope version
-        if let Some(version) = scope.version()
-            && !version.is_empty()
-        {
-            label_writer.emit("otel_scope_version", version)?;
-        }
+    fn serialize_histogram<'p, P: HistogramPoint + 'p, I, W: Write>(
+        &self,
+        family: &mut FamilyWriter<'_>,
+        data_points: impl Fn() -> I,
+        limit: Option<usize>,
+        scope: &InstrumentationScope,
+        writer: &mut W,
+    ) -> std::io::Result<()>
+    where
+        I: Iterator<Item = &'p P>,
+    {
+        let kept = self.kept_points(
+            family.name,
+            scope,
+            family.resource,
+            data_points().map(HistogramPoint::attributes),
+            limit,
+        );
+        let mut overflow: Option<HistogramOverflow<P::Value>> = None;
 
-        // Add scope schema URL
-        if let Some(schema_url) = scope.schema_url()
-            && !schema_url.is_empty()
-        {
-            label_writer.emit("otel_scope_schema_url", schema_url)?;
+        for (index, data_point) in data_points().enumerate() {
+            if kept.as_ref().is_some_and(|kept| !kept[index]) {
+                match &mut overflow {
+                    Some(overflow) => overflow.add(data_point),
+                    None => overflow = Some(HistogramOverflow::new(data_point)),
+                }
+                continue;
+            }
+
+            // The count changes whenever something is recorded
+            let count = data_point.count();
+            if self.is_idle(family, scope, data_point.attributes(), count) {
+                continue;
+            }
+
+            self.serialize_histogram_point(
+                family,
+                || data_point.attributes(),
+                data_point.bounds().zip(data_point.bucket_counts()),
+                data_point.count(),
+                data_point.sum(),
+                scope,
+                writer,
+            )?;
         }
 
-        // Add scope attributes (excluding name, version, schema_url to avoid conflicts)
-        for attr in scope.attributes() {
-            let key = attr.key.as_str();
-            if key != "name" && key != "version" && key != "schema_url" {
-                let sanitized_key = sanitize_name(key);
-                let value = format!("{}", attr.value);
-                let prefixed_key = format!("otel_scope_{}", sanitized_key.as_ref());
-                label_writer.emit(&prefixed_key, &value)?;
+        if let Some(overflow) = overflow {
+            let overflow_attribute = overflow_attribute();
+            let attributes = std::iter::once(&overflow_attribute);
+            if self.is_idle(family, scope, attributes, overflow.count) {
+                return Ok(());
             }
+
+            self.serialize_histogram_point(
+                family,
+                || std::iter::once(&overflow_attribute),
+                overflow.buckets(),
+                overflow.count,
+                overflow.sum,
+                scope,
+                writer,
+            )?;
         }
 
         Ok(())
     }
 
-    fn write_metric_labels<'a, W: Write>(
+    #[allow(clippy::too_many_arguments)]
+    fn serialize_histogram_point<'a, T: Numeric, W: Write, I>(
         &self,
-        attributes: impl Iterator<Item = &'a KeyValue>,
-        scope_metrics: &opentelemetry_sdk::metrics::data::ScopeMetrics,
+        family: &mut FamilyWriter<'_>,
+        attributes: impl Fn() -> I,
+        buckets: impl Iterator<Item = (f64, u64)>,
+        count: u64,
+        sum: T,
+        scope: &InstrumentationScope,
         writer: &mut W,
-    ) -> std::io::Result<()> {
-        let mut label_writer = LabelWriter::new(writer);
+    ) -> std::io::Result<()>
+    where
+        I: Iterator<Item = &'a KeyValue>,
+    {
+        let write_labels = |le_value: Option<&str>, labels: &mut dyn LabelSink| {
+            self.write_labels(attributes(), le_value, scope, labels)
+        };
+
+        // _count metric
+        self.write_sample(
+            family,
+            "_count",
+            |labels| write_labels(None, labels),
+            count,
+            writer,
+        )?;
+
+        // _sum metric
+        self.write_sample(
+            family,
+            "_sum",
+            |labels| write_labels(None, labels),
+            sum,
+            writer,
+        )?;
+
+        // _bucket metrics
+        let mut cumulative_count = 0u64;
+        for (bound, bucket_count) in buckets {
+            cumulative_count += bucket_count;
+
+            let le_value = bound.to_string();
+            self.write_sample(
+                family,
+                "_bucket",
+                |labels| write_labels(Some(&le_value), labels),
+                cumulative_count,
+                writer,
+            )?;
+        }
 
-        write_attributes_as_labels(attributes, &mut label_writer)?;
-        self.write_scope_labels(scope_metrics, &mut label_writer)?;
+        // +Inf bucket
+        self.write_sample(
+            family,
+            "_bucket",
+            |labels| write_labels(Some("+Inf"), labels),
+            count,
+            writer,
+        )?;
 
-        label_writer.finish()
+        Ok(())
     }
+}
 
-    fn write_bucket_labels<'a, W: Write>(
-        &self,
-        attributes: impl Iterator<Item = &'a KeyValue>,
-        scope_metrics: &opentelemetry_sdk::metrics::data::ScopeMetrics,
-        le_value: &str,
-        writer: &mut W,
-    ) -> std::io::Result<()> {
-        let mut label_writer = LabelWriter::new(writer);
+/// A metric family being rendered
+struct FamilyWriter<'a> {
+    /// The name of the family, after all the transformations
+    name: &'a str,
 
-        write_attributes_as_labels(attributes, &mut label_writer)?;
-        label_writer.emit("le", le_value)?;
-        self.write_scope_labels(scope_metrics, &mut label_writer)?;
+    /// The metadata of the family, until it is written along with the first
+    /// sample
+    pending_header: Option<FamilyHeader<'a>>,
 
-        label_writer.finish()
-    }
+    /// The labels of the series being written
+    labels: LabelBuffer,
 
-    fn serialize_gauge<T: Numeric, W: Write>(
+    /// The series to write, all of them if empty
+    selectors: &'a [SeriesSelector],
+
+    /// The name of the family whose metadata was already written, along with
+    /// another part of a merged family
+    written_family: Option<&'a str>,
+
+    /// The name of the family, once renamed by the relabeling rules of its
+    /// first series
+    renamed: Option<String>,
+
+    /// Whether the name was cut to fit in the label limits
+    truncated_name: bool,
+
+    /// Whether to leave out the idle series
+    track_idle: bool,
+
+    /// Identifies the resource of the metric, as the same series may come
+    /// from several resources
+    resource: u64,
+
+    /// The resource attributes added as labels to every series, with
+    /// sanitized names
+    resource_labels: &'a [(String, String)],
+
+    /// The labels telling apart the exporters of a registry, replacing the
+    /// labels of the series with the same names
+    discriminator_labels: &'a [(String, String)],
+
+    /// Whether any series was written
+    written: bool,
+}
+
+impl FamilyWriter<'_> {
+    /// Whether the series with the given family name and labels is written
+    fn selects<'a>(
         &self,
         name: &str,
-        gauge: &Gauge<T>,
-        scope_metrics: &opentelemetry_sdk::metrics::data::ScopeMetrics,
-        writer: &mut W,
-    ) -> std::io::Result<()> {
-        for data_point in gauge.data_points() {
-            write!(writer, "{name}")?;
-            self.write_metric_labels(data_point.attributes(), scope_metrics, writer)?;
-            write!(writer, " ")?;
-            data_point.value().serialize(writer)?;
-            writeln!(writer)?;
-        }
+        labels: impl Iterator<Item = (&'a str, &'a str)> + Clone,
+    ) -> bool {
+        self.selectors.is_empty()
+            || self
+                .selectors
+                .iter()
+                .any(|selector| selector.matches(name, &labels))
+    }
+}
 
-        Ok(())
+/// The metadata comments of a family
+struct FamilyHeader<'a> {
+    prometheus_type: &'static str,
+    description: &'a str,
+    unit: &'a str,
+}
+
+impl FamilyHeader<'_> {
+    fn write<W: Write>(&self, name: &str, writer: &mut W) -> std::io::Result<()> {
+        write_type_comment(writer, name, self.prometheus_type)?;
+        write_help_comment(writer, name, self.description)?;
+        write_unit_comment(writer, name, self.unit)
     }
+}
 
-    fn serialize_sum<T: Numeric, W: Write>(
-        &self,
-        name: &str,
-        sum: &Sum<T>,
-        scope_metrics: &opentelemetry_sdk::metrics::data::ScopeMetrics,
-        writer: &mut W,
-    ) -> std::io::Result<()> {
-        for data_point in sum.data_points() {
-            write!(writer, "{name}")?;
-            self.write_metric_labels(data_point.attributes(), scope_metrics, writer)?;
-            write!(writer, " ")?;
-            data_point.value().serialize(writer)?;
-            writeln!(writer)?;
+/// The aggregate of all the histogram data points beyond the cardinality limit
+struct HistogramOverflow<T> {
+    /// The bounds shared by all the data points, with the cumulative count of
+    /// their buckets
+    cumulative_buckets: Vec<(f64, u64)>,
+    count: u64,
+    sum: T,
+}
+
+impl<T: Numeric> HistogramOverflow<T> {
+    fn new<P: HistogramPoint<Value = T>>(data_point: &P) -> Self {
+        Self {
+            cumulative_buckets: cumulative_buckets(data_point).collect(),
+            count: data_point.count(),
+            sum: data_point.sum(),
         }
+    }
 
-        Ok(())
+    /// Add a data point, keeping only the bounds it shares with the previous
+    /// ones, as the counts of the other buckets can't be split
+    fn add<P: HistogramPoint<Value = T>>(&mut self, data_point: &P) {
+        let mut buckets = cumulative_buckets(data_point).peekable();
+        self.cumulative_buckets.retain_mut(|(bound, total)| {
+            while buckets.next_if(|(other, _)| other < bound).is_some() {}
+            match buckets.next_if(|(other, _)| other == bound) {
+                Some((_, count)) => {
+                    *total += count;
+                    true
+                }
+                None => false,
+            }
+        });
+        self.count += data_point.count();
+        self.sum = self.sum + data_point.sum();
     }
 
-    fn serialize_histogram<T: Numeric, W: Write>(
-        &self,
-        name: &str,
-        histogram: &Histogram<T>,
-        scope_metrics: &opentelemetry_sdk::metrics::data::ScopeMetrics,
-        writer: &mut W,
-    ) -> std::io::Result<()> {
-        for data_point in histogram.data_points() {
-            // _count metric
-            write!(writer, "{name}_count")?;
-            self.write_metric_labels(data_point.attributes(), scope_metrics, writer)?;
-            write!(writer, " ")?;
-            data_point.count().serialize(writer)?;
-            writeln!(writer)?;
+    /// The bounds with the number of values in each bucket, which is not
+    /// cumulative
+    fn buckets(&self) -> impl Iterator<Item = (f64, u64)> {
+        let previous = std::iter::once(0).chain(self.cumulative_buckets.iter().map(|(_, c)| *c));
+        self.cumulative_buckets
+            .iter()
+            .zip(previous)
+            .map(|(&(bound, cumulative), previous)| (bound, cumulative - previous))
+    }
+}
 
-            // _sum metric
-            write!(writer, "{name}_sum")?;
-            self.write_metric_labels(data_point.attributes(), scope_metrics, writer)?;
-            write!(writer, " ")?;
-            data_point.sum().serialize(writer)?;
-            writeln!(writer)?;
+/// The bounds of a histogram data point, with the cumulative count of their
+/// buckets
+fn cumulative_buckets<P: HistogramPoint>(data_point: &P) -> impl Iterator<Item = (f64, u64)> {
+    data_point
+        .bounds()
+        .zip(data_point.bucket_counts())
+        .scan(0, |cumulative, (bound, count)| {
+            *cumulative += count;
+            Some((bound, *cumulative))
+        })
+}
 
-            // _bucket metrics
-            let mut cumulative_count = 0u64;
-            for (bound, count) in data_point.bounds().zip(data_point.bucket_counts()) {
-                cumulative_count += count;
-
-                write!(writer, "{name}_bucket")?;
-                self.write_bucket_labels(
-                    data_point.attributes(),
-                    scope_metrics,
-                    &bound.to_string(),
-                    writer,
-                )?;
-                write!(writer, " ")?;
-                cumulative_count.serialize(writer)?;
-                writeln!(writer)?;
-            }
-
-            // +Inf bucket
-            write!(writer, "{name}_bucket")?;
-            self.write_bucket_labels(data_point.attributes(), scope_metrics, "+Inf", writer)?;
-            write!(writer, " ")?;
-            data_point.count().serialize(writer)?;
-            writeln!(writer)?;
-        }
+/// The resources rendered in `target_info`, leaving out the empty ones and
+/// the copies of a previous one, like a resource shared by the reader and a
+/// producer
+fn unique_resources<'a, 'b>(
+    resources: &'b [&'a Resource],
+) -> impl Iterator<Item = &'a Resource> + 'b {
+    resources
+        .iter()
+        .enumerate()
+        .filter(|&(index, resource)| !resource.is_empty() && !resources[..index].contains(resource))
+        .map(|(_, resource)| *resource)
+}
 
-        Ok(())
-    }
+/// The attribute identifying the overflow series of a metric, as defined by
+/// the OpenTelemetry specification
+fn overflow_attribute() -> KeyValue {
+    KeyValue::new("otel.metric.overflow", true)
 }
 
 impl Default for PrometheusSerializer {
@@ -369,8 +1877,85 @@ impl Default for PrometheusSerializer {
     }
 }
 
-trait Numeric: Copy {
+pub(crate) trait Numeric: Copy + std::ops::Add<Output = Self> {
     fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()>;
+
+    /// A value changing whenever this value does
+    fn fingerprint(self) -> u64;
+}
+
+/// A data point of a gauge or a sum
+pub(crate) trait NumberPoint {
+    type Value: Numeric;
+
+    fn attributes(&self) -> impl Iterator<Item = &KeyValue>;
+
+    fn value(&self) -> Self::Value;
+}
+
+impl<T: Numeric> NumberPoint for GaugeDataPoint<T> {
+    type Value = T;
+
+    fn attributes(&self) -> impl Iterator<Item = &KeyValue> {
+        self.attributes()
+    }
+
+    fn value(&self) -> T {
+        self.value()
+    }
+}
+
+impl<T: Numeric> NumberPoint for SumDataPoint<T> {
+    type Value = T;
+
+    fn attributes(&self) -> impl Iterator<Item = &KeyValue> {
+        self.attributes()
+    }
+
+    fn value(&self) -> T {
+        self.value()
+    }
+}
+
+/// A data point of a histogram with explicit buckets
+pub(crate) trait HistogramPoint {
+    type Value: Numeric;
+
+    fn attributes(&self) -> impl Iterator<Item = &KeyValue>;
+
+    /// The upper bounds of the buckets, without the `+Inf` one
+    fn bounds(&self) -> impl Iterator<Item = f64>;
+
+    /// The number of values in each bucket, which is not cumulative
+    fn bucket_counts(&self) -> impl Iterator<Item = u64>;
+
+    fn count(&self) -> u64;
+
+    fn sum(&self) -> Self::Value;
+}
+
+impl<T: Numeric> HistogramPoint for HistogramDataPoint<T> {
+    type Value = T;
+
+    fn attributes(&self) -> impl Iterator<Item = &KeyValue> {
+        self.attributes()
+    }
+
+    fn bounds(&self) -> impl Iterator<Item = f64> {
+        self.bounds()
+    }
+
+    fn bucket_counts(&self) -> impl Iterator<Item = u64> {
+        self.bucket_counts()
+    }
+
+    fn count(&self) -> u64 {
+        self.count()
+    }
+
+    fn sum(&self) -> T {
+        self.sum()
+    }
 }
 
 impl Numeric for f64 {
@@ -387,18 +1972,30 @@ impl Numeric for f64 {
             write!(writer, "{self}")
         }
     }
+
+    fn fingerprint(self) -> u64 {
+        self.to_bits()
+    }
 }
 
 impl Numeric for u64 {
     fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
         write!(writer, "{self}")
     }
+
+    fn fingerprint(self) -> u64 {
+        self
+    }
 }
 
 impl Numeric for i64 {
     fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
         write!(writer, "{self}")
     }
+
+    fn fingerprint(self) -> u64 {
+        self.cast_unsigned()
+    }
 }
 
 /// Sanitizes a metric or label name to follow Prometheus naming conventions.
@@ -411,7 +2008,7 @@ impl Numeric for i64 {
 /// - First character must be `[a-zA-Z_:]`, invalid chars become `_`
 /// - Subsequent characters must be `[a-zA-Z0-9_:]`, invalid chars become `_`
 /// - Multiple consecutive underscores are collapsed to single `_`
-fn sanitize_name(name: &str) -> Cow<'_, str> {
+pub(crate) fn sanitize_name(name: &str) -> Cow<'_, str> {
     // Check if name is already valid
     let mut chars = name.chars();
     let needs_sanitization = if let Some(first) = chars.next() {
@@ -536,6 +2133,107 @@ fn add_unit_suffix<'a>(name: &'a str, unit: &str) -> Cow<'a, str> {
     }
 }
 
+/// Receives the labels of a series, either to write them directly or to
+/// relabel them first
+trait LabelSink {
+    fn emit(&mut self, key: &str, value: &str) -> std::io::Result<()>;
+}
+
+impl<W: Write> LabelSink for LabelWriter<'_, W> {
+    fn emit(&mut self, key: &str, value: &str) -> std::io::Result<()> {
+        LabelWriter::emit(self, key, value)
+    }
+}
+
+/// The labels of a series, collected before being written so that labels
+/// with the same name can be merged.
+///
+/// Labels are stored in a single buffer, reused between series.
+#[derive(Default)]
+struct LabelBuffer {
+    data: String,
+
+    /// Positions of the name and value of each label in `data`
+    labels: Vec<(Range<usize>, Range<usize>)>,
+}
+
+impl LabelBuffer {
+    fn clear(&mut self) {
+        self.data.clear();
+        self.labels.clear();
+    }
+
+    fn push(&mut self, key: &str, value: &str) {
+        let key_start = self.data.len();
+        self.data.push_str(key);
+        let value_start = self.data.len();
+        self.data.push_str(value);
+        self.labels
+            .push((key_start..value_start, value_start..self.data.len()));
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&str, &str)> + Clone {
+        self.labels
+            .iter()
+            .map(|(key, value)| (&self.data[key.clone()], &self.data[value.clone()]))
+    }
+
+    fn has_collisions(&self) -> bool {
+        self.iter()
+            .enumerate()
+            .any(|(index, (key, _))| self.iter().take(index).any(|(other, _)| other == key))
+    }
+
+    /// Merge the labels with the same name, which happens when attributes
+    /// collide with the constant or resource labels added to every series.
+    /// Their values are joined with `;`, in the order they were added.
+    fn merge_collisions(&mut self) {
+        if !self.has_collisions() {
+            return;
+        }
+
+        let mut merged: Vec<(String, String)> = Vec::with_capacity(self.labels.len());
+        for (key, value) in self.iter() {
+            if let Some((_, existing)) = merged.iter_mut().find(|(other, _)| other == key) {
+                existing.push(';');
+                existing.push_str(value);
+            } else {
+                merged.push((key.to_owned(), value.to_owned()));
+            }
+        }
+
+        self.clear();
+        for (key, value) in &merged {
+            self.push(key, value);
+        }
+    }
+
+    /// Replace the labels with the same names as the given ones, or add them.
+    fn replace(&mut self, labels: &[(String, String)]) {
+        if labels.is_empty() {
+            return;
+        }
+
+        let kept: Vec<(String, String)> = self
+            .iter()
+            .filter(|(key, _)| labels.iter().all(|(name, _)| name != key))
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect();
+
+        self.clear();
+        for (key, value) in kept.iter().chain(labels) {
+            self.push(key, value);
+        }
+    }
+}
+
+impl LabelSink for LabelBuffer {
+    fn emit(&mut self, key: &str, value: &str) -> std::io::Result<()> {
+        self.push(key, value);
+        Ok(())
+    }
+}
+
 /// Writes attributes as Prometheus labels directly to the writer.
 ///
 /// Handles writing the brackets and separating labels with commas.
@@ -560,8 +2258,8 @@ impl<'a, W: Write> LabelWriter<'a, W> {
             write!(self.writer, ",")?;
         }
 
-        write!(self.writer, "{key}={value:?}")?;
-        Ok(())
+        write!(self.writer, "{key}=")?;
+        write_label_value(self.writer, value)
     }
 
     fn finish(self) -> std::io::Result<()> {
@@ -572,9 +2270,32 @@ impl<'a, W: Write> LabelWriter<'a, W> {
     }
 }
 
-fn write_attributes_as_labels<'a, W: Write>(
+/// Writes a quoted label value, escaping the backslashes, double quotes and
+/// line feeds, which are the only escape sequences of the text format
+pub(crate) fn write_label_value<W: Write>(writer: &mut W, value: &str) -> std::io::Result<()> {
+    writer.write_all(b"\"")?;
+
+    let bytes = value.as_bytes();
+    let mut start = 0;
+    for (index, byte) in bytes.iter().enumerate() {
+        let escaped: &[u8] = match byte {
+            b'\\' => b"\\\\",
+            b'"' => b"\\\"",
+            b'\n' => b"\\n",
+            _ => continue,
+        };
+        writer.write_all(&bytes[start..index])?;
+        writer.write_all(escaped)?;
+        start = index + 1;
+    }
+    writer.write_all(&bytes[start..])?;
+
+    writer.write_all(b"\"")
+}
+
+fn write_attributes_as_labels<'a>(
     attributes: impl Iterator<Item = &'a KeyValue>,
-    label_writer: &mut LabelWriter<W>,
+    label_writer: &mut dyn LabelSink,
 ) -> std::io::Result<()> {
     for attr in attributes {
         // This avoids allocating for small attribute values
@@ -586,6 +2307,44 @@ fn write_attributes_as_labels<'a, W: Write>(
     Ok(())
 }
 
+/// Writes the labels of a series, once the label limits are applied
+fn write_limited_labels<W: Write>(
+    labels: &[(&str, Cow<'_, str>)],
+    writer: &mut W,
+) -> std::io::Result<()> {
+    let mut label_writer = LabelWriter::new(writer);
+    for (key, value) in labels {
+        label_writer.emit(key, value)?;
+    }
+    label_writer.finish()
+}
+
+/// Writes a family of the exporter metrics, with at most one label per sample
+fn write_self_metric<W: Write, T: Numeric>(
+    writer: &mut W,
+    name: &str,
+    description: &str,
+    metric_type: &str,
+    samples: &[(Option<(&str, &str)>, T)],
+) -> std::io::Result<()> {
+    write_type_comment(writer, name, metric_type)?;
+    write_help_comment(writer, name, description)?;
+
+    for (label, value) in samples {
+        write!(writer, "{name}")?;
+        let mut label_writer = LabelWriter::new(writer);
+        if let Some((key, value)) = label {
+            label_writer.emit(key, value)?;
+        }
+        label_writer.finish()?;
+        write!(writer, " ")?;
+        value.serialize(writer)?;
+        writeln!(writer)?;
+    }
+
+    writeln!(writer)
+}
+
 /// Writes TYPE comment
 fn write_type_comment<W: Write>(
     writer: &mut W,
@@ -807,6 +2566,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_write_label_value_escapes_only_text_format_sequences() {
+        let mut output = Vec::new();
+        write_label_value(&mut output, "a \"quoted\" \\path\nwith\ttab and \u{1b}").unwrap();
+
+        let result = String::from_utf8(output).unwrap();
+        assert_eq!(
+            result,
+            "\"a \\\"quoted\\\" \\\\path\\nwith\ttab and \u{1b}\""
+        );
+    }
+
+    #[test]
+    fn test_label_buffer_merges_collisions() {
+        let mut labels = LabelBuffer::default();
+        labels.push("a_b", "1");
+        labels.push("c", "2");
+        labels.merge_collisions();
+        assert_eq!(
+            labels.iter().collect::<Vec<_>>(),
+            [("a_b", "1"), ("c", "2")]
+        );
+
+        labels.clear();
+        labels.push("a_b", "1");
+        labels.push("c", "2");
+        labels.push("a_b", "3");
+        labels.merge_collisions();
+        assert_eq!(
+            labels.iter().collect::<Vec<_>>(),
+            [("a_b", "1;3"), ("c", "2")]
+        );
+    }
+
     #[test]
     fn test_without_units_configuration() {
         use crate::exporter::ExporterConfig;
@@ -0,0 +1,936 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "testing")]
+use crate::exporter::PrometheusExporter;
+use crate::parse::{
+    ExpositionFormat, MetricFamily, MetricType, ParseExpositionError, Sample, parse_exposition,
+};
+
+/// The metrics exported at one point in time, to query them or compare them
+/// with a later snapshot.
+///
+/// The snapshot is parsed from the exposition, so it sees the metrics like
+/// Prometheus does: with their translated names, units and suffixes, and with
+/// the scope and constant labels added by the exporter. It is taken with
+/// [`PrometheusExporter::snapshot`], or parsed from a scrape.
+///
+/// [`PrometheusExporter::snapshot`]: crate::PrometheusExporter::snapshot
+///
+/// # Example
+///
+/// ```rust
+/// use opentelemetry::KeyValue;
+/// use opentelemetry::metrics::MeterProvider;
+/// use opentelemetry_prometheus_text_exporter::PrometheusExporter;
+/// use opentelemetry_sdk::metrics::SdkMeterProvider;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let exporter = PrometheusExporter::builder().without_scope_info().build();
+/// let provider = SdkMeterProvider::builder()
+///     .with_reader(exporter.clone())
+///     .build();
+/// let meter = provider.meter("my-app");
+///
+/// let requests = meter.u64_counter("http.requests").build();
+/// requests.add(2, &[KeyValue::new("method", "GET")]);
+/// requests.add(1, &[KeyValue::new("method", "POST")]);
+///
+/// let snapshot = exporter.snapshot()?;
+/// let requests = snapshot.counter("http_requests_total");
+/// assert_eq!(requests.with_label("method", "GET").value(), 2.0);
+/// assert_eq!(requests.sum(), 3.0);
+/// assert!(requests.with_label("method", "PUT").series().is_empty());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    families: Vec<MetricFamily>,
+    timestamp: SystemTime,
+}
+
+impl Snapshot {
+    /// Export the metrics of the exporter and parse them, for tests.
+    ///
+    /// The same instrument name in several instrumentation scopes is rendered
+    /// as several families, which the parser rejects: give the instruments
+    /// different names, or only register one meter in the test.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the export fails, or if the exposition can't be parsed.
+    #[cfg(feature = "testing")]
+    #[must_use]
+    #[track_caller]
+    pub fn capture(exporter: &PrometheusExporter) -> Self {
+        match exporter.snapshot() {
+            Ok(snapshot) => snapshot,
+            Err(error) => panic!("failed to snapshot the metrics: {error}"),
+        }
+    }
+
+    /// Parse an exposition in the given format
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the exposition is invalid.
+    pub fn parse(exposition: &str, format: ExpositionFormat) -> Result<Self, ParseExpositionError> {
+        let families = parse_exposition(exposition, format)?;
+        Ok(Self {
+            families,
+            timestamp: SystemTime::now(),
+        })
+    }
+
+    /// Set the time the snapshot was taken at, which is when it was parsed by
+    /// default
+    #[must_use]
+    pub fn with_timestamp(mut self, timestamp: SystemTime) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// The time the snapshot was taken at
+    #[must_use]
+    pub fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    /// The families of the snapshot, in the order they were exported
+    #[must_use]
+    pub fn families(&self) -> &[MetricFamily] {
+        &self.families
+    }
+
+    /// The family with the given name, if any
+    #[must_use]
+    pub fn family(&self, name: &str) -> Option<&MetricFamily> {
+        self.families.iter().find(|family| family.name == name)
+    }
+
+    /// Query the series of a counter.
+    ///
+    /// The name can be given with or without its `_total` suffix.
+    #[must_use]
+    pub fn counter(&self, name: &str) -> SeriesQuery<'_> {
+        let family = self.families.iter().find(|family| {
+            family.metric_type == MetricType::Counter
+                && (family.name == name || family.name.strip_suffix("_total") == Some(name))
+        });
+        SeriesQuery {
+            query: Query::new(MetricType::Counter, name, family),
+        }
+    }
+
+    /// Query the series of a gauge
+    #[must_use]
+    pub fn gauge(&self, name: &str) -> SeriesQuery<'_> {
+        SeriesQuery {
+            query: Query::new(MetricType::Gauge, name, self.find(MetricType::Gauge, name)),
+        }
+    }
+
+    /// Query the series of a histogram
+    #[must_use]
+    pub fn histogram(&self, name: &str) -> HistogramQuery<'_> {
+        HistogramQuery {
+            query: Query::new(
+                MetricType::Histogram,
+                name,
+                self.find(MetricType::Histogram, name),
+            ),
+        }
+    }
+
+    /// Compare the snapshot with a later one, giving the change of each series
+    /// found in both and its rate over the time between them.
+    ///
+    /// A counter which decreased was reset in between, so its increase is its
+    /// new value, like the `increase()` of `PromQL`. The same goes for
+    /// histograms, whose bucket deltas give the distribution of the
+    /// observations made between the snapshots. Series of other types, like
+    /// summaries, are not compared, and a series whose type changed is both
+    /// removed and added.
+    #[must_use]
+    pub fn diff(&self, later: &Self) -> SnapshotDiff {
+        let elapsed = later
+            .timestamp
+            .duration_since(self.timestamp)
+            .unwrap_or_default();
+        let seconds = elapsed.as_secs_f64();
+
+        let mut old = self.points();
+        let mut series = Vec::new();
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        for (id, new) in later.points() {
+            let delta = match (old.remove(&id), new) {
+                (Some(Point::Counter(old)), Point::Counter(new)) => Delta::Counter {
+                    old,
+                    new,
+                    increase: if new < old { new } else { new - old },
+                    reset: new < old,
+                },
+                (Some(Point::Gauge(old)), Point::Gauge(new)) => Delta::Gauge { old, new },
+                (Some(Point::Histogram(old)), Point::Histogram(new)) => {
+                    Delta::Histogram(HistogramDelta::new(&old, new))
+                }
+                (old, _) => {
+                    // A series which changed type is another series
+                    if old.is_some() {
+                        removed.push(id.clone());
+                    }
+                    added.push(id);
+                    continue;
+                }
+            };
+            series.push(SeriesDelta { id, delta, seconds });
+        }
+
+        removed.extend(old.into_keys());
+        removed.sort();
+        SnapshotDiff {
+            elapsed,
+            series,
+            added,
+            removed,
+        }
+    }
+
+    /// The value of each counter, gauge and histogram series, by name and
+    /// sorted labels
+    fn points(&self) -> BTreeMap<SeriesId, Point> {
+        let mut points = BTreeMap::new();
+        for family in &self.families {
+            for sample in &family.samples {
+                let suffix = sample
+                    .name
+                    .strip_prefix(family.name.as_str())
+                    .unwrap_or_default();
+                let mut labels = sample.labels.clone();
+                labels.retain(|(name, _)| {
+                    family.metric_type != MetricType::Histogram || name != "le"
+                });
+                labels.sort();
+                let id = SeriesId {
+                    name: family.name.clone(),
+                    labels,
+                };
+
+                match (family.metric_type, suffix) {
+                    (MetricType::Counter, "" | "_total") => {
+                        points.insert(id, Point::Counter(sample.value));
+                    }
+                    (MetricType::Gauge | MetricType::Unknown, "") => {
+                        points.insert(id, Point::Gauge(sample.value));
+                    }
+                    (MetricType::Histogram, "_bucket" | "_sum" | "_count") => {
+                        let Point::Histogram(histogram) = points
+                            .entry(id)
+                            .or_insert_with(|| Point::Histogram(HistogramPoint::default()))
+                        else {
+                            continue;
+                        };
+                        match suffix {
+                            "_sum" => histogram.sum = sample.value,
+                            "_count" => histogram.count = sample.value,
+                            _ => {
+                                if let Some(bound) =
+                                    sample.label("le").and_then(|le| le.parse().ok())
+                                {
+                                    histogram.buckets.push((bound, sample.value));
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        for point in points.values_mut() {
+            if let Point::Histogram(histogram) = point {
+                histogram.buckets.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+            }
+        }
+        points
+    }
+
+    fn find(&self, metric_type: MetricType, name: &str) -> Option<&MetricFamily> {
+        self.families
+            .iter()
+            .find(|family| family.metric_type == metric_type && family.name == name)
+    }
+}
+
+/// The labels a query selects series with, and the family it looks into
+#[derive(Debug, Clone)]
+struct Query<'a> {
+    metric_type: MetricType,
+    name: String,
+    family: Option<&'a MetricFamily>,
+    labels: Vec<(String, String)>,
+}
+
+impl<'a> Query<'a> {
+    fn new(metric_type: MetricType, name: &str, family: Option<&'a MetricFamily>) -> Self {
+        Self {
+            metric_type,
+            name: name.to_owned(),
+            family,
+            labels: Vec::new(),
+        }
+    }
+
+    fn with_label(mut self, name: String, value: String) -> Self {
+        self.labels.retain(|(label, _)| *label != name);
+        self.labels.push((name, value));
+        self
+    }
+
+    #[track_caller]
+    fn family(&self) -> &'a MetricFamily {
+        match self.family {
+            Some(family) => family,
+            None => panic!(
+                "no {} named `{}` in the snapshot",
+                self.metric_type, self.name
+            ),
+        }
+    }
+
+    /// The samples of the family with the given suffix matching the labels
+    #[track_caller]
+    fn samples(&self, suffix: &str) -> impl Iterator<Item = &'a Sample> {
+        let family = self.family();
+        family.samples.iter().filter(move |sample| {
+            sample.name.strip_prefix(family.name.as_str()) == Some(suffix)
+                && self
+                    .labels
+                    .iter()
+                    .all(|(name, value)| sample.label(name) == Some(value.as_str()))
+        })
+    }
+
+    /// The only sample with the given suffix matching the labels, and the
+    /// extra predicate
+    #[track_caller]
+    fn single(&self, suffix: &str, predicate: impl Fn(&Sample) -> bool) -> &'a Sample {
+        let samples: Vec<_> = self
+            .samples(suffix)
+            .filter(|sample| predicate(sample))
+            .collect();
+        match samples.as_slice() {
+            [sample] => sample,
+            [] => panic!("no series of {self}"),
+            _ => panic!(
+                "{} series of {self}, add labels to select one",
+                samples.len()
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Query<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} `{}` matching {{", self.metric_type, self.name)?;
+        for (index, (name, value)) in self.labels.iter().enumerate() {
+            if index > 0 {
+                f.write_char(',')?;
+            }
+            write!(f, "{name}={value:?}")?;
+        }
+        f.write_char('}')
+    }
+}
+
+/// The series of a counter or gauge selected by their labels, from
+/// [`Snapshot::counter`] or [`Snapshot::gauge`]
+#[derive(Debug, Clone)]
+pub struct SeriesQuery<'a> {
+    query: Query<'a>,
+}
+
+impl<'a> SeriesQuery<'a> {
+    /// Only select the series with the given label value
+    #[must_use]
+    pub fn with_label(&self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            query: self.query.clone().with_label(name.into(), value.into()),
+        }
+    }
+
+    /// The selected series, which is empty if the metric doesn't exist
+    #[must_use]
+    pub fn series(&self) -> Vec<&'a Sample> {
+        if self.query.family.is_none() {
+            return Vec::new();
+        }
+        let suffix = self.suffix();
+        self.query.samples(suffix).collect()
+    }
+
+    /// The value of the only selected series
+    ///
+    /// # Panics
+    ///
+    /// Panics if the metric doesn't exist, or if no series or several series
+    /// are selected.
+    #[must_use]
+    #[track_caller]
+    pub fn value(&self) -> f64 {
+        self.query.single(self.suffix(), |_| true).value
+    }
+
+    /// The sum of the values of the selected series
+    ///
+    /// # Panics
+    ///
+    /// Panics if the metric doesn't exist.
+    #[must_use]
+    #[track_caller]
+    pub fn sum(&self) -> f64 {
+        self.query
+            .samples(self.suffix())
+            .map(|sample| sample.value)
+            .sum()
+    }
+
+    /// The suffix of the samples, which is `_total` for counters named after
+    /// it in `OpenMetrics`
+    fn suffix(&self) -> &'static str {
+        match self.query.family {
+            Some(family)
+                if family.metric_type == MetricType::Counter
+                    && !family.name.ends_with("_total") =>
+            {
+                "_total"
+            }
+            _ => "",
+        }
+    }
+}
+
+/// The series of a histogram selected by their labels, from
+/// [`Snapshot::histogram`]
+#[derive(Debug, Clone)]
+pub struct HistogramQuery<'a> {
+    query: Query<'a>,
+}
+
+impl HistogramQuery<'_> {
+    /// Only select the series with the given label value
+    #[must_use]
+    pub fn with_label(&self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            query: self.query.clone().with_label(name.into(), value.into()),
+        }
+    }
+
+    /// The number of observations of the only selected series
+    ///
+    /// # Panics
+    ///
+    /// Panics if the histogram doesn't exist, or if no series or several
+    /// series are selected.
+    #[must_use]
+    #[track_caller]
+    pub fn count(&self) -> f64 {
+        self.query.single("_count", |_| true).value
+    }
+
+    /// The sum of the observations of the only selected series
+    ///
+    /// # Panics
+    ///
+    /// Panics if the histogram doesn't exist, or if no series or several
+    /// series are selected.
+    #[must_use]
+    #[track_caller]
+    pub fn sum(&self) -> f64 {
+        self.query.single("_sum", |_| true).value
+    }
+
+    /// The number of observations lower than or equal to the upper bound, in
+    /// the bucket with that bound of the only selected series
+    ///
+    /// # Panics
+    ///
+    /// Panics if the histogram doesn't exist, if no series or several series
+    /// are selected, or if the series doesn't have a bucket with that bound.
+    #[must_use]
+    #[track_caller]
+    pub fn bucket(&self, upper_bound: f64) -> f64 {
+        #[allow(clippy::float_cmp, reason = "bounds are written exactly")]
+        let predicate = |sample: &Sample| {
+            sample
+                .label("le")
+                .and_then(|le| le.parse::<f64>().ok())
+                .is_some_and(|le| le == upper_bound)
+        };
+        self.query.single("_bucket", predicate).value
+    }
+}
+
+/// A series of a snapshot, by its name and sorted labels
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SeriesId {
+    name: String,
+    labels: Vec<(String, String)>,
+}
+
+impl SeriesId {
+    /// The name of the family of the series
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The labels of the series, sorted by name, without the `le` label of
+    /// histogram buckets
+    #[must_use]
+    pub fn labels(&self) -> &[(String, String)] {
+        &self.labels
+    }
+
+    /// The value of the label with the given name, if the series has it
+    #[must_use]
+    pub fn label(&self, name: &str) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(label, _)| label == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl fmt::Display for SeriesId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if self.labels.is_empty() {
+            return Ok(());
+        }
+        f.write_char('{')?;
+        for (index, (name, value)) in self.labels.iter().enumerate() {
+            if index > 0 {
+                f.write_char(',')?;
+            }
+            write!(f, "{name}={value:?}")?;
+        }
+        f.write_char('}')
+    }
+}
+
+/// The value of a series in a snapshot
+enum Point {
+    Counter(f64),
+    Gauge(f64),
+    Histogram(HistogramPoint),
+}
+
+#[derive(Default)]
+struct HistogramPoint {
+    buckets: Vec<(f64, f64)>,
+    sum: f64,
+    count: f64,
+}
+
+/// The changes between two snapshots, from [`Snapshot::diff`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotDiff {
+    elapsed: Duration,
+    series: Vec<SeriesDelta>,
+    added: Vec<SeriesId>,
+    removed: Vec<SeriesId>,
+}
+
+impl SnapshotDiff {
+    /// The time between the two snapshots
+    #[must_use]
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The changes of the series found in both snapshots, sorted by series
+    #[must_use]
+    pub fn series(&self) -> &[SeriesDelta] {
+        &self.series
+    }
+
+    /// The change of the first series of the family with the given name which
+    /// has all the given labels
+    #[must_use]
+    pub fn find(&self, name: &str, labels: &[(&str, &str)]) -> Option<&SeriesDelta> {
+        self.series.iter().find(|series| {
+            series.id.name == name
+                && labels
+                    .iter()
+                    .all(|(label, value)| series.id.label(label) == Some(*value))
+        })
+    }
+
+    /// The series only found in the later snapshot, or which changed type
+    /// and are also [removed](Self::removed)
+    #[must_use]
+    pub fn added(&self) -> &[SeriesId] {
+        &self.added
+    }
+
+    /// The series only found in the earlier snapshot, or which changed type
+    /// and are also [added](Self::added)
+    #[must_use]
+    pub fn removed(&self) -> &[SeriesId] {
+        &self.removed
+    }
+}
+
+/// The change of a series between two snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeriesDelta {
+    id: SeriesId,
+    delta: Delta,
+    seconds: f64,
+}
+
+impl SeriesDelta {
+    /// The series which changed
+    #[must_use]
+    pub fn id(&self) -> &SeriesId {
+        &self.id
+    }
+
+    /// How the series changed
+    #[must_use]
+    pub fn delta(&self) -> &Delta {
+        &self.delta
+    }
+
+    /// The change of the series per second: the increase of counters, the
+    /// difference of gauges, and the number of observations of histograms.
+    ///
+    /// It is not finite if both snapshots were taken at the same time.
+    #[must_use]
+    pub fn rate(&self) -> f64 {
+        let change = match &self.delta {
+            Delta::Counter { increase, .. } => *increase,
+            Delta::Gauge { old, new } => new - old,
+            Delta::Histogram(histogram) => histogram.count,
+        };
+        change / self.seconds
+    }
+}
+
+/// How a series changed between two snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delta {
+    /// A counter increased
+    Counter {
+        /// The value in the earlier snapshot
+        old: f64,
+
+        /// The value in the later snapshot
+        new: f64,
+
+        /// The increase, which is the new value if the counter was reset
+        increase: f64,
+
+        /// Whether the counter decreased, because it was reset in between
+        reset: bool,
+    },
+
+    /// A gauge changed
+    Gauge {
+        /// The value in the earlier snapshot
+        old: f64,
+
+        /// The value in the later snapshot
+        new: f64,
+    },
+
+    /// A histogram got observations
+    Histogram(HistogramDelta),
+}
+
+/// The observations a histogram got between two snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramDelta {
+    buckets: Vec<(f64, f64)>,
+    sum: f64,
+    count: f64,
+    reset: bool,
+}
+
+impl HistogramDelta {
+    fn new(old: &HistogramPoint, new: HistogramPoint) -> Self {
+        let reset = new.count < old.count
+            || new.buckets.len() != old.buckets.len()
+            || new
+                .buckets
+                .iter()
+                .zip(&old.buckets)
+                .any(|(new, old)| new.0.total_cmp(&old.0).is_ne() || new.1 < old.1);
+        if reset {
+            return Self {
+                buckets: new.buckets,
+                sum: new.sum,
+                count: new.count,
+                reset,
+            };
+        }
+
+        Self {
+            buckets: new
+                .buckets
+                .iter()
+                .zip(&old.buckets)
+                .map(|(new, old)| (new.0, new.1 - old.1))
+                .collect(),
+            sum: new.sum - old.sum,
+            count: new.count - old.count,
+            reset,
+        }
+    }
+
+    /// The cumulative number of new observations of each bucket, by upper
+    /// bound, in increasing order
+    #[must_use]
+    pub fn buckets(&self) -> &[(f64, f64)] {
+        &self.buckets
+    }
+
+    /// The sum of the new observations
+    #[must_use]
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// The number of new observations
+    #[must_use]
+    pub fn count(&self) -> f64 {
+        self.count
+    }
+
+    /// Whether the histogram was reset in between, in which case its deltas
+    /// are its values in the later snapshot
+    #[must_use]
+    pub fn reset(&self) -> bool {
+        self.reset
+    }
+
+    /// Estimate the given quantile of the new observations, between 0 and 1.
+    ///
+    /// Like the `histogram_quantile()` of `PromQL`, the observations are
+    /// assumed to be spread evenly in their bucket, and the lowest bucket
+    /// starts at zero if its upper bound is positive. A quantile falling in
+    /// the `+Inf` bucket is the highest finite bound.
+    ///
+    /// Returns `None` if the quantile is out of range, if there are no new
+    /// observations, or if the histogram has no `+Inf` bucket.
+    #[must_use]
+    pub fn quantile(&self, quantile: f64) -> Option<f64> {
+        if !(0.0..=1.0).contains(&quantile) {
+            return None;
+        }
+        let &(last_bound, total) = self.buckets.last()?;
+        if !(last_bound.is_infinite() && last_bound > 0.0) || total <= 0.0 {
+            return None;
+        }
+
+        let rank = quantile * total;
+        let (mut lower_bound, mut lower_count) = (0.0, 0.0);
+        for (index, &(bound, count)) in self.buckets.iter().enumerate() {
+            if count >= rank {
+                if bound.is_infinite() {
+                    return index.checked_sub(1).map(|index| self.buckets[index].0);
+                }
+                if index == 0 && bound <= 0.0 {
+                    return Some(bound);
+                }
+                let in_bucket = count - lower_count;
+                if in_bucket <= 0.0 {
+                    return Some(lower_bound);
+                }
+                return Some(
+                    lower_bound + (bound - lower_bound) * (rank - lower_count) / in_bucket,
+                );
+            }
+            lower_bound = bound;
+            lower_count = count;
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPOSITION: &str = "# TYPE http_requests_total counter\n\
+                              http_requests_total{method=\"GET\",code=\"200\"} 3\n\
+                              http_requests_total{method=\"GET\",code=\"500\"} 1\n\
+                              http_requests_total{method=\"POST\",code=\"200\"} 2\n\
+                              # TYPE queue_depth gauge\n\
+                              queue_depth 7\n\
+                              # TYPE latency_seconds histogram\n\
+                              latency_seconds_bucket{route=\"/\",le=\"0.5\"} 1\n\
+                              latency_seconds_bucket{route=\"/\",le=\"+Inf\"} 3\n\
+                              latency_seconds_sum{route=\"/\"} 4.5\n\
+                              latency_seconds_count{route=\"/\"} 3\n";
+
+    #[test]
+    fn test_queries() {
+        let snapshot = Snapshot::parse(EXPOSITION, ExpositionFormat::Text).unwrap();
+
+        let requests = snapshot.counter("http_requests");
+        assert_eq!(requests.series().len(), 3);
+        let get = requests.with_label("method", "GET");
+        assert!((get.sum() - 4.0).abs() < f64::EPSILON);
+        assert!((get.with_label("code", "500").value() - 1.0).abs() < f64::EPSILON);
+        assert!(snapshot.counter("missing").series().is_empty());
+        assert!((snapshot.gauge("queue_depth").value() - 7.0).abs() < f64::EPSILON);
+
+        let latency = snapshot.histogram("latency_seconds");
+        assert!((latency.count() - 3.0).abs() < f64::EPSILON);
+        assert!((latency.sum() - 4.5).abs() < f64::EPSILON);
+        assert!((latency.bucket(0.5) - 1.0).abs() < f64::EPSILON);
+        assert!((latency.bucket(f64::INFINITY) - 3.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "2 series of counter `http_requests_total` matching {method=\"GET\"}, add labels to select one"
+    )]
+    fn test_ambiguous_query() {
+        let snapshot = Snapshot::parse(EXPOSITION, ExpositionFormat::Text).unwrap();
+        let _ = snapshot
+            .counter("http_requests_total")
+            .with_label("method", "GET")
+            .value();
+    }
+
+    #[test]
+    fn test_diff() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let before = Snapshot::parse(EXPOSITION, ExpositionFormat::Text)
+            .unwrap()
+            .with_timestamp(start);
+        let after = Snapshot::parse(
+            "# TYPE http_requests_total counter\n\
+             http_requests_total{code=\"200\",method=\"GET\"} 23\n\
+             http_requests_total{method=\"GET\",code=\"500\"} 1\n\
+             http_requests_total{method=\"PUT\",code=\"200\"} 1\n\
+             # TYPE queue_depth gauge\n\
+             queue_depth 2\n\
+             # TYPE latency_seconds histogram\n\
+             latency_seconds_bucket{route=\"/\",le=\"0.5\"} 5\n\
+             latency_seconds_bucket{route=\"/\",le=\"+Inf\"} 11\n\
+             latency_seconds_sum{route=\"/\"} 12.5\n\
+             latency_seconds_count{route=\"/\"} 11\n",
+            ExpositionFormat::Text,
+        )
+        .unwrap()
+        .with_timestamp(start + Duration::from_secs(10));
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.elapsed(), Duration::from_secs(10));
+        assert_eq!(diff.series().len(), 4);
+
+        let get = diff
+            .find("http_requests_total", &[("method", "GET"), ("code", "200")])
+            .unwrap();
+        assert_eq!(
+            get.id().to_string(),
+            "http_requests_total{code=\"200\",method=\"GET\"}"
+        );
+        assert!(matches!(
+            get.delta(),
+            Delta::Counter { increase, reset: false, .. } if (increase - 20.0).abs() < f64::EPSILON
+        ));
+        assert!((get.rate() - 2.0).abs() < f64::EPSILON);
+
+        let depth = diff.find("queue_depth", &[]).unwrap();
+        assert!((depth.rate() + 0.5).abs() < f64::EPSILON);
+
+        let Delta::Histogram(latency) = diff.find("latency_seconds", &[]).unwrap().delta() else {
+            panic!("latency_seconds is a histogram");
+        };
+        assert!(!latency.reset());
+        assert_eq!(latency.buckets(), &[(0.5, 4.0), (f64::INFINITY, 8.0)]);
+        assert!((latency.sum() - 8.0).abs() < f64::EPSILON);
+        assert!((latency.count() - 8.0).abs() < f64::EPSILON);
+        assert!((latency.quantile(0.25).unwrap() - 0.25).abs() < f64::EPSILON);
+        assert!((latency.quantile(0.99).unwrap() - 0.5).abs() < f64::EPSILON);
+        assert_eq!(latency.quantile(1.5), None);
+
+        let added: Vec<_> = diff.added().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            added,
+            vec!["http_requests_total{code=\"200\",method=\"PUT\"}"]
+        );
+        let removed: Vec<_> = diff.removed().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            removed,
+            vec!["http_requests_total{code=\"200\",method=\"POST\"}"]
+        );
+    }
+
+    #[test]
+    fn test_diff_type_change() {
+        let before = Snapshot::parse(
+            "# TYPE queue_depth gauge\n\
+             queue_depth 3\n",
+            ExpositionFormat::Text,
+        )
+        .unwrap();
+        let after = Snapshot::parse(
+            "# TYPE queue_depth histogram\n\
+             queue_depth_bucket{le=\"+Inf\"} 1\n\
+             queue_depth_sum 3\n\
+             queue_depth_count 1\n",
+            ExpositionFormat::Text,
+        )
+        .unwrap();
+
+        let diff = before.diff(&after);
+        assert!(diff.series().is_empty());
+        let added: Vec<_> = diff.added().iter().map(ToString::to_string).collect();
+        assert_eq!(added, vec!["queue_depth"]);
+        let removed: Vec<_> = diff.removed().iter().map(ToString::to_string).collect();
+        assert_eq!(removed, vec!["queue_depth"]);
+    }
+
+    #[test]
+    fn test_diff_reset() {
+        let before = Snapshot::parse(EXPOSITION, ExpositionFormat::Text).unwrap();
+        let after = Snapshot::parse(
+            "# TYPE http_requests_total counter\n\
+             http_requests_total{method=\"GET\",code=\"200\"} 2\n\
+             # TYPE latency_seconds histogram\n\
+             latency_seconds_bucket{route=\"/\",le=\"0.5\"} 0\n\
+             latency_seconds_bucket{route=\"/\",le=\"+Inf\"} 1\n\
+             latency_seconds_sum{route=\"/\"} 2\n\
+             latency_seconds_count{route=\"/\"} 1\n",
+            ExpositionFormat::Text,
+        )
+        .unwrap();
+
+        let diff = before.diff(&after);
+        let get = diff
+            .find("http_requests_total", &[("code", "200")])
+            .unwrap();
+        assert!(matches!(
+            get.delta(),
+            Delta::Counter { increase, reset: true, .. } if (increase - 2.0).abs() < f64::EPSILON
+        ));
+
+        let Delta::Histogram(latency) = diff.find("latency_seconds", &[]).unwrap().delta() else {
+            panic!("latency_seconds is a histogram");
+        };
+        assert!(latency.reset());
+        assert!((latency.count() - 1.0).abs() < f64::EPSILON);
+        // The only observation is above the highest finite bound
+        assert_eq!(latency.quantile(0.5), Some(0.5));
+    }
+}
@@ -0,0 +1,199 @@
+use std::fmt::{self, Write as _};
+
+use crate::parse::{ExpositionFormat, ParseExpositionError, Sample, parse_exposition};
+
+/// Normalize an exposition so that two expositions with the same families and
+/// series compare equal, whatever the order of their families, series and
+/// labels.
+///
+/// The families are sorted by name, their samples by name and labels, and the
+/// labels of each sample by name. Values are written in their shortest form,
+/// so `1.0` and `1` are the same. Expositions ending with `# EOF` are parsed
+/// as `OpenMetrics`, the others in the text format. This is what
+/// [`assert_exposition_eq!`](crate::assert_exposition_eq) compares.
+///
+/// # Errors
+///
+/// Returns an error if the exposition is invalid.
+pub fn normalize_exposition(exposition: &str) -> Result<String, ParseExpositionError> {
+    let format = if exposition.trim_end().ends_with("# EOF") {
+        ExpositionFormat::OpenMetrics
+    } else {
+        ExpositionFormat::Text
+    };
+    let mut families = parse_exposition(exposition, format)?;
+    families.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut output = String::new();
+    for family in &mut families {
+        if let Some(help) = &family.help {
+            let _ = writeln!(output, "# HELP {} {help:?}", family.name);
+        }
+        let _ = writeln!(output, "# TYPE {} {}", family.name, family.metric_type);
+        if let Some(unit) = &family.unit {
+            let _ = writeln!(output, "# UNIT {} {unit}", family.name);
+        }
+
+        let mut lines: Vec<String> = family.samples.iter_mut().map(sample_line).collect();
+        lines.sort();
+        for line in lines {
+            output.push_str(&line);
+            output.push('\n');
+        }
+    }
+
+    Ok(output)
+}
+
+fn sample_line(sample: &mut Sample) -> String {
+    sample.labels.sort();
+
+    let mut line = sample.name.clone();
+    if !sample.labels.is_empty() {
+        line.push('{');
+        for (index, (name, value)) in sample.labels.iter().enumerate() {
+            if index > 0 {
+                line.push(',');
+            }
+            let _ = write!(line, "{name}={value:?}");
+        }
+        line.push('}');
+    }
+    let _ = write!(line, " {}", sample.value);
+    if let Some(timestamp) = sample.timestamp {
+        let _ = write!(line, " {timestamp}");
+    }
+    if let Some(exemplar) = &sample.exemplar {
+        let _ = write!(line, " # {:?} {}", exemplar.labels, exemplar.value);
+        if let Some(timestamp) = exemplar.timestamp {
+            let _ = write!(line, " {timestamp}");
+        }
+    }
+    line
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn __assert_exposition_eq<L, R>(left: &L, right: &R, message: Option<fmt::Arguments<'_>>)
+where
+    L: AsRef<[u8]> + ?Sized,
+    R: AsRef<[u8]> + ?Sized,
+{
+    #[track_caller]
+    fn normalize(side: &str, exposition: &[u8]) -> String {
+        let exposition = match std::str::from_utf8(exposition) {
+            Ok(exposition) => exposition,
+            Err(error) => panic!("the {side} exposition isn't UTF-8: {error}"),
+        };
+        match normalize_exposition(exposition) {
+            Ok(normalized) => normalized,
+            Err(error) => panic!("the {side} exposition is invalid: {error}\n{exposition}"),
+        }
+    }
+
+    let left = normalize("left", left.as_ref());
+    let right = normalize("right", right.as_ref());
+    if left == right {
+        return;
+    }
+
+    let mut differences = String::new();
+    for line in left
+        .lines()
+        .filter(|line| !right.lines().any(|l| l == *line))
+    {
+        let _ = writeln!(differences, "- {line}");
+    }
+    for line in right
+        .lines()
+        .filter(|line| !left.lines().any(|l| l == *line))
+    {
+        let _ = writeln!(differences, "+ {line}");
+    }
+    match message {
+        Some(message) => panic!("expositions differ: {message}\n{differences}"),
+        None => panic!("expositions differ\n{differences}"),
+    }
+}
+
+/// Assert that two expositions have the same families and series, whatever
+/// the order of their families, series and labels.
+///
+/// Both sides can be anything which is `AsRef<[u8]>`, like a `&str` or the
+/// `Vec<u8>` an exporter wrote to. They are compared after going through
+/// [`normalize_exposition`](crate::normalize_exposition), and the lines only
+/// found on one side are printed when they differ. Like [`assert_eq!`], it
+/// takes an optional message.
+///
+/// # Panics
+///
+/// Panics if the expositions differ, or if one of them is invalid.
+///
+/// # Example
+///
+/// ```rust
+/// use opentelemetry_prometheus_text_exporter::assert_exposition_eq;
+///
+/// assert_exposition_eq!(
+///     "up{job=\"api\",instance=\"a\"} 1\nup{instance=\"b\",job=\"api\"} 0\n",
+///     "up{instance=\"b\",job=\"api\"} 0\nup{instance=\"a\",job=\"api\"} 1.0\n",
+/// );
+/// ```
+#[macro_export]
+macro_rules! assert_exposition_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        $crate::__assert_exposition_eq(&$left, &$right, ::std::option::Option::None)
+    };
+    ($left:expr, $right:expr, $($arg:tt)+) => {
+        $crate::__assert_exposition_eq(
+            &$left,
+            &$right,
+            ::std::option::Option::Some(::std::format_args!($($arg)+)),
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPOSITION: &str = "# TYPE http_requests_total counter\n\
+                              http_requests_total{method=\"GET\",code=\"200\"} 3\n\
+                              http_requests_total{method=\"GET\",code=\"500\"} 1\n\
+                              http_requests_total{method=\"POST\",code=\"200\"} 2\n\
+                              # TYPE queue_depth gauge\n\
+                              queue_depth 7\n\
+                              # TYPE latency_seconds histogram\n\
+                              latency_seconds_bucket{route=\"/\",le=\"0.5\"} 1\n\
+                              latency_seconds_bucket{route=\"/\",le=\"+Inf\"} 3\n\
+                              latency_seconds_sum{route=\"/\"} 4.5\n\
+                              latency_seconds_count{route=\"/\"} 3\n";
+
+    #[test]
+    fn test_normalize_exposition() {
+        let reordered = "# TYPE queue_depth gauge\n\
+                         queue_depth 7.0\n\
+                         # TYPE http_requests_total counter\n\
+                         http_requests_total{code=\"200\",method=\"POST\"} 2\n\
+                         http_requests_total{code=\"500\",method=\"GET\"} 1\n\
+                         http_requests_total{code=\"200\",method=\"GET\"} 3\n\
+                         # TYPE latency_seconds histogram\n\
+                         latency_seconds_sum{route=\"/\"} 4.5\n\
+                         latency_seconds_count{route=\"/\"} 3\n\
+                         latency_seconds_bucket{le=\"0.5\",route=\"/\"} 1\n\
+                         latency_seconds_bucket{le=\"+Inf\",route=\"/\"} 3\n";
+        assert_eq!(
+            normalize_exposition(EXPOSITION).unwrap(),
+            normalize_exposition(reordered).unwrap()
+        );
+        crate::assert_exposition_eq!(EXPOSITION, reordered);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "expositions differ: after a scrape\n- queue_depth 7\n+ queue_depth 8\n"
+    )]
+    fn test_assert_exposition_eq_failure() {
+        crate::assert_exposition_eq!("queue_depth 7\n", "queue_depth 8\n", "after a {}", "scrape");
+    }
+}
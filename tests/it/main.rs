@@ -386,3 +386,2499 @@ fn test_builder_pattern_comprehensive_example() {
         }
     }
 }
+
+#[test]
+fn test_cumulative_temporality_is_enforced() {
+    use opentelemetry_sdk::metrics::reader::MetricReader;
+    use opentelemetry_sdk::metrics::{InstrumentKind, Temporality};
+
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_temporality(Temporality::Delta)
+        .build();
+
+    assert_eq!(
+        exporter.temporality(InstrumentKind::Counter),
+        Temporality::Cumulative
+    );
+    assert_eq!(
+        exporter.temporality(InstrumentKind::ObservableCounter),
+        Temporality::Cumulative
+    );
+    assert_eq!(
+        exporter.temporality(InstrumentKind::Histogram),
+        Temporality::Cumulative
+    );
+
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_temporality(Temporality::Delta)
+        .with_delta_conversion()
+        .build();
+
+    assert_eq!(
+        exporter.temporality(InstrumentKind::Counter),
+        Temporality::Delta
+    );
+    assert_eq!(
+        exporter.temporality(InstrumentKind::Histogram),
+        Temporality::Delta
+    );
+    // The SDK always uses cumulative temporality for up-down counters
+    assert_eq!(
+        exporter.temporality(InstrumentKind::UpDownCounter),
+        Temporality::Cumulative
+    );
+}
+
+#[test]
+fn test_with_producer() {
+    let bridged_reader = std::sync::Arc::new(opentelemetry_sdk::metrics::ManualReader::default());
+    let bridged_provider = SdkMeterProvider::builder()
+        .with_resource(
+            Resource::builder_empty()
+                .with_attribute(KeyValue::new("service.name", "plugin-host"))
+                .build(),
+        )
+        .with_reader(SharedReader(bridged_reader.clone()))
+        .build();
+
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_producer(bridged_reader)
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(
+            Resource::builder_empty()
+                .with_attribute(KeyValue::new("service.name", "main"))
+                .build(),
+        )
+        .with_reader(exporter.clone())
+        .build();
+
+    provider
+        .meter("main")
+        .u64_counter("main.requests")
+        .build()
+        .add(1, &[]);
+    bridged_provider
+        .meter("plugin")
+        .u64_counter("plugin.requests")
+        .build()
+        .add(2, &[]);
+
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    let output = String::from_utf8(buffer).unwrap();
+
+    assert!(output.contains("main_requests_total{otel_scope_name=\"main\"} 1\n"));
+    assert!(output.contains("plugin_requests_total{otel_scope_name=\"plugin\"} 2\n"));
+
+    // Both resources end up in a single target_info family
+    assert_eq!(output.matches("# TYPE target_info gauge").count(), 1);
+    assert!(output.contains("target_info{service_name=\"main\"} 1\n"));
+    assert!(output.contains("target_info{service_name=\"plugin-host\"} 1\n"));
+}
+
+#[test]
+fn test_producer_metrics_named_like_the_reader_ones() {
+    let resource = Resource::builder_empty()
+        .with_attribute(KeyValue::new("service.name", "main"))
+        .build();
+    let bridged_reader = std::sync::Arc::new(opentelemetry_sdk::metrics::ManualReader::default());
+    let bridged_provider = SdkMeterProvider::builder()
+        .with_resource(resource.clone())
+        .with_reader(SharedReader(bridged_reader.clone()))
+        .build();
+
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_producer(bridged_reader)
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(resource)
+        .with_reader(exporter.clone())
+        .build();
+
+    provider
+        .meter("main")
+        .u64_counter("requests")
+        .build()
+        .add(1, &[]);
+    provider
+        .meter("main")
+        .u64_counter("errors")
+        .build()
+        .add(3, &[]);
+    bridged_provider
+        .meter("plugin")
+        .u64_counter("requests")
+        .build()
+        .add(2, &[]);
+    bridged_provider
+        .meter("plugin")
+        .f64_gauge("errors.total")
+        .build()
+        .record(4.0, &[]);
+
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    let output = String::from_utf8(buffer).unwrap();
+
+    // Both counters are rendered in a single family
+    assert_eq!(output.matches("# TYPE requests_total counter").count(), 1);
+    assert!(output.contains(
+        "requests_total{otel_scope_name=\"main\"} 1\nrequests_total{otel_scope_name=\"plugin\"} 2\n"
+    ));
+
+    // The gauge named like the counter is left out
+    assert_eq!(output.matches("# TYPE errors_total").count(), 1);
+    assert!(output.contains("errors_total{otel_scope_name=\"main\"} 3\n"));
+    assert!(!output.contains("errors_total{otel_scope_name=\"plugin\"}"));
+
+    // The shared resource is rendered once
+    assert_eq!(output.matches("target_info{").count(), 1);
+}
+
+/// Lets a [`ManualReader`] be registered on a provider while keeping a handle
+/// on it.
+///
+/// [`ManualReader`]: opentelemetry_sdk::metrics::ManualReader
+#[derive(Debug, Clone)]
+struct SharedReader(std::sync::Arc<opentelemetry_sdk::metrics::ManualReader>);
+
+impl opentelemetry_sdk::metrics::reader::MetricReader for SharedReader {
+    fn register_pipeline(&self, pipeline: std::sync::Weak<opentelemetry_sdk::metrics::Pipeline>) {
+        self.0.register_pipeline(pipeline);
+    }
+
+    fn collect(
+        &self,
+        rm: &mut opentelemetry_sdk::metrics::data::ResourceMetrics,
+    ) -> opentelemetry_sdk::error::OTelSdkResult {
+        self.0.collect(rm)
+    }
+
+    fn force_flush(&self) -> opentelemetry_sdk::error::OTelSdkResult {
+        self.0.force_flush()
+    }
+
+    fn shutdown_with_timeout(
+        &self,
+        timeout: std::time::Duration,
+    ) -> opentelemetry_sdk::error::OTelSdkResult {
+        self.0.shutdown_with_timeout(timeout)
+    }
+
+    fn temporality(
+        &self,
+        kind: opentelemetry_sdk::metrics::InstrumentKind,
+    ) -> opentelemetry_sdk::metrics::Temporality {
+        self.0.temporality(kind)
+    }
+}
+
+#[cfg(any(feature = "tokio", feature = "futures"))]
+fn setup_async_export() -> (
+    SdkMeterProvider,
+    opentelemetry_prometheus_text_exporter::PrometheusExporter,
+) {
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::new();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(
+            Resource::builder_empty()
+                .with_attribute(KeyValue::new("service.name", "async"))
+                .build(),
+        )
+        .with_reader(exporter.clone())
+        .build();
+
+    let meter = provider.meter("test");
+    meter
+        .u64_counter("http.requests")
+        .build()
+        .add(3, &[KeyValue::new("method", "GET")]);
+    meter
+        .f64_histogram("http.duration")
+        .with_unit("s")
+        .build()
+        .record(0.2, &[]);
+
+    (provider, exporter)
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_export_async_tokio() {
+    let (_provider, exporter) = setup_async_export();
+
+    let mut expected = Vec::new();
+    exporter.export(&mut expected).unwrap();
+
+    let mut buffer = Vec::new();
+    exporter.export_async_tokio(&mut buffer).await.unwrap();
+
+    assert_eq!(
+        String::from_utf8(buffer).unwrap(),
+        String::from_utf8(expected).unwrap()
+    );
+}
+
+#[cfg(feature = "futures")]
+#[tokio::test]
+async fn test_export_async_futures() {
+    let (_provider, exporter) = setup_async_export();
+
+    let mut expected = Vec::new();
+    exporter.export(&mut expected).unwrap();
+
+    let mut buffer = Vec::new();
+    exporter.export_async_futures(&mut buffer).await.unwrap();
+
+    assert_eq!(
+        String::from_utf8(buffer).unwrap(),
+        String::from_utf8(expected).unwrap()
+    );
+}
+
+#[test]
+fn test_export_to_vec_reserves_previous_size() {
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::new();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(Resource::builder_empty().build())
+        .with_reader(exporter.clone())
+        .build();
+
+    let counter = provider.meter("test").u64_counter("http.requests").build();
+    for i in 0..100 {
+        counter.add(1, &[KeyValue::new("path", format!("/path/{i}"))]);
+    }
+
+    let mut expected = Vec::new();
+    exporter.export(&mut expected).unwrap();
+
+    let mut buffer = b"leftover".to_vec();
+    exporter.export_to_vec(&mut buffer).unwrap();
+    assert_eq!(buffer, expected);
+
+    // A fresh buffer gets enough capacity for the whole exposition upfront
+    let mut buffer = Vec::new();
+    exporter.export_to_vec(&mut buffer).unwrap();
+    assert_eq!(buffer, expected);
+    assert!(buffer.capacity() >= expected.len() + expected.len() / 8);
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_export_bytes() {
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::new();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(Resource::builder_empty().build())
+        .with_reader(exporter.clone())
+        .build();
+
+    let counter = provider.meter("test").u64_counter("http.requests").build();
+    counter.add(1, &[KeyValue::new("method", "GET")]);
+
+    let mut expected = Vec::new();
+    exporter.export(&mut expected).unwrap();
+
+    let first = exporter.export_bytes().unwrap();
+    assert_eq!(first, expected);
+
+    // Previous exports are left untouched by the next ones
+    counter.add(1, &[KeyValue::new("method", "GET")]);
+    let second = exporter.export_bytes().unwrap();
+    assert_eq!(first, expected);
+    assert_ne!(second, first);
+
+    let mut buffer = bytes::BytesMut::new();
+    let third = exporter.export_to_bytes_mut(&mut buffer).unwrap();
+    assert_eq!(third, second);
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn test_export_cached() {
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_min_scrape_interval(std::time::Duration::from_mins(1))
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(Resource::builder_empty().build())
+        .with_reader(exporter.clone())
+        .build();
+
+    let counter = provider.meter("test").u64_counter("http.requests").build();
+    counter.add(1, &[]);
+
+    let first = exporter.export_cached().unwrap();
+    assert!(
+        std::str::from_utf8(&first)
+            .unwrap()
+            .contains("http_requests_total{otel_scope_name=\"test\"} 1\n")
+    );
+
+    // Within the interval, the same exposition is served
+    counter.add(1, &[]);
+    let second = exporter.export_cached().unwrap();
+    assert_eq!(second, first);
+
+    // Uncached exports still see the latest values
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    assert!(
+        String::from_utf8(buffer)
+            .unwrap()
+            .contains("http_requests_total{otel_scope_name=\"test\"} 2\n")
+    );
+}
+
+#[test]
+fn test_export_with_timeout() {
+    use opentelemetry::metrics::ObservableGauge;
+
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_collect_timeout(std::time::Duration::from_mins(1))
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(Resource::builder_empty().build())
+        .with_reader(exporter.clone())
+        .build();
+
+    let slow = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let _gauge: ObservableGauge<u64> = provider
+        .meter("test")
+        .u64_observable_gauge("queue.size")
+        .with_callback({
+            let slow = slow.clone();
+            move |observer| {
+                if slow.load(std::sync::atomic::Ordering::SeqCst) {
+                    std::thread::sleep(std::time::Duration::from_millis(300));
+                }
+                observer.observe(3, &[]);
+            }
+        })
+        .build();
+
+    let exposition = exporter.export_with_timeout(None).unwrap();
+    assert!(!exposition.is_stale());
+    assert!(
+        std::str::from_utf8(&exposition)
+            .unwrap()
+            .contains("queue_size{otel_scope_name=\"test\"} 3\n")
+    );
+
+    // The shortest timeout wins
+    slow.store(true, std::sync::atomic::Ordering::SeqCst);
+    let error = exporter
+        .export_with_timeout(Some(std::time::Duration::from_millis(10)))
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn test_export_with_timeout_serves_stale() {
+    use opentelemetry::metrics::ObservableGauge;
+
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_collect_timeout(std::time::Duration::from_millis(50))
+        .with_stale_on_timeout()
+        .with_min_scrape_interval(std::time::Duration::from_mins(1))
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(Resource::builder_empty().build())
+        .with_reader(exporter.clone())
+        .build();
+
+    let slow = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let _gauge: ObservableGauge<u64> = provider
+        .meter("test")
+        .u64_observable_gauge("queue.size")
+        .with_callback({
+            let slow = slow.clone();
+            move |observer| {
+                if slow.load(std::sync::atomic::Ordering::SeqCst) {
+                    std::thread::sleep(std::time::Duration::from_millis(300));
+                }
+                observer.observe(3, &[]);
+            }
+        })
+        .build();
+
+    let fresh = exporter.export_with_timeout(None).unwrap();
+    assert!(!fresh.is_stale());
+
+    slow.store(true, std::sync::atomic::Ordering::SeqCst);
+    let stale = exporter.export_with_timeout(None).unwrap();
+    assert!(stale.is_stale());
+    assert_eq!(stale.as_bytes(), fresh.as_bytes());
+
+    // Cached exports honor the deadline too, without caching stale results
+    let stale = exporter.export_cached().unwrap();
+    assert!(stale.is_stale());
+    slow.store(false, std::sync::atomic::Ordering::SeqCst);
+    std::thread::sleep(std::time::Duration::from_millis(600));
+    let fresh = exporter.export_cached().unwrap();
+    assert!(!fresh.is_stale());
+}
+
+#[test]
+fn test_self_metrics() {
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_self_metrics()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(
+            Resource::builder_empty()
+                .with_attribute(KeyValue::new("service.name", "test"))
+                .build(),
+        )
+        .with_reader(exporter.clone())
+        .build();
+
+    let counter = provider.meter("test").u64_counter("http.requests").build();
+    counter.add(1, &[KeyValue::new("method", "GET")]);
+    counter.add(1, &[KeyValue::new("method", "POST")]);
+
+    // Same name, different scope: this is merged in the same family
+    let other = provider.meter("other").u64_counter("http.requests").build();
+    other.add(1, &[]);
+
+    // Same name, different type: this is left out
+    provider
+        .meter("other")
+        .u64_gauge("http.requests.total")
+        .build()
+        .record(1, &[]);
+
+    provider
+        .meter("test")
+        .f64_histogram("http.duration")
+        .with_boundaries(vec![0.1, 1.0])
+        .build()
+        .record(0.5, &[]);
+
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    let output = String::from_utf8(buffer).unwrap();
+
+    // The exporter metrics come after the user metrics
+    let user_metrics = &output[..output.find("# TYPE otel_prometheus_exporter_").unwrap()];
+    assert!(user_metrics.contains("target_info"));
+
+    assert!(output.contains("# TYPE otel_prometheus_exporter_scrape_duration_seconds gauge\n"));
+    assert!(output.contains("# TYPE otel_prometheus_exporter_collect_duration_seconds gauge\n"));
+    assert!(output.contains("# TYPE otel_prometheus_exporter_serialize_duration_seconds gauge\n"));
+    assert!(output.contains(&format!(
+        "otel_prometheus_exporter_exposition_size_bytes {}\n",
+        user_metrics.len()
+    )));
+    assert!(output.contains("otel_prometheus_exporter_families 3\n"));
+    // 3 counters, 2 buckets + the +Inf one, _sum and _count, and target_info
+    assert!(output.contains("otel_prometheus_exporter_series 9\n"));
+    assert!(output.contains("otel_prometheus_exporter_conflicting_metrics 1\n"));
+    assert!(
+        output
+            .contains("otel_prometheus_exporter_dropped_metrics{reason=\"unsupported_type\"} 0\n")
+    );
+    assert!(output.contains("otel_prometheus_exporter_scrapes_total 1\n"));
+    assert!(output.contains("otel_prometheus_exporter_errors_total{kind=\"collect\"} 0\n"));
+
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    let output = String::from_utf8(buffer).unwrap();
+    assert!(output.contains("otel_prometheus_exporter_scrapes_total 2\n"));
+}
+
+#[test]
+fn test_cardinality_limit() {
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_cardinality_limit(3)
+        .with_metric_cardinality_limit("http.duration", 1)
+        .with_metric_cardinality_limit("queue.depth", 10)
+        .without_scope_info()
+        .without_target_info()
+        .with_self_metrics()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_reader(exporter.clone())
+        .build();
+    let meter = provider.meter("test");
+
+    let counter = meter.u64_counter("http.requests").build();
+    for (user, count) in [("a", 1), ("b", 2), ("c", 4), ("d", 8), ("e", 16)] {
+        counter.add(count, &[KeyValue::new("user", user)]);
+    }
+
+    let gauge = meter.i64_gauge("queue.depth").build();
+    for queue in ["a", "b", "c", "d"] {
+        gauge.record(1, &[KeyValue::new("queue", queue)]);
+    }
+
+    let histogram = meter
+        .f64_histogram("http.duration")
+        .with_boundaries(vec![1.0])
+        .build();
+    histogram.record(0.5, &[KeyValue::new("user", "a")]);
+    histogram.record(2.0, &[KeyValue::new("user", "b")]);
+
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    let output = String::from_utf8(buffer).unwrap();
+
+    let samples = |prefix: &str| -> Vec<(String, f64)> {
+        output
+            .lines()
+            .filter(|line| line.starts_with(prefix))
+            .map(|line| {
+                let (series, value) = line.rsplit_once(' ').unwrap();
+                (series.to_owned(), value.parse().unwrap())
+            })
+            .collect()
+    };
+
+    // 2 series kept as-is, and the 3 others folded in the overflow series
+    let requests = samples("http_requests_total{");
+    assert_eq!(requests.len(), 3);
+    let overflow: Vec<_> = requests
+        .iter()
+        .filter(|(series, _)| series.contains("otel_metric_overflow=\"true\""))
+        .collect();
+    assert_eq!(overflow.len(), 1);
+    assert_eq!(requests.iter().map(|(_, value)| value).sum::<f64>(), 31.0);
+
+    // The per-metric override raises the limit above the global one
+    let depths = samples("queue_depth{");
+    assert_eq!(depths.len(), 4);
+    assert!(!output.contains("queue_depth{otel_metric_overflow"));
+
+    // A limit of 1 folds everything in the overflow series
+    assert_eq!(
+        samples("http_duration_bucket{"),
+        [
+            (
+                "http_duration_bucket{otel_metric_overflow=\"true\",le=\"1\"}".to_owned(),
+                1.0
+            ),
+            (
+                "http_duration_bucket{otel_metric_overflow=\"true\",le=\"+Inf\"}".to_owned(),
+                2.0
+            ),
+        ]
+    );
+    assert_eq!(
+        samples("http_duration_sum{"),
+        [(
+            "http_duration_sum{otel_metric_overflow=\"true\"}".to_owned(),
+            2.5
+        )]
+    );
+    assert_eq!(
+        samples("http_duration_count{"),
+        [(
+            "http_duration_count{otel_metric_overflow=\"true\"}".to_owned(),
+            2.0
+        )]
+    );
+
+    assert!(output.contains(
+        "otel_prometheus_exporter_overflowed_series{metric=\"http_requests_total\"} 3\n"
+    ));
+    assert!(
+        output.contains("otel_prometheus_exporter_overflowed_series{metric=\"http_duration\"} 2\n")
+    );
+    assert!(!output.contains("otel_prometheus_exporter_overflowed_series{metric=\"queue_depth\"}"));
+    // 3 counters, 4 gauges, and the overflowed histogram
+    assert!(output.contains("otel_prometheus_exporter_series 11\n"));
+}
+
+#[test]
+fn test_cardinality_limit_keeps_the_admitted_series() {
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_cardinality_limit(3)
+        .without_scope_info()
+        .without_target_info()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_reader(exporter.clone())
+        .build();
+    let meter = provider.meter("test");
+
+    let counter = meter.u64_counter("http.requests").build();
+    let gauge = meter.i64_gauge("queue.depth").build();
+    for user in ["a", "b"] {
+        counter.add(1, &[KeyValue::new("user", user)]);
+        gauge.record(5, &[KeyValue::new("queue", user)]);
+    }
+    exporter.export(&mut Vec::new()).unwrap();
+
+    for user in ["c", "d", "e", "f"] {
+        counter.add(1, &[KeyValue::new("user", user)]);
+        gauge.record(5, &[KeyValue::new("queue", user)]);
+    }
+
+    // The series admitted first are kept on every scrape, whatever the order
+    // of the data points
+    for _ in 0..5 {
+        let mut buffer = Vec::new();
+        exporter.export(&mut buffer).unwrap();
+        let output = String::from_utf8(buffer).unwrap();
+
+        assert!(output.contains("http_requests_total{user=\"a\"} 1\n"));
+        assert!(output.contains("http_requests_total{user=\"b\"} 1\n"));
+        assert!(output.contains("http_requests_total{otel_metric_overflow=\"true\"} 4\n"));
+
+        // Gauges are not summed in the overflow series
+        assert!(output.contains("queue_depth{queue=\"a\"} 5\n"));
+        assert!(output.contains("queue_depth{queue=\"b\"} 5\n"));
+        assert!(output.contains("queue_depth{otel_metric_overflow=\"true\"} 5\n"));
+    }
+}
+
+#[test]
+fn test_cardinality_limit_keeps_the_admitted_series_of_each_resource() {
+    let bridged_reader = std::sync::Arc::new(opentelemetry_sdk::metrics::ManualReader::default());
+    let bridged_provider = SdkMeterProvider::builder()
+        .with_resource(
+            Resource::builder_empty()
+                .with_attribute(KeyValue::new("service.name", "plugin"))
+                .build(),
+        )
+        .with_reader(SharedReader(bridged_reader.clone()))
+        .build();
+
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_producer(bridged_reader)
+        .with_cardinality_limit(2)
+        .with_resource_constant_labels(["service.name"])
+        .without_scope_info()
+        .without_target_info()
+        .with_self_metrics()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(
+            Resource::builder_empty()
+                .with_attribute(KeyValue::new("service.name", "main"))
+                .build(),
+        )
+        .with_reader(exporter.clone())
+        .build();
+
+    let counter = provider.meter("test").u64_counter("http.requests").build();
+    let bridged_counter = bridged_provider
+        .meter("test")
+        .u64_counter("http.requests")
+        .build();
+    counter.add(1, &[KeyValue::new("user", "a")]);
+    bridged_counter.add(1, &[KeyValue::new("user", "x")]);
+    exporter.export(&mut Vec::new()).unwrap();
+
+    for user in ["b", "c", "d"] {
+        counter.add(1, &[KeyValue::new("user", user)]);
+    }
+    for user in ["y", "z"] {
+        bridged_counter.add(1, &[KeyValue::new("user", user)]);
+    }
+
+    // Both parts of the family keep the series they admitted first
+    for _ in 0..5 {
+        let mut buffer = Vec::new();
+        exporter.export(&mut buffer).unwrap();
+        let output = String::from_utf8(buffer).unwrap();
+
+        assert!(output.contains("http_requests_total{user=\"a\",service_name=\"main\"} 1\n"));
+        assert!(output.contains(
+            "http_requests_total{otel_metric_overflow=\"true\",service_name=\"main\"} 3\n"
+        ));
+        assert!(output.contains("http_requests_total{user=\"x\",service_name=\"plugin\"} 1\n"));
+        assert!(output.contains(
+            "http_requests_total{otel_metric_overflow=\"true\",service_name=\"plugin\"} 2\n"
+        ));
+    }
+}
+
+#[test]
+fn test_cardinality_limit_merges_the_sdk_overflow() {
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_cardinality_limit(2)
+        .without_scope_info()
+        .without_target_info()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_reader(exporter.clone())
+        .with_view(|_: &opentelemetry_sdk::metrics::Instrument| {
+            opentelemetry_sdk::metrics::Stream::builder()
+                .with_cardinality_limit(3)
+                .build()
+                .ok()
+        })
+        .build();
+
+    let counter = provider.meter("test").u64_counter("http.requests").build();
+    for user in ["a", "b", "c", "d", "e"] {
+        counter.add(1, &[KeyValue::new("user", user)]);
+    }
+
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    let output = String::from_utf8(buffer).unwrap();
+
+    // The overflow series of the SDK is merged in the one of the exporter
+    let requests: Vec<_> = output
+        .lines()
+        .filter(|line| line.starts_with("http_requests_total{"))
+        .collect();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(
+        requests
+            .iter()
+            .filter(|line| line.contains("otel_metric_overflow=\"true\""))
+            .count(),
+        1
+    );
+    let total: u64 = requests
+        .iter()
+        .map(|line| line.rsplit_once(' ').unwrap().1.parse::<u64>().unwrap())
+        .sum();
+    assert_eq!(total, 5);
+}
+
+#[test]
+fn test_size_budget() {
+    let setup = |budget: usize| {
+        let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+            .with_size_budget(budget)
+            .with_scope_priority("noisy", -1)
+            .with_name_priority("app_*", 1)
+            .without_target_info()
+            .build();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(exporter.clone())
+            .build();
+
+        let app = provider.meter("app");
+        app.u64_counter("app.requests").build().add(1, &[]);
+        app.u64_counter("other.requests").build().add(1, &[]);
+        let noisy = provider.meter("noisy").u64_counter("noisy.events").build();
+        for id in 0..50 {
+            noisy.add(1, &[KeyValue::new("id", id)]);
+        }
+
+        (exporter, provider)
+    };
+
+    // Without going over the budget, everything is rendered
+    let (exporter, _provider) = setup(1 << 20);
+    let full = String::from_utf8(exporter.export_cached().unwrap().to_vec()).unwrap();
+    assert!(full.contains("noisy_events_total"));
+    assert!(!full.contains("budget_dropped"));
+
+    let budget = full.len() / 2;
+    let (exporter, _provider) = setup(budget);
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    assert!(buffer.len() <= budget);
+
+    let output = String::from_utf8(buffer).unwrap();
+    assert!(output.contains("# TYPE app_requests_total counter\n"));
+    assert!(output.contains("# TYPE other_requests_total counter\n"));
+    assert!(!output.contains("noisy_events_total{"));
+    assert!(output.contains(&format!(
+        "# Over the size budget of {budget} bytes, dropped: noisy_events_total\n"
+    )));
+    assert!(output.contains(
+        "otel_prometheus_exporter_budget_dropped_families_total{family=\"noisy_events_total\"} 1\n"
+    ));
+
+    // The counter keeps going up on the next scrapes
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    let output = String::from_utf8(buffer).unwrap();
+    assert!(output.contains(
+        "otel_prometheus_exporter_budget_dropped_families_total{family=\"noisy_events_total\"} 2\n"
+    ));
+}
+
+#[test]
+fn test_filter() {
+    use opentelemetry_prometheus_text_exporter::{InstrumentType, MetricSelector};
+
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_include(MetricSelector::new().scope_name("app"))
+        .with_include(
+            MetricSelector::new()
+                .scope_name("hyper")
+                .scope_version("1.*"),
+        )
+        .with_exclude(MetricSelector::new().name("*.debug.*"))
+        .with_exclude(
+            MetricSelector::new()
+                .prometheus_name_regex("hyper_.*_seconds")
+                .unwrap()
+                .instrument_type(InstrumentType::Histogram),
+        )
+        .without_target_info()
+        .with_self_metrics()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_reader(exporter.clone())
+        .build();
+
+    let app = provider.meter("app");
+    app.u64_counter("app.requests").build().add(1, &[]);
+    app.u64_counter("app.debug.events").build().add(1, &[]);
+
+    let hyper = provider.meter_with_scope(
+        opentelemetry::InstrumentationScope::builder("hyper")
+            .with_version("1.4.0")
+            .build(),
+    );
+    hyper.u64_counter("hyper.connections").build().add(1, &[]);
+    hyper
+        .f64_histogram("hyper.duration")
+        .with_unit("s")
+        .build()
+        .record(1.0, &[]);
+
+    let old_hyper = provider.meter_with_scope(
+        opentelemetry::InstrumentationScope::builder("hyper")
+            .with_version("0.14.0")
+            .build(),
+    );
+    old_hyper.u64_counter("hyper.legacy").build().add(1, &[]);
+
+    provider
+        .meter("other")
+        .u64_counter("other.requests")
+        .build()
+        .add(1, &[]);
+
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    let output = String::from_utf8(buffer).unwrap();
+
+    assert!(output.contains("# TYPE app_requests_total counter\n"));
+    assert!(output.contains("# TYPE hyper_connections_total counter\n"));
+    assert!(!output.contains("app_debug_events"));
+    assert!(!output.contains("hyper_duration_seconds"));
+    assert!(!output.contains("hyper_legacy"));
+    assert!(!output.contains("other_requests"));
+    assert!(output.contains("otel_prometheus_exporter_dropped_metrics{reason=\"filtered\"} 4\n"));
+    assert!(output.contains("otel_prometheus_exporter_families 2\n"));
+}
+
+#[test]
+fn test_relabel() {
+    use opentelemetry_prometheus_text_exporter::RelabelConfig;
+
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_relabel_config(
+            RelabelConfig::replace("http_route")
+                .source_labels(["http_route"])
+                .regex("/users/[^/]+(/.*)?")
+                .unwrap()
+                .replacement("/users/:id$1"),
+        )
+        .with_relabel_config(
+            RelabelConfig::drop()
+                .source_labels(["http_route"])
+                .regex("/health")
+                .unwrap(),
+        )
+        .with_relabel_config(
+            RelabelConfig::replace("__name__")
+                .source_labels(["__name__"])
+                .regex("http_duration_(.*)")
+                .unwrap()
+                .replacement("legacy_http_duration_$1"),
+        )
+        .with_relabel_config(RelabelConfig::label_drop().regex("otel_scope_.*").unwrap())
+        .with_relabel_config(
+            RelabelConfig::drop()
+                .source_labels(["le"])
+                .regex("1")
+                .unwrap(),
+        )
+        .without_target_info()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_reader(exporter.clone())
+        .build();
+    let meter = provider.meter("test");
+
+    let counter = meter.u64_counter("http.requests").build();
+    counter.add(1, &[KeyValue::new("http_route", "/users/42/posts")]);
+    counter.add(1, &[KeyValue::new("http_route", "/health")]);
+
+    meter
+        .u64_counter("health.checks")
+        .build()
+        .add(1, &[KeyValue::new("http_route", "/health")]);
+
+    meter
+        .f64_histogram("http.duration")
+        .with_boundaries(vec![1.0, 2.0])
+        .build()
+        .record(1.5, &[]);
+
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    let output = String::from_utf8(buffer).unwrap();
+
+    insta::assert_snapshot!(output, @r#"
+    # TYPE http_requests_total counter
+    http_requests_total{http_route="/users/:id/posts"} 1
+
+    # TYPE legacy_http_duration histogram
+    legacy_http_duration_count 1
+    legacy_http_duration_sum 1.5
+    legacy_http_duration_bucket{le="2"} 1
+    legacy_http_duration_bucket{le="+Inf"} 1
+    "#);
+}
+
+#[test]
+fn test_relabel_keeps_the_series_in_their_family() {
+    use opentelemetry_prometheus_text_exporter::RelabelConfig;
+
+    let rename = |user: &str, name: &str| {
+        RelabelConfig::replace("__name__")
+            .source_labels(["user"])
+            .regex(user)
+            .unwrap()
+            .replacement(name)
+    };
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        // Renames the series of a family, and of both parts of a merged one,
+        // after different families
+        .with_relabel_config(rename("a", "first_total"))
+        .with_relabel_config(rename("b", "second_total"))
+        .with_relabel_config(rename("c", "third_total"))
+        // Renames to an invalid metric name
+        .with_relabel_config(rename("d", "errors-total"))
+        // Renames the buckets of a histogram without their suffix
+        .with_relabel_config(
+            RelabelConfig::replace("__name__")
+                .source_labels(["__name__"])
+                .regex("latency_bucket")
+                .unwrap()
+                .replacement("latency"),
+        )
+        .without_target_info()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_reader(exporter.clone())
+        .build();
+
+    let requests = provider.meter("app").u64_counter("requests").build();
+    requests.add(1, &[KeyValue::new("user", "a")]);
+    requests.add(1, &[KeyValue::new("user", "b")]);
+    provider
+        .meter("plugin")
+        .u64_counter("requests")
+        .build()
+        .add(1, &[KeyValue::new("user", "c")]);
+    provider
+        .meter("app")
+        .u64_counter("errors")
+        .build()
+        .add(1, &[KeyValue::new("user", "d")]);
+    provider
+        .meter("app")
+        .f64_histogram("latency")
+        .with_boundaries(vec![1.0])
+        .build()
+        .record(0.5, &[]);
+
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    let output = String::from_utf8(buffer).unwrap();
+
+    // Every sample is named after the family it is written in
+    let mut family = "";
+    for line in output.lines() {
+        if let Some(metadata) = line.strip_prefix("# TYPE ") {
+            family = metadata.split(' ').next().unwrap();
+        } else if !line.is_empty() && !line.starts_with('#') {
+            let name = line.split(['{', ' ']).next().unwrap();
+            let name = ["_bucket", "_count", "_sum"]
+                .into_iter()
+                .find_map(|suffix| name.strip_suffix(suffix).filter(|name| *name == family))
+                .unwrap_or(name);
+            assert_eq!(name, family, "{line}");
+        }
+    }
+
+    // The family is named after the series written first
+    let renamed = ["first_total", "second_total", "third_total"];
+    assert_eq!(
+        renamed
+            .iter()
+            .filter(|name| output.contains(&format!("# TYPE {name} counter\n")))
+            .count(),
+        1
+    );
+    assert_eq!(
+        renamed
+            .iter()
+            .filter(|name| output.contains(&format!("\n{name}{{")))
+            .count(),
+        1
+    );
+
+    assert!(!output.contains("errors"));
+    assert!(output.contains("latency_count{otel_scope_name=\"app\"} 1\n"));
+    assert!(!output.contains("latency_bucket"));
+    assert!(!output.contains("latency{"));
+}
+
+#[test]
+fn test_namespace_and_const_labels() {
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_namespace("my-app")
+        .with_const_labels([
+            KeyValue::new("cluster", "eu-1"),
+            KeyValue::new("region", "europe"),
+        ])
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(
+            Resource::builder_empty()
+                .with_attribute(KeyValue::new("service.name", "app"))
+                .build(),
+        )
+        .with_reader(exporter.clone())
+        .build();
+
+    provider
+        .meter("test")
+        .u64_counter("http.requests")
+        .with_unit("{request}")
+        .build()
+        .add(
+            1,
+            &[
+                KeyValue::new("method", "GET"),
+                // Collides with the constant label once sanitized
+                KeyValue::new("region", "west"),
+            ],
+        );
+    provider
+        .meter("test")
+        .f64_gauge("cpu.usage")
+        .with_unit("s")
+        .build()
+        .record(0.5, &[KeyValue::new("a.b", "1"), KeyValue::new("a_b", "2")]);
+
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    let output = String::from_utf8(buffer).unwrap();
+
+    insta::assert_snapshot!(output, @r#"
+    # TYPE my_app_http_requests_total counter
+    my_app_http_requests_total{method="GET",region="west;europe",otel_scope_name="test",cluster="eu-1"} 1
+
+    # TYPE my_app_cpu_usage_seconds gauge
+    # UNIT my_app_cpu_usage_seconds seconds
+    my_app_cpu_usage_seconds{a_b="1;2",otel_scope_name="test",cluster="eu-1",region="europe"} 0.5
+
+    # TYPE target_info gauge
+    # HELP target_info Target metadata
+    target_info{service_name="app",cluster="eu-1",region="europe"} 1
+    "#);
+}
+
+#[test]
+fn test_export_filtered() {
+    use opentelemetry_prometheus_text_exporter::SeriesSelector;
+
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .without_scope_info()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_reader(exporter.clone())
+        .build();
+
+    let meter = provider.meter("test");
+    let requests = meter.u64_counter("http.requests").build();
+    requests.add(1, &[KeyValue::new("method", "GET")]);
+    requests.add(2, &[KeyValue::new("method", "POST")]);
+    meter
+        .f64_histogram("http.duration")
+        .with_unit("s")
+        .with_boundaries(vec![1.0])
+        .build()
+        .record(0.5, &[KeyValue::new("method", "GET")]);
+    meter.u64_counter("rpc.calls").build().add(1, &[]);
+
+    let export = |selectors: &[&str]| {
+        let selectors: Vec<SeriesSelector> = selectors
+            .iter()
+            .map(|selector| selector.parse().unwrap())
+            .collect();
+        let mut buffer = Vec::new();
+        exporter.export_filtered(&selectors, &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    };
+
+    insta::assert_snapshot!(export(&["rpc_calls_total", r#"{__name__=~"http_.*",method="GET"}"#]), @r#"
+    # TYPE http_requests_total counter
+    http_requests_total{method="GET"} 1
+
+    # TYPE http_duration_seconds histogram
+    # UNIT http_duration_seconds seconds
+    http_duration_seconds_count{method="GET"} 1
+    http_duration_seconds_sum{method="GET"} 0.5
+    http_duration_seconds_bucket{method="GET",le="1"} 1
+    http_duration_seconds_bucket{method="GET",le="+Inf"} 1
+
+    # TYPE rpc_calls_total counter
+    rpc_calls_total 1
+    "#);
+
+    // Nothing matches
+    assert_eq!(export(&[r#"{method="DELETE"}"#]), "");
+
+    // Without selectors, everything is rendered
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    assert_eq!(export(&[]), String::from_utf8(buffer).unwrap());
+}
+
+#[test]
+fn test_metadata_overrides() {
+    use opentelemetry_prometheus_text_exporter::MetadataOverride;
+
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_namespace("app")
+        .with_metadata_override(
+            MetadataOverride::new("db.duration")
+                .scope("legacy-db")
+                .unit("s")
+                .description("Duration of the queries"),
+        )
+        .with_metadata_override(MetadataOverride::new("pool.waiting").name("db_pool_waiters"))
+        .with_generated_help()
+        .without_scope_info()
+        .without_target_info()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_reader(exporter.clone())
+        .build();
+
+    let legacy = provider.meter("legacy-db");
+    legacy
+        .f64_gauge("db.duration")
+        .with_unit("ms")
+        .build()
+        .record(0.25, &[]);
+    legacy
+        .i64_up_down_counter("pool.waiting")
+        .build()
+        .add(3, &[]);
+
+    // Another scope, not matching the scoped override
+    provider
+        .meter("other-db")
+        .f64_gauge("db.duration")
+        .with_unit("ms")
+        .with_description("Query latency")
+        .build()
+        .record(12.0, &[]);
+
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    let output = String::from_utf8(buffer).unwrap();
+
+    // Scopes are rendered in no particular order
+    assert!(output.contains(
+        "# TYPE app_db_duration_seconds gauge\n\
+         # HELP app_db_duration_seconds Duration of the queries\n\
+         # UNIT app_db_duration_seconds seconds\n\
+         app_db_duration_seconds 0.25\n"
+    ));
+    assert!(output.contains(
+        "# TYPE db_pool_waiters gauge\n\
+         # HELP db_pool_waiters OpenTelemetry instrument pool.waiting\n\
+         db_pool_waiters 3\n"
+    ));
+    assert!(output.contains(
+        "# TYPE app_db_duration_milliseconds gauge\n\
+         # HELP app_db_duration_milliseconds Query latency\n\
+         # UNIT app_db_duration_milliseconds milliseconds\n\
+         app_db_duration_milliseconds 12\n"
+    ));
+}
+
+#[test]
+fn test_label_limits() {
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_label_limit(4)
+        .with_label_name_length_limit(8)
+        .with_label_value_length_limit(20)
+        .with_const_labels([KeyValue::new("cluster", "eu-1")])
+        .without_target_info()
+        .with_self_metrics()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_reader(exporter.clone())
+        .build();
+
+    let queries = provider.meter("db").u64_counter("db.queries").build();
+    queries.add(
+        1,
+        &[KeyValue::new(
+            "db.statement",
+            "SELECT * FROM users WHERE name = 'José'",
+        )],
+    );
+    queries.add(
+        2,
+        &[KeyValue::new(
+            "db.statement",
+            "SELECT * FROM users WHERE name = 'Ana'",
+        )],
+    );
+    queries.add(
+        3,
+        &[
+            KeyValue::new("db.system", "pg"),
+            KeyValue::new("db.name", "users"),
+            KeyValue::new("db.user", "app"),
+        ],
+    );
+    provider
+        .meter("db")
+        .u64_counter("http.requests")
+        .build()
+        .add(
+            1,
+            &[
+                KeyValue::new("http.request.method", "GET"),
+                KeyValue::new("http.request.path", "/"),
+            ],
+        );
+    provider
+        .meter("db")
+        .u64_counter("a.very.long.instrument.name")
+        .build()
+        .add(1, &[]);
+    provider
+        .meter("db")
+        .f64_histogram("db.duration")
+        .with_boundaries(vec![1.0])
+        .build()
+        .record(0.5, &[]);
+
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    let output = String::from_utf8(buffer).unwrap();
+    // The statements are cut with a hash of the full value, keeping them apart
+    assert!(output.contains(
+        "db_queries_total{db_state=\"SELECT * bd244aec...\",otel_scope_name=\"db\",cluster=\"eu-1\"} 2\n"
+    ));
+    assert!(output.contains(
+        "db_queries_total{db_state=\"SELECT * eb576392...\",otel_scope_name=\"db\",cluster=\"eu-1\"} 1\n"
+    ));
+    // The series with too many labels, and the ones whose label names are
+    // the same once cut, are left out
+    assert!(!output.contains("db_syste"));
+    assert!(!output.contains("http_req"));
+    // The name keeps its suffix, and the `le` label is never cut
+    assert!(output.contains("# TYPE a_very_long_in_total counter\n"));
+    assert!(
+        output.contains("db_duration_bucket{le=\"1\",otel_scope_name=\"db\",cluster=\"eu-1\"} 1\n")
+    );
+    assert!(output.contains("otel_prometheus_exporter_truncated_series_total 3\n"));
+    assert!(output.contains("otel_prometheus_exporter_label_limit_dropped_series_total 2\n"));
+}
+
+#[test]
+fn test_idle_series() {
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_idle_series_scrapes(1)
+        .without_scope_info()
+        .without_target_info()
+        .with_self_metrics()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_reader(exporter.clone())
+        .build();
+
+    let meter = provider.meter("test");
+    let requests = meter.u64_counter("requests").build();
+    requests.add(1, &[KeyValue::new("peer", "old-pod")]);
+    requests.add(1, &[KeyValue::new("peer", "new-pod")]);
+    let latency = meter.f64_histogram("latency").build();
+    latency.record(1.0, &[KeyValue::new("peer", "old-pod")]);
+
+    let export = || {
+        let mut buffer = Vec::new();
+        exporter.export(&mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    };
+
+    let output = export();
+    assert!(output.contains("requests_total{peer=\"old-pod\"} 1\n"));
+    assert!(output.contains("latency_count{peer=\"old-pod\"} 1\n"));
+    assert!(output.contains("otel_prometheus_exporter_idle_series 0\n"));
+
+    // Only the series which changed are left
+    requests.add(1, &[KeyValue::new("peer", "new-pod")]);
+    let output = export();
+    assert!(output.contains("requests_total{peer=\"new-pod\"} 2\n"));
+    assert!(!output.contains("old-pod"));
+    assert!(!output.contains("# TYPE latency histogram"));
+    assert!(output.contains("otel_prometheus_exporter_idle_series 2\n"));
+
+    // Filtered exports still render the idle series
+    let mut buffer = Vec::new();
+    exporter.export_filtered(&[], &mut buffer).unwrap();
+    assert!(String::from_utf8(buffer).unwrap().contains("old-pod"));
+
+    // A series comes back as soon as it changes
+    latency.record(1.0, &[KeyValue::new("peer", "old-pod")]);
+    let output = export();
+    assert!(output.contains("latency_count{peer=\"old-pod\"} 2\n"));
+    assert!(!output.contains("requests_total"));
+}
+
+#[test]
+fn test_idle_series_of_several_resources() {
+    let bridged_reader = std::sync::Arc::new(opentelemetry_sdk::metrics::ManualReader::default());
+    let bridged_provider = SdkMeterProvider::builder()
+        .with_resource(
+            Resource::builder_empty()
+                .with_attribute(KeyValue::new("service.name", "plugin"))
+                .build(),
+        )
+        .with_reader(SharedReader(bridged_reader.clone()))
+        .build();
+
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_producer(bridged_reader)
+        .with_idle_series_scrapes(1)
+        .with_resource_constant_labels(["service.name"])
+        .without_scope_info()
+        .without_target_info()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(
+            Resource::builder_empty()
+                .with_attribute(KeyValue::new("service.name", "main"))
+                .build(),
+        )
+        .with_reader(exporter.clone())
+        .build();
+
+    let requests = provider.meter("test").u64_counter("requests").build();
+    let bridged_requests = bridged_provider
+        .meter("test")
+        .u64_counter("requests")
+        .build();
+    requests.add(1, &[]);
+    bridged_requests.add(1, &[]);
+    exporter.export(&mut Vec::new()).unwrap();
+
+    // The series of the plugin changing doesn't keep the identical one of the
+    // main resource alive
+    bridged_requests.add(1, &[]);
+    let mut buffer = Vec::new();
+    exporter.export(&mut buffer).unwrap();
+    let output = String::from_utf8(buffer).unwrap();
+    assert!(output.contains("requests_total{service_name=\"plugin\"} 2\n"));
+    assert!(!output.contains("service_name=\"main\""));
+}
+
+#[test]
+fn test_resource_constant_labels() {
+    let exporter = opentelemetry_prometheus_text_exporter::PrometheusExporter::builder()
+        .with_resource_constant_labels(["service.*", "deployment.environment"])
+        .without_resource_constant_labels(["service.instance.id"])
+        .without_scope_info()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(
+            Resource::builder_empty()
+                .with_attributes([
+                    KeyValue::new("service.name", "checkout"),
+                    KeyValue::new("service.instance.id", "pod-1"),
+                    KeyValue::new("deployment.environment", "prod"),
+                    KeyValue::new("host.name", "node-1"),
+                ])
+                .build(),
+        )
+        .with_reader(exporter.clone())
+        .build();
+
+    let meter = provider.meter("test");
+    let counter = meter.u64_counter("orders").build();
+    counter.add(1, &[KeyValue::new("method", "card")]);
+
+    let mut output = Vec::new();
+    exporter.export(&mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(output.contains(
+        "orders_total{method=\"card\",deployment_environment=\"prod\",service_name=\"checkout\"} 1\n"
+    ));
+    // `target_info` keeps all the resource attributes, only once
+    let target_info = output
+        .lines()
+        .find(|line| line.starts_with("target_info{"))
+        .unwrap();
+    assert!(target_info.contains("service_instance_id=\"pod-1\""));
+    assert_eq!(target_info.matches("service_name=").count(), 1);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_declarative_config() {
+    use opentelemetry_prometheus_text_exporter::PrometheusConfig;
+
+    let config: PrometheusConfig = serde_json::from_str(
+        r#"{
+            "host": "0.0.0.0",
+            "port": 9090,
+            "without_units": null,
+            "without_scope_info": true,
+            "without_target_info": true,
+            "with_resource_constant_labels": {"included": ["service.name"]},
+            "translation_strategy": "UnderscoreEscapingWithoutSuffixes"
+        }"#,
+    )
+    .unwrap();
+
+    let exporter = config.build();
+    assert_eq!(exporter.listen_address(), ("0.0.0.0", 9090));
+
+    let provider = SdkMeterProvider::builder()
+        .with_resource(
+            Resource::builder_empty()
+                .with_attribute(KeyValue::new("service.name", "checkout"))
+                .build(),
+        )
+        .with_reader(exporter.clone())
+        .build();
+    let meter = provider.meter("test");
+    let counter = meter.f64_counter("request.duration").with_unit("s").build();
+    counter.add(1.5, &[]);
+
+    let mut output = Vec::new();
+    exporter.export(&mut output).unwrap();
+    insta::assert_snapshot!(String::from_utf8(output).unwrap(), @r#"
+    # TYPE request_duration counter
+    request_duration{service_name="checkout"} 1.5
+    "#);
+
+    let error =
+        serde_json::from_str::<PrometheusConfig>(r#"{"translation_strategy": "NoTranslation"}"#)
+            .unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("unknown variant `NoTranslation`")
+    );
+
+    let error = serde_json::from_str::<PrometheusConfig>(r#"{"port": 70000}"#).unwrap_err();
+    assert!(error.to_string().contains("70000"));
+
+    // Builder methods take precedence over the configuration
+    let exporter = config
+        .builder()
+        .with_units()
+        .with_counter_suffixes()
+        .with_scope_info()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(
+            Resource::builder_empty()
+                .with_attribute(KeyValue::new("service.name", "checkout"))
+                .build(),
+        )
+        .with_reader(exporter.clone())
+        .build();
+    provider
+        .meter("test")
+        .f64_counter("request.duration")
+        .with_unit("s")
+        .build()
+        .add(1.5, &[]);
+
+    let mut output = Vec::new();
+    exporter.export(&mut output).unwrap();
+    insta::assert_snapshot!(String::from_utf8(output).unwrap(), @r#"
+    # TYPE request_duration_seconds_total counter
+    # UNIT request_duration_seconds_total seconds
+    request_duration_seconds_total{otel_scope_name="test",service_name="checkout"} 1.5
+    "#);
+}
+
+#[test]
+fn test_registry_discriminator_labels_replace_the_series_labels() {
+    use opentelemetry_prometheus_text_exporter::{PrometheusExporter, PrometheusRegistry};
+
+    let exporter = PrometheusExporter::builder()
+        .without_scope_info()
+        .with_resource_constant_labels(["job"])
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(
+            Resource::builder_empty()
+                .with_attributes([
+                    KeyValue::new("service.name", "checkout"),
+                    KeyValue::new("job", "batch"),
+                ])
+                .build(),
+        )
+        .with_reader(exporter.clone())
+        .build();
+    let requests = provider.meter("test").u64_counter("requests").build();
+    requests.add(
+        1,
+        &[
+            KeyValue::new("instance", "attribute"),
+            KeyValue::new("tenant_id", "attribute"),
+        ],
+    );
+
+    let export = |registry: &PrometheusRegistry| {
+        let mut output = Vec::new();
+        registry.export(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    // The `job` resource label and the `instance` attribute are replaced
+    let registry = PrometheusRegistry::new().with_exporter("checkout-1", exporter);
+    let output = export(&registry);
+    assert!(output.contains(
+        "requests_total{tenant_id=\"attribute\",job=\"checkout\",instance=\"checkout-1\"} 1\n"
+    ));
+    let target_info = output
+        .lines()
+        .find(|line| line.starts_with("target_info{"))
+        .unwrap();
+    assert!(!target_info.contains("batch"));
+    assert_eq!(target_info.matches("job=").count(), 1);
+
+    // The name of the discriminator label is sanitized, and replaces the
+    // attribute of the same name
+    let registry = registry.with_discriminator_label("tenant.id");
+    let output = export(&registry);
+    assert!(output.contains(
+        "requests_total{instance=\"attribute\",job=\"batch\",tenant_id=\"checkout-1\"} 1\n"
+    ));
+    assert!(!output.contains("tenant.id"));
+    assert!(output.contains(",tenant_id=\"checkout-1\"} 1\n"));
+}
+
+#[test]
+fn test_registry() {
+    use opentelemetry_prometheus_text_exporter::{PrometheusExporter, PrometheusRegistry};
+
+    let provider = |exporter: &PrometheusExporter, service: &str| {
+        SdkMeterProvider::builder()
+            .with_resource(
+                Resource::builder_empty()
+                    .with_attributes([
+                        KeyValue::new("service.namespace", "shop"),
+                        KeyValue::new("service.name", service.to_owned()),
+                        KeyValue::new("service.instance.id", "pod-1"),
+                    ])
+                    .build(),
+            )
+            .with_reader(exporter.clone())
+            .build()
+    };
+
+    let tenant = PrometheusExporter::builder().without_scope_info().build();
+    let tenant_provider = provider(&tenant, "checkout");
+    let plugins = PrometheusExporter::builder().without_scope_info().build();
+    let plugins_provider = provider(&plugins, "plugins");
+
+    let requests = tenant_provider
+        .meter("test")
+        .u64_counter("requests")
+        .build();
+    requests.add(1, &[KeyValue::new("method", "GET")]);
+    let requests = plugins_provider
+        .meter("test")
+        .u64_counter("requests")
+        .build();
+    requests.add(2, &[KeyValue::new("method", "GET")]);
+    let loaded = plugins_provider.meter("test").u64_gauge("loaded").build();
+    loaded.record(3, &[]);
+    // Same name, but a different type than the first family
+    let conflicting = plugins_provider
+        .meter("test")
+        .f64_histogram("requests_total")
+        .build();
+    conflicting.record(1.0, &[]);
+
+    let export = |registry: &PrometheusRegistry| {
+        let mut output = Vec::new();
+        registry.export(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    let registry = PrometheusRegistry::new()
+        .with_exporter("tenant", tenant)
+        .with_exporter("plugins", plugins);
+    let output = export(&registry);
+    assert!(output.starts_with(
+        "# TYPE requests_total counter\n\
+         requests_total{method=\"GET\",job=\"shop/checkout\",instance=\"pod-1\"} 1\n\
+         requests_total{method=\"GET\",job=\"shop/plugins\",instance=\"pod-1\"} 2\n\
+         \n\
+         # TYPE loaded gauge\n\
+         loaded{job=\"shop/plugins\",instance=\"pod-1\"} 3\n\
+         \n\
+         # TYPE target_info gauge\n"
+    ));
+    assert!(!output.contains("histogram"));
+    // Resource attributes are not in order
+    let target_info: Vec<_> = output
+        .lines()
+        .filter(|line| line.starts_with("target_info{"))
+        .collect();
+    assert_eq!(target_info.len(), 2);
+    assert!(target_info[0].ends_with(",job=\"shop/checkout\",instance=\"pod-1\"} 1"));
+    assert!(target_info[1].ends_with(",job=\"shop/plugins\",instance=\"pod-1\"} 1"));
+
+    let registry = registry.with_discriminator_label("provider");
+    let output = export(&registry);
+    assert!(output.contains("requests_total{method=\"GET\",provider=\"tenant\"} 1\n"));
+    assert!(output.contains("requests_total{method=\"GET\",provider=\"plugins\"} 2\n"));
+    assert!(output.contains(",provider=\"plugins\"} 1\n"));
+    assert_eq!(output.matches("# TYPE").count(), 3);
+
+    // Without instance id, like with the default resource, the registered
+    // names tell the exporters apart
+    let first = PrometheusExporter::builder().without_scope_info().build();
+    let first_provider = SdkMeterProvider::builder()
+        .with_reader(first.clone())
+        .build();
+    let second = PrometheusExporter::builder().without_scope_info().build();
+    let second_provider = SdkMeterProvider::builder()
+        .with_reader(second.clone())
+        .build();
+    for provider in [&first_provider, &second_provider] {
+        let requests = provider.meter("test").u64_counter("requests").build();
+        requests.add(1, &[]);
+    }
+
+    let registry = PrometheusRegistry::new()
+        .with_exporter("first", first)
+        .with_exporter("second", second);
+    let output = export(&registry);
+    let job = "job=\"unknown_service:it-";
+    let requests: Vec<_> = output
+        .lines()
+        .filter(|line| line.starts_with("requests_total{"))
+        .collect();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[0].contains(job) && requests[0].ends_with(",instance=\"first\"} 1"));
+    assert!(requests[1].contains(job) && requests[1].ends_with(",instance=\"second\"} 1"));
+    assert!(output.contains(",instance=\"first\"} 1\ntarget_info{"));
+    assert!(output.trim_end().ends_with(",instance=\"second\"} 1"));
+}
+
+#[cfg(feature = "otlp")]
+#[test]
+fn test_otlp_gateway() {
+    use std::sync::Arc;
+
+    use opentelemetry_prometheus_text_exporter::{ExporterBuilder, PrometheusExporter};
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_sdk::metrics::ManualReader;
+    use opentelemetry_sdk::metrics::data::ResourceMetrics;
+    use opentelemetry_sdk::metrics::reader::MetricReader;
+    use prost::Message;
+
+    // The same metrics are exported in-process and pushed over OTLP
+    let exporter = PrometheusExporter::builder().without_units().build();
+    let reader = SharedReader(Arc::new(ManualReader::builder().build()));
+    let provider = SdkMeterProvider::builder()
+        .with_resource(
+            Resource::builder_empty()
+                .with_attribute(KeyValue::new("service.name", "sidecar"))
+                .build(),
+        )
+        .with_reader(exporter.clone())
+        .with_reader(reader.clone())
+        .build();
+    let meter = provider.meter("test");
+    let counter = meter.u64_counter("jobs.processed").build();
+    counter.add(3, &[KeyValue::new("queue", "emails")]);
+    let gauge = meter.f64_gauge("queue.depth").with_unit("{job}").build();
+    gauge.record(1.5, &[]);
+    let histogram = meter
+        .f64_histogram("job.duration")
+        .with_unit("s")
+        .with_boundaries(vec![0.1, 1.0])
+        .build();
+    histogram.record(0.5, &[KeyValue::new("queue", "emails")]);
+
+    let mut rm = ResourceMetrics::default();
+    reader.collect(&mut rm).unwrap();
+    let request = ExportMetricsServiceRequest::from(&rm);
+
+    let mut expected = Vec::new();
+    exporter.export(&mut expected).unwrap();
+    let expected = String::from_utf8(expected).unwrap();
+
+    let export = |gateway: &opentelemetry_prometheus_text_exporter::OtlpGateway| {
+        let mut output = Vec::new();
+        gateway.export(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    let gateway = ExporterBuilder::default().without_units().build_gateway();
+    let response = gateway
+        .ingest("application/x-protobuf", &request.encode_to_vec())
+        .unwrap();
+    // No data point was rejected
+    assert!(response.is_empty());
+    assert_eq!(export(&gateway), expected);
+
+    let gateway = ExporterBuilder::default().without_units().build_gateway();
+    let response = gateway
+        .ingest(
+            "application/json; charset=utf-8",
+            &serde_json::to_vec(&request).unwrap(),
+        )
+        .unwrap();
+    assert_eq!(response, b"{}");
+    assert_eq!(export(&gateway), expected);
+
+    let error = gateway.ingest("text/plain", b"").unwrap_err();
+    assert!(error.is_unsupported_content_type());
+    let error = gateway.ingest("application/json", b"[").unwrap_err();
+    assert!(!error.is_unsupported_content_type());
+
+    // Resources which stopped pushing are forgotten
+    let gateway = gateway.with_resource_ttl(std::time::Duration::ZERO);
+    assert_eq!(export(&gateway), "");
+}
+
+#[cfg(feature = "otlp")]
+#[test]
+fn test_otlp_json_lines() {
+    use opentelemetry_prometheus_text_exporter::OtlpMetrics;
+
+    // Two exports, as written by the file exporter of the OpenTelemetry
+    // Collector: the gauge is replaced, and the delta sum accumulated
+    let file = r#"{"resourceMetrics":[{"resource":{"attributes":[{"key":"service.name","value":{"stringValue":"ci"}}]},"scopeMetrics":[{"scope":{"name":"runner","version":"1.2"},"metrics":[{"name":"queue.depth","unit":"{job}","gauge":{"dataPoints":[{"timeUnixNano":"1700000000000000000","asDouble":4.5}]}},{"name":"jobs.done","description":"Jobs done","sum":{"aggregationTemporality":1,"isMonotonic":true,"dataPoints":[{"attributes":[{"key":"status","value":{"stringValue":"ok"}}],"asInt":"2"}]}}]}]}]}
+
+{"resourceMetrics":[{"resource":{"attributes":[{"key":"service.name","value":{"stringValue":"ci"}}]},"scopeMetrics":[{"scope":{"name":"runner","version":"1.2"},"metrics":[{"name":"queue.depth","unit":"{job}","gauge":{"dataPoints":[{"asDouble":1}]}},{"name":"jobs.done","description":"Jobs done","sum":{"aggregationTemporality":1,"isMonotonic":true,"dataPoints":[{"attributes":[{"key":"status","value":{"stringValue":"ok"}}],"asInt":"3"}]}},{"name":"job.duration","unit":"s","histogram":{"aggregationTemporality":2,"dataPoints":[{"count":"3","sum":4.5,"bucketCounts":["1","2"],"explicitBounds":[1]}]}},{"name":"latency","exponentialHistogram":{"aggregationTemporality":2,"dataPoints":[{"attributes":[],"startTimeUnixNano":"0","timeUnixNano":"0","count":"1","scale":0,"zeroCount":"0","positive":{"offset":0,"bucketCounts":["1"]},"flags":0,"exemplars":[],"zeroThreshold":0}]}}]}]}]}
+"#;
+
+    let metrics = OtlpMetrics::from_json_lines(file.as_bytes()).unwrap();
+    assert_eq!(metrics.rejected_data_points(), 1);
+
+    let mut output = Vec::new();
+    metrics.export(&mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    insta::assert_snapshot!(output, @r#"
+    # TYPE queue_depth gauge
+    queue_depth{otel_scope_name="runner",otel_scope_version="1.2"} 1
+
+    # TYPE jobs_done_total counter
+    # HELP jobs_done_total Jobs done
+    jobs_done_total{status="ok",otel_scope_name="runner",otel_scope_version="1.2"} 5
+
+    # TYPE job_duration_seconds histogram
+    # UNIT job_duration_seconds seconds
+    job_duration_seconds_count{otel_scope_name="runner",otel_scope_version="1.2"} 3
+    job_duration_seconds_sum{otel_scope_name="runner",otel_scope_version="1.2"} 4.5
+    job_duration_seconds_bucket{le="1",otel_scope_name="runner",otel_scope_version="1.2"} 1
+    job_duration_seconds_bucket{le="+Inf",otel_scope_name="runner",otel_scope_version="1.2"} 3
+
+    # TYPE target_info gauge
+    # HELP target_info Target metadata
+    target_info{service_name="ci"} 1
+    "#);
+
+    let error =
+        OtlpMetrics::from_json_lines("\n{\"resourceMetrics\": [}\n".as_bytes()).unwrap_err();
+    assert_eq!(error.line(), 2);
+    assert_eq!(error.column(), Some(22));
+    assert_eq!(
+        error.to_string(),
+        "invalid OTLP JSON at line 2, column 22: expected value"
+    );
+
+    // Errors in the structure of the message have no column
+    let error = OtlpMetrics::from_json_lines("{\"resourceMetrics\": 1}".as_bytes()).unwrap_err();
+    assert_eq!(error.line(), 1);
+    assert_eq!(error.column(), None);
+}
+
+/// A small deterministic random generator, so that the round-trip test covers
+/// many shapes of metrics while failing reproducibly
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        usize::try_from(self.next() % bound as u64).unwrap()
+    }
+
+    fn float(&mut self) -> f64 {
+        let magnitude = [1e-3, 1.0, 1e3, 1e12][self.below(4)];
+        #[allow(clippy::cast_precision_loss)]
+        let unit = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        unit * magnitude
+    }
+
+    /// A label value with the characters that have to be escaped
+    fn string(&mut self) -> String {
+        const CHARS: &[char] = &[
+            'a', 'Z', '0', ' ', '"', '\\', '\n', '{', '}', ',', '=', '#', 'é', '🦀',
+        ];
+        (0..self.below(8))
+            .map(|_| CHARS[self.below(CHARS.len())])
+            .collect()
+    }
+}
+
+/// The series a data point should be rendered to
+#[derive(Debug)]
+enum ExpectedPoint {
+    Number(f64),
+    Histogram {
+        bounds: Vec<f64>,
+        bucket_counts: Vec<u64>,
+        count: u64,
+        sum: f64,
+    },
+}
+
+#[test]
+fn test_parse_round_trip() {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use opentelemetry_prometheus_text_exporter::{
+        ExpositionFormat, MetricType, PrometheusExporter, parse_exposition,
+    };
+    use opentelemetry_sdk::metrics::ManualReader;
+    use opentelemetry_sdk::metrics::data::{AggregatedMetrics, MetricData, ResourceMetrics};
+    use opentelemetry_sdk::metrics::reader::MetricReader;
+
+    fn sorted_labels<'a>(
+        attributes: impl Iterator<Item = &'a KeyValue>,
+        extra: &[(&str, &str)],
+    ) -> Vec<(String, String)> {
+        let mut labels: Vec<(String, String)> = attributes
+            .map(|kv| (kv.key.to_string(), kv.value.as_str().into_owned()))
+            .chain(
+                extra
+                    .iter()
+                    .map(|(name, value)| ((*name).to_owned(), (*value).to_owned())),
+            )
+            .collect();
+        labels.sort();
+        labels
+    }
+
+    macro_rules! numbers {
+        ($points:expr) => {
+            $points
+                .map(|point| {
+                    #[allow(clippy::cast_precision_loss, clippy::cast_lossless)]
+                    let value = point.value() as f64;
+                    (
+                        point.attributes().collect::<Vec<_>>(),
+                        ExpectedPoint::Number(value),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+    }
+
+    macro_rules! histograms {
+        ($points:expr) => {
+            $points
+                .map(|point| {
+                    #[allow(clippy::cast_precision_loss, clippy::cast_lossless)]
+                    let sum = point.sum() as f64;
+                    (
+                        point.attributes().collect::<Vec<_>>(),
+                        ExpectedPoint::Histogram {
+                            bounds: point.bounds().collect(),
+                            bucket_counts: point.bucket_counts().collect(),
+                            count: point.count(),
+                            sum,
+                        },
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+    }
+
+    for seed in 1..=64_u64 {
+        let mut rng = XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+
+        let exporter = PrometheusExporter::new();
+        let reader = SharedReader(Arc::new(ManualReader::builder().build()));
+        let service_name = rng.string();
+        let provider = SdkMeterProvider::builder()
+            .with_resource(
+                Resource::builder_empty()
+                    .with_attribute(KeyValue::new("service.name", service_name.clone()))
+                    .build(),
+            )
+            .with_reader(exporter.clone())
+            .with_reader(reader.clone())
+            .build();
+        let meter = provider.meter("round_trip");
+
+        for index in 0..=rng.below(6) {
+            let mut attributes = Vec::new();
+            for _ in 0..=rng.below(4) {
+                let mut point = Vec::new();
+                for key in ["a", "b", "c"] {
+                    if rng.below(2) == 0 {
+                        point.push(KeyValue::new(key, rng.string()));
+                    }
+                }
+                attributes.push(point);
+            }
+
+            match rng.below(7) {
+                0 => {
+                    let counter = meter.u64_counter(format!("m{index}_u64_counter")).build();
+                    for point in &attributes {
+                        counter.add(rng.next() >> 12, point);
+                    }
+                }
+                1 => {
+                    let counter = meter.f64_counter(format!("m{index}_f64_counter")).build();
+                    for point in &attributes {
+                        counter.add(rng.float(), point);
+                    }
+                }
+                2 => {
+                    let counter = meter
+                        .i64_up_down_counter(format!("m{index}_up_down"))
+                        .build();
+                    for point in &attributes {
+                        counter.add(i64::try_from(rng.next() >> 12).unwrap() - (1 << 51), point);
+                    }
+                }
+                3 => {
+                    let gauge = meter.f64_gauge(format!("m{index}_f64_gauge")).build();
+                    for point in &attributes {
+                        gauge.record(rng.float() - rng.float(), point);
+                    }
+                }
+                4 => {
+                    let gauge = meter.i64_gauge(format!("m{index}_i64_gauge")).build();
+                    for point in &attributes {
+                        gauge.record(-i64::try_from(rng.next() >> 12).unwrap(), point);
+                    }
+                }
+                5 => {
+                    let mut boundaries: Vec<f64> = (0..rng.below(5)).map(|_| rng.float()).collect();
+                    boundaries.sort_by(f64::total_cmp);
+                    boundaries.dedup();
+                    let histogram = meter
+                        .f64_histogram(format!("m{index}_f64_histogram"))
+                        .with_boundaries(boundaries)
+                        .build();
+                    for point in &attributes {
+                        for _ in 0..=rng.below(5) {
+                            histogram.record(rng.float(), point);
+                        }
+                    }
+                }
+                _ => {
+                    let histogram = meter
+                        .u64_histogram(format!("m{index}_u64_histogram"))
+                        .build();
+                    for point in &attributes {
+                        for _ in 0..=rng.below(5) {
+                            histogram.record(rng.next() % 20_000, point);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut rm = ResourceMetrics::default();
+        reader.collect(&mut rm).unwrap();
+        let mut exposition = Vec::new();
+        exporter.export(&mut exposition).unwrap();
+        let exposition = String::from_utf8(exposition).unwrap();
+
+        let families = parse_exposition(&exposition, ExpositionFormat::Text)
+            .unwrap_or_else(|error| panic!("seed {seed}: {error}\n{exposition}"));
+        let families: HashMap<&str, _> = families
+            .iter()
+            .map(|family| (family.name.as_str(), family))
+            .collect();
+
+        for scope_metrics in rm.scope_metrics() {
+            for metric in scope_metrics.metrics() {
+                let (name, metric_type, points) = match metric.data() {
+                    AggregatedMetrics::U64(MetricData::Sum(sum)) => (
+                        format!("{}_total", metric.name()),
+                        MetricType::Counter,
+                        numbers!(sum.data_points()),
+                    ),
+                    AggregatedMetrics::F64(MetricData::Sum(sum)) => (
+                        format!("{}_total", metric.name()),
+                        MetricType::Counter,
+                        numbers!(sum.data_points()),
+                    ),
+                    AggregatedMetrics::I64(MetricData::Sum(sum)) => (
+                        metric.name().to_owned(),
+                        MetricType::Gauge,
+                        numbers!(sum.data_points()),
+                    ),
+                    AggregatedMetrics::F64(MetricData::Gauge(gauge)) => (
+                        metric.name().to_owned(),
+                        MetricType::Gauge,
+                        numbers!(gauge.data_points()),
+                    ),
+                    AggregatedMetrics::I64(MetricData::Gauge(gauge)) => (
+                        metric.name().to_owned(),
+                        MetricType::Gauge,
+                        numbers!(gauge.data_points()),
+                    ),
+                    AggregatedMetrics::F64(MetricData::Histogram(histogram)) => (
+                        metric.name().to_owned(),
+                        MetricType::Histogram,
+                        histograms!(histogram.data_points()),
+                    ),
+                    AggregatedMetrics::U64(MetricData::Histogram(histogram)) => (
+                        metric.name().to_owned(),
+                        MetricType::Histogram,
+                        histograms!(histogram.data_points()),
+                    ),
+                    data => panic!("unexpected data {data:?}"),
+                };
+
+                let family = families
+                    .get(name.as_str())
+                    .unwrap_or_else(|| panic!("seed {seed}: missing {name}\n{exposition}"));
+                assert_eq!(family.metric_type, metric_type, "seed {seed}: {name}");
+
+                let samples = |suffix: &str, labels: &[(String, String)]| {
+                    family
+                        .samples
+                        .iter()
+                        .filter(|sample| {
+                            let mut sample_labels = sample.labels.clone();
+                            sample_labels.retain(|(label, _)| label != "le");
+                            sample_labels.sort();
+                            sample.name == format!("{name}{suffix}") && sample_labels == labels
+                        })
+                        .collect::<Vec<_>>()
+                };
+
+                let mut series = 0;
+                for (attributes, expected) in points {
+                    let labels =
+                        sorted_labels(attributes.into_iter(), &[("otel_scope_name", "round_trip")]);
+                    match expected {
+                        ExpectedPoint::Number(value) => {
+                            let samples = samples("", &labels);
+                            assert_eq!(samples.len(), 1, "seed {seed}: {name} {labels:?}");
+                            assert_eq!(samples[0].value, value, "seed {seed}: {name} {labels:?}");
+                            series += 1;
+                        }
+                        ExpectedPoint::Histogram {
+                            bounds,
+                            bucket_counts,
+                            count,
+                            sum,
+                        } => {
+                            let buckets = samples("_bucket", &labels);
+                            assert_eq!(buckets.len(), bounds.len() + 1, "seed {seed}: {name}");
+                            let mut cumulative = 0;
+                            for (bucket, bucket_count) in buckets.iter().zip(bucket_counts) {
+                                cumulative += bucket_count;
+                                let le: f64 = bucket.label("le").unwrap().parse().unwrap();
+                                assert!(le == f64::INFINITY || bounds.contains(&le));
+                                #[allow(clippy::cast_precision_loss)]
+                                let cumulative = cumulative as f64;
+                                assert_eq!(bucket.value, cumulative, "seed {seed}: {name}");
+                            }
+
+                            #[allow(clippy::cast_precision_loss)]
+                            let count = count as f64;
+                            assert_eq!(samples("_count", &labels)[0].value, count);
+                            assert_eq!(samples("_sum", &labels)[0].value, sum);
+                            series += buckets.len() + 2;
+                        }
+                    }
+                }
+                assert_eq!(family.samples.len(), series, "seed {seed}: {name}");
+            }
+        }
+
+        let target_info = families["target_info"];
+        assert_eq!(
+            target_info.samples[0].label("service_name"),
+            Some(service_name.as_str())
+        );
+    }
+}
+
+#[test]
+fn test_lint_exporter_output() {
+    use opentelemetry_prometheus_text_exporter::{
+        ExpositionFormat, LintRule, Linter, PrometheusExporter,
+    };
+
+    let exporter = PrometheusExporter::builder().without_scope_info().build();
+    let provider = SdkMeterProvider::builder()
+        .with_resource(
+            Resource::builder_empty()
+                .with_attribute(KeyValue::new("service.name", "api"))
+                .build(),
+        )
+        .with_reader(exporter.clone())
+        .build();
+    let meter = provider.meter("test");
+    meter
+        .u64_counter("http.requests")
+        .with_description("HTTP requests")
+        .build()
+        .add(1, &[KeyValue::new("method", "GET")]);
+    let duration = meter.f64_histogram("http.duration").with_unit("ms").build();
+    duration.record(12.0, &[KeyValue::new("method", "GET")]);
+    duration.record(30.0, &[KeyValue::new("method", "POST")]);
+    meter
+        .i64_gauge("queue.count")
+        .with_description("Jobs in the queue")
+        .build()
+        .record(3, &[]);
+
+    let mut output = Vec::new();
+    exporter.export(&mut output).unwrap();
+
+    let findings = Linter::new()
+        .lint_exposition(output.as_slice(), ExpositionFormat::Text)
+        .unwrap();
+    let findings: Vec<String> = findings.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        findings,
+        vec![
+            "http_duration_milliseconds: use the base unit `seconds` instead of `milliseconds` \
+             (base-unit)",
+            "http_duration_milliseconds: no help text (missing-help)",
+            "queue_count: only histograms and summaries should end with `_count` \
+             (reserved-suffix)",
+        ]
+    );
+
+    // The histogram renders 2 series, of 17 samples each with the default
+    // buckets
+    let findings = Linter::new()
+        .without_rule(LintRule::BaseUnit)
+        .without_rule(LintRule::MissingHelp)
+        .without_rule(LintRule::ReservedSuffix)
+        .with_series_budget(1)
+        .lint_exposition(output.as_slice(), ExpositionFormat::Text)
+        .unwrap();
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].family(), "http_duration_milliseconds");
+    assert_eq!(findings[0].rule(), LintRule::SeriesBudget);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_cli() {
+    use std::process::Command;
+
+    use opentelemetry_prometheus_text_exporter::{ExpositionFormat, parse_exposition};
+
+    let directory = std::env::temp_dir().join(format!("prometheus-text-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let input = directory.join("metrics.json");
+    std::fs::write(
+        &input,
+        r#"{"resourceMetrics":[{"resource":{"attributes":[{"key":"service.name","value":{"stringValue":"ci"}}]},"scopeMetrics":[{"scope":{"name":"runner"},"metrics":[{"name":"jobs.done","description":"Jobs done","sum":{"aggregationTemporality":2,"isMonotonic":true,"dataPoints":[{"attributes":[{"key":"status","value":{"stringValue":"ok"}}],"asInt":"2"}]}}]}]}]}"#,
+    )
+    .unwrap();
+
+    let run = |args: &[&std::ffi::OsStr]| {
+        Command::new(env!("CARGO_BIN_EXE_prometheus-text"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&[
+        "convert".as_ref(),
+        "--without-scope-info".as_ref(),
+        "--namespace".as_ref(),
+        "ci".as_ref(),
+        input.as_ref(),
+    ]);
+    assert!(output.status.success());
+    let text = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        text.contains("ci_jobs_done_total{status=\"ok\"} 2\n"),
+        "{text}"
+    );
+    let old = directory.join("old.txt");
+    std::fs::write(&old, &text).unwrap();
+
+    let output = run(&[
+        "convert".as_ref(),
+        "--output-format".as_ref(),
+        "openmetrics".as_ref(),
+        input.as_ref(),
+    ]);
+    assert!(output.status.success());
+    let openmetrics = String::from_utf8(output.stdout).unwrap();
+    assert!(openmetrics.ends_with("# EOF\n"));
+    parse_exposition(&openmetrics, ExpositionFormat::OpenMetrics).unwrap();
+
+    // The exporter output follows the conventions
+    let output = run(&["lint".as_ref(), old.as_ref()]);
+    assert!(output.status.success(), "{output:?}");
+
+    let new = directory.join("new.txt");
+    std::fs::write(&new, text.replace("} 2\n", "} 5\n")).unwrap();
+    let output = run(&["diff".as_ref(), old.as_ref(), new.as_ref()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "~ ci_jobs_done_total{status=\"ok\"} 2 -> 5 (+3)\n0 added, 0 removed, 1 changed\n"
+    );
+
+    // The families with the lowest priority are dropped first
+    let output = run(&[
+        "convert".as_ref(),
+        "--size-budget".as_ref(),
+        "1".as_ref(),
+        "--name-priority".as_ref(),
+        "target_*=1".as_ref(),
+        input.as_ref(),
+    ]);
+    assert!(output.status.success());
+    let text = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        text.starts_with(
+            "# Over the size budget of 1 bytes, dropped: jobs_done_total target_info\n"
+        ),
+        "{text}"
+    );
+
+    let output = run(&["diff".as_ref(), "-".as_ref(), "-".as_ref()]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = run(&["convert".as_ref(), directory.join("missing.json").as_ref()]);
+    assert_eq!(output.status.code(), Some(2));
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_testing_snapshot() {
+    use opentelemetry_prometheus_text_exporter::{
+        PrometheusExporter, Snapshot, assert_exposition_eq,
+    };
+
+    let exporter = PrometheusExporter::builder()
+        .without_scope_info()
+        .without_target_info()
+        .build();
+    let provider = SdkMeterProvider::builder()
+        .with_reader(exporter.clone())
+        .build();
+    let meter = provider.meter("test");
+
+    let requests = meter
+        .u64_counter("http.requests")
+        .with_description("HTTP requests")
+        .build();
+    requests.add(
+        2,
+        &[KeyValue::new("method", "GET"), KeyValue::new("code", "200")],
+    );
+    requests.add(
+        1,
+        &[KeyValue::new("code", "500"), KeyValue::new("method", "GET")],
+    );
+    let duration = meter
+        .f64_histogram("http.duration")
+        .with_unit("s")
+        .with_boundaries(vec![0.1, 1.0])
+        .build();
+    duration.record(0.05, &[KeyValue::new("method", "GET")]);
+    duration.record(0.5, &[KeyValue::new("method", "GET")]);
+
+    let snapshot = Snapshot::capture(&exporter);
+    let get = snapshot
+        .counter("http_requests_total")
+        .with_label("method", "GET");
+    assert_eq!(get.series().len(), 2);
+    assert_eq!(get.sum(), 3.0);
+    assert_eq!(get.with_label("code", "500").value(), 1.0);
+
+    let duration = snapshot
+        .histogram("http_duration_seconds")
+        .with_label("method", "GET");
+    assert_eq!(duration.count(), 2.0);
+    assert_eq!(duration.sum(), 0.55);
+    assert_eq!(duration.bucket(0.1), 1.0);
+    assert_eq!(duration.bucket(f64::INFINITY), 2.0);
+
+    let mut output = Vec::new();
+    exporter.export(&mut output).unwrap();
+    assert_exposition_eq!(
+        output,
+        r#"# TYPE http_duration_seconds histogram
+# UNIT http_duration_seconds seconds
+http_duration_seconds_count{method="GET"} 2
+http_duration_seconds_sum{method="GET"} 0.55
+http_duration_seconds_bucket{method="GET",le="+Inf"} 2
+http_duration_seconds_bucket{method="GET",le="1"} 2
+http_duration_seconds_bucket{method="GET",le="0.1"} 1
+# HELP http_requests_total HTTP requests
+# TYPE http_requests_total counter
+http_requests_total{method="GET",code="500"} 1
+http_requests_total{method="GET",code="200"} 2
+"#
+    );
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_cli_top() {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::process::Command;
+
+    use opentelemetry_prometheus_text_exporter::PrometheusExporter;
+
+    let exporter = PrometheusExporter::builder().without_scope_info().build();
+    let provider = SdkMeterProvider::builder()
+        .with_reader(exporter.clone())
+        .build();
+    let requests = provider.meter("test").u64_counter("requests").build();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        // One request more than the number of refreshes
+        for _ in 0..3 {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut chunk = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut chunk).unwrap();
+                assert!(read > 0, "the request ended early");
+                request.extend_from_slice(&chunk[..read]);
+            }
+
+            requests.add(5, &[KeyValue::new("method", "GET")]);
+            let mut body = Vec::new();
+            exporter.export(&mut body).unwrap();
+            write!(
+                stream,
+                "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\r\n"
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        }
+    });
+
+    let output = Command::new(env!("CARGO_BIN_EXE_prometheus-text"))
+        .args(["top", "--interval", "0.05", "--iterations", "2"])
+        .arg(format!("http://{address}/metrics"))
+        .output()
+        .unwrap();
+    server.join().unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let refreshes: Vec<_> = stdout.matches("1 series changed").collect();
+    assert_eq!(refreshes.len(), 2, "{stdout}");
+    assert!(
+        stdout.contains("+5                            requests_total{method=\"GET\"}"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn test_snapshot_diff() {
+    use std::time::Duration;
+
+    use opentelemetry_prometheus_text_exporter::{Delta, PrometheusExporter};
+
+    let exporter = PrometheusExporter::builder().without_scope_info().build();
+    let provider = SdkMeterProvider::builder()
+        .with_reader(exporter.clone())
+        .build();
+    let meter = provider.meter("test");
+    let requests = meter.u64_counter("http.requests").build();
+    let duration = meter
+        .f64_histogram("http.duration")
+        .with_unit("s")
+        .with_boundaries(vec![0.1, 1.0])
+        .build();
+    let queue = meter.i64_gauge("queue.depth").build();
+
+    requests.add(10, &[KeyValue::new("method", "GET")]);
+    duration.record(0.05, &[]);
+    queue.record(4, &[]);
+    let before = exporter.snapshot().unwrap();
+
+    requests.add(6, &[KeyValue::new("method", "GET")]);
+    requests.add(1, &[KeyValue::new("method", "POST")]);
+    for value in [0.05, 0.5, 0.5, 2.0] {
+        duration.record(value, &[]);
+    }
+    queue.record(1, &[]);
+    let after = exporter
+        .snapshot()
+        .unwrap()
+        .with_timestamp(before.timestamp() + Duration::from_secs(2));
+
+    let diff = before.diff(&after);
+    assert_eq!(diff.elapsed(), Duration::from_secs(2));
+    assert_eq!(diff.added().len(), 1);
+    assert_eq!(diff.added()[0].label("method"), Some("POST"));
+    assert!(diff.removed().is_empty());
+
+    let get = diff
+        .find("http_requests_total", &[("method", "GET")])
+        .unwrap();
+    assert_eq!(
+        get.delta(),
+        &Delta::Counter {
+            old: 10.0,
+            new: 16.0,
+            increase: 6.0,
+            reset: false
+        }
+    );
+    assert_eq!(get.rate(), 3.0);
+
+    let queue = diff.find("queue_depth", &[]).unwrap();
+    assert_eq!(queue.delta(), &Delta::Gauge { old: 4.0, new: 1.0 });
+    assert_eq!(queue.rate(), -1.5);
+
+    let Delta::Histogram(duration) = diff.find("http_duration_seconds", &[]).unwrap().delta()
+    else {
+        panic!("http_duration_seconds is a histogram");
+    };
+    assert_eq!(duration.count(), 4.0);
+    assert!((duration.sum() - 3.05).abs() < 1e-9);
+    assert_eq!(
+        duration.buckets(),
+        &[(0.1, 1.0), (1.0, 3.0), (f64::INFINITY, 4.0)]
+    );
+    assert!((duration.quantile(0.5).unwrap() - 0.55).abs() < 1e-9);
+}